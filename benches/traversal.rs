@@ -0,0 +1,167 @@
+//! Times `Vec3` arithmetic and closest-hit queries through the binary and the four-wide BVH, on the random sphere
+//! scene and on a large triangle mesh, and camera rays traced one at a time against 8x8 packets
+//!
+//! `cargo bench --bench traversal` runs the scalar path and `cargo bench --bench traversal --features simd` the SSE one
+
+use rust_ray_tracer_in_one_weekend::bvh::{BinaryBvh, Bvh};
+use rust_ray_tracer_in_one_weekend::camera::Camera;
+use rust_ray_tracer_in_one_weekend::float::{random_float, Float};
+use rust_ray_tracer_in_one_weekend::hit::Hittable;
+use rust_ray_tracer_in_one_weekend::material::Lambertian;
+use rust_ray_tracer_in_one_weekend::ray::Ray;
+use rust_ray_tracer_in_one_weekend::triangle::Triangle;
+use rust_ray_tracer_in_one_weekend::vec3::Vec3;
+use rust_ray_tracer_in_one_weekend::{scenes, simd};
+use std::hint::black_box;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const VECTOR_OPERATIONS: usize = 2_000_000;
+const RAYS: usize = 200_000;
+const MESH_RESOLUTION: usize = 300;
+const IMAGE_WIDTH: usize = 400;
+const IMAGE_HEIGHT: usize = 200;
+const PACKET_WIDTH: usize = 8;
+
+/// Median of five runs of `run`, which is less thrown by other work on the machine than the mean
+fn time<T>(mut run: impl FnMut() -> T) -> Duration {
+  let mut durations: Vec<Duration> = (0..5)
+    .map(|_| {
+      let start = Instant::now();
+      black_box(run());
+      start.elapsed()
+    })
+    .collect();
+  durations.sort();
+  durations[2]
+}
+
+fn random_vector() -> Vec3 {
+  Vec3::new(random_float(), random_float(), random_float())
+}
+
+fn bench_vectors() {
+  let vectors: Vec<(Vec3, Vec3)> = (0..1024).map(|_| (random_vector(), random_vector())).collect();
+  let duration = time(|| {
+    let mut sum = Vec3::new_empty();
+    for index in 0..VECTOR_OPERATIONS {
+      let (first, second) = &vectors[index % vectors.len()];
+      sum += first.cross(second) * first.dot(second) + (first - second) / 3.0;
+    }
+    sum
+  });
+  println!(
+    "vec3 cross, dot, sub, div, mul and add: {:.1} ns per iteration",
+    duration.as_secs_f64() * 1e9 / VECTOR_OPERATIONS as f64
+  );
+}
+
+/// A bumpy heightfield split into two triangles per grid cell
+fn mesh() -> Vec<Box<dyn Hittable>> {
+  let material = Arc::new(Lambertian::new(&Vec3::new(0.5, 0.5, 0.5)));
+  let vertex = |x: usize, z: usize| {
+    let (x, z) = (x as Float / MESH_RESOLUTION as Float, z as Float / MESH_RESOLUTION as Float);
+    Vec3::new(20.0 * x - 10.0, (12.0 * x).sin() * (9.0 * z).cos(), 20.0 * z - 10.0)
+  };
+  let mut triangles: Vec<Box<dyn Hittable>> = Vec::new();
+  for x in 0..MESH_RESOLUTION {
+    for z in 0..MESH_RESOLUTION {
+      let corners = [vertex(x, z), vertex(x + 1, z), vertex(x + 1, z + 1), vertex(x, z + 1)];
+      triangles.push(Box::new(Triangle::new(&corners[0], &corners[1], &corners[2], material.clone())));
+      triangles.push(Box::new(Triangle::new(&corners[0], &corners[2], &corners[3], material.clone())));
+    }
+  }
+  triangles
+}
+
+/// Rays from around `look_from` towards points scattered around `look_at`
+fn rays(look_from: &Vec3, look_at: &Vec3, spread: Float) -> Vec<Ray> {
+  (0..RAYS)
+    .map(|_| {
+      let target = look_at + (random_vector() - Vec3::new(0.5, 0.5, 0.5)) * spread;
+      Ray::new(look_from, &(target - look_from))
+    })
+    .collect()
+}
+
+fn bench_bvhs(name: &str, primitives: impl Fn() -> Vec<Box<dyn Hittable>>, rays: &[Ray]) {
+  let (binary, wide) = (BinaryBvh::new(primitives()), Bvh::new(primitives()));
+  let trace = |bvh: &dyn Hittable| rays.iter().filter(|ray| bvh.is_hit(ray, 0.0, Float::MAX).is_some()).count();
+  assert_eq!(trace(&binary), trace(&wide), "the BVHs disagree on {name}");
+  let binary_duration = time(|| trace(&binary));
+  let wide_duration = time(|| trace(&wide));
+  let rate = |duration: Duration| RAYS as f64 / duration.as_secs_f64() / 1e6;
+  println!(
+    "{name}: binary BVH {:.2} Mrays/s, four-wide BVH {:.2} Mrays/s ({:.2}x)",
+    rate(binary_duration),
+    rate(wide_duration),
+    binary_duration.as_secs_f64() / wide_duration.as_secs_f64()
+  );
+}
+
+/// One ray through each pixel of the image, ordered block by block so each run of 64 fills one 8x8 packet
+fn camera_rays(look_from: &Vec3, look_at: &Vec3) -> Vec<Ray> {
+  let camera = Camera::builder()
+    .with_look_from(look_from)
+    .with_look_at(look_at)
+    .with_vertical_fov(30.0)
+    .with_aspect(IMAGE_WIDTH as Float / IMAGE_HEIGHT as Float)
+    .build()
+    .expect("the benchmark camera is valid");
+  let mut rays = Vec::with_capacity(IMAGE_WIDTH * IMAGE_HEIGHT);
+  for block_y in (0..IMAGE_HEIGHT).step_by(PACKET_WIDTH) {
+    for block_x in (0..IMAGE_WIDTH).step_by(PACKET_WIDTH) {
+      for y in block_y..block_y + PACKET_WIDTH {
+        for x in block_x..block_x + PACKET_WIDTH {
+          rays.push(camera.get_ray((x as Float + 0.5) / IMAGE_WIDTH as Float, (y as Float + 0.5) / IMAGE_HEIGHT as Float));
+        }
+      }
+    }
+  }
+  rays
+}
+
+fn bench_packets(name: &str, primitives: Vec<Box<dyn Hittable>>, rays: &[Ray]) {
+  let bvh = Bvh::new(primitives);
+  let single = || rays.iter().filter(|ray| bvh.is_hit(ray, 0.0, Float::MAX).is_some()).count();
+  let packets = || {
+    rays
+      .chunks(PACKET_WIDTH * PACKET_WIDTH)
+      .map(|packet| bvh.is_hit_packet(packet, 0.0, Float::MAX).iter().filter(|hit| hit.is_some()).count())
+      .sum::<usize>()
+  };
+  assert_eq!(single(), packets(), "packets and single rays disagree on {name}");
+  let single_duration = time(single);
+  let packet_duration = time(packets);
+  let rate = |duration: Duration| rays.len() as f64 / duration.as_secs_f64() / 1e6;
+  println!(
+    "{name} camera rays: single {:.2} Mrays/s, 8x8 packets {:.2} Mrays/s ({:.2}x)",
+    rate(single_duration),
+    rate(packet_duration),
+    single_duration.as_secs_f64() / packet_duration.as_secs_f64()
+  );
+}
+
+fn main() {
+  println!("{} backend", if simd::ENABLED { "SSE" } else { "scalar" });
+  fastrand::seed(7);
+  bench_vectors();
+  let random_scene_rays = rays(&Vec3::new(13.0, 2.0, 3.0), &Vec3::new(0.0, 0.0, 0.0), 8.0);
+  bench_bvhs(
+    "random scene",
+    || {
+      let mut scene = scenes::random_scene(1);
+      scene.world_mut().list_mut().drain(..).collect()
+    },
+    &random_scene_rays,
+  );
+  let mesh_rays = rays(&Vec3::new(0.0, 8.0, 14.0), &Vec3::new(0.0, 0.0, 0.0), 16.0);
+  bench_bvhs(&format!("{}-triangle mesh", 2 * MESH_RESOLUTION * MESH_RESOLUTION), mesh, &mesh_rays);
+  let random_scene = scenes::random_scene(1).world_mut().list_mut().drain(..).collect();
+  bench_packets(
+    "random scene",
+    random_scene,
+    &camera_rays(&Vec3::new(13.0, 2.0, 3.0), &Vec3::new(0.0, 0.0, 0.0)),
+  );
+  bench_packets("mesh", mesh(), &camera_rays(&Vec3::new(0.0, 8.0, 14.0), &Vec3::new(0.0, 0.0, 0.0)));
+}
@@ -0,0 +1,30 @@
+//! Renders the random scene through the library API and writes it to stdout as a PPM
+//!
+//! `cargo run --release --example random_scene -- [samples per pixel] > random_scene.ppm`
+
+use rust_ray_tracer_in_one_weekend::camera::Camera;
+use rust_ray_tracer_in_one_weekend::filter::Filter;
+use rust_ray_tracer_in_one_weekend::float::Float;
+use rust_ray_tracer_in_one_weekend::settings::ImageSettings;
+use rust_ray_tracer_in_one_weekend::vec3::Vec3;
+use rust_ray_tracer_in_one_weekend::{render, scenes, Error};
+
+const WIDTH: usize = 400;
+const HEIGHT: usize = 200;
+
+fn main() -> Result<(), Error> {
+  let samples_per_pixel = std::env::args().nth(1).map_or(Ok(32), |samples| samples.parse())?;
+  let mut scene = scenes::random_scene(1);
+  scene.world_mut().build_bvh();
+  let camera = Camera::builder()
+    .with_look_from(&Vec3::new(13.0, 2.0, 3.0))
+    .with_look_at(&Vec3::new(0.0, 0.0, 0.0))
+    .with_vertical_fov(20.0)
+    .with_aspect(WIDTH as Float / HEIGHT as Float)
+    .with_aperture(0.1)
+    .with_focus_distance(10.0)
+    .build()?;
+  let settings = ImageSettings::new(WIDTH, HEIGHT, samples_per_pixel).with_filter(Filter::Gaussian { radius: 1.5, sigma: 0.5 });
+  let framebuffer = render(&scene, &camera, &settings)?;
+  framebuffer.write_ppm(true, &mut std::io::stdout().lock())
+}
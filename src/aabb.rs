@@ -0,0 +1,93 @@
+use crate::float::Float;
+use crate::ray::Ray;
+use crate::vec3::{Vec3, Vec3Index};
+
+/// Axis-aligned bounding box
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+  minimum: Vec3,
+  maximum: Vec3,
+}
+
+impl Aabb {
+  pub fn new(minimum: &Vec3, maximum: &Vec3) -> Self {
+    Self {
+      minimum: *minimum,
+      maximum: *maximum,
+    }
+  }
+  pub fn new_from_point(point: &Vec3) -> Self {
+    Self::new(point, point)
+  }
+  pub fn minimum(&self) -> &Vec3 {
+    &self.minimum
+  }
+  pub fn maximum(&self) -> &Vec3 {
+    &self.maximum
+  }
+  pub fn union(&self, other: &Self) -> Self {
+    Self::new(
+      &Vec3::new(
+        self.minimum.x().min(other.minimum.x()),
+        self.minimum.y().min(other.minimum.y()),
+        self.minimum.z().min(other.minimum.z()),
+      ),
+      &Vec3::new(
+        self.maximum.x().max(other.maximum.x()),
+        self.maximum.y().max(other.maximum.y()),
+        self.maximum.z().max(other.maximum.z()),
+      ),
+    )
+  }
+  pub fn union_point(&self, point: &Vec3) -> Self {
+    self.union(&Self::new_from_point(point))
+  }
+  pub fn centroid(&self) -> Vec3 {
+    0.5 * (self.minimum + self.maximum)
+  }
+  pub fn diagonal(&self) -> Vec3 {
+    self.maximum - self.minimum
+  }
+  pub fn surface_area(&self) -> Float {
+    let diagonal = self.diagonal();
+    2.0
+      * diagonal
+        .z()
+        .mul_add(diagonal.x(), diagonal.x().mul_add(diagonal.y(), diagonal.y() * diagonal.z()))
+  }
+  /// Position of `point` relative to the box, zero at the minimum corner and one at the maximum
+  pub fn offset(&self, point: &Vec3) -> Vec3 {
+    let relative = point - self.minimum;
+    let diagonal = self.diagonal();
+    let component = |value: Float, extent: Float| if extent > 0.0 { value / extent } else { value };
+    Vec3::new(
+      component(relative.x(), diagonal.x()),
+      component(relative.y(), diagonal.y()),
+      component(relative.z(), diagonal.z()),
+    )
+  }
+  /// Center and radius of a sphere enclosing the box
+  pub fn bounding_sphere(&self) -> (Vec3, Float) {
+    let center = self.centroid();
+    (center, (self.maximum - center).length())
+  }
+  /// Whether `ray` passes through the box between the given scalars
+  pub fn is_hit(&self, ray: &Ray, scalar_from_ray_origin_min: Float, scalar_from_ray_origin_max: Float) -> bool {
+    let mut minimum_scalar = scalar_from_ray_origin_min;
+    let mut maximum_scalar = scalar_from_ray_origin_max;
+    for axis in Vec3Index::ALL {
+      let inverse_direction = 1.0 / ray.direction()[axis];
+      let mut near = (self.minimum[axis] - ray.origin()[axis]) * inverse_direction;
+      let mut far = (self.maximum[axis] - ray.origin()[axis]) * inverse_direction;
+      if inverse_direction < 0.0 {
+        std::mem::swap(&mut near, &mut far);
+      }
+      minimum_scalar = near.max(minimum_scalar);
+      maximum_scalar = far.min(maximum_scalar);
+      if maximum_scalar < minimum_scalar {
+        return false;
+      }
+    }
+    true
+  }
+}
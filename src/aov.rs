@@ -0,0 +1,123 @@
+use crate::film::Film;
+use crate::float::Float;
+use crate::hit::Hittable;
+use crate::material::{material_id, ScatterKind};
+use crate::ray::Ray;
+use crate::scene::Scene;
+use crate::spectrum::Radiance;
+use crate::vec3::Vec3;
+
+/// Depth recorded for camera rays that hit nothing, far enough to sort behind any geometry
+const MISS_DEPTH: Float = 1e10;
+
+/// A share of the rendered light, split by how it reached the camera, for compositing
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LightPass {
+  /// Light seen directly, from emitters and the background
+  Emission,
+  /// Light that reached the camera after one diffuse bounce
+  DiffuseDirect,
+  /// Light whose first bounce from the camera was diffuse but which bounced again before that
+  DiffuseIndirect,
+  /// Light whose first bounce from the camera was a glossy reflection
+  Specular,
+  /// Light whose first bounce from the camera was a refraction
+  Transmission,
+  /// Light whose first bounce from the camera was in a participating medium
+  Volume,
+}
+
+impl LightPass {
+  pub const ALL: [Self; 6] = [
+    Self::Emission,
+    Self::DiffuseDirect,
+    Self::DiffuseIndirect,
+    Self::Specular,
+    Self::Transmission,
+    Self::Volume,
+  ];
+  /// The pass that light arriving by way of a first bounce of `kind` belongs to
+  pub fn from_first_bounce(kind: ScatterKind, is_direct: bool) -> Self {
+    match kind {
+      ScatterKind::Diffuse if is_direct => Self::DiffuseDirect,
+      ScatterKind::Diffuse => Self::DiffuseIndirect,
+      ScatterKind::Glossy => Self::Specular,
+      ScatterKind::Transmission => Self::Transmission,
+      ScatterKind::Volume => Self::Volume,
+    }
+  }
+  /// Name of the pass's film channel and output layer
+  pub fn name(self) -> &'static str {
+    match self {
+      Self::Emission => "emission",
+      Self::DiffuseDirect => "diffuse_direct",
+      Self::DiffuseIndirect => "diffuse_indirect",
+      Self::Specular => "specular",
+      Self::Transmission => "transmission",
+      Self::Volume => "volume",
+    }
+  }
+  fn index(self) -> usize {
+    match self {
+      Self::Emission => 0,
+      Self::DiffuseDirect => 1,
+      Self::DiffuseIndirect => 2,
+      Self::Specular => 3,
+      Self::Transmission => 4,
+      Self::Volume => 5,
+    }
+  }
+}
+
+/// A radiance estimate split into light passes that sum to it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LightPasses<R> {
+  passes: [R; 6],
+}
+
+impl<R: Radiance> Default for LightPasses<R> {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl<R: Radiance> LightPasses<R> {
+  pub fn new() -> Self {
+    Self { passes: [R::black(); 6] }
+  }
+  pub fn add(&mut self, pass: LightPass, radiance: R) {
+    self.passes[pass.index()] += radiance;
+  }
+  pub fn pass(&self, pass: LightPass) -> R {
+    self.passes[pass.index()]
+  }
+}
+
+/// Turns a 32-bit ID hash into a float the way Cryptomatte does, nudging the exponent so the float is never zero,
+/// denormal, infinite or NaN
+pub fn id_hash_to_float(hash: u32) -> f32 {
+  let exponent = (hash >> 23) & 0xff;
+  if exponent == 0 || exponent == 0xff {
+    f32::from_bits(hash ^ (1 << 23))
+  } else {
+    f32::from_bits(hash)
+  }
+}
+
+/// Records what the camera `ray` first hits into the alpha, depth, albedo, normal, position and ID channels of the
+/// pixel `x` from the left and `y` from the bottom
+pub fn record_surface(film: &mut Film, scene: &Scene, ray: &Ray, x: usize, y: usize) {
+  let Some(hit_record) = scene.world().is_hit(ray, 0.0, Float::MAX) else {
+    film.add_data_sample("alpha", x, y, &Vec3::new_empty());
+    film.add_data_sample("depth", x, y, &Vec3::new(MISS_DEPTH, MISS_DEPTH, MISS_DEPTH));
+    return;
+  };
+  let depth = hit_record.scalar_from_ray_origin() * ray.direction().length();
+  film.add_data_sample("alpha", x, y, &Vec3::new(1.0, 1.0, 1.0));
+  film.add_data_sample("depth", x, y, &Vec3::new(depth, depth, depth));
+  film.add_data_sample("albedo", x, y, &hit_record.material().albedo());
+  film.add_data_sample("normal", x, y, hit_record.normal());
+  film.add_data_sample("position", x, y, hit_record.point());
+  film.add_id_sample("object_id", x, y, hit_record.object_id());
+  film.add_id_sample("material_id", x, y, material_id(hit_record.material().as_ref()));
+}
@@ -0,0 +1,47 @@
+use crate::environment::EnvironmentMap;
+use crate::float::{random_float, Float};
+use crate::light::LightSample;
+use crate::sky::PhysicalSky;
+use crate::vec3::Vec3;
+
+/// What a ray sees when it leaves the scene without hitting anything
+#[derive(Clone, Debug, PartialEq)]
+pub enum Background {
+  /// The same radiance in every direction, black for scenes lit only by their lights
+  Constant(Vec3),
+  Environment(EnvironmentMap),
+  Sky(PhysicalSky),
+}
+
+/// The default daylight sky and sun
+impl Default for Background {
+  fn default() -> Self {
+    Self::Sky(PhysicalSky::default())
+  }
+}
+
+impl Background {
+  pub fn radiance(&self, direction: &Vec3) -> Vec3 {
+    match self {
+      Self::Constant(radiance) => *radiance,
+      Self::Environment(environment_map) => environment_map.radiance(direction),
+      Self::Sky(sky) => sky.radiance(direction),
+    }
+  }
+  /// Samples an incident direction for direct lighting, or None if this background is not worth sampling as a light
+  pub fn sample(&self) -> Option<LightSample> {
+    match self {
+      Self::Constant(_) => None,
+      Self::Environment(environment_map) => environment_map.sample(random_float(), random_float()),
+      Self::Sky(sky) => sky.sample_sun(random_float(), random_float()),
+    }
+    .map(|(direction, radiance, pdf)| LightSample::new(&direction.unit_vector(), Float::INFINITY, &radiance, pdf, false))
+  }
+  pub fn pdf(&self, direction: &Vec3) -> Float {
+    match self {
+      Self::Constant(_) => 0.0,
+      Self::Environment(environment_map) => environment_map.pdf(direction),
+      Self::Sky(sky) => sky.sun_pdf(direction),
+    }
+  }
+}
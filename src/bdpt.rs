@@ -0,0 +1,525 @@
+use crate::camera::Camera;
+use crate::film::Film;
+use crate::float::{random_float, Float};
+use crate::hit::{HitRecord, Hittable};
+use crate::integrator::{escaped, estimate_direct, Integrator};
+use crate::ray::{Ray, SHADOW_EPSILON};
+use crate::sampling::Distribution1D;
+use crate::scene::Scene;
+use crate::spectrum::Radiance;
+use crate::stats::{self, Counter};
+use crate::vec3::Vec3;
+
+#[derive(Clone, Debug)]
+enum VertexKind {
+  Camera,
+  Light(usize),
+  Surface { hit_record: HitRecord, incoming: Ray },
+}
+
+/// A vertex of a camera or light subpath with the area densities of reaching it from either end of the path
+#[derive(Clone)]
+struct Vertex<R> {
+  kind: VertexKind,
+  point: Vec3,
+  error: Vec3,
+  normal: Vec3,
+  throughput: R,
+  pdf_forward: Float,
+  pdf_reverse: Float,
+  delta: bool,
+}
+
+impl<R: Radiance> Vertex<R> {
+  fn new(kind: VertexKind, point: &Vec3, normal: &Vec3, throughput: R, pdf_forward: Float) -> Self {
+    Self {
+      kind,
+      point: *point,
+      error: Vec3::new_empty(),
+      normal: *normal,
+      throughput,
+      pdf_forward,
+      pdf_reverse: 0.0,
+      delta: false,
+    }
+  }
+  /// Sets a bound on the rounding error in each coordinate of the vertex
+  fn with_error(mut self, error: &Vec3) -> Self {
+    self.error = *error;
+    self
+  }
+  /// BSDF times cosine at a surface vertex for light leaving towards `direction`
+  fn evaluate(&self, direction: &Vec3) -> Option<Vec3> {
+    match &self.kind {
+      VertexKind::Surface { hit_record, incoming } => hit_record
+        .material()
+        .evaluate(incoming, hit_record, direction)
+        .map(|(value, _)| value)
+        .filter(|value| value.squared_length() > 0.0),
+      VertexKind::Camera | VertexKind::Light(_) => None,
+    }
+  }
+  /// Index of the scene light this vertex lies on, if any
+  fn light(&self) -> Option<usize> {
+    match &self.kind {
+      VertexKind::Light(light) => Some(*light),
+      VertexKind::Surface { hit_record, .. } => hit_record.light(),
+      VertexKind::Camera => None,
+    }
+  }
+}
+
+/// Contribution of a connection, the endpoint sampled for it and the image position it splats to
+type Connection<R> = (R, Option<Vertex<R>>, Option<(Float, Float)>);
+
+/// Converts a solid angle density at `from` into an area density at `to`
+fn convert_density<R>(pdf: Float, from: &Vertex<R>, to: &Vertex<R>) -> Float {
+  let offset = to.point - from.point;
+  let squared_distance = offset.squared_length();
+  if squared_distance <= 0.0 {
+    return 0.0;
+  }
+  let pdf = pdf / squared_distance;
+  if to.normal.squared_length() > 0.0 {
+    pdf * to.normal.dot(&offset).abs() / squared_distance.sqrt()
+  } else {
+    pdf
+  }
+}
+
+/// Whether nothing blocks the segment from `from` to `to`, the ray running from the vertex at zero to `to` at one
+fn is_visible<R>(scene: &Scene, from: &Vertex<R>, to: &Vec3) -> bool {
+  let ray = Ray::spawn_to(&from.point, &from.error, &from.normal, to);
+  stats::count(Counter::ShadowRays, 1);
+  scene.world().is_hit(&ray, 0.0, 1.0 - SHADOW_EPSILON).is_none()
+}
+
+/// Bidirectional path tracing after Veach (1997), connecting every vertex of a camera subpath with every vertex of a
+/// light subpath and weighting the strategies with the balance heuristic
+///
+/// Only lights with bounds start light subpaths; the background and directional lights are handled as in the path
+/// integrator, by light sampling and escaping rays weighted with the power heuristic
+#[derive(Clone, Debug, PartialEq)]
+pub struct BdptIntegrator {
+  camera: Camera,
+  max_depth: usize,
+  light_distribution: Distribution1D,
+  infinite_lights: Vec<usize>,
+}
+
+impl BdptIntegrator {
+  /// Creates an integrator for `scene` whose paths have at most `max_depth` bounces, splatting light paths through
+  /// `camera`
+  pub fn new(scene: &Scene, camera: &Camera, max_depth: usize) -> Self {
+    let power: Vec<Float> = scene
+      .lights()
+      .iter()
+      .map(|light| light.bounds().map_or(0.0, |bounds| bounds.phi()))
+      .collect();
+    Self {
+      camera: *camera,
+      max_depth,
+      light_distribution: Distribution1D::new(if power.is_empty() { &[0.0] } else { &power }),
+      infinite_lights: (0..scene.lights().len()).filter(|index| power[*index] <= 0.0).collect(),
+    }
+  }
+  /// Extends `path` from its last vertex along `ray` up to the vertex count `max_depth` allows, returning the light from infinite lights found along the way
+  /// when `path` starts at the camera
+  fn random_walk<R: Radiance>(
+    &self,
+    scene: &Scene,
+    context: &R::Context,
+    path: &mut Vec<Vertex<R>>,
+    mut ray: Ray,
+    mut throughput: R,
+    mut pdf_direction: Float,
+  ) -> R {
+    let from_camera = matches!(path[0].kind, VertexKind::Camera);
+    let max_vertices = if from_camera { self.max_depth + 2 } else { self.max_depth + 1 };
+    let mut infinite_radiance = R::black();
+    let mut previous_bounce = None;
+    while path.len() < max_vertices {
+      let Some(hit_record) = scene.world().is_hit(&ray, 0.0, Float::MAX) else {
+        if from_camera {
+          infinite_radiance += throughput * escaped::<R>(&ray, scene, context, previous_bounce);
+        }
+        break;
+      };
+      let point = *hit_record.point();
+      let normal = *hit_record.normal();
+      let mut vertex = Vertex::new(
+        VertexKind::Surface {
+          hit_record: hit_record.clone(),
+          incoming: ray,
+        },
+        &point,
+        &normal,
+        throughput,
+        0.0,
+      )
+      .with_error(hit_record.error());
+      vertex.pdf_forward = convert_density(pdf_direction, &path[path.len() - 1], &vertex);
+      path.push(vertex);
+      if path.len() >= max_vertices {
+        break;
+      }
+      let Some(scatter_result) = hit_record.material().scatter(&ray, &hit_record) else {
+        break;
+      };
+      if from_camera {
+        infinite_radiance += throughput * self.sample_infinite_lights::<R>(&ray, scene, &hit_record, context);
+      }
+      throughput = throughput * R::from_albedo(scatter_result.attenuation(), context);
+      let direction = scatter_result.scattered().direction().unit_vector();
+      let pdf_reverse = scatter_result.pdf().map_or(0.0, |_| {
+        hit_record
+          .material()
+          .evaluate(&Ray::new(&(point + direction), &-direction), &hit_record, &-ray.direction().unit_vector())
+          .map_or(0.0, |(_, pdf)| pdf)
+      });
+      let last = path.len() - 1;
+      path[last].delta = scatter_result.pdf().is_none();
+      path[last - 1].pdf_reverse = convert_density(pdf_reverse, &path[last], &path[last - 1]);
+      pdf_direction = scatter_result.pdf().unwrap_or(0.0);
+      previous_bounce = scatter_result.pdf().map(|pdf| (normal, pdf));
+      ray = *scatter_result.scattered();
+      stats::count(Counter::SecondaryRays, 1);
+    }
+    infinite_radiance
+  }
+  /// Direct light at a camera path vertex from the background and every light at infinity
+  fn sample_infinite_lights<R: Radiance>(&self, ray: &Ray, scene: &Scene, hit_record: &HitRecord, context: &R::Context) -> R {
+    scene
+      .background()
+      .sample()
+      .into_iter()
+      .chain(
+        self
+          .infinite_lights
+          .iter()
+          .filter_map(|light| scene.lights()[*light].sample(hit_record.point())),
+      )
+      .fold(R::black(), |total, light_sample| {
+        total + estimate_direct::<R>(ray, scene, hit_record, &light_sample, context)
+      })
+  }
+  fn camera_subpath<R: Radiance>(&self, ray: &Ray, scene: &Scene, context: &R::Context) -> (Vec<Vertex<R>>, R) {
+    let throughput = R::from_albedo(&Vec3::new(1.0, 1.0, 1.0), context);
+    let mut path = vec![Vertex::new(VertexKind::Camera, ray.origin(), &Vec3::new_empty(), throughput, 1.0)];
+    let (_, pdf_direction) = self.camera.pdf(ray);
+    let infinite_radiance = self.random_walk(scene, context, &mut path, *ray, throughput, pdf_direction);
+    (path, infinite_radiance)
+  }
+  fn light_subpath<R: Radiance>(&self, scene: &Scene, context: &R::Context) -> Vec<Vertex<R>> {
+    if self.light_distribution.integral() <= 0.0 {
+      return Vec::new();
+    }
+    let (light, choice_probability) = self.light_distribution.sample_discrete(random_float());
+    let Some(emission) = scene.lights()[light].sample_emission() else {
+      return Vec::new();
+    };
+    if emission.pdf_position() <= 0.0 || emission.pdf_direction() <= 0.0 || emission.radiance().squared_length() <= 0.0 {
+      return Vec::new();
+    }
+    let radiance = R::from_illuminant(emission.radiance(), context);
+    let mut path = vec![Vertex::new(
+      VertexKind::Light(light),
+      emission.point(),
+      emission.normal(),
+      radiance,
+      emission.pdf_position() * choice_probability,
+    )
+    .with_error(emission.error())];
+    let cosine = if emission.normal().squared_length() > 0.0 {
+      emission.normal().dot(emission.direction()).abs()
+    } else {
+      1.0
+    };
+    let throughput = radiance * (cosine / (choice_probability * emission.pdf_position() * emission.pdf_direction()));
+    stats::count(Counter::SecondaryRays, 1);
+    self.random_walk(scene, context, &mut path, emission.ray(), throughput, emission.pdf_direction());
+    path
+  }
+  /// Area density at `next` of light leaving the light that `vertex` lies on
+  fn light_pdf<R>(scene: &Scene, vertex: &Vertex<R>, light: usize, next: &Vertex<R>) -> Float {
+    let direction = (next.point - vertex.point).unit_vector();
+    let (_, pdf_direction) = scene.lights()[light].emission_pdf(&vertex.point, &direction);
+    convert_density(pdf_direction, vertex, next)
+  }
+  /// Area density of a light subpath starting at `vertex` on its way towards `next`
+  fn light_origin_pdf<R>(&self, scene: &Scene, vertex: &Vertex<R>, light: usize, next: &Vertex<R>) -> Float {
+    let direction = (next.point - vertex.point).unit_vector();
+    let (pdf_position, _) = scene.lights()[light].emission_pdf(&vertex.point, &direction);
+    pdf_position * self.light_distribution.discrete_pmf(light)
+  }
+  /// Area density at `next` of scattering at `vertex` after arriving from `previous`
+  fn vertex_pdf<R>(&self, scene: &Scene, vertex: &Vertex<R>, previous: Option<&Vertex<R>>, next: &Vertex<R>) -> Float {
+    match &vertex.kind {
+      VertexKind::Light(light) => Self::light_pdf(scene, vertex, *light, next),
+      VertexKind::Camera => {
+        let (_, pdf_direction) = self.camera.pdf(&Ray::new(&vertex.point, &(next.point - vertex.point)));
+        convert_density(pdf_direction, vertex, next)
+      }
+      VertexKind::Surface { hit_record, .. } => previous.map_or(0.0, |previous| {
+        let incoming = Ray::new(&previous.point, &(vertex.point - previous.point));
+        hit_record
+          .material()
+          .evaluate(&incoming, hit_record, &(next.point - vertex.point).unit_vector())
+          .map_or(0.0, |(_, pdf)| convert_density(pdf, vertex, next))
+      }),
+    }
+  }
+  /// Balance heuristic weight of connecting the first `s` light and first `t` camera vertices, where `sampled`
+  /// replaces the light or camera endpoint when it was sampled specially for the connection
+  fn mis_weight<R: Radiance>(
+    &self,
+    scene: &Scene,
+    light_path: &[Vertex<R>],
+    camera_path: &[Vertex<R>],
+    sampled: Option<&Vertex<R>>,
+    s: usize,
+    t: usize,
+  ) -> Float {
+    if s + t == 2 {
+      return 1.0;
+    }
+    let light_vertex = |index: usize| match sampled {
+      Some(sampled) if s == 1 && index == 0 => sampled,
+      _ => &light_path[index],
+    };
+    let camera_vertex = |index: usize| match sampled {
+      Some(sampled) if t == 1 && index == 0 => sampled,
+      _ => &camera_path[index],
+    };
+    let densities = |vertex: &Vertex<R>| (vertex.pdf_forward, vertex.pdf_reverse, vertex.delta);
+    let mut light_densities: Vec<(Float, Float, bool)> = (0..s).map(|index| densities(light_vertex(index))).collect();
+    let mut camera_densities: Vec<(Float, Float, bool)> = (0..t).map(|index| densities(camera_vertex(index))).collect();
+    let qs = (s > 0).then(|| light_vertex(s - 1));
+    let pt = camera_vertex(t - 1);
+    let qs_minus = (s > 1).then(|| light_vertex(s - 2));
+    let pt_minus = (t > 1).then(|| camera_vertex(t - 2));
+    camera_densities[t - 1].2 = false;
+    camera_densities[t - 1].1 = match (qs, pt.light(), pt_minus) {
+      (Some(qs), _, _) => self.vertex_pdf(scene, qs, qs_minus, pt),
+      (None, Some(light), Some(pt_minus)) => self.light_origin_pdf(scene, pt, light, pt_minus),
+      (None, _, _) => 0.0,
+    };
+    if let Some(pt_minus) = pt_minus {
+      camera_densities[t - 2].1 = match (qs, pt.light()) {
+        (Some(qs), _) => self.vertex_pdf(scene, pt, Some(qs), pt_minus),
+        (None, Some(light)) => Self::light_pdf(scene, pt, light, pt_minus),
+        (None, None) => 0.0,
+      };
+    }
+    if let Some(qs) = qs {
+      light_densities[s - 1].2 = false;
+      light_densities[s - 1].1 = self.vertex_pdf(scene, pt, pt_minus, qs);
+      if let Some(qs_minus) = qs_minus {
+        light_densities[s - 2].1 = self.vertex_pdf(scene, qs, Some(pt), qs_minus);
+      }
+    }
+    let remap_zero = |pdf: Float| if pdf == 0.0 { 1.0 } else { pdf };
+    let mut sum_of_ratios = 0.0;
+    let mut ratio = 1.0;
+    for index in (1..t).rev() {
+      ratio *= remap_zero(camera_densities[index].1) / remap_zero(camera_densities[index].0);
+      if !camera_densities[index].2 && !camera_densities[index - 1].2 {
+        sum_of_ratios += ratio;
+      }
+    }
+    ratio = 1.0;
+    for index in (0..s).rev() {
+      ratio *= remap_zero(light_densities[index].1) / remap_zero(light_densities[index].0);
+      let previous_is_delta = if index > 0 {
+        light_densities[index - 1].2
+      } else {
+        light_vertex(0).light().is_some_and(|light| scene.lights()[light].is_delta())
+      };
+      if !light_densities[index].2 && !previous_is_delta {
+        sum_of_ratios += ratio;
+      }
+    }
+    1.0 / (1.0 + sum_of_ratios)
+  }
+  /// Unweighted contribution of connecting the first `s` light and first `t` camera vertices, together with the
+  /// endpoint sampled for the connection and the image position to splat to when `t` is one
+  fn connect<R: Radiance>(
+    &self,
+    scene: &Scene,
+    context: &R::Context,
+    light_path: &[Vertex<R>],
+    camera_path: &[Vertex<R>],
+    s: usize,
+    t: usize,
+  ) -> Option<Connection<R>> {
+    if s == 0 {
+      let pt = &camera_path[t - 1];
+      let VertexKind::Surface { hit_record, incoming } = &pt.kind else {
+        return None;
+      };
+      hit_record.light()?;
+      let emitted = hit_record.material().emitted(incoming, hit_record);
+      if emitted.squared_length() <= 0.0 {
+        return None;
+      }
+      return Some((pt.throughput * R::from_illuminant(&emitted, context), None, None));
+    }
+    if t == 1 {
+      let qs = &light_path[s - 1];
+      let camera_sample = self.camera.sample_importance(&qs.point)?;
+      if camera_sample.pdf() <= 0.0 || camera_sample.importance() <= 0.0 {
+        return None;
+      }
+      let value = qs.evaluate(camera_sample.direction())?;
+      if !is_visible(scene, qs, camera_sample.lens_point()) {
+        return None;
+      }
+      let importance = camera_sample.importance() / camera_sample.pdf();
+      let sampled = Vertex::new(
+        VertexKind::Camera,
+        camera_sample.lens_point(),
+        &Vec3::new_empty(),
+        R::from_albedo(&Vec3::new(importance, importance, importance), context),
+        0.0,
+      );
+      let contribution = qs.throughput * R::from_albedo(&value, context) * importance;
+      return Some((contribution, Some(sampled), Some(camera_sample.raster())));
+    }
+    if s == 1 {
+      let pt = &camera_path[t - 1];
+      let (light, choice_probability) = self.light_distribution.sample_discrete(random_float());
+      let light_sample = scene.lights()[light].sample(&pt.point)?;
+      if light_sample.pdf() <= 0.0 || light_sample.distance().is_infinite() {
+        return None;
+      }
+      let value = pt.evaluate(light_sample.direction())?;
+      let light_point = pt.point + light_sample.direction() * light_sample.distance();
+      if !is_visible(scene, pt, &light_point) {
+        return None;
+      }
+      let mut sampled = Vertex::new(
+        VertexKind::Light(light),
+        &light_point,
+        &scene.lights()[light].normal(&light_point),
+        R::from_illuminant(light_sample.radiance(), context) * (1.0 / (light_sample.pdf() * choice_probability)),
+        0.0,
+      );
+      sampled.pdf_forward = self.light_origin_pdf(scene, &sampled, light, pt);
+      let contribution = pt.throughput * R::from_albedo(&value, context) * sampled.throughput;
+      return Some((contribution, Some(sampled), None));
+    }
+    let qs = &light_path[s - 1];
+    let pt = &camera_path[t - 1];
+    let offset = pt.point - qs.point;
+    let squared_distance = offset.squared_length();
+    if squared_distance <= 0.0 {
+      return None;
+    }
+    let direction = offset.unit_vector();
+    let light_value = qs.evaluate(&direction)?;
+    let camera_value = pt.evaluate(&-direction)?;
+    if !is_visible(scene, qs, &pt.point) {
+      return None;
+    }
+    let contribution =
+      qs.throughput * R::from_albedo(&light_value, context) * R::from_albedo(&camera_value, context) * pt.throughput * (1.0 / squared_distance);
+    Some((contribution, None, None))
+  }
+}
+
+impl Integrator for BdptIntegrator {
+  fn radiance<R: Radiance>(&self, ray: &Ray, scene: &Scene, context: &R::Context, film: &Film) -> R {
+    let (camera_path, mut radiance) = self.camera_subpath::<R>(ray, scene, context);
+    let light_path = self.light_subpath::<R>(scene, context);
+    for t in 1..=camera_path.len() {
+      for s in 0..=light_path.len() {
+        if (s == 1 && t == 1) || s + t < 2 || s + t - 2 > self.max_depth {
+          continue;
+        }
+        let Some((contribution, sampled, raster)) = self.connect(scene, context, &light_path, &camera_path, s, t) else {
+          continue;
+        };
+        let weighted = contribution * self.mis_weight(scene, &light_path, &camera_path, sampled.as_ref(), s, t);
+        match raster {
+          Some(raster) => film.add_splat(raster, &weighted.into_rgb(context)),
+          None => radiance += weighted,
+        }
+      }
+    }
+    radiance
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::background::Background;
+  use crate::hit::HittableList;
+  use crate::integrator::PathIntegrator;
+  use crate::material::{Dielectric, Lambertian};
+  use crate::settings::RenderSettings;
+  use crate::sphere::Sphere;
+  use std::sync::Arc;
+
+  fn test_scene() -> Scene {
+    let mut world = HittableList::new();
+    world.list_mut().push(Box::new(Sphere::new(
+      &Vec3::new(0.0, -1000.0, 0.0),
+      1000.0,
+      Arc::new(Lambertian::new(&Vec3::new(0.5, 0.5, 0.5))),
+    )));
+    world.list_mut().push(Box::new(Sphere::new(
+      &Vec3::new(-1.2, 0.5, 0.0),
+      0.5,
+      Arc::new(Lambertian::new(&Vec3::new(0.7, 0.3, 0.2))),
+    )));
+    world
+      .list_mut()
+      .push(Box::new(Sphere::new(&Vec3::new(0.8, 0.6, 0.0), 0.6, Arc::new(Dielectric::new(1.5)))));
+    let mut scene = Scene::new(world);
+    scene.set_background(Background::Constant(Vec3::new_empty()));
+    scene.add_sphere_light(&Vec3::new(0.5, 4.0, 0.5), 0.5, &Vec3::new(10.0, 10.0, 10.0));
+    scene
+  }
+
+  #[allow(clippy::cast_precision_loss)]
+  fn mean_image<I: Integrator>(integrator: &I, scene: &Scene, camera: &Camera, samples_per_pixel: usize) -> Vec3 {
+    let (width, height) = (8, 4);
+    let mut film = Film::new(width, height);
+    for y in 0..height {
+      for x in 0..width {
+        for _ in 0..samples_per_pixel {
+          let position = (x as Float + random_float(), y as Float + random_float());
+          let ray = camera.get_ray(position.0 / width as Float, position.1 / height as Float);
+          let sample = integrator.radiance::<Vec3>(&ray, scene, &(), &film);
+          film.add_sample(position, &sample);
+        }
+      }
+    }
+    let mut total = Vec3::new_empty();
+    for y in 0..height {
+      for x in 0..width {
+        total += film.pixel(x, y, samples_per_pixel);
+      }
+    }
+    total / (width * height) as Float
+  }
+
+  #[test]
+  fn bdpt_matches_path_integrator() {
+    let scene = test_scene();
+    let camera = Camera::new_from_fov_and_aspect(
+      &Vec3::new(0.0, 1.5, 6.0),
+      &Vec3::new(0.0, 0.5, 0.0),
+      &Vec3::new(0.0, 1.0, 0.0),
+      40.0,
+      2.0,
+      0.1,
+      6.0,
+    );
+    fastrand::seed(7);
+    let path = mean_image(&PathIntegrator::new(RenderSettings::new(100, 3, 3, 3, 3)), &scene, &camera, 4000);
+    let bdpt = mean_image(&BdptIntegrator::new(&scene, &camera, 4), &scene, &camera, 4000);
+    assert!((path - bdpt).length() <= 0.03 * path.length(), "path {:?}, bdpt {:?}", path, bdpt);
+  }
+}
@@ -0,0 +1,478 @@
+use crate::aabb::Aabb;
+use crate::float::Float;
+use crate::hit::{HitRecord, Hittable};
+use crate::ray::Ray;
+use crate::simd::{self, Boxes4, Lanes};
+use crate::stats::{self, Counter};
+use crate::vec3::Vec3Index;
+
+#[derive(Debug)]
+enum BvhNode {
+  Interior { bounds: Aabb, left: Box<BvhNode>, right: Box<BvhNode> },
+  Leaf { bounds: Aabb, hittable: Box<dyn Hittable> },
+}
+
+impl BvhNode {
+  /// Builds a subtree over `hittables`, which must all be bounded, splitting at the median centroid along the axis
+  /// the centroids spread furthest on
+  fn new(mut hittables: Vec<(Aabb, Box<dyn Hittable>)>) -> Self {
+    if hittables.len() == 1 {
+      let (bounds, hittable) = hittables.remove(0);
+      return Self::Leaf { bounds, hittable };
+    }
+    let centroid_bounds = hittables[1..]
+      .iter()
+      .fold(Aabb::new_from_point(&hittables[0].0.centroid()), |centroid_bounds, (bounds, _)| {
+        centroid_bounds.union_point(&bounds.centroid())
+      });
+    let extent = centroid_bounds.diagonal();
+    let axis = Vec3Index::ALL
+      .iter()
+      .copied()
+      .fold(Vec3Index::Zero, |widest, axis| if extent[axis] > extent[widest] { axis } else { widest });
+    let middle = hittables.len() / 2;
+    hittables.select_nth_unstable_by(middle, |(first, _), (second, _)| {
+      first.centroid()[axis].total_cmp(&second.centroid()[axis])
+    });
+    let right = hittables.split_off(middle);
+    let (left, right) = (Self::new(hittables), Self::new(right));
+    Self::Interior {
+      bounds: left.bounds().union(right.bounds()),
+      left: Box::new(left),
+      right: Box::new(right),
+    }
+  }
+  fn bounds(&self) -> &Aabb {
+    match self {
+      Self::Interior { bounds, .. } | Self::Leaf { bounds, .. } => bounds,
+    }
+  }
+  fn is_hit(&self, ray: &Ray, scalar_from_ray_origin_min: Float, scalar_from_ray_origin_max: Float) -> Option<HitRecord> {
+    stats::count(Counter::NodeVisits, 1);
+    if !self.bounds().is_hit(ray, scalar_from_ray_origin_min, scalar_from_ray_origin_max) {
+      return None;
+    }
+    match self {
+      Self::Leaf { hittable, .. } => {
+        stats::count(Counter::PrimitiveTests, 1);
+        hittable.is_hit(ray, scalar_from_ray_origin_min, scalar_from_ray_origin_max)
+      }
+      Self::Interior { left, right, .. } => {
+        let left_hit = left.is_hit(ray, scalar_from_ray_origin_min, scalar_from_ray_origin_max);
+        let closest = left_hit.as_ref().map_or(scalar_from_ray_origin_max, HitRecord::scalar_from_ray_origin);
+        right.is_hit(ray, scalar_from_ray_origin_min, closest).or(left_hit)
+      }
+    }
+  }
+}
+
+/// Builds the binary tree over `hittables`, which must be non-empty and all have a bounding box
+fn build_binary(hittables: Vec<Box<dyn Hittable>>) -> BvhNode {
+  BvhNode::new(
+    hittables
+      .into_iter()
+      .map(|hittable| {
+        let bounds = hittable.bounding_box().expect("BVH primitives must be bounded");
+        (bounds, hittable)
+      })
+      .collect(),
+  )
+}
+
+/// Bounding volume hierarchy with two children per node, each box tested on its own, kept to compare the four-wide
+/// [`Bvh`] against
+#[derive(Debug)]
+pub struct BinaryBvh {
+  root: BvhNode,
+}
+
+impl BinaryBvh {
+  /// Builds a binary BVH over `hittables`, which must be non-empty and all have a bounding box
+  ///
+  /// # Panics
+  ///
+  /// Panics if `hittables` is empty or any of them is unbounded
+  pub fn new(hittables: Vec<Box<dyn Hittable>>) -> Self {
+    Self {
+      root: build_binary(hittables),
+    }
+  }
+}
+
+impl Hittable for BinaryBvh {
+  fn is_hit(&self, ray: &Ray, scalar_from_ray_origin_min: Float, scalar_from_ray_origin_max: Float) -> Option<HitRecord> {
+    self.root.is_hit(ray, scalar_from_ray_origin_min, scalar_from_ray_origin_max)
+  }
+  fn bounding_box(&self) -> Option<Aabb> {
+    Some(*self.root.bounds())
+  }
+}
+
+/// Deepest a four-wide traversal stack can get, which three entries per level of a median split tree leaves room for
+/// far more primitives than fit in memory
+const STACK_SIZE: usize = 96;
+/// Most rays a packet traversal carries at once, one per bit of its masks of active rays
+const MAX_PACKET_SIZE: usize = 64;
+/// Fewest rays a packet traversal keeps together, below which they are traced into a subtree one at a time
+const MIN_PACKET_SIZE: u32 = 8;
+
+/// Origin and componentwise inverse direction of `ray`, as the box tests take them
+fn ray_lanes(ray: &Ray) -> (Lanes, Lanes) {
+  let (origin, direction) = (ray.origin(), ray.direction());
+  (
+    simd::lanes(origin.x(), origin.y(), origin.z()),
+    simd::lanes(1.0 / direction.x(), 1.0 / direction.y(), 1.0 / direction.z()),
+  )
+}
+
+/// Smallest and largest of the products of values from two ranges
+fn multiply_ranges((first_low, first_high): (Float, Float), (second_low, second_high): (Float, Float)) -> (Float, Float) {
+  let products = [
+    first_low * second_low,
+    first_low * second_high,
+    first_high * second_low,
+    first_high * second_high,
+  ];
+  products[1..]
+    .iter()
+    .fold((products[0], products[0]), |(low, high), product| (low.min(*product), high.max(*product)))
+}
+
+/// Ranges of the origins and inverse directions of a packet of rays along each axis, which bound where any ray of the
+/// packet can enter and leave a box, so whole subtrees can be skipped without testing the rays one by one
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Frustum {
+  origin: [(Float, Float); 3],
+  /// None along axes the directions point both ways along, or not at all, where the inverse is unbounded
+  inverse_direction: [Option<(Float, Float)>; 3],
+}
+
+impl Frustum {
+  fn new(rays: &[Ray]) -> Self {
+    let range = |value: &dyn Fn(&Ray) -> Float| {
+      rays.iter().fold((Float::INFINITY, Float::NEG_INFINITY), |(low, high), ray| {
+        (low.min(value(ray)), high.max(value(ray)))
+      })
+    };
+    let mut frustum = Self {
+      origin: [(0.0, 0.0); 3],
+      inverse_direction: [None; 3],
+    };
+    for (row, axis) in Vec3Index::ALL.iter().enumerate() {
+      frustum.origin[row] = range(&|ray| ray.origin()[*axis]);
+      let (low, high) = range(&|ray| ray.direction()[*axis]);
+      if low > 0.0 || high < 0.0 {
+        frustum.inverse_direction[row] = Some((1.0 / high, 1.0 / low));
+      }
+    }
+    frustum
+  }
+  /// Whether no ray of the packet can pass through the box in `lane` of `boxes` between the given scalars
+  fn misses(&self, boxes: &Boxes4, lane: usize, scalar_from_ray_origin_min: Float, scalar_from_ray_origin_max: Float) -> bool {
+    let (mut near, mut far) = (scalar_from_ray_origin_min, scalar_from_ray_origin_max);
+    for row in 0..3 {
+      let Some(inverse_direction) = self.inverse_direction[row] else {
+        continue;
+      };
+      let (origin_low, origin_high) = self.origin[row];
+      let slab = |bound: Float| multiply_ranges((bound - origin_high, bound - origin_low), inverse_direction);
+      let (first, second) = (slab(boxes.minimum[row][lane]), slab(boxes.maximum[row][lane]));
+      near = near.max(first.0.min(second.0));
+      far = far.min(first.1.max(second.1));
+    }
+    near > far
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum WideChild {
+  Empty,
+  Node(usize),
+  Leaf(usize),
+}
+
+/// Node of the four-wide BVH, holding the boxes of up to four children so a ray is tested against them together
+#[derive(Debug)]
+struct WideNode {
+  boxes: Boxes4,
+  children: [WideChild; 4],
+}
+
+/// Bounding volume hierarchy over bounded hittables with up to four children per node, whose boxes a ray is tested
+/// against at once, so a ray only tests the primitives whose boxes it passes through
+#[derive(Debug)]
+pub struct Bvh {
+  nodes: Vec<WideNode>,
+  primitives: Vec<Box<dyn Hittable>>,
+  root: WideChild,
+  bounds: Aabb,
+}
+
+impl Bvh {
+  /// Builds a BVH over `hittables`, which must be non-empty and all have a bounding box, by building a binary tree and
+  /// pulling each node's grandchildren up into it, largest first, until it has four children
+  ///
+  /// # Panics
+  ///
+  /// Panics if `hittables` is empty or any of them is unbounded
+  pub fn new(hittables: Vec<Box<dyn Hittable>>) -> Self {
+    let root = build_binary(hittables);
+    let bounds = *root.bounds();
+    let mut bvh = Self {
+      nodes: Vec::new(),
+      primitives: Vec::new(),
+      root: WideChild::Empty,
+      bounds,
+    };
+    bvh.root = bvh.collapse(root);
+    bvh
+  }
+  fn collapse(&mut self, node: BvhNode) -> WideChild {
+    let (left, right) = match node {
+      BvhNode::Leaf { hittable, .. } => {
+        self.primitives.push(hittable);
+        return WideChild::Leaf(self.primitives.len() - 1);
+      }
+      BvhNode::Interior { left, right, .. } => (left, right),
+    };
+    let mut children = vec![*left, *right];
+    while children.len() < 4 {
+      let largest_interior = children
+        .iter()
+        .enumerate()
+        .filter(|(_, child)| matches!(child, BvhNode::Interior { .. }))
+        .max_by(|(_, first), (_, second)| first.bounds().surface_area().total_cmp(&second.bounds().surface_area()))
+        .map(|(index, _)| index);
+      let Some(index) = largest_interior else {
+        break;
+      };
+      if let BvhNode::Interior { left, right, .. } = children.swap_remove(index) {
+        children.push(*left);
+        children.push(*right);
+      }
+    }
+    let index = self.nodes.len();
+    self.nodes.push(WideNode {
+      boxes: Boxes4::default(),
+      children: [WideChild::Empty; 4],
+    });
+    for (lane, child) in children.into_iter().enumerate() {
+      for (row, axis) in Vec3Index::ALL.iter().enumerate() {
+        self.nodes[index].boxes.minimum[row][lane] = child.bounds().minimum()[*axis];
+        self.nodes[index].boxes.maximum[row][lane] = child.bounds().maximum()[*axis];
+      }
+      let child = self.collapse(child);
+      self.nodes[index].children[lane] = child;
+    }
+    WideChild::Node(index)
+  }
+  /// Closest hit of `ray` between the given scalars within the subtree under `start`
+  fn traverse(&self, start: WideChild, ray: &Ray, scalar_from_ray_origin_min: Float, scalar_from_ray_origin_max: Float) -> Option<HitRecord> {
+    let (origin, inverse_direction) = ray_lanes(ray);
+    let mut closest = scalar_from_ray_origin_max;
+    let mut closest_hit = None;
+    let mut stack = [(WideChild::Empty, 0.0); STACK_SIZE];
+    stack[0] = (start, scalar_from_ray_origin_min);
+    let mut stack_len = 1;
+    while stack_len > 0 {
+      stack_len -= 1;
+      let (child, near) = stack[stack_len];
+      if near > closest {
+        continue;
+      }
+      match child {
+        WideChild::Empty => {}
+        WideChild::Leaf(index) => {
+          stats::count(Counter::PrimitiveTests, 1);
+          if let Some(hit_record) = self.primitives[index].is_hit(ray, scalar_from_ray_origin_min, closest) {
+            closest = hit_record.scalar_from_ray_origin();
+            closest_hit = Some(hit_record);
+          }
+        }
+        WideChild::Node(index) => {
+          stats::count(Counter::NodeVisits, 1);
+          let node = &self.nodes[index];
+          let hits = simd::hit_boxes(&node.boxes, &origin, &inverse_direction, scalar_from_ray_origin_min, closest);
+          let mut entered = [(WideChild::Empty, 0.0); 4];
+          let mut entered_len = 0;
+          for (child, hit) in node.children.iter().zip(hits) {
+            if let (WideChild::Node(_) | WideChild::Leaf(_), Some(near)) = (child, hit) {
+              entered[entered_len] = (*child, near);
+              entered_len += 1;
+            }
+          }
+          // Pushed furthest first so the nearest child is popped first and can shrink `closest` for the rest
+          entered[..entered_len].sort_unstable_by(|(_, first), (_, second)| second.total_cmp(first));
+          stack[stack_len..stack_len + entered_len].copy_from_slice(&entered[..entered_len]);
+          stack_len += entered_len;
+        }
+      }
+    }
+    closest_hit
+  }
+}
+
+impl Hittable for Bvh {
+  fn is_hit(&self, ray: &Ray, scalar_from_ray_origin_min: Float, scalar_from_ray_origin_max: Float) -> Option<HitRecord> {
+    self.traverse(self.root, ray, scalar_from_ray_origin_min, scalar_from_ray_origin_max)
+  }
+  /// Traverses the tree once for each packet of up to 64 rays, skipping children outside the packet's frustum and
+  /// carrying a mask of the rays that pass through each child's box down to it, so leaves only test those
+  fn is_hit_packet(&self, rays: &[Ray], scalar_from_ray_origin_min: Float, scalar_from_ray_origin_max: Float) -> Vec<Option<HitRecord>> {
+    if rays.is_empty() {
+      return Vec::new();
+    }
+    if rays.len() > MAX_PACKET_SIZE {
+      return rays
+        .chunks(MAX_PACKET_SIZE)
+        .flat_map(|packet| self.is_hit_packet(packet, scalar_from_ray_origin_min, scalar_from_ray_origin_max))
+        .collect();
+    }
+    let frustum = Frustum::new(rays);
+    let ray_lanes: Vec<(Lanes, Lanes)> = rays.iter().map(ray_lanes).collect();
+    let mut closest = vec![scalar_from_ray_origin_max; rays.len()];
+    let mut closest_hits = vec![None; rays.len()];
+    let mut stack = [(WideChild::Empty, 0_u64); STACK_SIZE];
+    stack[0] = (self.root, u64::MAX >> (MAX_PACKET_SIZE - rays.len()));
+    let mut stack_len = 1;
+    while stack_len > 0 {
+      stack_len -= 1;
+      let (child, active) = stack[stack_len];
+      let active_rays = || (0..rays.len()).filter(move |ray| active & (1 << ray) != 0);
+      match child {
+        WideChild::Empty => {}
+        WideChild::Leaf(index) => {
+          for ray in active_rays() {
+            stats::count(Counter::PrimitiveTests, 1);
+            if let Some(hit_record) = self.primitives[index].is_hit(&rays[ray], scalar_from_ray_origin_min, closest[ray]) {
+              closest[ray] = hit_record.scalar_from_ray_origin();
+              closest_hits[ray] = Some(hit_record);
+            }
+          }
+        }
+        WideChild::Node(index) => {
+          stats::count(Counter::NodeVisits, 1);
+          let node = &self.nodes[index];
+          let furthest = active_rays().fold(scalar_from_ray_origin_min, |furthest, ray| furthest.max(closest[ray]));
+          let open = [0, 1, 2, 3]
+            .map(|lane| node.children[lane] != WideChild::Empty && !frustum.misses(&node.boxes, lane, scalar_from_ray_origin_min, furthest));
+          if !open.contains(&true) {
+            continue;
+          }
+          let mut entered = [(WideChild::Empty, 0_u64, Float::INFINITY); 4];
+          for ray in active_rays() {
+            let (origin, inverse_direction) = &ray_lanes[ray];
+            let hits = simd::hit_boxes(&node.boxes, origin, inverse_direction, scalar_from_ray_origin_min, closest[ray]);
+            for (lane, hit) in hits.iter().enumerate() {
+              if let (true, Some(near)) = (open[lane], hit) {
+                let (_, mask, nearest) = &mut entered[lane];
+                *mask |= 1 << ray;
+                *nearest = nearest.min(*near);
+              }
+            }
+          }
+          // Pushed furthest first so the child some ray reaches soonest is popped first
+          for (lane, entry) in entered.iter_mut().enumerate() {
+            entry.0 = node.children[lane];
+          }
+          entered.sort_unstable_by(|(_, _, first), (_, _, second)| second.total_cmp(first));
+          for (child, mask, _) in entered {
+            if mask.count_ones() > MIN_PACKET_SIZE {
+              stack[stack_len] = (child, mask);
+              stack_len += 1;
+              continue;
+            }
+            // Too few rays are left together for the shared tests to pay off, so trace them one at a time
+            for ray in (0..rays.len()).filter(|ray| mask & (1 << ray) != 0) {
+              if let Some(hit_record) = self.traverse(child, &rays[ray], scalar_from_ray_origin_min, closest[ray]) {
+                closest[ray] = hit_record.scalar_from_ray_origin();
+                closest_hits[ray] = Some(hit_record);
+              }
+            }
+          }
+        }
+      }
+    }
+    closest_hits
+  }
+  fn bounding_box(&self) -> Option<Aabb> {
+    Some(self.bounds)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::float::random_float;
+  use crate::material::Lambertian;
+  use crate::sphere::Sphere;
+  use crate::triangle::Triangle;
+  use crate::vec3::Vec3;
+  use std::sync::Arc;
+
+  fn random_point(scale: Float) -> Vec3 {
+    Vec3::new(random_float() - 0.5, random_float() - 0.5, random_float() - 0.5) * scale
+  }
+
+  fn primitives() -> Vec<Box<dyn Hittable>> {
+    let material = Arc::new(Lambertian::new(&Vec3::new(0.5, 0.5, 0.5)));
+    (0..200)
+      .map(|index| -> Box<dyn Hittable> {
+        let center = random_point(20.0);
+        if index % 2 == 0 {
+          Box::new(Sphere::new(&center, 0.1 + random_float(), material.clone()))
+        } else {
+          Box::new(Triangle::new(
+            &(center + random_point(2.0)),
+            &(center + random_point(2.0)),
+            &(center + random_point(2.0)),
+            material.clone(),
+          ))
+        }
+      })
+      .collect()
+  }
+
+  #[test]
+  fn wide_bvh_finds_the_same_hits_as_binary_bvh() {
+    fastrand::seed(1);
+    let binary = BinaryBvh::new(primitives());
+    fastrand::seed(1);
+    let wide = Bvh::new(primitives());
+    assert_eq!(binary.bounding_box(), wide.bounding_box());
+    for _ in 0..2000 {
+      let ray = Ray::new(&random_point(30.0), &random_point(1.0));
+      let (expected, found) = (binary.is_hit(&ray, 0.0, Float::MAX), wide.is_hit(&ray, 0.0, Float::MAX));
+      assert_eq!(
+        expected.as_ref().map(|hit_record| *hit_record.point()),
+        found.as_ref().map(|hit_record| *hit_record.point()),
+        "{ray:?}"
+      );
+    }
+  }
+
+  #[test]
+  fn packets_find_the_same_hits_as_single_rays() {
+    fastrand::seed(2);
+    let bvh = Bvh::new(primitives());
+    let origin = random_point(30.0);
+    for spread in [0.05, 2.0] {
+      let target = random_point(10.0);
+      let rays: Vec<Ray> = (0..64).map(|_| Ray::new(&origin, &(target + random_point(spread) - origin))).collect();
+      let packet_hits = bvh.is_hit_packet(&rays, 0.0, Float::MAX);
+      for (ray, packet_hit) in rays.iter().zip(packet_hits) {
+        assert_eq!(
+          bvh.is_hit(ray, 0.0, Float::MAX).map(|hit_record| *hit_record.point()),
+          packet_hit.map(|hit_record| *hit_record.point()),
+          "{ray:?}"
+        );
+      }
+    }
+  }
+
+  #[test]
+  fn empty_packets_find_no_hits() {
+    let bvh = Bvh::new(primitives());
+    assert!(bvh.is_hit_packet(&[], 0.0, Float::MAX).is_empty());
+  }
+}
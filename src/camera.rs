@@ -1,11 +1,14 @@
+use crate::float::consts::PI;
+use crate::float::{random_float, Float};
 use crate::ray::Ray;
+use crate::stats::{self, Counter};
 use crate::vec3::Vec3;
-use std::f32::consts::PI;
+use crate::Error;
 
 fn random_in_unit_disk() -> Vec3 {
   let point_in_unit_disk;
   loop {
-    let potential_point_in_unit_disk = 2.0 * Vec3::new(fastrand::f32(), fastrand::f32(), fastrand::f32()) - Vec3::new(1.0, 1.0, 0.0);
+    let potential_point_in_unit_disk = 2.0 * Vec3::new(random_float(), random_float(), 0.0) - Vec3::new(1.0, 1.0, 0.0);
     if potential_point_in_unit_disk.squared_length() < 1.0 {
       point_in_unit_disk = potential_point_in_unit_disk;
       break;
@@ -14,6 +17,37 @@ fn random_in_unit_disk() -> Vec3 {
   point_in_unit_disk
 }
 
+/// A point on the lens from which the camera sees a given point, found by `Camera::sample_importance`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CameraSample {
+  lens_point: Vec3,
+  direction: Vec3,
+  importance: Float,
+  pdf: Float,
+  raster: (Float, Float),
+}
+
+impl CameraSample {
+  pub fn lens_point(&self) -> &Vec3 {
+    &self.lens_point
+  }
+  /// Unit vector from the seen point towards `lens_point`
+  pub fn direction(&self) -> &Vec3 {
+    &self.direction
+  }
+  pub fn importance(&self) -> Float {
+    self.importance
+  }
+  /// Solid angle density of `direction` as seen from the point
+  pub fn pdf(&self) -> Float {
+    self.pdf
+  }
+  /// Image coordinates in the same [0, 1) range `get_ray` takes
+  pub fn raster(&self) -> (Float, Float) {
+    self.raster
+  }
+}
+
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Camera {
   origin: Vec3,
@@ -23,31 +57,22 @@ pub struct Camera {
   u: Vec3,
   v: Vec3,
   w: Vec3,
-  lens_radius: f32,
+  lens_radius: Float,
 }
 
 impl Camera {
-  #[allow(clippy::too_many_arguments)]
-  pub fn new(origin: &Vec3, horizontal: &Vec3, vertical: &Vec3, u: &Vec3, v: &Vec3, w: &Vec3, lower_left_corner: &Vec3, lens_radius: f32) -> Self {
-    Self {
-      origin: *origin,
-      horizontal: *horizontal,
-      vertical: *vertical,
-      lower_left_corner: *lower_left_corner,
-      lens_radius,
-      u: *u,
-      v: *v,
-      w: *w,
-    }
+  /// A builder for a camera at the origin looking down -z with a 90 degree field of view and a pinhole lens
+  pub fn builder() -> CameraBuilder {
+    CameraBuilder::default()
   }
   pub fn new_from_fov_and_aspect(
     look_from: &Vec3,
     look_at: &Vec3,
     vup: &Vec3,
-    vertical_fov: f32,
-    aspect: f32,
-    aperture: f32,
-    focus_distance: f32,
+    vertical_fov: Float,
+    aspect: Float,
+    aperture: Float,
+    focus_distance: Float,
   ) -> Self {
     let lens_radius = aperture / 2.0;
     let theta = vertical_fov * (PI / 180.0);
@@ -72,7 +97,8 @@ impl Camera {
     }
   }
 
-  pub fn get_ray(&self, s: f32, t: f32) -> Ray {
+  pub fn get_ray(&self, s: Float, t: Float) -> Ray {
+    stats::count(Counter::CameraRays, 1);
     let random_position_on_lens_disk = self.lens_radius * random_in_unit_disk();
     let offset = self.u * random_position_on_lens_disk.x() + self.v * random_position_on_lens_disk.y();
     Ray::new(
@@ -80,4 +106,217 @@ impl Camera {
       &(self.lower_left_corner + s * self.horizontal + t * self.vertical - self.origin - offset),
     )
   }
+  fn focus_distance(&self) -> Float {
+    (self.origin - self.lower_left_corner).dot(&self.w)
+  }
+  fn lens_area(&self) -> Float {
+    if self.lens_radius > 0.0 {
+      PI * self.lens_radius * self.lens_radius
+    } else {
+      1.0
+    }
+  }
+  /// Area of the image at unit distance from the lens
+  fn image_area(&self) -> Float {
+    let focus_distance = self.focus_distance();
+    self.horizontal.cross(&self.vertical).length() / (focus_distance * focus_distance)
+  }
+  /// Image coordinates at which `point` appears when seen through `lens_point`, in the same [0, 1) range `get_ray`
+  /// takes, or None if it is behind the camera or outside the image
+  pub fn project(&self, point: &Vec3, lens_point: &Vec3) -> Option<(Float, Float)> {
+    let direction = point - lens_point;
+    let forward = -direction.dot(&self.w);
+    if forward <= 0.0 {
+      return None;
+    }
+    let on_focus_plane = lens_point + direction * (self.focus_distance() / forward) - self.lower_left_corner;
+    let s = on_focus_plane.dot(&self.horizontal) / self.horizontal.squared_length();
+    let t = on_focus_plane.dot(&self.vertical) / self.vertical.squared_length();
+    if (0.0..1.0).contains(&s) && (0.0..1.0).contains(&t) {
+      Some((s, t))
+    } else {
+      None
+    }
+  }
+  /// Importance the camera emits from `lens_point` along `direction`, normalized over the image and lens
+  fn importance(&self, lens_point: &Vec3, direction: &Vec3) -> Option<(Float, (Float, Float))> {
+    let raster = self.project(&(lens_point + direction), lens_point)?;
+    let cos_theta = -direction.unit_vector().dot(&self.w);
+    let cos_theta_squared = cos_theta * cos_theta;
+    Some((
+      1.0 / (self.image_area() * self.lens_area() * cos_theta_squared * cos_theta_squared),
+      raster,
+    ))
+  }
+  /// Position and direction densities with which `get_ray` produces `ray`
+  pub fn pdf(&self, ray: &Ray) -> (Float, Float) {
+    if self.project(&(ray.origin() + ray.direction()), ray.origin()).is_none() {
+      return (0.0, 0.0);
+    }
+    let cos_theta = -ray.direction().unit_vector().dot(&self.w);
+    (1.0 / self.lens_area(), 1.0 / (self.image_area() * cos_theta * cos_theta * cos_theta))
+  }
+  /// Samples a point on the lens that sees `point`, for splatting light paths onto the image
+  pub fn sample_importance(&self, point: &Vec3) -> Option<CameraSample> {
+    let random_position_on_lens_disk = self.lens_radius * random_in_unit_disk();
+    let lens_point = self.origin + self.u * random_position_on_lens_disk.x() + self.v * random_position_on_lens_disk.y();
+    let to_lens = lens_point - point;
+    let distance = to_lens.length();
+    if distance <= 0.0 {
+      return None;
+    }
+    let direction = to_lens / distance;
+    let cos_lens = direction.dot(&self.w).abs();
+    if cos_lens <= 0.0 {
+      return None;
+    }
+    let (importance, raster) = self.importance(&lens_point, &-direction)?;
+    Some(CameraSample {
+      lens_point,
+      direction,
+      importance,
+      pdf: distance * distance / (cos_lens * self.lens_area()),
+      raster,
+    })
+  }
+}
+
+/// Builds a `Camera` from where it is and what it looks at, checking the settings make a camera that can see
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CameraBuilder {
+  look_from: Vec3,
+  look_at: Vec3,
+  up: Vec3,
+  vertical_fov: Float,
+  aspect: Float,
+  aperture: Float,
+  focus_distance: Option<Float>,
+}
+
+impl Default for CameraBuilder {
+  fn default() -> Self {
+    Self {
+      look_from: Vec3::new_empty(),
+      look_at: Vec3::new(0.0, 0.0, -1.0),
+      up: Vec3::new(0.0, 1.0, 0.0),
+      vertical_fov: 90.0,
+      aspect: 2.0,
+      aperture: 0.0,
+      focus_distance: None,
+    }
+  }
+}
+
+impl CameraBuilder {
+  pub fn with_look_from(mut self, look_from: &Vec3) -> Self {
+    self.look_from = *look_from;
+    self
+  }
+  pub fn with_look_at(mut self, look_at: &Vec3) -> Self {
+    self.look_at = *look_at;
+    self
+  }
+  /// Direction that appears upwards in the image, which need not be perpendicular to the view
+  pub fn with_up(mut self, up: &Vec3) -> Self {
+    self.up = *up;
+    self
+  }
+  /// Angle in degrees the image spans from bottom to top
+  pub fn with_vertical_fov(mut self, vertical_fov: Float) -> Self {
+    self.vertical_fov = vertical_fov;
+    self
+  }
+  /// Width of the image divided by its height
+  pub fn with_aspect(mut self, aspect: Float) -> Self {
+    self.aspect = aspect;
+    self
+  }
+  /// Diameter of the lens, zero for a pinhole camera with everything in focus
+  pub fn with_aperture(mut self, aperture: Float) -> Self {
+    self.aperture = aperture;
+    self
+  }
+  /// Distance from the lens at which things are in focus, the distance to the point looked at unless given
+  pub fn with_focus_distance(mut self, focus_distance: Float) -> Self {
+    self.focus_distance = Some(focus_distance);
+    self
+  }
+  /// Builds the camera
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if the field of view is not between 0 and 180 degrees, the aspect ratio or focus distance is
+  /// not positive, the aperture is negative, the camera looks at the point it is at or up is along the view
+  pub fn build(&self) -> Result<Camera, Error> {
+    if !(self.vertical_fov > 0.0 && self.vertical_fov < 180.0) {
+      return Err(format!("Vertical field of view must be between 0 and 180 degrees, found {}", self.vertical_fov).into());
+    }
+    if !(self.aspect > 0.0 && self.aspect.is_finite()) {
+      return Err(format!("Aspect ratio must be positive, found {}", self.aspect).into());
+    }
+    if !(self.aperture >= 0.0 && self.aperture.is_finite()) {
+      return Err(format!("Aperture must not be negative, found {}", self.aperture).into());
+    }
+    let view = self.look_at - self.look_from;
+    if view.squared_length() == 0.0 {
+      return Err("The camera must look at a point other than the one it is at".into());
+    }
+    if view.cross(&self.up).squared_length() == 0.0 {
+      return Err("The up direction must not be zero or along the view direction".into());
+    }
+    let focus_distance = self.focus_distance.unwrap_or_else(|| view.length());
+    if !(focus_distance > 0.0 && focus_distance.is_finite()) {
+      return Err(format!("Focus distance must be positive, found {focus_distance}").into());
+    }
+    Ok(Camera::new_from_fov_and_aspect(
+      &self.look_from,
+      &self.look_at,
+      &self.up,
+      self.vertical_fov,
+      self.aspect,
+      self.aperture,
+      focus_distance,
+    ))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn builder_matches_positional_constructor() {
+    let built = Camera::builder()
+      .with_look_from(&Vec3::new(13.0, 2.0, 3.0))
+      .with_look_at(&Vec3::new(0.0, 0.0, 0.0))
+      .with_vertical_fov(20.0)
+      .with_aperture(0.1)
+      .with_focus_distance(10.0)
+      .build()
+      .unwrap();
+    let positional = Camera::new_from_fov_and_aspect(
+      &Vec3::new(13.0, 2.0, 3.0),
+      &Vec3::new(0.0, 0.0, 0.0),
+      &Vec3::new(0.0, 1.0, 0.0),
+      20.0,
+      2.0,
+      0.1,
+      10.0,
+    );
+    assert_eq!(built, positional);
+  }
+
+  #[test]
+  fn builder_rejects_cameras_that_cannot_see() {
+    let builder = Camera::builder();
+    assert!(builder.with_vertical_fov(0.0).build().is_err());
+    assert!(builder.with_vertical_fov(180.0).build().is_err());
+    assert!(builder.with_vertical_fov(Float::NAN).build().is_err());
+    assert!(builder.with_aspect(-1.0).build().is_err());
+    assert!(builder.with_aperture(-0.1).build().is_err());
+    assert!(builder.with_focus_distance(0.0).build().is_err());
+    assert!(builder.with_look_at(&Vec3::new_empty()).build().is_err());
+    assert!(builder.with_up(&Vec3::new(0.0, 0.0, 1.0)).build().is_err());
+    assert!(builder.build().is_ok());
+  }
 }
@@ -0,0 +1,220 @@
+use crate::film::Film;
+use crate::float::Float;
+use crate::vec3::Vec3;
+use crate::Error;
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::time::Duration;
+
+/// Bytes every checkpoint file starts with
+const MAGIC: &[u8; 8] = b"RTCHKPT4";
+
+/// What a render needs to carry on where it left off: the command line it was started with, which fixes its settings
+/// and seed, how many passes it has made, how long they took and the film they accumulated into
+#[derive(Clone, Debug, PartialEq)]
+pub struct Checkpoint {
+  arguments: Vec<String>,
+  passes: usize,
+  elapsed: Duration,
+  film: Film,
+}
+
+impl Checkpoint {
+  /// A checkpoint for a render started with `arguments` that has made no passes into `film` yet
+  pub fn new(arguments: &[String], film: Film) -> Self {
+    Self {
+      arguments: arguments.to_vec(),
+      passes: 0,
+      elapsed: Duration::ZERO,
+      film,
+    }
+  }
+  pub fn arguments(&self) -> &[String] {
+    &self.arguments
+  }
+  pub fn passes(&self) -> usize {
+    self.passes
+  }
+  /// Time spent rendering passes, across every run that resumed the render
+  pub fn elapsed(&self) -> Duration {
+    self.elapsed
+  }
+  pub fn film(&self) -> &Film {
+    &self.film
+  }
+  pub fn film_mut(&mut self) -> &mut Film {
+    &mut self.film
+  }
+  pub fn into_film(self) -> Film {
+    self.film
+  }
+  /// Counts a pass that took `duration`
+  pub fn complete_pass(&mut self, duration: Duration) {
+    self.passes += 1;
+    self.elapsed += duration;
+  }
+  /// Writes the checkpoint to `path`, by way of a temporary file next to it so a crash mid-write leaves the last
+  /// checkpoint intact
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if the file cannot be written
+  pub fn save(&self, path: &Path) -> Result<(), Error> {
+    let temporary_path = path.with_extension("partial");
+    let mut output = BufWriter::new(File::create(&temporary_path)?);
+    output.write_all(MAGIC)?;
+    write_usize(&mut output, std::mem::size_of::<Float>())?;
+    write_usize(&mut output, self.arguments.len())?;
+    for argument in &self.arguments {
+      write_string(&mut output, argument)?;
+    }
+    write_usize(&mut output, self.passes)?;
+    write_usize(&mut output, usize::try_from(self.elapsed.as_millis())?)?;
+    self.film.write(&mut output)?;
+    output.into_inner().map_err(std::io::IntoInnerError::into_error)?.sync_all()?;
+    std::fs::rename(temporary_path, path)?;
+    Ok(())
+  }
+  /// Reads a checkpoint written by `save`
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if the file cannot be read or is not a checkpoint
+  pub fn load(path: &Path) -> Result<Self, Error> {
+    let mut input = BufReader::new(File::open(path)?);
+    let mut magic = [0; 8];
+    input.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+      return Err(format!("{} is not a checkpoint", path.display()).into());
+    }
+    let float_size = read_usize(&mut input)?;
+    if float_size != std::mem::size_of::<Float>() {
+      return Err(
+        format!(
+          "{} was saved by a build computing with {}-bit floats, so it can only be resumed by one",
+          path.display(),
+          8 * float_size
+        )
+        .into(),
+      );
+    }
+    let argument_count = read_usize(&mut input)?;
+    let arguments = (0..argument_count).map(|_| read_string(&mut input)).collect::<Result<_, _>>()?;
+    let passes = read_usize(&mut input)?;
+    let elapsed = Duration::from_millis(u64::try_from(read_usize(&mut input)?)?);
+    let film = Film::read(&mut input)?;
+    Ok(Self {
+      arguments,
+      passes,
+      elapsed,
+      film,
+    })
+  }
+}
+
+/// Writes `value` as a little-endian 64-bit integer
+///
+/// # Errors
+///
+/// Returns an error if writing to `output` fails or `value` does not fit in 64 bits
+pub fn write_usize(output: &mut impl Write, value: usize) -> Result<(), Error> {
+  output.write_all(&u64::try_from(value)?.to_le_bytes())?;
+  Ok(())
+}
+
+/// Reads a value written by `write_usize`
+///
+/// # Errors
+///
+/// Returns an error if reading from `input` fails or the value does not fit in a `usize`
+pub fn read_usize(input: &mut impl Read) -> Result<usize, Error> {
+  let mut bytes = [0; 8];
+  input.read_exact(&mut bytes)?;
+  Ok(usize::try_from(u64::from_le_bytes(bytes))?)
+}
+
+/// Writes `value` as a little-endian 32-bit integer
+///
+/// # Errors
+///
+/// Returns an error if writing to `output` fails
+pub fn write_u32(output: &mut impl Write, value: u32) -> Result<(), Error> {
+  output.write_all(&value.to_le_bytes())?;
+  Ok(())
+}
+
+/// Reads a value written by `write_u32`
+///
+/// # Errors
+///
+/// Returns an error if reading from `input` fails
+pub fn read_u32(input: &mut impl Read) -> Result<u32, Error> {
+  let mut bytes = [0; 4];
+  input.read_exact(&mut bytes)?;
+  Ok(u32::from_le_bytes(bytes))
+}
+
+/// Writes the bits of `value` in little-endian order, taking 4 bytes or 8 with the `f64` feature
+///
+/// # Errors
+///
+/// Returns an error if writing to `output` fails
+pub fn write_float(output: &mut impl Write, value: Float) -> Result<(), Error> {
+  output.write_all(&value.to_le_bytes())?;
+  Ok(())
+}
+
+/// Reads a value written by `write_float`
+///
+/// # Errors
+///
+/// Returns an error if reading from `input` fails
+pub fn read_float(input: &mut impl Read) -> Result<Float, Error> {
+  let mut bytes = [0; std::mem::size_of::<Float>()];
+  input.read_exact(&mut bytes)?;
+  Ok(Float::from_le_bytes(bytes))
+}
+
+/// Writes the components of `value` with `write_float`
+///
+/// # Errors
+///
+/// Returns an error if writing to `output` fails
+pub fn write_vec3(output: &mut impl Write, value: &Vec3) -> Result<(), Error> {
+  write_float(output, value.x())?;
+  write_float(output, value.y())?;
+  write_float(output, value.z())
+}
+
+/// Reads a value written by `write_vec3`
+///
+/// # Errors
+///
+/// Returns an error if reading from `input` fails
+pub fn read_vec3(input: &mut impl Read) -> Result<Vec3, Error> {
+  Ok(Vec3::new(read_float(input)?, read_float(input)?, read_float(input)?))
+}
+
+/// Writes the length of `value` followed by its UTF-8 bytes
+///
+/// # Errors
+///
+/// Returns an error if writing to `output` fails
+pub fn write_string(output: &mut impl Write, value: &str) -> Result<(), Error> {
+  write_usize(output, value.len())?;
+  output.write_all(value.as_bytes())?;
+  Ok(())
+}
+
+/// Reads a value written by `write_string`
+///
+/// # Errors
+///
+/// Returns an error if reading from `input` fails or the bytes are not UTF-8
+pub fn read_string(input: &mut impl Read) -> Result<String, Error> {
+  let mut bytes = vec![0; read_usize(input)?];
+  input.read_exact(&mut bytes)?;
+  Ok(String::from_utf8(bytes)?)
+}
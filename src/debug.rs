@@ -0,0 +1,115 @@
+use crate::film::Film;
+use crate::float::Float;
+use crate::hit::Hittable;
+use crate::integrator::Integrator;
+use crate::material::material_id;
+use crate::ray::Ray;
+use crate::scene::Scene;
+use crate::spectrum::Radiance;
+use crate::stats::{self, Counter, RayCounts};
+use crate::vec3::Vec3;
+use crate::Error;
+
+/// Number of BVH tests per ray shown at the hot end of the heatmap
+const HEATMAP_SCALE: Float = 100.0;
+
+/// What a debug render shows for the first surface each camera ray hits
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DebugMode {
+  /// Shading normal, mapped from [-1, 1] to [0, 1]
+  Normal,
+  /// Distance along the ray as a fraction of `far`
+  Depth {
+    far: Float,
+  },
+  Albedo,
+  /// Surface coordinates in the red and green channels
+  Uv,
+  /// A color hashed from the type and parameters of the surface's material
+  MaterialId,
+  /// BVH node and primitive tests made by the camera ray, from blue for none to red for `HEATMAP_SCALE` or more
+  BvhHeatmap,
+}
+
+/// Parses a debug mode name as given on the command line, with depth normalized to `depth_range`
+///
+/// # Errors
+///
+/// Returns an error if `name` is not a debug mode
+pub fn parse_debug_mode(name: &str, depth_range: Float) -> Result<DebugMode, Error> {
+  match name {
+    "normal" => Ok(DebugMode::Normal),
+    "depth" => Ok(DebugMode::Depth { far: depth_range }),
+    "albedo" => Ok(DebugMode::Albedo),
+    "uv" => Ok(DebugMode::Uv),
+    "material-id" => Ok(DebugMode::MaterialId),
+    "bvh-heatmap" => Ok(DebugMode::BvhHeatmap),
+    _ => Err(format!("Unknown debug mode {name}").into()),
+  }
+}
+
+/// Renders a diagnostic view of the scene instead of its lighting
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DebugIntegrator {
+  mode: DebugMode,
+}
+
+impl DebugIntegrator {
+  pub fn new(mode: DebugMode) -> Self {
+    Self { mode }
+  }
+}
+
+/// A film showing how many samples each pixel of `film` took, from blue for none to red for as many as any took
+#[allow(clippy::cast_precision_loss)]
+pub fn sample_count_heatmap(film: &Film) -> Film {
+  let max_samples = film.max_sample_count().max(1);
+  let mut heatmap_film = Film::new(film.width(), film.height());
+  for y in 0..film.height() {
+    for x in 0..film.width() {
+      heatmap_film.set_pixel(x, y, &heatmap(film.sample_count(x, y) as Float / max_samples as Float), 1);
+    }
+  }
+  heatmap_film
+}
+
+/// Blue through green to red as `value` goes from zero to one
+fn heatmap(value: Float) -> Vec3 {
+  let value = value.clamp(0.0, 1.0);
+  if value < 0.5 {
+    Vec3::new(0.0, 2.0 * value, Float::mul_add(2.0, -value, 1.0))
+  } else {
+    Vec3::new(Float::mul_add(2.0, value, -1.0), Float::mul_add(2.0, -value, 2.0), 0.0)
+  }
+}
+
+impl Integrator for DebugIntegrator {
+  #[allow(clippy::cast_precision_loss)]
+  fn radiance<R: Radiance>(&self, ray: &Ray, scene: &Scene, context: &R::Context, _: &Film) -> R {
+    let tests = |counts: RayCounts| counts.get(Counter::NodeVisits) + counts.get(Counter::PrimitiveTests);
+    let tests_before = tests(stats::thread_counts());
+    let hit = scene.world().is_hit(ray, 0.0, Float::MAX);
+    let tests_made = tests(stats::thread_counts()) - tests_before;
+    let color = match (self.mode, hit) {
+      (DebugMode::BvhHeatmap, _) => heatmap(tests_made as Float / HEATMAP_SCALE),
+      (DebugMode::Depth { .. }, None) => Vec3::new(1.0, 1.0, 1.0),
+      (_, None) => Vec3::new_empty(),
+      (DebugMode::Normal, Some(hit_record)) => 0.5 * (hit_record.normal() + Vec3::new(1.0, 1.0, 1.0)),
+      (DebugMode::Depth { far }, Some(hit_record)) => {
+        let depth = (hit_record.scalar_from_ray_origin() * ray.direction().length() / far).min(1.0);
+        Vec3::new(depth, depth, depth)
+      }
+      (DebugMode::Albedo, Some(hit_record)) => hit_record.material().albedo(),
+      (DebugMode::Uv, Some(hit_record)) => {
+        let (u, v) = hit_record.uv();
+        Vec3::new(u, v, 0.0)
+      }
+      (DebugMode::MaterialId, Some(hit_record)) => {
+        let id = material_id(hit_record.material().as_ref());
+        let channel = |shift: u32| ((id >> shift) & 0xff) as Float / 255.0;
+        Vec3::new(channel(0), channel(8), channel(16))
+      }
+    };
+    R::from_albedo(&color, context)
+  }
+}
@@ -0,0 +1,305 @@
+use crate::environment::luminance;
+use crate::film::Film;
+use crate::float::Float;
+use crate::vec3::Vec3;
+use crate::Error;
+
+/// Weights of the B3 spline each à-trous pass spreads over five taps along each axis
+const KERNEL: [Float; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+/// Power the cosine between two normals is raised to, so neighbours across creases barely count
+const NORMAL_EXPONENT: i32 = 64;
+/// Relative depth difference per pixel of offset at which a neighbour's weight falls by a factor of e
+const DEPTH_SIGMA: Float = 0.02;
+/// Squared albedo difference at which a neighbour's weight falls by a factor of e
+const ALBEDO_SIGMA_SQUARED: Float = 0.01;
+/// Albedo below which a color component is filtered as is rather than divided by it
+const MIN_MODULATION: Float = 0.01;
+
+/// Feature buffers of one pixel, which stay sharp at low sample counts and so tell the filter where edges are
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Features {
+  albedo: Vec3,
+  normal: Vec3,
+  depth: Float,
+}
+
+impl Features {
+  fn weight(&self, other: &Self, offset: Float) -> Float {
+    let normal_weight = match (self.normal.squared_length() > 0.0, other.normal.squared_length() > 0.0) {
+      (true, true) => (self.normal.dot(&other.normal) / (self.normal.length() * other.normal.length()))
+        .max(0.0)
+        .powi(NORMAL_EXPONENT),
+      (false, false) => 1.0,
+      _ => 0.0,
+    };
+    let relative_depth = (self.depth - other.depth).abs() / self.depth.max(other.depth).max(Float::EPSILON);
+    let depth_weight = (-relative_depth / (DEPTH_SIGMA * offset)).exp();
+    let albedo_weight = (-(self.albedo - other.albedo).squared_length() / ALBEDO_SIGMA_SQUARED).exp();
+    normal_weight * depth_weight * albedo_weight
+  }
+  /// What the radiance is divided by before filtering so texture detail is not blurred with the noise
+  fn modulation(&self) -> Vec3 {
+    let component = |albedo: Float| if albedo < MIN_MODULATION { 1.0 } else { albedo };
+    Vec3::new(component(self.albedo.x()), component(self.albedo.y()), component(self.albedo.z()))
+  }
+}
+
+/// Edge-aware à-trous wavelet denoiser, which repeatedly blurs the image with a kernel twice as wide as the last while
+/// keeping edges found in the albedo, normal and depth channels and differences bigger than the pixels' noise
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Denoiser {
+  iterations: usize,
+  color_sigma: Float,
+}
+
+impl Denoiser {
+  /// A denoiser making `iterations` passes, which widen the blur to 2^(`iterations` + 1) pixels, that blends
+  /// neighbours whose luminance is within about `color_sigma` standard deviations of noise
+  pub fn new(iterations: usize, color_sigma: Float) -> Self {
+    Self { iterations, color_sigma }
+  }
+  /// Returns a copy of `film` with its radiance denoised, using the albedo, normal and depth channels filled in by
+  /// `aov::record_surface` and the variance of each pixel
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if the film lacks the albedo, normal or depth channel
+  #[allow(clippy::cast_precision_loss)]
+  pub fn denoise(&self, film: &Film, samples_per_pixel: usize) -> Result<Film, Error> {
+    for name in ["albedo", "normal", "depth"] {
+      if !film.channel_names().any(|channel| channel == name) {
+        return Err(format!("Denoising needs the {name} channel, which this render did not record").into());
+      }
+    }
+    let (width, height) = (film.width(), film.height());
+    let mut features = Vec::with_capacity(width * height);
+    let mut colors = Vec::with_capacity(width * height);
+    let mut variances = Vec::with_capacity(width * height);
+    for y in 0..height {
+      for x in 0..width {
+        let pixel_features = Features {
+          albedo: film.channel_pixel("albedo", x, y, samples_per_pixel),
+          normal: film.channel_pixel("normal", x, y, samples_per_pixel),
+          depth: film.channel_pixel("depth", x, y, samples_per_pixel).x(),
+        };
+        let modulation = pixel_features.modulation();
+        colors.push(film.pixel(x, y, samples_per_pixel) / modulation);
+        variances.push(film.pixel_variance(x, y) / luminance(&modulation).powi(2));
+        features.push(pixel_features);
+      }
+    }
+    for iteration in 0..self.iterations {
+      let step = 1 << iteration;
+      let smoothed_variances = smooth(&variances, width, height);
+      let mut next_colors = colors.clone();
+      let mut next_variances = variances.clone();
+      for y in 0..height {
+        for x in 0..width {
+          let index = y * width + x;
+          let color_scale = self.color_sigma.mul_add(smoothed_variances[index].sqrt(), 1e-4);
+          let (mut color_sum, mut variance_sum, mut weight_sum) = (Vec3::new_empty(), 0.0, 0.0);
+          for (row, row_weight) in KERNEL.iter().enumerate() {
+            for (column, column_weight) in KERNEL.iter().enumerate() {
+              let (Some(neighbour_x), Some(neighbour_y)) = (offset(x, column, step, width), offset(y, row, step, height)) else {
+                continue;
+              };
+              let neighbour = neighbour_y * width + neighbour_x;
+              let distance = (x.abs_diff(neighbour_x).max(y.abs_diff(neighbour_y)) as Float).max(1.0);
+              let color_weight = (-(luminance(&colors[index]) - luminance(&colors[neighbour])).abs() / color_scale).exp();
+              let weight = row_weight * column_weight * color_weight * features[index].weight(&features[neighbour], distance);
+              color_sum += weight * colors[neighbour];
+              variance_sum += weight * weight * variances[neighbour];
+              weight_sum += weight;
+            }
+          }
+          next_colors[index] = color_sum / weight_sum;
+          next_variances[index] = variance_sum / (weight_sum * weight_sum);
+        }
+      }
+      colors = next_colors;
+      variances = next_variances;
+    }
+    let mut denoised = film.clone();
+    for y in 0..height {
+      for x in 0..width {
+        let index = y * width + x;
+        denoised.set_pixel(x, y, &(colors[index] * features[index].modulation()), film.sample_count(x, y).max(1));
+      }
+    }
+    Ok(denoised)
+  }
+}
+
+/// Coordinate of the `tap`th of the five kernel taps around `coordinate` when they are `step` pixels apart, if it is
+/// inside the image
+fn offset(coordinate: usize, tap: usize, step: usize, size: usize) -> Option<usize> {
+  (coordinate + tap * step).checked_sub(2 * step).filter(|neighbour| *neighbour < size)
+}
+
+/// Variances blurred over each pixel's 3x3 neighbourhood, which steadies estimates made from only a few samples
+fn smooth(variances: &[Float], width: usize, height: usize) -> Vec<Float> {
+  const WEIGHTS: [Float; 3] = [0.25, 0.5, 0.25];
+  let mut smoothed = vec![0.0; variances.len()];
+  for y in 0..height {
+    for x in 0..width {
+      let (mut sum, mut weight_sum) = (0.0, 0.0);
+      for (row, row_weight) in WEIGHTS.iter().enumerate() {
+        for (column, column_weight) in WEIGHTS.iter().enumerate() {
+          let (Some(neighbour_x), Some(neighbour_y)) = ((x + column).checked_sub(1), (y + row).checked_sub(1)) else {
+            continue;
+          };
+          if neighbour_x >= width || neighbour_y >= height {
+            continue;
+          }
+          sum += row_weight * column_weight * variances[neighbour_y * width + neighbour_x];
+          weight_sum += row_weight * column_weight;
+        }
+      }
+      smoothed[y * width + x] = sum / weight_sum;
+    }
+  }
+  smoothed
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::background::Background;
+  use crate::camera::Camera;
+  use crate::checkpoint::Checkpoint;
+  use crate::integrator::PathIntegrator;
+  use crate::renderer::{ColorMode, Renderer};
+  use crate::scene::Scene;
+  use crate::settings::{RenderSettings, SamplingSettings};
+
+  const WIDTH: usize = 48;
+  const HEIGHT: usize = 32;
+  const SAMPLES_PER_PIXEL: usize = 4;
+
+  /// Albedo, normal, depth and noise-free radiance of the pixel `x` from the left and `y` from the bottom of a
+  /// smoothly shaded grey wall on the left meeting a flat lit orange one on the right
+  #[allow(clippy::cast_precision_loss)]
+  fn surface(x: usize, y: usize) -> (Vec3, Vec3, Float, Vec3) {
+    if x < WIDTH / 2 {
+      let albedo = Vec3::new(0.5, 0.5, 0.5);
+      let shading = 0.4 + 0.4 * y as Float / HEIGHT as Float;
+      (albedo, Vec3::new(0.0, 0.0, 1.0), 2.0, albedo * shading)
+    } else {
+      let albedo = Vec3::new(0.8, 0.3, 0.2);
+      (albedo, Vec3::new(1.0, 0.0, 0.0), 3.0, albedo)
+    }
+  }
+
+  /// A film of the walls with four samples per pixel, each their radiance scaled by noise spread evenly between 0.2
+  /// and 1.8 drawn from a fixed xorshift sequence, so the film is the same whatever the random number generator
+  #[allow(clippy::cast_precision_loss)]
+  fn noisy_film() -> Film {
+    let mut state: u32 = 0x9e37_79b9;
+    let mut noise = || {
+      state ^= state << 13;
+      state ^= state >> 17;
+      state ^= state << 5;
+      Float::mul_add(1.6, state as Float / u32::MAX as Float, 0.2)
+    };
+    let mut film = Film::new(WIDTH, HEIGHT);
+    for y in 0..HEIGHT {
+      for x in 0..WIDTH {
+        let (albedo, normal, depth, radiance) = surface(x, y);
+        for (x_offset, y_offset) in [(0.25, 0.25), (0.75, 0.25), (0.25, 0.75), (0.75, 0.75)] {
+          let position = (x as Float + x_offset, y as Float + y_offset);
+          film.add_sample(position, &(radiance * noise()));
+          film.add_data_sample("albedo", x, y, &albedo);
+          film.add_data_sample("normal", x, y, &normal);
+          film.add_data_sample("depth", x, y, &Vec3::new(depth, depth, depth));
+        }
+      }
+    }
+    film
+  }
+
+  /// Mean squared error of the pixels of `film` relative to the noise-free radiance, and the largest relative error
+  /// of the pixels beside the edge between the walls
+  #[allow(clippy::cast_precision_loss)]
+  fn errors(film: &Film) -> (Float, Float) {
+    let (mut total, mut edge) = (0.0, 0.0);
+    for y in 0..HEIGHT {
+      for x in 0..WIDTH {
+        let expected = surface(x, y).3;
+        let relative_error = (film.pixel(x, y, SAMPLES_PER_PIXEL) - expected).length() / expected.length();
+        total += relative_error * relative_error;
+        if x == WIDTH / 2 - 1 || x == WIDTH / 2 {
+          edge = Float::max(edge, relative_error);
+        }
+      }
+    }
+    (total / (WIDTH * HEIGHT) as Float, edge)
+  }
+
+  #[test]
+  fn denoising_removes_noise_without_blurring_across_edges() {
+    let noisy = noisy_film();
+    let (noisy_error, noisy_edge) = errors(&noisy);
+    let (light_error, _) = errors(&Denoiser::new(1, 4.0).denoise(&noisy, SAMPLES_PER_PIXEL).unwrap());
+    let (strong_error, strong_edge) = errors(&Denoiser::new(3, 4.0).denoise(&noisy, SAMPLES_PER_PIXEL).unwrap());
+    assert!(light_error < 0.5 * noisy_error, "noisy {}, light {}", noisy_error, light_error);
+    assert!(strong_error < 0.5 * light_error, "light {}, strong {}", light_error, strong_error);
+    // Pixels beside the edge keep to their own wall's colour, where blending in the other wall would put them off by
+    // more than the noise did
+    assert!(strong_edge < 0.5 * noisy_edge, "noisy {}, strong {} at the edge", noisy_edge, strong_edge);
+  }
+
+  #[test]
+  fn denoising_needs_the_feature_channels() {
+    let mut film = Film::new(2, 2);
+    film.add_sample((0.5, 0.5), &Vec3::new(1.0, 1.0, 1.0));
+    assert!(Denoiser::new(1, 4.0).denoise(&film, 1).is_err());
+  }
+
+  /// A film of a red and a grey ball on a white floor under an even sky, path traced with `samples_per_pixel` and
+  /// the feature channels recorded
+  fn render_balls(samples_per_pixel: usize) -> Result<Film, Error> {
+    let mut builder = Scene::builder();
+    let floor = builder.add_lambertian("floor", &Vec3::new(0.8, 0.8, 0.8))?;
+    let red = builder.add_lambertian("red", &Vec3::new(0.7, 0.2, 0.1))?;
+    let grey = builder.add_lambertian("grey", &Vec3::new(0.4, 0.4, 0.4))?;
+    builder
+      .add_sphere(&Vec3::new(0.0, -100.5, -1.0), 100.0, floor)?
+      .add_sphere(&Vec3::new(-0.55, 0.0, -1.0), 0.5, red)?
+      .add_sphere(&Vec3::new(0.55, 0.0, -1.0), 0.5, grey)?
+      .set_background(Background::Constant(Vec3::new(1.0, 1.0, 1.0)));
+    let camera = Camera::builder()
+      .with_look_from(&Vec3::new(0.0, 0.5, 2.0))
+      .with_look_at(&Vec3::new(0.0, 0.0, -1.0))
+      .with_vertical_fov(40.0)
+      .build()?;
+    Renderer::new(ColorMode::Rgb, SamplingSettings::fixed(samples_per_pixel)?, true, 3).render(
+      &PathIntegrator::new(RenderSettings::default()),
+      &builder.build(),
+      &camera,
+      Checkpoint::new(&[], Film::new(WIDTH, HEIGHT)),
+    )
+  }
+
+  /// Mean squared difference between the pixels of `film` and those of `reference`
+  #[allow(clippy::cast_precision_loss)]
+  fn squared_error(film: &Film, samples_per_pixel: usize, reference: &Film, reference_samples_per_pixel: usize) -> Float {
+    let mut total = 0.0;
+    for y in 0..HEIGHT {
+      for x in 0..WIDTH {
+        total += (film.pixel(x, y, samples_per_pixel) - reference.pixel(x, y, reference_samples_per_pixel)).squared_length();
+      }
+    }
+    total / (WIDTH * HEIGHT) as Float
+  }
+
+  #[test]
+  fn denoising_a_render_brings_it_closer_to_one_with_many_more_samples() -> Result<(), Error> {
+    let reference = render_balls(256)?;
+    let noisy = render_balls(SAMPLES_PER_PIXEL)?;
+    let denoised = Denoiser::new(3, 4.0).denoise(&noisy, SAMPLES_PER_PIXEL)?;
+    let noisy_error = squared_error(&noisy, SAMPLES_PER_PIXEL, &reference, 256);
+    let denoised_error = squared_error(&denoised, SAMPLES_PER_PIXEL, &reference, 256);
+    assert!(denoised_error < 0.5 * noisy_error, "noisy {}, denoised {}", noisy_error, denoised_error);
+    Ok(())
+  }
+}
@@ -0,0 +1,266 @@
+use crate::float::consts::PI;
+use crate::float::{from_f32, Float};
+use crate::sampling::Distribution2D;
+use crate::vec3::Vec3;
+use crate::Error;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+
+fn rgbe_to_vec3(rgbe: [u8; 4]) -> Vec3 {
+  if rgbe[3] == 0 {
+    return Vec3::new_empty();
+  }
+  let scale = Float::powi(2.0, i32::from(rgbe[3]) - (128 + 8));
+  Vec3::new(
+    (Float::from(rgbe[0]) + 0.5) * scale,
+    (Float::from(rgbe[1]) + 0.5) * scale,
+    (Float::from(rgbe[2]) + 0.5) * scale,
+  )
+}
+
+fn read_rgbe_scanline(reader: &mut impl Read, width: usize) -> Result<Vec<[u8; 4]>, Error> {
+  let mut first = [0_u8; 4];
+  reader.read_exact(&mut first)?;
+  if !(8..0x8000).contains(&width) || first[0] != 2 || first[1] != 2 || first[2] & 0x80 != 0 {
+    let mut scanline = vec![first];
+    (1..width).try_for_each(|_| {
+      let mut pixel = [0_u8; 4];
+      reader.read_exact(&mut pixel)?;
+      scanline.push(pixel);
+      Ok::<(), Error>(())
+    })?;
+    return Ok(scanline);
+  }
+  if usize::from(first[2]) << 8 | usize::from(first[3]) != width {
+    return Err("Run-length encoded scanline has the wrong width".into());
+  }
+  let mut scanline = vec![[0_u8; 4]; width];
+  (0..4).try_for_each(|channel| {
+    let mut position = 0;
+    while position < width {
+      let mut count = [0_u8; 1];
+      reader.read_exact(&mut count)?;
+      let (run, is_repeated) = if count[0] > 128 {
+        (usize::from(count[0] - 128), true)
+      } else {
+        (usize::from(count[0]), false)
+      };
+      if run == 0 || position + run > width {
+        return Err::<(), Error>("Corrupt run-length encoded scanline".into());
+      }
+      if is_repeated {
+        let mut value = [0_u8; 1];
+        reader.read_exact(&mut value)?;
+        scanline[position..position + run].iter_mut().for_each(|pixel| pixel[channel] = value[0]);
+      } else {
+        let mut values = vec![0_u8; run];
+        reader.read_exact(&mut values)?;
+        scanline[position..position + run]
+          .iter_mut()
+          .zip(values.iter())
+          .for_each(|(pixel, value)| pixel[channel] = *value);
+      }
+      position += run;
+    }
+    Ok(())
+  })?;
+  Ok(scanline)
+}
+
+/// Reads a Radiance RGBE image, returning its width, height and pixels in row-major order from the top
+fn load_hdr(path: &Path) -> Result<(usize, usize, Vec<Vec3>), Error> {
+  let mut reader = BufReader::new(File::open(path)?);
+  let mut line = String::new();
+  reader.read_line(&mut line)?;
+  if !line.starts_with("#?") {
+    return Err("Not a Radiance HDR file".into());
+  }
+  loop {
+    line.clear();
+    if reader.read_line(&mut line)? == 0 {
+      return Err("Radiance HDR header is not terminated".into());
+    }
+    let header_line = line.trim();
+    if header_line.is_empty() {
+      break;
+    }
+    if let Some(format) = header_line.strip_prefix("FORMAT=") {
+      if format != "32-bit_rle_rgbe" {
+        return Err(format!("Unsupported Radiance HDR format {format}").into());
+      }
+    }
+  }
+  line.clear();
+  reader.read_line(&mut line)?;
+  let resolution: Vec<&str> = line.split_whitespace().collect();
+  let (height, width) = match resolution.as_slice() {
+    ["-Y", height, "+X", width] => (height.parse::<usize>()?, width.parse::<usize>()?),
+    _ => return Err(format!("Unsupported Radiance HDR orientation {}", line.trim()).into()),
+  };
+  if width == 0 || height == 0 {
+    return Err("Radiance HDR image has no pixels".into());
+  }
+  let mut pixels = Vec::with_capacity(width * height);
+  (0..height).try_for_each(|_| {
+    pixels.extend(read_rgbe_scanline(&mut reader, width)?.into_iter().map(rgbe_to_vec3));
+    Ok::<(), Error>(())
+  })?;
+  Ok((width, height, pixels))
+}
+
+fn load_exr(path: &Path) -> Result<(usize, usize, Vec<Vec3>), Error> {
+  let image = exr::prelude::read_first_rgba_layer_from_file(
+    path,
+    |resolution, _| (resolution.width(), vec![Vec3::new_empty(); resolution.width() * resolution.height()]),
+    |(width, pixels), position, (r, g, b, _): (f32, f32, f32, f32)| {
+      pixels[position.y() * *width + position.x()] = Vec3::new(from_f32(r), from_f32(g), from_f32(b));
+    },
+  )?;
+  let (width, pixels) = image.layer_data.channel_data.pixels;
+  if pixels.is_empty() {
+    return Err("OpenEXR image has no pixels".into());
+  }
+  Ok((width, pixels.len() / width, pixels))
+}
+
+/// Relative luminance of a linear sRGB color
+pub fn luminance(color: &Vec3) -> Float {
+  Float::mul_add(0.0722, color.b(), Float::mul_add(0.2126, color.r(), 0.7152 * color.g()))
+}
+
+/// An equirectangular (latitude-longitude) image surrounding the scene, with +y up through the top row
+#[derive(Clone, Debug, PartialEq)]
+pub struct EnvironmentMap {
+  width: usize,
+  height: usize,
+  pixels: Vec<Vec3>,
+  rotation: Float,
+  intensity: Float,
+  distribution: Distribution2D,
+}
+
+impl EnvironmentMap {
+  /// Builds an environment map from row-major pixels, rotated by `rotation` degrees about the y axis
+  #[allow(clippy::cast_precision_loss)]
+  pub fn new(width: usize, height: usize, pixels: Vec<Vec3>, rotation: Float, intensity: Float) -> Self {
+    let weights: Vec<Float> = pixels
+      .iter()
+      .enumerate()
+      .map(|(index, pixel)| {
+        let sin_theta = (PI * ((index / width) as Float + 0.5) / height as Float).sin();
+        luminance(pixel).max(0.0) * sin_theta
+      })
+      .collect();
+    Self {
+      width,
+      height,
+      distribution: Distribution2D::new(&weights, width, height),
+      pixels,
+      rotation: rotation.to_radians(),
+      intensity,
+    }
+  }
+  /// Loads a `.hdr` or `.exr` latitude-longitude image
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if the file cannot be read or is not a supported image
+  pub fn load(path: &Path, rotation: Float, intensity: Float) -> Result<Self, Error> {
+    let extension = path.extension().and_then(std::ffi::OsStr::to_str).map(str::to_ascii_lowercase);
+    let (width, height, pixels) = match extension.as_deref() {
+      Some("hdr") => load_hdr(path)?,
+      Some("exr") => load_exr(path)?,
+      _ => return Err(format!("Unsupported environment map {}", path.display()).into()),
+    };
+    Ok(Self::new(width, height, pixels, rotation, intensity))
+  }
+  fn direction_to_uv(&self, direction: &Vec3) -> (Float, Float) {
+    let unit_direction = direction.unit_vector();
+    let theta = unit_direction.y().clamp(-1.0, 1.0).acos();
+    let phi = (unit_direction.z().atan2(unit_direction.x()) - self.rotation).rem_euclid(2.0 * PI);
+    (phi / (2.0 * PI), theta / PI)
+  }
+  fn uv_to_direction(&self, u: Float, v: Float) -> Vec3 {
+    let theta = v * PI;
+    let phi = u.mul_add(2.0 * PI, self.rotation);
+    Vec3::new(theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin())
+  }
+  #[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss, clippy::cast_sign_loss)]
+  fn lookup(&self, u: Float, v: Float) -> Vec3 {
+    let x = ((u * self.width as Float) as usize).min(self.width - 1);
+    let y = ((v * self.height as Float) as usize).min(self.height - 1);
+    self.intensity * self.pixels[y * self.width + x]
+  }
+  pub fn radiance(&self, direction: &Vec3) -> Vec3 {
+    let (u, v) = self.direction_to_uv(direction);
+    self.lookup(u, v)
+  }
+  /// Importance samples a direction proportional to luminance, returning it with its radiance and solid angle pdf
+  pub fn sample(&self, u1: Float, u2: Float) -> Option<(Vec3, Vec3, Float)> {
+    let ((u, v), uv_pdf) = self.distribution.sample_continuous(u1, u2);
+    let sin_theta = (v * PI).sin();
+    if uv_pdf <= 0.0 || sin_theta <= 0.0 {
+      return None;
+    }
+    Some((self.uv_to_direction(u, v), self.lookup(u, v), uv_pdf / (2.0 * PI * PI * sin_theta)))
+  }
+  pub fn pdf(&self, direction: &Vec3) -> Float {
+    let (u, v) = self.direction_to_uv(direction);
+    let sin_theta = (v * PI).sin();
+    if sin_theta <= 0.0 {
+      return 0.0;
+    }
+    self.distribution.pdf(u, v) / (2.0 * PI * PI * sin_theta)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  #[allow(clippy::cast_precision_loss)]
+  fn pdf_of_a_sampled_direction_is_the_density_it_was_sampled_with() {
+    let (width, height) = (8, 4);
+    let pixels = (0..width * height)
+      .map(|index| {
+        let value = ((index * 7) % 5) as Float;
+        Vec3::new(value, 0.5 * value, 1.0)
+      })
+      .collect();
+    let environment_map = EnvironmentMap::new(width, height, pixels, 30.0, 2.0);
+    let mut total = 0.0;
+    for u1 in 0..40 {
+      for u2 in 0..40 {
+        let (u1, u2) = ((u1 as Float + 0.37) / 40.0, (u2 as Float + 0.37) / 40.0);
+        let (direction, radiance, pdf) = environment_map.sample(u1, u2).unwrap();
+        let relative_error = (environment_map.pdf(&direction) - pdf).abs() / pdf;
+        assert!(
+          relative_error < 1e-3,
+          "pdf {} of the direction sampled with pdf {}",
+          environment_map.pdf(&direction),
+          pdf
+        );
+        assert!((radiance - environment_map.radiance(&direction)).length() < 1e-5);
+        total += 1.0 / pdf;
+      }
+    }
+    // The sampled directions' inverse densities average out to the solid angle of the sphere
+    let solid_angle = total / 1600.0;
+    assert!(
+      (solid_angle / (4.0 * PI) - 1.0).abs() < 0.1,
+      "sampling covers a solid angle of {}",
+      solid_angle
+    );
+  }
+
+  #[test]
+  fn an_image_without_pixels_is_an_error() {
+    let path = std::env::temp_dir().join(format!("empty-environment-{}.hdr", std::process::id()));
+    std::fs::write(&path, "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 0 +X 16\n").unwrap();
+    let result = EnvironmentMap::load(&path, 0.0, 1.0);
+    std::fs::remove_file(&path).unwrap();
+    assert!(result.is_err());
+  }
+}
@@ -0,0 +1,519 @@
+use crate::checkpoint::{read_float, read_string, read_u32, read_usize, read_vec3, write_float, write_string, write_u32, write_usize, write_vec3};
+use crate::environment::luminance;
+use crate::filter::Filter;
+use crate::float::Float;
+use crate::vec3::Vec3;
+use crate::Error;
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::ops::Range;
+#[cfg(not(feature = "f64"))]
+use std::sync::atomic::AtomicU32 as AtomicFloatBits;
+#[cfg(feature = "f64")]
+use std::sync::atomic::AtomicU64 as AtomicFloatBits;
+use std::sync::atomic::Ordering;
+
+/// Luminance added to a pixel's mean before dividing its standard error by it, so nearly black pixels are not
+/// sampled forever chasing a relative error they cannot reach
+const RELATIVE_ERROR_FLOOR: Float = 0.01;
+
+/// Radiance splatted onto each pixel, kept as the bits of floats so it can be added to from several threads at once
+#[derive(Debug, Default)]
+struct SplatBuffer {
+  components: Vec<AtomicFloatBits>,
+}
+
+impl SplatBuffer {
+  fn new(size: usize) -> Self {
+    Self {
+      components: (0..3 * size).map(|_| AtomicFloatBits::new(Float::to_bits(0.0))).collect(),
+    }
+  }
+  fn add(&self, index: usize, radiance: &Vec3) {
+    for (component, value) in self.components[3 * index..3 * index + 3]
+      .iter()
+      .zip([radiance.x(), radiance.y(), radiance.z()])
+    {
+      let mut current = component.load(Ordering::Relaxed);
+      while let Err(actual) = component.compare_exchange_weak(
+        current,
+        (Float::from_bits(current) + value).to_bits(),
+        Ordering::Relaxed,
+        Ordering::Relaxed,
+      ) {
+        current = actual;
+      }
+    }
+  }
+  fn get(&self, index: usize) -> Vec3 {
+    let component = |offset: usize| Float::from_bits(self.components[3 * index + offset].load(Ordering::Relaxed));
+    Vec3::new(component(0), component(1), component(2))
+  }
+  fn set(&mut self, index: usize, radiance: &Vec3) {
+    for (component, value) in self.components[3 * index..3 * index + 3]
+      .iter_mut()
+      .zip([radiance.x(), radiance.y(), radiance.z()])
+    {
+      *component.get_mut() = value.to_bits();
+    }
+  }
+}
+
+impl Clone for SplatBuffer {
+  fn clone(&self) -> Self {
+    Self {
+      components: self
+        .components
+        .iter()
+        .map(|component| AtomicFloatBits::new(component.load(Ordering::Relaxed)))
+        .collect(),
+    }
+  }
+}
+
+impl PartialEq for SplatBuffer {
+  fn eq(&self, other: &Self) -> bool {
+    self.components.len() == other.components.len()
+      && self
+        .components
+        .iter()
+        .zip(&other.components)
+        .all(|(first, second)| first.load(Ordering::Relaxed) == second.load(Ordering::Relaxed))
+  }
+}
+
+/// Pixels along one axis, clipped to `window`, whose centers lie within `radius` of a sample at `position`
+#[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss, clippy::cast_sign_loss)]
+fn footprint(position: Float, radius: Float, window: &Range<usize>) -> Range<usize> {
+  let first = ((position - 0.5 - radius).floor() + 1.0).max(window.start as Float) as usize;
+  let end = ((position - 0.5 + radius).floor() + 1.0).clamp(0.0, window.end as Float) as usize;
+  first..end.max(first)
+}
+
+/// Calls `add` with the index into a film covering `x_window` and `y_window` and the weight of every pixel that
+/// `filter` spreads a sample at `position` over
+#[allow(clippy::cast_precision_loss)]
+fn for_each_weight(filter: &Filter, x_window: &Range<usize>, y_window: &Range<usize>, (x, y): (Float, Float), mut add: impl FnMut(usize, Float)) {
+  let radius = filter.radius();
+  for pixel_y in footprint(y, radius, y_window) {
+    for pixel_x in footprint(x, radius, x_window) {
+      let weight = filter.evaluate((pixel_x as Float + 0.5 - x, pixel_y as Float + 0.5 - y));
+      if weight != 0.0 {
+        add((pixel_y - y_window.start) * x_window.len() + pixel_x - x_window.start, weight);
+      }
+    }
+  }
+}
+
+/// Accumulates radiance samples, spread by a reconstruction filter over the pixels around them, plus splats from light
+/// paths that can land anywhere on the image and may come from several threads at once
+///
+/// Alongside the radiance the film keeps named channels, which are filtered the same way, named data channels, which
+/// average the samples taken inside each pixel so depths and normals are never blended across edges, and named ID
+/// channels, which count how many samples of each pixel saw each ID. It also counts the samples taken inside each
+/// pixel and sums their luminance and squared luminance so the variance of its estimate can be tracked
+///
+/// A film can cover just a window of the image, as the tiles rendered in parallel do before they are merged into the
+/// film of the whole image, but pixels are always addressed by where they are in the whole image. The
+/// `samples_per_pixel` the accessors take is the average across the image, which splats are divided by and which
+/// stands in for pixels that counted no samples of their own
+#[derive(Clone, Debug, PartialEq)]
+pub struct Film {
+  filter: Filter,
+  x_window: Range<usize>,
+  y_window: Range<usize>,
+  pixels: Vec<Vec3>,
+  weights: Vec<Float>,
+  splats: SplatBuffer,
+  sample_counts: Vec<usize>,
+  luminance_sums: Vec<Float>,
+  luminance_squares: Vec<Float>,
+  channels: BTreeMap<String, Vec<Vec3>>,
+  data_channels: BTreeMap<String, Vec<Vec3>>,
+  id_channels: BTreeMap<String, Vec<Vec<(u32, usize)>>>,
+}
+
+impl Film {
+  /// A film for a `width` by `height` image that averages the samples inside each pixel
+  pub fn new(width: usize, height: usize) -> Self {
+    Self::new_window(Filter::default(), 0..width, 0..height)
+  }
+  fn new_window(filter: Filter, x_window: Range<usize>, y_window: Range<usize>) -> Self {
+    let size = x_window.len() * y_window.len();
+    Self {
+      filter,
+      x_window,
+      y_window,
+      pixels: vec![Vec3::new_empty(); size],
+      weights: vec![0.0; size],
+      splats: SplatBuffer::new(size),
+      sample_counts: vec![0; size],
+      luminance_sums: vec![0.0; size],
+      luminance_squares: vec![0.0; size],
+      channels: BTreeMap::new(),
+      data_channels: BTreeMap::new(),
+      id_channels: BTreeMap::new(),
+    }
+  }
+  /// The film reconstructing its pixels with `filter` instead
+  pub fn with_filter(mut self, filter: Filter) -> Self {
+    self.filter = filter;
+    self
+  }
+  pub fn width(&self) -> usize {
+    self.x_window.len()
+  }
+  pub fn height(&self) -> usize {
+    self.y_window.len()
+  }
+  fn index(&self, x: usize, y: usize) -> usize {
+    (y - self.y_window.start) * self.x_window.len() + x - self.x_window.start
+  }
+  /// An empty film with the same filter for rendering the pixels in `x_range` and `y_range` on their own, which also
+  /// covers the pixels around them that the filter spreads their samples over
+  #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+  pub fn tile(&self, x_range: Range<usize>, y_range: Range<usize>) -> Self {
+    let padding = (self.filter.radius() + 0.5).ceil() as usize;
+    Self::new_window(
+      self.filter,
+      x_range.start.saturating_sub(padding).max(self.x_window.start)..(x_range.end + padding).min(self.x_window.end),
+      y_range.start.saturating_sub(padding).max(self.y_window.start)..(y_range.end + padding).min(self.y_window.end),
+    )
+  }
+  /// Adds everything `tile`, which must lie within this film, has accumulated
+  pub fn merge(&mut self, tile: &Self) {
+    let indices: Vec<(usize, usize)> = tile
+      .y_window
+      .clone()
+      .flat_map(|y| tile.x_window.clone().map(move |x| (x, y)))
+      .map(|(x, y)| (self.index(x, y), tile.index(x, y)))
+      .collect();
+    for (index, tile_index) in indices.iter().copied() {
+      self.pixels[index] += tile.pixels[tile_index];
+      self.weights[index] += tile.weights[tile_index];
+      self.splats.add(index, &tile.splats.get(tile_index));
+      self.sample_counts[index] += tile.sample_counts[tile_index];
+      self.luminance_sums[index] += tile.luminance_sums[tile_index];
+      self.luminance_squares[index] += tile.luminance_squares[tile_index];
+    }
+    let size = self.pixels.len();
+    for (channels, tile_channels) in [(&mut self.channels, &tile.channels), (&mut self.data_channels, &tile.data_channels)] {
+      for (name, tile_channel) in tile_channels {
+        let channel = channels.entry(name.clone()).or_insert_with(|| vec![Vec3::new_empty(); size]);
+        for (index, tile_index) in indices.iter().copied() {
+          channel[index] += tile_channel[tile_index];
+        }
+      }
+    }
+    for (name, tile_channel) in &tile.id_channels {
+      for (index, tile_index) in indices.iter().copied() {
+        for (id, count) in &tile_channel[tile_index] {
+          self.add_id_samples(name, index, *id, *count);
+        }
+      }
+    }
+  }
+  /// Adds a sample taken at `position`, in pixels from the bottom left corner of the image, to the pixels the filter
+  /// spreads it over and counts it as a sample of the pixel it lies in, or the nearest one if it rounded onto the edge
+  #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+  pub fn add_sample(&mut self, position: (Float, Float), radiance: &Vec3) {
+    let (pixels, weights) = (&mut self.pixels, &mut self.weights);
+    for_each_weight(&self.filter, &self.x_window, &self.y_window, position, |index, weight| {
+      pixels[index] += weight * radiance;
+      weights[index] += weight;
+    });
+    let x = (position.0.max(0.0) as usize).clamp(self.x_window.start, self.x_window.end - 1);
+    let y = (position.1.max(0.0) as usize).clamp(self.y_window.start, self.y_window.end - 1);
+    let index = self.index(x, y);
+    let sample_luminance = luminance(radiance);
+    self.sample_counts[index] += 1;
+    self.luminance_sums[index] += sample_luminance;
+    self.luminance_squares[index] += sample_luminance * sample_luminance;
+  }
+  /// Adds radiance at image coordinates in the same [0, 1) range `Camera::get_ray` takes to the pixel they lie in,
+  /// which is safe to do from several threads sharing the film
+  #[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss, clippy::cast_sign_loss)]
+  pub fn add_splat(&self, (s, t): (Float, Float), radiance: &Vec3) {
+    if !(0.0..1.0).contains(&s) || !(0.0..1.0).contains(&t) {
+      return;
+    }
+    let x = ((s * self.width() as Float) as usize).min(self.width() - 1);
+    let y = ((t * self.height() as Float) as usize).min(self.height() - 1);
+    self.splats.add(self.index(x, y), radiance);
+  }
+  /// Replaces what the pixel `x` from the left and `y` from the bottom has accumulated with `radiance`, estimated
+  /// from `samples_per_pixel` samples, for integrators that refine a per-pixel estimate rather than summing samples
+  pub fn set_pixel(&mut self, x: usize, y: usize, radiance: &Vec3, samples_per_pixel: usize) {
+    let index = self.index(x, y);
+    self.pixels[index] = *radiance;
+    self.weights[index] = 1.0;
+    self.splats.set(index, &Vec3::new_empty());
+    self.sample_counts[index] = samples_per_pixel;
+    self.luminance_sums[index] = 0.0;
+    self.luminance_squares[index] = 0.0;
+  }
+  /// Number of samples taken inside the pixel `x` from the left and `y` from the bottom
+  pub fn sample_count(&self, x: usize, y: usize) -> usize {
+    self.sample_counts[self.index(x, y)]
+  }
+  pub fn total_sample_count(&self) -> usize {
+    self.sample_counts.iter().sum()
+  }
+  pub fn max_sample_count(&self) -> usize {
+    self.sample_counts.iter().copied().max().unwrap_or(0)
+  }
+  /// Samples taken per pixel across the image, rounded to the nearest whole sample
+  #[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss, clippy::cast_sign_loss)]
+  pub fn average_sample_count(&self) -> usize {
+    (self.total_sample_count() as Float / self.sample_counts.len() as Float).round() as usize
+  }
+  #[allow(clippy::cast_precision_loss)]
+  fn samples(&self, index: usize, samples_per_pixel: usize) -> Float {
+    match self.sample_counts[index] {
+      0 => samples_per_pixel as Float,
+      count => count as Float,
+    }
+  }
+  /// Estimated radiance of the pixel `x` from the left and `y` from the bottom after an average of
+  /// `samples_per_pixel` samples
+  #[allow(clippy::cast_precision_loss)]
+  pub fn pixel(&self, x: usize, y: usize, samples_per_pixel: usize) -> Vec3 {
+    let index = self.index(x, y);
+    let filtered = if self.weights[index] == 0.0 {
+      Vec3::new_empty()
+    } else {
+      self.pixels[index] / self.weights[index]
+    };
+    filtered + self.splats.get(index) / samples_per_pixel as Float
+  }
+  /// Estimated variance of the mean luminance of the samples taken inside the pixel `x` from the left and `y` from
+  /// the bottom, zero if there are too few to tell or it was set with `set_pixel`
+  #[allow(clippy::cast_precision_loss)]
+  pub fn pixel_variance(&self, x: usize, y: usize) -> Float {
+    let index = self.index(x, y);
+    let count = self.sample_counts[index];
+    if count < 2 || self.luminance_squares[index] == 0.0 {
+      return 0.0;
+    }
+    let count = count as Float;
+    let mean = self.luminance_sums[index] / count;
+    mean.mul_add(-mean, self.luminance_squares[index] / count).max(0.0) / (count - 1.0)
+  }
+  /// Standard error of the mean luminance of the pixel `x` from the left and `y` from the bottom relative to that mean
+  #[allow(clippy::cast_precision_loss)]
+  pub fn relative_error(&self, x: usize, y: usize) -> Float {
+    let index = self.index(x, y);
+    let mean = self.luminance_sums[index] / self.sample_counts[index].max(1) as Float;
+    self.pixel_variance(x, y).sqrt() / (mean + RELATIVE_ERROR_FLOOR)
+  }
+  /// Relative error of the pixels averaged across the image, an estimate of how noisy it still looks
+  #[allow(clippy::cast_precision_loss)]
+  pub fn mean_relative_error(&self) -> Float {
+    let mut total = 0.0;
+    for y in self.y_window.clone() {
+      for x in self.x_window.clone() {
+        total += self.relative_error(x, y);
+      }
+    }
+    total / self.pixels.len() as Float
+  }
+  /// Adds a sample taken at `position` to the channel `name`, filtered like the radiance, creating it if needed
+  pub fn add_channel_sample(&mut self, name: &str, position: (Float, Float), value: &Vec3) {
+    let size = self.pixels.len();
+    if !self.channels.contains_key(name) {
+      self.channels.insert(name.to_string(), vec![Vec3::new_empty(); size]);
+    }
+    if let Some(channel) = self.channels.get_mut(name) {
+      for_each_weight(&self.filter, &self.x_window, &self.y_window, position, |index, weight| {
+        channel[index] += weight * value;
+      });
+    }
+  }
+  /// Adds a sample to the pixel `x` from the left and `y` from the bottom of the data channel `name`, creating it if
+  /// needed
+  pub fn add_data_sample(&mut self, name: &str, x: usize, y: usize, value: &Vec3) {
+    let (size, index) = (self.pixels.len(), self.index(x, y));
+    let channel = self
+      .data_channels
+      .entry(name.to_string())
+      .or_insert_with(|| vec![Vec3::new_empty(); size]);
+    channel[index] += *value;
+  }
+  /// Counts a sample of the pixel `x` from the left and `y` from the bottom that saw `id` in the ID channel `name`
+  pub fn add_id_sample(&mut self, name: &str, x: usize, y: usize, id: u32) {
+    self.add_id_samples(name, self.index(x, y), id, 1);
+  }
+  fn add_id_samples(&mut self, name: &str, index: usize, id: u32, samples: usize) {
+    let size = self.pixels.len();
+    let counts = &mut self.id_channels.entry(name.to_string()).or_insert_with(|| vec![Vec::new(); size])[index];
+    match counts.iter_mut().find(|(existing, _)| *existing == id) {
+      Some((_, count)) => *count += samples,
+      None => counts.push((id, samples)),
+    }
+  }
+  /// Names of the filtered and data channels
+  pub fn channel_names(&self) -> impl Iterator<Item = &str> {
+    self.channels.keys().chain(self.data_channels.keys()).map(String::as_str)
+  }
+  pub fn id_channel_names(&self) -> impl Iterator<Item = &str> {
+    self.id_channels.keys().map(String::as_str)
+  }
+  /// Value of the pixel `x` from the left and `y` from the bottom in the filtered or data channel `name`, black if
+  /// there is no such channel
+  pub fn channel_pixel(&self, name: &str, x: usize, y: usize, samples_per_pixel: usize) -> Vec3 {
+    let index = self.index(x, y);
+    if let Some(channel) = self.channels.get(name) {
+      return if self.weights[index] == 0.0 {
+        Vec3::new_empty()
+      } else {
+        channel[index] / self.weights[index]
+      };
+    }
+    self
+      .data_channels
+      .get(name)
+      .map_or_else(Vec3::new_empty, |channel| channel[index] / self.samples(index, samples_per_pixel))
+  }
+  /// IDs seen by the pixel `x` from the left and `y` from the bottom in the ID channel `name` with the fraction of
+  /// its samples that saw each, most covered first
+  #[allow(clippy::cast_precision_loss)]
+  pub fn id_coverage(&self, name: &str, x: usize, y: usize, samples_per_pixel: usize) -> Vec<(u32, Float)> {
+    let index = self.index(x, y);
+    let mut coverage: Vec<(u32, Float)> = self.id_channels.get(name).map_or_else(Vec::new, |channel| {
+      channel[index]
+        .iter()
+        .map(|(id, count)| (*id, *count as Float / self.samples(index, samples_per_pixel)))
+        .collect()
+    });
+    coverage.sort_by(|first, second| second.1.total_cmp(&first.1).then(first.0.cmp(&second.0)));
+    coverage
+  }
+  /// Writes everything the film of a whole image has accumulated in the binary layout `read` expects
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if writing to `output` fails
+  pub fn write(&self, output: &mut impl Write) -> Result<(), Error> {
+    self.filter.write(output)?;
+    write_usize(output, self.width())?;
+    write_usize(output, self.height())?;
+    for index in 0..self.pixels.len() {
+      write_vec3(output, &self.pixels[index])?;
+      write_float(output, self.weights[index])?;
+      write_vec3(output, &self.splats.get(index))?;
+      write_usize(output, self.sample_counts[index])?;
+      write_float(output, self.luminance_sums[index])?;
+      write_float(output, self.luminance_squares[index])?;
+    }
+    for channels in [&self.channels, &self.data_channels] {
+      write_usize(output, channels.len())?;
+      for (name, channel) in channels {
+        write_string(output, name)?;
+        for value in channel {
+          write_vec3(output, value)?;
+        }
+      }
+    }
+    write_usize(output, self.id_channels.len())?;
+    for (name, channel) in &self.id_channels {
+      write_string(output, name)?;
+      for counts in channel {
+        write_usize(output, counts.len())?;
+        for (id, count) in counts {
+          write_u32(output, *id)?;
+          write_usize(output, *count)?;
+        }
+      }
+    }
+    Ok(())
+  }
+  /// Reads a film written by `write`
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if reading from `input` fails or it holds no film
+  pub fn read(input: &mut impl Read) -> Result<Self, Error> {
+    let filter = Filter::read(input)?;
+    let mut film = Self::new(read_usize(input)?, read_usize(input)?).with_filter(filter);
+    let size = film.pixels.len();
+    for index in 0..size {
+      film.pixels[index] = read_vec3(input)?;
+      film.weights[index] = read_float(input)?;
+      film.splats.set(index, &read_vec3(input)?);
+      film.sample_counts[index] = read_usize(input)?;
+      film.luminance_sums[index] = read_float(input)?;
+      film.luminance_squares[index] = read_float(input)?;
+    }
+    for channels in [&mut film.channels, &mut film.data_channels] {
+      for _ in 0..read_usize(input)? {
+        let name = read_string(input)?;
+        let channel = (0..size).map(|_| read_vec3(input)).collect::<Result<_, _>>()?;
+        channels.insert(name, channel);
+      }
+    }
+    for _ in 0..read_usize(input)? {
+      let name = read_string(input)?;
+      let channel = (0..size)
+        .map(|_| (0..read_usize(input)?).map(|_| Ok((read_u32(input)?, read_usize(input)?))).collect())
+        .collect::<Result<_, Error>>()?;
+      film.id_channels.insert(name, channel);
+    }
+    Ok(film)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::float::random_float;
+
+  #[test]
+  #[allow(clippy::cast_precision_loss)]
+  fn tiles_merge_into_the_same_film_as_one_pass() {
+    let filter = Filter::Mitchell {
+      radius: 2.0,
+      b: 1.0 / 3.0,
+      c: 1.0 / 3.0,
+    };
+    let mut whole = Film::new(10, 6).with_filter(filter);
+    let mut merged = Film::new(10, 6).with_filter(filter);
+    let mut tiles = [merged.tile(0..5, 0..6), merged.tile(5..10, 0..6)];
+    fastrand::seed(3);
+    for y in 0..6 {
+      for x in 0..10 {
+        let position = (x as Float + random_float(), y as Float + random_float());
+        let radiance = Vec3::new(random_float(), random_float(), random_float());
+        whole.add_sample(position, &radiance);
+        tiles[x / 5].add_sample(position, &radiance);
+      }
+    }
+    for tile in &tiles {
+      merged.merge(tile);
+    }
+    for y in 0..6 {
+      for x in 0..10 {
+        let difference = (whole.pixel(x, y, 1) - merged.pixel(x, y, 1)).length();
+        assert!(difference < 1e-5, "pixel ({}, {}) differs by {}", x, y, difference);
+      }
+      assert_eq!(whole.sample_count(0, y), merged.sample_count(0, y));
+    }
+  }
+
+  #[test]
+  fn wide_filters_blur_a_sharp_edge_and_box_filter_does_not() {
+    let edge = |filter: Filter| {
+      let mut film = Film::new(8, 1).with_filter(filter);
+      for x in 0..8 {
+        for sample in 0..16 {
+          #[allow(clippy::cast_precision_loss)]
+          let position = (x as Float + (sample as Float + 0.5) / 16.0, 0.5);
+          film.add_sample(position, &if x < 4 { Vec3::new_empty() } else { Vec3::new(1.0, 1.0, 1.0) });
+        }
+      }
+      film.pixel(3, 0, 16).x()
+    };
+    assert!(edge(Filter::default()) < 1e-6);
+    assert!(edge(Filter::Tent { radius: 1.0 }) > 0.05);
+    assert!(edge(Filter::Gaussian { radius: 1.5, sigma: 0.5 }) > 0.05);
+  }
+}
@@ -0,0 +1,224 @@
+use crate::checkpoint::{read_float, read_u32, write_float, write_u32};
+use crate::float::consts::PI;
+use crate::float::Float;
+use crate::Error;
+use std::io::{Read, Write};
+
+/// How a sample is weighted in each pixel whose center lies within the filter's radius of it, in pixels
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Filter {
+  /// Equal weight within the radius, which with the default half-pixel radius averages the samples inside each pixel
+  Box { radius: Float },
+  /// Weight falling linearly to zero at the radius
+  Tent { radius: Float },
+  /// A Gaussian of standard deviation `sigma`, shifted down to reach zero at the radius
+  Gaussian { radius: Float, sigma: Float },
+  /// The Mitchell–Netravali cubic, whose small negative lobes sharpen edges that a Gaussian would blur
+  Mitchell { radius: Float, b: Float, c: Float },
+  /// A sinc windowed by a sinc `tau` times wider, the sharpest filter here but the most prone to ringing
+  Lanczos { radius: Float, tau: Float },
+}
+
+impl Default for Filter {
+  fn default() -> Self {
+    Self::Box { radius: 0.5 }
+  }
+}
+
+fn sinc(x: Float) -> Float {
+  if x.abs() < 1e-5 {
+    1.0
+  } else {
+    (PI * x).sin() / (PI * x)
+  }
+}
+
+/// The Mitchell–Netravali cubic at `x` in [0, 2]
+fn mitchell(x: Float, b: Float, c: Float) -> Float {
+  let (cubic, quadratic, linear, constant) = if x > 1.0 {
+    (
+      Float::mul_add(6.0, -c, -b),
+      Float::mul_add(6.0, b, 30.0 * c),
+      Float::mul_add(-12.0, b, -48.0 * c),
+      Float::mul_add(8.0, b, 24.0 * c),
+    )
+  } else {
+    (
+      Float::mul_add(9.0, -b, Float::mul_add(6.0, -c, 12.0)),
+      Float::mul_add(12.0, b, Float::mul_add(6.0, c, -18.0)),
+      0.0,
+      Float::mul_add(2.0, -b, 6.0),
+    )
+  };
+  cubic.mul_add(x, quadratic).mul_add(x, linear).mul_add(x, constant) / 6.0
+}
+
+impl Filter {
+  pub fn radius(&self) -> Float {
+    match *self {
+      Self::Box { radius } | Self::Tent { radius } | Self::Gaussian { radius, .. } | Self::Mitchell { radius, .. } | Self::Lanczos { radius, .. } => {
+        radius
+      }
+    }
+  }
+  /// Weight along one axis of a sample `offset` pixels from a pixel center
+  fn evaluate_axis(&self, offset: Float) -> Float {
+    let offset = offset.abs();
+    match *self {
+      Self::Box { radius } => {
+        if offset <= radius {
+          1.0
+        } else {
+          0.0
+        }
+      }
+      Self::Tent { radius } => (radius - offset).max(0.0),
+      Self::Gaussian { radius, sigma } => {
+        let gaussian = |x: Float| (-x * x / (2.0 * sigma * sigma)).exp();
+        (gaussian(offset) - gaussian(radius)).max(0.0)
+      }
+      Self::Mitchell { radius, b, c } => {
+        if offset >= radius {
+          0.0
+        } else {
+          mitchell(2.0 * offset / radius, b, c)
+        }
+      }
+      Self::Lanczos { radius, tau } => {
+        if offset >= radius {
+          0.0
+        } else {
+          sinc(offset) * sinc(offset / tau)
+        }
+      }
+    }
+  }
+  /// Weight of a sample `offset` pixels from a pixel center
+  pub fn evaluate(&self, (x, y): (Float, Float)) -> Float {
+    self.evaluate_axis(x) * self.evaluate_axis(y)
+  }
+  /// Writes the filter as a tag followed by its parameters, in the binary layout `read` expects
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if writing to `output` fails
+  pub fn write(&self, output: &mut impl Write) -> Result<(), Error> {
+    let (tag, parameters): (u32, &[Float]) = match self {
+      Self::Box { radius } => (0, &[*radius]),
+      Self::Tent { radius } => (1, &[*radius]),
+      Self::Gaussian { radius, sigma } => (2, &[*radius, *sigma]),
+      Self::Mitchell { radius, b, c } => (3, &[*radius, *b, *c]),
+      Self::Lanczos { radius, tau } => (4, &[*radius, *tau]),
+    };
+    write_u32(output, tag)?;
+    for parameter in parameters {
+      write_float(output, *parameter)?;
+    }
+    Ok(())
+  }
+  /// Reads a filter written by `write`
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if reading from `input` fails or it holds no filter
+  pub fn read(input: &mut impl Read) -> Result<Self, Error> {
+    Ok(match read_u32(input)? {
+      0 => Self::Box { radius: read_float(input)? },
+      1 => Self::Tent { radius: read_float(input)? },
+      2 => Self::Gaussian {
+        radius: read_float(input)?,
+        sigma: read_float(input)?,
+      },
+      3 => Self::Mitchell {
+        radius: read_float(input)?,
+        b: read_float(input)?,
+        c: read_float(input)?,
+      },
+      4 => Self::Lanczos {
+        radius: read_float(input)?,
+        tau: read_float(input)?,
+      },
+      tag => return Err(format!("Unknown filter tag {tag}").into()),
+    })
+  }
+}
+
+/// Parses a filter name as given on the command line, using `radius` in place of the filter's usual radius if given
+///
+/// # Errors
+///
+/// Returns an error if `name` is not a filter
+pub fn parse_filter(name: &str, radius: Option<Float>) -> Result<Filter, Error> {
+  Ok(match name {
+    "box" => Filter::Box {
+      radius: radius.unwrap_or(0.5),
+    },
+    "tent" => Filter::Tent {
+      radius: radius.unwrap_or(1.0),
+    },
+    "gaussian" => Filter::Gaussian {
+      radius: radius.unwrap_or(1.5),
+      sigma: 0.5,
+    },
+    "mitchell" => Filter::Mitchell {
+      radius: radius.unwrap_or(2.0),
+      b: 1.0 / 3.0,
+      c: 1.0 / 3.0,
+    },
+    "lanczos" => Filter::Lanczos {
+      radius: radius.unwrap_or(3.0),
+      tau: 3.0,
+    },
+    _ => return Err(format!("Unknown filter {name}").into()),
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Sum of the weights along one axis of the pixels a sample `offset` pixels from a pixel center reaches
+  #[allow(clippy::cast_precision_loss)]
+  fn weight_sum(filter: &Filter, offset: Float) -> Float {
+    (-4..=4).map(|pixel| filter.evaluate_axis(offset + pixel as Float)).sum()
+  }
+
+  #[test]
+  fn filter_weights_peak_at_the_center_and_vanish_past_the_radius() {
+    for name in ["box", "tent", "gaussian", "mitchell", "lanczos"] {
+      let filter = parse_filter(name, None).unwrap();
+      let radius = filter.radius();
+      assert!(filter.evaluate((0.0, 0.0)) > 0.0);
+      assert!(filter.evaluate((0.3, -0.2)) <= filter.evaluate((0.0, 0.0)), "{} peaks off center", name);
+      assert!((filter.evaluate((0.3, -0.2)) - filter.evaluate((-0.3, 0.2))).abs() < 1e-6);
+      assert!(filter.evaluate((radius + 0.01, 0.0)) == 0.0 && filter.evaluate((0.0, -radius - 0.01)) == 0.0);
+      let mut written = Vec::new();
+      filter.write(&mut written).unwrap();
+      assert_eq!(Filter::read(&mut written.as_slice()).unwrap(), filter);
+    }
+    assert!(parse_filter("sharpest", None).is_err());
+  }
+
+  #[test]
+  fn tent_and_mitchell_weights_sum_to_one_wherever_a_sample_falls() {
+    // With B + 2C = 1 the Mitchell–Netravali cubic passes a constant image through unchanged, as a one pixel tent does
+    let filters = [parse_filter("tent", None).unwrap(), parse_filter("mitchell", None).unwrap()];
+    for filter in &filters {
+      for offset in [0.0, 0.1, 0.25, 0.5, 0.8] {
+        assert!(
+          (weight_sum(filter, offset) - 1.0).abs() < 1e-5,
+          "{:?} sums to {} at {}",
+          filter,
+          weight_sum(filter, offset),
+          offset
+        );
+      }
+    }
+    // The Lanczos filter interpolates, weighing only the pixel a sample is centered on
+    let lanczos = parse_filter("lanczos", None).unwrap();
+    assert!((lanczos.evaluate_axis(0.0) - 1.0).abs() < 1e-6);
+    for pixel in 1..3_u8 {
+      assert!(lanczos.evaluate_axis(Float::from(pixel)).abs() < 1e-6);
+    }
+  }
+}
@@ -0,0 +1,68 @@
+//! The scalar type geometry and shading are computed in, `f32` by default or `f64` with the `f64` feature
+
+#[cfg(not(feature = "f64"))]
+pub use std::f32::consts;
+#[cfg(feature = "f64")]
+pub use std::f64::consts;
+
+#[cfg(not(feature = "f64"))]
+pub type Float = f32;
+#[cfg(feature = "f64")]
+pub type Float = f64;
+
+/// Uniformly distributed random number in [0, 1)
+#[cfg(not(feature = "f64"))]
+pub fn random_float() -> Float {
+  fastrand::f32()
+}
+/// Uniformly distributed random number in [0, 1), drawn in single precision so both builds make the same random
+/// choices from the same seed
+#[cfg(feature = "f64")]
+pub fn random_float() -> Float {
+  Float::from(fastrand::f32())
+}
+
+/// Rounds `value` to single precision for output formats that store `f32`
+#[cfg(not(feature = "f64"))]
+pub fn to_f32(value: Float) -> f32 {
+  value
+}
+/// Rounds `value` to single precision for output formats that store `f32`
+#[cfg(feature = "f64")]
+#[allow(clippy::cast_possible_truncation)]
+pub fn to_f32(value: Float) -> f32 {
+  value as f32
+}
+
+/// Widens a single precision value read from a file
+#[cfg(not(feature = "f64"))]
+pub fn from_f32(value: f32) -> Float {
+  value
+}
+/// Widens a single precision value read from a file
+#[cfg(feature = "f64")]
+pub fn from_f32(value: f32) -> Float {
+  Float::from(value)
+}
+
+/// Bound on the relative error of `n` rounded floating point operations, pbrt's gamma
+pub fn gamma(n: u8) -> Float {
+  let error = Float::from(n) * Float::EPSILON * 0.5;
+  error / (1.0 - error)
+}
+
+/// The smallest float greater than `value`
+pub fn next_float_up(value: Float) -> Float {
+  if value.is_infinite() && value > 0.0 {
+    return value;
+  }
+  // Treat -0 as 0 so the step goes up to the smallest positive float rather than down from the sign bit
+  let value = if value == 0.0 { 0.0 } else { value };
+  let bits = value.to_bits();
+  Float::from_bits(if value >= 0.0 { bits + 1 } else { bits - 1 })
+}
+
+/// The largest float less than `value`
+pub fn next_float_down(value: Float) -> Float {
+  -next_float_up(-value)
+}
@@ -0,0 +1,58 @@
+use crate::film::Film;
+use crate::vec3::Vec3;
+use crate::Error;
+use std::io::Write;
+
+/// Final linear RGB color of every pixel of a rendered image
+#[derive(Clone, Debug, PartialEq)]
+pub struct Framebuffer {
+  width: usize,
+  height: usize,
+  pixels: Vec<Vec3>,
+}
+
+impl Framebuffer {
+  /// Resolves the pixels of `film` after an average of `samples_per_pixel` samples
+  pub fn from_film(film: &Film, samples_per_pixel: usize) -> Self {
+    let (width, height) = (film.width(), film.height());
+    let pixels = (0..height)
+      .flat_map(|y| (0..width).map(move |x| (x, y)))
+      .map(|(x, y)| film.pixel(x, y, samples_per_pixel))
+      .collect();
+    Self { width, height, pixels }
+  }
+  pub fn width(&self) -> usize {
+    self.width
+  }
+  pub fn height(&self) -> usize {
+    self.height
+  }
+  /// Color of the pixel `x` from the left and `y` from the bottom
+  pub fn pixel(&self, x: usize, y: usize) -> &Vec3 {
+    &self.pixels[y * self.width + x]
+  }
+  /// Writes the image as a plain PPM, top row first, square-root gamma encoded for display if `gamma_encode`
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if writing to `output` fails
+  #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+  pub fn write_ppm(&self, gamma_encode: bool, output: &mut impl Write) -> Result<(), Error> {
+    writeln!(output, "P3\n{} {}\n255", self.width, self.height)?;
+    for current_y_pixel in (0..self.height).rev() {
+      for current_x_pixel in 0..self.width {
+        let pixel_color = self.pixel(current_x_pixel, current_y_pixel);
+        let pixel_color = if gamma_encode {
+          Vec3::new(pixel_color.x().sqrt(), pixel_color.y().sqrt(), pixel_color.z().sqrt())
+        } else {
+          *pixel_color
+        };
+        let red_value = (255.99 * pixel_color.r().min(1.0)) as usize;
+        let green_value = (255.99 * pixel_color.g().min(1.0)) as usize;
+        let blue_value = (255.99 * pixel_color.b().min(1.0)) as usize;
+        writeln!(output, "{red_value} {green_value} {blue_value}")?;
+      }
+    }
+    Ok(())
+  }
+}
@@ -1,46 +1,115 @@
+use crate::aabb::Aabb;
+use crate::bvh::Bvh;
+use crate::float::Float;
 use crate::material::Material;
 use crate::ray::Ray;
 use crate::vec3::Vec3;
+use std::fmt::Debug;
 use std::sync::Arc;
 
 #[allow(clippy::module_name_repetitions)]
 #[derive(Clone, Debug)]
 pub struct HitRecord {
-  scalar_from_ray_origin: f32,
+  scalar_from_ray_origin: Float,
   point: Vec3,
+  error: Vec3,
   normal: Vec3,
   material: Arc<dyn Material>,
+  light: Option<usize>,
+  uv: (Float, Float),
+  object_id: u32,
 }
 
 impl HitRecord {
-  pub fn new(scalar_from_ray_origin: f32, point: &Vec3, normal: &Vec3, material: Arc<dyn Material>) -> Self {
+  pub fn new(scalar_from_ray_origin: Float, point: &Vec3, normal: &Vec3, material: Arc<dyn Material>) -> Self {
     Self {
       scalar_from_ray_origin,
       point: *point,
+      error: Vec3::new_empty(),
       normal: *normal,
       material,
+      light: None,
+      uv: (0.0, 0.0),
+      object_id: 0,
     }
   }
-  pub fn scalar_from_ray_origin(&self) -> f32 {
+  /// Sets a bound on the rounding error in each coordinate of the hit point
+  pub fn with_error(mut self, error: &Vec3) -> Self {
+    self.error = *error;
+    self
+  }
+  /// Sets the surface coordinates of the hit, each in [0, 1]
+  pub fn with_uv(mut self, u: Float, v: Float) -> Self {
+    self.uv = (u, v);
+    self
+  }
+  /// Marks the hit surface as the emitter of the scene light at `light`
+  pub fn with_light(mut self, light: Option<usize>) -> Self {
+    self.light = light;
+    self
+  }
+  /// Tags the hit with an ID shared by every hit on the same object
+  pub fn with_object_id(mut self, object_id: u32) -> Self {
+    self.object_id = object_id;
+    self
+  }
+  pub fn scalar_from_ray_origin(&self) -> Float {
     self.scalar_from_ray_origin
   }
   pub fn point(&self) -> &Vec3 {
     &self.point
   }
+  /// Bound on the rounding error in each coordinate of `point`
+  pub fn error(&self) -> &Vec3 {
+    &self.error
+  }
   pub fn normal(&self) -> &Vec3 {
     &self.normal
   }
+  /// Ray leaving the hit along `direction` that cannot hit the same surface at the same point
+  pub fn spawn_ray(&self, direction: &Vec3) -> Ray {
+    Ray::spawn(&self.point, &self.error, &self.normal, direction)
+  }
+  /// Ray leaving the hit that reaches `target` at a parameter of one
+  pub fn spawn_ray_to(&self, target: &Vec3) -> Ray {
+    Ray::spawn_to(&self.point, &self.error, &self.normal, target)
+  }
   pub fn material(&self) -> Arc<dyn Material> {
     self.material.clone()
   }
+  pub fn light(&self) -> Option<usize> {
+    self.light
+  }
+  pub fn uv(&self) -> (Float, Float) {
+    self.uv
+  }
+  pub fn object_id(&self) -> u32 {
+    self.object_id
+  }
 }
 
-pub trait Hittable {
-  fn is_hit(&self, ray: &Ray, scalar_from_ray_origin_min: f32, scalar_from_ray_origin_max: f32) -> Option<HitRecord>;
+pub trait Hittable: Debug + Send + Sync {
+  fn is_hit(&self, ray: &Ray, scalar_from_ray_origin_min: Float, scalar_from_ray_origin_max: Float) -> Option<HitRecord>;
+  /// Closest hit of each of `rays` between the given scalars, which hittables can find for coherent rays together
+  fn is_hit_packet(&self, rays: &[Ray], scalar_from_ray_origin_min: Float, scalar_from_ray_origin_max: Float) -> Vec<Option<HitRecord>> {
+    rays
+      .iter()
+      .map(|ray| self.is_hit(ray, scalar_from_ray_origin_min, scalar_from_ray_origin_max))
+      .collect()
+  }
+  /// Box enclosing everything the hittable can be hit at, or None if it is unbounded
+  fn bounding_box(&self) -> Option<Aabb>;
 }
 
+#[derive(Debug)]
 pub struct HittableList(Vec<Box<dyn Hittable>>);
 
+impl Default for HittableList {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
 impl HittableList {
   pub fn new() -> Self {
     Self(Vec::<Box<dyn Hittable>>::new())
@@ -51,10 +120,18 @@ impl HittableList {
   pub fn list_mut(&mut self) -> &mut Vec<Box<dyn Hittable>> {
     &mut self.0
   }
+  /// Moves every bounded hittable in the list into a BVH, leaving unbounded ones to be tested one by one
+  pub fn build_bvh(&mut self) {
+    let (bounded, mut unbounded): (Vec<_>, Vec<_>) = self.0.drain(..).partition(|hittable| hittable.bounding_box().is_some());
+    if !bounded.is_empty() {
+      unbounded.push(Box::new(Bvh::new(bounded)));
+    }
+    self.0 = unbounded;
+  }
 }
 
 impl Hittable for HittableList {
-  fn is_hit(&self, ray: &Ray, scalar_from_ray_origin_min: f32, scalar_from_ray_origin_max: f32) -> Option<HitRecord> {
+  fn is_hit(&self, ray: &Ray, scalar_from_ray_origin_min: Float, scalar_from_ray_origin_max: Float) -> Option<HitRecord> {
     let mut closet_so_far = scalar_from_ray_origin_max;
     self.list().iter().fold(None::<HitRecord>, |accumulator, current_hittable| {
       current_hittable
@@ -65,4 +142,25 @@ impl Hittable for HittableList {
         })
     })
   }
+  fn is_hit_packet(&self, rays: &[Ray], scalar_from_ray_origin_min: Float, scalar_from_ray_origin_max: Float) -> Vec<Option<HitRecord>> {
+    self.list().iter().fold(vec![None; rays.len()], |closest, current_hittable| {
+      closest
+        .into_iter()
+        .zip(current_hittable.is_hit_packet(rays, scalar_from_ray_origin_min, scalar_from_ray_origin_max))
+        .map(|(closest, hit)| match (closest, hit) {
+          (Some(closest), Some(hit)) if hit.scalar_from_ray_origin >= closest.scalar_from_ray_origin => Some(closest),
+          (closest, None) => closest,
+          (_, hit) => hit,
+        })
+        .collect()
+    })
+  }
+  fn bounding_box(&self) -> Option<Aabb> {
+    self
+      .list()
+      .iter()
+      .map(|hittable| hittable.bounding_box())
+      .reduce(|total, bounds| total.zip(bounds).map(|(total, bounds)| total.union(&bounds)))
+      .flatten()
+  }
 }
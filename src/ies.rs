@@ -0,0 +1,146 @@
+use crate::float::Float;
+use crate::Error;
+use std::fs;
+use std::path::Path;
+
+fn find_interval(angles: &[Float], angle: Float) -> (usize, Float) {
+  if angles.len() < 2 {
+    return (0, 0.0);
+  }
+  let index = angles[1..angles.len() - 1].partition_point(|value| *value <= angle);
+  let span = angles[index + 1] - angles[index];
+  let fraction = if span > 0.0 {
+    ((angle - angles[index]) / span).clamp(0.0, 1.0)
+  } else {
+    0.0
+  };
+  (index, fraction)
+}
+
+/// A goniometric intensity distribution read from an IESNA LM-63 photometric file, normalized to a peak of one
+#[derive(Clone, Debug, PartialEq)]
+pub struct IesProfile {
+  vertical_angles: Vec<Float>,
+  horizontal_angles: Vec<Float>,
+  candela: Vec<Float>,
+}
+
+impl IesProfile {
+  /// Loads an `.ies` file
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if the file cannot be read, uses a tilt file, or its photometric data is malformed
+  pub fn load(path: &Path) -> Result<Self, Error> {
+    Self::parse(&fs::read_to_string(path)?)
+  }
+  /// Parses the contents of an `.ies` file
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if the contents use a tilt file or the photometric data is malformed
+  #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+  pub fn parse(contents: &str) -> Result<Self, Error> {
+    let mut lines = contents.lines();
+    let tilt = lines
+      .by_ref()
+      .find_map(|line| line.trim().strip_prefix("TILT="))
+      .ok_or("IES file is missing its TILT line")?;
+    if tilt.trim() != "NONE" {
+      return Err("IES files with tilt data are not supported".into());
+    }
+    let values = lines
+      .flat_map(|line| line.split(|character: char| character.is_whitespace() || character == ','))
+      .filter(|token| !token.is_empty())
+      .map(str::parse::<Float>)
+      .collect::<Result<Vec<Float>, _>>()?;
+    if values.len() < 13 {
+      return Err("IES file is missing photometric data".into());
+    }
+    let multiplier = values[2];
+    let vertical_count = values[3] as usize;
+    let horizontal_count = values[4] as usize;
+    let start = 13;
+    if vertical_count == 0 || horizontal_count == 0 || values.len() < start + vertical_count + horizontal_count * (vertical_count + 1) {
+      return Err("IES file has too few angles or candela values".into());
+    }
+    let vertical_angles = values[start..start + vertical_count].to_vec();
+    let horizontal_angles = values[start + vertical_count..start + vertical_count + horizontal_count].to_vec();
+    let candela_start = start + vertical_count + horizontal_count;
+    let mut candela: Vec<Float> = values[candela_start..candela_start + vertical_count * horizontal_count]
+      .iter()
+      .map(|value| value * multiplier)
+      .collect();
+    let peak = candela.iter().fold(0.0, |peak: Float, value| peak.max(*value));
+    if peak > 0.0 {
+      for value in &mut candela {
+        *value /= peak;
+      }
+    }
+    Ok(Self {
+      vertical_angles,
+      horizontal_angles,
+      candela,
+    })
+  }
+  /// Relative intensity at `vertical` degrees from the photometric nadir and `horizontal` degrees around it
+  pub fn intensity(&self, vertical: Float, horizontal: Float) -> Float {
+    let last_horizontal = *self.horizontal_angles.last().unwrap_or(&0.0);
+    let horizontal = horizontal.rem_euclid(360.0);
+    let horizontal = if last_horizontal <= 0.0 {
+      0.0
+    } else if last_horizontal <= 90.0 {
+      let folded = horizontal % 180.0;
+      if folded > 90.0 {
+        180.0 - folded
+      } else {
+        folded
+      }
+    } else if last_horizontal <= 180.0 && horizontal > 180.0 {
+      360.0 - horizontal
+    } else {
+      horizontal
+    };
+    let vertical_count = self.vertical_angles.len();
+    let (vertical_index, vertical_fraction) = find_interval(&self.vertical_angles, vertical);
+    let (horizontal_index, horizontal_fraction) = find_interval(&self.horizontal_angles, horizontal);
+    let value = |horizontal: usize, vertical: usize| {
+      let horizontal = horizontal.min(self.horizontal_angles.len() - 1);
+      let vertical = vertical.min(vertical_count - 1);
+      self.candela[horizontal * vertical_count + vertical]
+    };
+    if vertical < self.vertical_angles[0] || vertical > self.vertical_angles[vertical_count - 1] {
+      return 0.0;
+    }
+    let lower = (1.0 - vertical_fraction).mul_add(
+      value(horizontal_index, vertical_index),
+      vertical_fraction * value(horizontal_index, vertical_index + 1),
+    );
+    let upper = (1.0 - vertical_fraction).mul_add(
+      value(horizontal_index + 1, vertical_index),
+      vertical_fraction * value(horizontal_index + 1, vertical_index + 1),
+    );
+    (1.0 - horizontal_fraction).mul_add(lower, horizontal_fraction * upper)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// A profile symmetric about its nadir, falling linearly from 200 cd straight down to nothing at the horizon
+  const DOWNLIGHT: &str = "IESNA:LM-63-2002\n[TEST] downlight\nTILT=NONE\n1 -1 1 3 1 1 2 0 0 0\n1 1 100\n0 45 90\n0\n200 100 0\n";
+
+  #[test]
+  fn parse_normalizes_and_interpolates_intensity() {
+    let profile = IesProfile::parse(DOWNLIGHT).unwrap();
+    for (vertical, expected) in [(0.0, 1.0), (22.5, 0.75), (45.0, 0.5), (67.5, 0.25), (90.0, 0.0), (120.0, 0.0)] {
+      for horizontal in [0.0, 90.0, 270.0] {
+        let intensity = profile.intensity(vertical, horizontal);
+        assert!((intensity - expected).abs() < 1e-5, "{} at {} degrees", intensity, vertical);
+      }
+    }
+    assert!(IesProfile::parse("TILT=INCLUDE\n").is_err());
+    assert!(IesProfile::parse("TILT=NONE\n1 -1 1 3 1 1 2 0 0 0\n1 1 100\n0 45\n").is_err());
+  }
+}
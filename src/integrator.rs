@@ -0,0 +1,334 @@
+use crate::aov::{LightPass, LightPasses};
+use crate::film::Film;
+use crate::float::{random_float, Float};
+use crate::hit::{HitRecord, Hittable};
+use crate::light::LightSample;
+use crate::material::ScatterKind;
+use crate::ray::{Ray, SHADOW_EPSILON};
+use crate::sampling::power_heuristic;
+use crate::scene::Scene;
+use crate::settings::{BounceCounts, RenderSettings};
+use crate::spectrum::Radiance;
+use crate::stats::{self, Counter};
+use crate::vec3::Vec3;
+use std::fmt::Debug;
+
+/// Estimates the radiance arriving along camera rays
+pub trait Integrator: Debug + Send + Sync {
+  /// Returns a single-sample estimate of the radiance arriving at the origin of `ray`, splatting any contributions
+  /// that land elsewhere on the image to `film`
+  fn radiance<R: Radiance>(&self, ray: &Ray, scene: &Scene, context: &R::Context, film: &Film) -> R;
+  /// Like `radiance`, also splitting the estimate into light passes that sum to it, or giving None for the passes if
+  /// the integrator cannot tell them apart
+  fn radiance_and_passes<R: Radiance>(&self, ray: &Ray, scene: &Scene, context: &R::Context, film: &Film) -> (R, Option<LightPasses<R>>) {
+    (self.radiance(ray, scene, context, film), None)
+  }
+  /// Like `radiance_and_passes`, for a camera ray whose closest hit, `hit`, was already found by tracing it in a packet.
+  /// Integrators that do not override this trace the ray again
+  fn radiance_and_passes_from_hit<R: Radiance>(
+    &self,
+    ray: &Ray,
+    hit: Option<HitRecord>,
+    scene: &Scene,
+    context: &R::Context,
+    film: &Film,
+  ) -> (R, Option<LightPasses<R>>) {
+    let _ = hit;
+    self.radiance_and_passes(ray, scene, context, film)
+  }
+}
+
+/// Light arriving at the hit from `light_sample`, weighted against reaching the light by scattering
+pub fn estimate_direct<R: Radiance>(ray: &Ray, scene: &Scene, hit_record: &HitRecord, light_sample: &LightSample, context: &R::Context) -> R {
+  hit_record
+    .material()
+    .evaluate(ray, hit_record, light_sample.direction())
+    .filter(|(_, scattering_pdf)| *scattering_pdf > 0.0)
+    .filter(|_| {
+      let distance = light_sample.distance();
+      let (shadow_ray, scalar_max) = if distance.is_finite() {
+        let target = hit_record.point() + distance * light_sample.direction();
+        (hit_record.spawn_ray_to(&target), 1.0 - SHADOW_EPSILON)
+      } else {
+        (hit_record.spawn_ray(light_sample.direction()), Float::INFINITY)
+      };
+      stats::count(Counter::ShadowRays, 1);
+      scene.world().is_hit(&shadow_ray, 0.0, scalar_max).is_none()
+    })
+    .map_or_else(R::black, |(value, scattering_pdf)| {
+      let weight = if light_sample.is_delta() {
+        1.0
+      } else {
+        power_heuristic(light_sample.pdf(), scattering_pdf)
+      };
+      R::from_illuminant(light_sample.radiance(), context) * R::from_albedo(&value, context) * (weight / light_sample.pdf())
+    })
+}
+
+/// Direct lighting at the hit from the background and one scene light picked by the scene's light sampler
+pub fn sample_lights<R: Radiance>(ray: &Ray, scene: &Scene, hit_record: &HitRecord, context: &R::Context) -> R {
+  let point = hit_record.point();
+  let scene_light_sample = scene
+    .light_sampler()
+    .sample(point, hit_record.normal(), random_float())
+    .and_then(|(light, pmf)| {
+      scene.lights()[light]
+        .sample(point)
+        .map(|light_sample| light_sample.with_selection_probability(pmf))
+    });
+  scene
+    .background()
+    .sample()
+    .into_iter()
+    .chain(scene_light_sample)
+    .fold(R::black(), |total, light_sample| {
+      total + estimate_direct::<R>(ray, scene, hit_record, &light_sample, context)
+    })
+}
+
+/// Radiance emitted by the hit surface, weighted against the chance that light sampling at the previous bounce,
+/// with surface normal and scattering density `previous_bounce`, already picked it
+pub fn emitted<R: Radiance>(ray: &Ray, scene: &Scene, hit_record: &HitRecord, context: &R::Context, previous_bounce: Option<(Vec3, Float)>) -> R {
+  let emitted = hit_record.material().emitted(ray, hit_record);
+  if emitted.squared_length() <= 0.0 {
+    return R::black();
+  }
+  let weight = previous_bounce.zip(hit_record.light()).map_or(1.0, |((normal, scattering_pdf), light)| {
+    let light_pdf = scene.light_sampler().pmf(ray.origin(), &normal, light) * scene.lights()[light].pdf(ray.origin(), ray.direction());
+    power_heuristic(scattering_pdf, light_pdf)
+  });
+  R::from_illuminant(&emitted, context) * weight
+}
+
+/// Radiance from the background along an escaping `ray`, weighted against light sampling of the background
+pub fn escaped<R: Radiance>(ray: &Ray, scene: &Scene, context: &R::Context, previous_bounce: Option<(Vec3, Float)>) -> R {
+  let background = scene.background();
+  let weight = previous_bounce.map_or(1.0, |(_, scattering_pdf)| {
+    power_heuristic(scattering_pdf, background.pdf(ray.direction()))
+  });
+  R::from_illuminant(&background.radiance(ray.direction()), context) * weight
+}
+
+/// The original recursive path tracer, which multiplies attenuation on the way back up and is kept as a reference
+/// for regression tests
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SimpleIntegrator {
+  settings: RenderSettings,
+}
+
+impl SimpleIntegrator {
+  pub fn new(settings: RenderSettings) -> Self {
+    Self { settings }
+  }
+  /// Radiance arriving along `ray` for a path that has already made `bounces` and carries `throughput`
+  fn color<R: Radiance>(
+    &self,
+    ray: &Ray,
+    scene: &Scene,
+    bounces: BounceCounts,
+    throughput: &Vec3,
+    context: &R::Context,
+    previous_bounce: Option<(Vec3, Float)>,
+  ) -> R {
+    scene.world().is_hit(ray, 0.0, Float::MAX).map_or_else(
+      || escaped::<R>(ray, scene, context, previous_bounce),
+      |hit_record| {
+        let emitted = emitted::<R>(ray, scene, &hit_record, context, previous_bounce);
+        let Some(scatter_result) = hit_record.material().scatter(ray, &hit_record) else {
+          return emitted;
+        };
+        let direct = emitted + sample_lights::<R>(ray, scene, &hit_record, context);
+        let bounces = bounces.with_bounce(scatter_result.kind());
+        if !self.settings.allows(&bounces) {
+          return direct;
+        }
+        let throughput = throughput * scatter_result.attenuation();
+        let survival_probability = self.settings.survival_probability(&bounces, &throughput);
+        if survival_probability <= 0.0 || random_float() >= survival_probability {
+          return direct;
+        }
+        let previous_bounce = scatter_result.pdf().map(|scattering_pdf| (*hit_record.normal(), scattering_pdf));
+        stats::count(Counter::SecondaryRays, 1);
+        direct
+          + R::from_albedo(scatter_result.attenuation(), context)
+            * self.color::<R>(
+              scatter_result.scattered(),
+              scene,
+              bounces,
+              &(throughput / survival_probability),
+              context,
+              previous_bounce,
+            )
+            * (1.0 / survival_probability)
+      },
+    )
+  }
+}
+
+impl Integrator for SimpleIntegrator {
+  fn radiance<R: Radiance>(&self, ray: &Ray, scene: &Scene, context: &R::Context, _: &Film) -> R {
+    self.color::<R>(ray, scene, BounceCounts::new(), &Vec3::new(1.0, 1.0, 1.0), context, None)
+  }
+}
+
+/// An iterative unidirectional path tracer with next event estimation and multiple importance sampling
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PathIntegrator {
+  settings: RenderSettings,
+}
+
+impl PathIntegrator {
+  pub fn new(settings: RenderSettings) -> Self {
+    Self { settings }
+  }
+}
+
+impl Integrator for PathIntegrator {
+  fn radiance<R: Radiance>(&self, ray: &Ray, scene: &Scene, context: &R::Context, film: &Film) -> R {
+    self.radiance_and_passes(ray, scene, context, film).0
+  }
+  fn radiance_and_passes<R: Radiance>(&self, ray: &Ray, scene: &Scene, context: &R::Context, film: &Film) -> (R, Option<LightPasses<R>>) {
+    self.radiance_and_passes_from_hit(ray, scene.world().is_hit(ray, 0.0, Float::MAX), scene, context, film)
+  }
+  fn radiance_and_passes_from_hit<R: Radiance>(
+    &self,
+    ray: &Ray,
+    hit: Option<HitRecord>,
+    scene: &Scene,
+    context: &R::Context,
+    _: &Film,
+  ) -> (R, Option<LightPasses<R>>) {
+    let mut radiance = R::black();
+    let mut passes = LightPasses::new();
+    let mut throughput = R::from_albedo(&Vec3::new(1.0, 1.0, 1.0), context);
+    let mut roulette_throughput = Vec3::new(1.0, 1.0, 1.0);
+    let mut bounces = BounceCounts::new();
+    let mut first_bounce = None;
+    let mut previous_bounce = None;
+    let mut ray = *ray;
+    let mut hit = hit;
+    let pass = |first_bounce: Option<ScatterKind>, is_direct: bool| {
+      first_bounce.map_or(LightPass::Emission, |kind| LightPass::from_first_bounce(kind, is_direct))
+    };
+    loop {
+      let Some(hit_record) = hit else {
+        let escaped = throughput * escaped::<R>(&ray, scene, context, previous_bounce);
+        radiance += escaped;
+        passes.add(pass(first_bounce, bounces.total() <= 1), escaped);
+        break;
+      };
+      let emitted = throughput * emitted::<R>(&ray, scene, &hit_record, context, previous_bounce);
+      radiance += emitted;
+      passes.add(pass(first_bounce, bounces.total() <= 1), emitted);
+      let Some(scatter_result) = hit_record.material().scatter(&ray, &hit_record) else {
+        break;
+      };
+      first_bounce.get_or_insert(scatter_result.kind());
+      let direct = throughput * sample_lights::<R>(&ray, scene, &hit_record, context);
+      radiance += direct;
+      passes.add(pass(first_bounce, bounces.total() == 0), direct);
+      bounces = bounces.with_bounce(scatter_result.kind());
+      if !self.settings.allows(&bounces) {
+        break;
+      }
+      roulette_throughput = roulette_throughput * scatter_result.attenuation();
+      let survival_probability = self.settings.survival_probability(&bounces, &roulette_throughput);
+      if survival_probability <= 0.0 || random_float() >= survival_probability {
+        break;
+      }
+      roulette_throughput /= survival_probability;
+      throughput = throughput * R::from_albedo(scatter_result.attenuation(), context) * (1.0 / survival_probability);
+      previous_bounce = scatter_result.pdf().map(|scattering_pdf| (*hit_record.normal(), scattering_pdf));
+      ray = *scatter_result.scattered();
+      stats::count(Counter::SecondaryRays, 1);
+      hit = scene.world().is_hit(&ray, 0.0, Float::MAX);
+    }
+    (radiance, Some(passes))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::hit::HittableList;
+  use crate::light::PointLight;
+  use crate::material::{Dielectric, Lambertian, Metal};
+  use crate::sphere::Sphere;
+  use std::sync::Arc;
+
+  fn test_scene() -> Scene {
+    let mut world = HittableList::new();
+    world.list_mut().push(Box::new(Sphere::new(
+      &Vec3::new(0.0, -1000.0, 0.0),
+      1000.0,
+      Arc::new(Lambertian::new(&Vec3::new(0.5, 0.5, 0.5))),
+    )));
+    world
+      .list_mut()
+      .push(Box::new(Sphere::new(&Vec3::new(0.0, 1.0, 0.0), 1.0, Arc::new(Dielectric::new(1.5)))));
+    world.list_mut().push(Box::new(Sphere::new(
+      &Vec3::new(-2.0, 1.0, 0.0),
+      1.0,
+      Arc::new(Lambertian::new(&Vec3::new(0.4, 0.2, 0.1))),
+    )));
+    world.list_mut().push(Box::new(Sphere::new(
+      &Vec3::new(2.0, 1.0, 0.0),
+      1.0,
+      Arc::new(Metal::new(&Vec3::new(0.7, 0.6, 0.5), 0.2)),
+    )));
+    let mut scene = Scene::new(world);
+    scene.add_sphere_light(&Vec3::new(0.0, 3.0, 1.5), 0.3, &Vec3::new(8.0, 8.0, 8.0));
+    scene.add_light(Box::new(PointLight::new(&Vec3::new(3.0, 4.0, 2.0), &Vec3::new(5.0, 5.0, 5.0), None)));
+    scene.build_light_bvh();
+    scene
+  }
+
+  #[test]
+  fn path_integrator_matches_simple_integrator() {
+    let scene = test_scene();
+    let settings = RenderSettings::new(2, 8, 8, 8, 8);
+    let simple = SimpleIntegrator::new(settings);
+    let path = PathIntegrator::new(settings);
+    let origin = Vec3::new(0.0, 1.5, 8.0);
+    let film = Film::new(1, 1);
+    (0..2000).for_each(|index| {
+      fastrand::seed(index);
+      let direction = Vec3::new(Float::mul_add(6.0, random_float(), -3.0), Float::mul_add(3.0, random_float(), -1.5), -8.0);
+      let ray = Ray::new(&origin, &direction);
+      fastrand::seed(index);
+      let expected = simple.radiance::<Vec3>(&ray, &scene, &(), &film);
+      fastrand::seed(index);
+      let actual = path.radiance::<Vec3>(&ray, &scene, &(), &film);
+      assert!(
+        (expected - actual).length() <= 1e-4 * expected.length().max(1.0),
+        "ray {}: simple {:?}, path {:?}",
+        index,
+        expected,
+        actual
+      );
+    });
+  }
+
+  #[test]
+  fn light_passes_sum_to_radiance() {
+    let scene = test_scene();
+    let path = PathIntegrator::new(RenderSettings::new(2, 8, 8, 8, 8));
+    let origin = Vec3::new(0.0, 1.5, 8.0);
+    let film = Film::new(1, 1);
+    for index in 0..2000 {
+      fastrand::seed(index);
+      let direction = Vec3::new(Float::mul_add(6.0, random_float(), -3.0), Float::mul_add(3.0, random_float(), -1.5), -8.0);
+      let ray = Ray::new(&origin, &direction);
+      let (radiance, passes) = path.radiance_and_passes::<Vec3>(&ray, &scene, &(), &film);
+      let passes = passes.expect("path integrator splits its estimate into light passes");
+      let sum = LightPass::ALL.iter().fold(Vec3::new_empty(), |sum, pass| sum + passes.pass(*pass));
+      assert!(
+        (radiance - sum).length() <= 1e-4 * radiance.length().max(1.0),
+        "ray {}: radiance {:?}, sum of passes {:?}",
+        index,
+        radiance,
+        sum
+      );
+    }
+  }
+}
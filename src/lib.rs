@@ -0,0 +1,206 @@
+//! Ray tracing library behind the `rust_ray_tracer_in_one_weekend` binary
+//!
+//! Build a [`scene::Scene`] with [`scene::Scene::builder`], or start from one of the [`scenes`], point a
+//! [`camera::Camera`] at it and call [`render`] to get a [`framebuffer::Framebuffer`] of linear RGB pixels:
+//!
+//! ```no_run
+//! use rust_ray_tracer_in_one_weekend::camera::Camera;
+//! use rust_ray_tracer_in_one_weekend::scene::Scene;
+//! use rust_ray_tracer_in_one_weekend::settings::ImageSettings;
+//! use rust_ray_tracer_in_one_weekend::vec3::Vec3;
+//! use rust_ray_tracer_in_one_weekend::render;
+//!
+//! # fn main() -> Result<(), rust_ray_tracer_in_one_weekend::Error> {
+//! let mut builder = Scene::builder();
+//! let ground = builder.add_lambertian("ground", &Vec3::new(0.5, 0.5, 0.5))?;
+//! let glass = builder.add_dielectric("glass", 1.5)?;
+//! builder
+//!   .add_sphere(&Vec3::new(0.0, -1000.0, 0.0), 1000.0, ground)?
+//!   .add_sphere(&Vec3::new(0.0, 1.0, 0.0), 1.0, glass)?;
+//! let scene = builder.build();
+//! let camera = Camera::builder()
+//!   .with_look_from(&Vec3::new(13.0, 2.0, 3.0))
+//!   .with_look_at(&Vec3::new(0.0, 1.0, 0.0))
+//!   .with_vertical_fov(20.0)
+//!   .with_aperture(0.1)
+//!   .build()?;
+//! let framebuffer = render(&scene, &camera, &ImageSettings::new(200, 100, 16))?;
+//! framebuffer.write_ppm(true, &mut std::io::stdout().lock())?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! For progressive, adaptive or checkpointed renders, other integrators and extra output layers, drive a
+//! [`renderer::Renderer`] and a [`film::Film`] directly the way the binary does
+#![deny(
+  clippy::await_holding_lock,
+  clippy::dbg_macro,
+  clippy::debug_assert_with_mut_call,
+  clippy::doc_markdown,
+  clippy::empty_enums,
+  clippy::enum_glob_use,
+  clippy::exit,
+  clippy::explicit_into_iter_loop,
+  clippy::filter_map_next,
+  clippy::fn_params_excessive_bools,
+  clippy::if_let_mutex,
+  clippy::imprecise_flops,
+  clippy::inefficient_to_string,
+  clippy::large_types_passed_by_value,
+  clippy::let_unit_value,
+  clippy::linkedlist,
+  clippy::lossy_float_literal,
+  clippy::macro_use_imports,
+  clippy::map_err_ignore,
+  clippy::map_flatten,
+  clippy::map_unwrap_or,
+  clippy::match_same_arms,
+  clippy::match_wildcard_for_single_variants,
+  clippy::mem_forget,
+  clippy::needless_borrow,
+  clippy::needless_continue,
+  clippy::option_option,
+  clippy::ref_option_ref,
+  clippy::rest_pat_in_fully_bound_structs,
+  clippy::string_add_assign,
+  clippy::string_add,
+  clippy::suboptimal_flops,
+  clippy::todo,
+  clippy::unimplemented,
+  clippy::unnested_or_patterns,
+  clippy::unused_self,
+  clippy::verbose_file_reads,
+  clippy::cargo,
+  clippy::correctness,
+  clippy::complexity,
+  clippy::perf,
+  clippy::style,
+  missing_debug_implementations,
+  future_incompatible,
+  nonstandard_style,
+  rust_2018_idioms
+)]
+#![warn(clippy::pedantic)]
+#![allow(clippy::must_use_candidate, clippy::return_self_not_must_use)]
+// Conversions written for `f32` become lossless or do nothing when `Float` is `f64`
+#![cfg_attr(feature = "f64", allow(clippy::cast_lossless, clippy::useless_conversion))]
+
+pub mod aabb;
+pub mod aov;
+pub mod background;
+pub mod bdpt;
+pub mod bvh;
+pub mod camera;
+pub mod checkpoint;
+pub mod debug;
+pub mod denoise;
+pub mod environment;
+pub mod film;
+pub mod filter;
+pub mod float;
+pub mod framebuffer;
+pub mod hit;
+pub mod ies;
+pub mod integrator;
+pub mod light;
+pub mod light_bounds;
+pub mod light_sampler;
+pub mod material;
+pub mod medium;
+pub mod output;
+pub mod progress;
+pub mod ray;
+pub mod renderer;
+pub mod sampling;
+pub mod scene;
+pub mod scenes;
+pub mod settings;
+pub mod simd;
+pub mod sky;
+pub mod spectrum;
+pub mod sphere;
+pub mod sppm;
+pub mod stats;
+pub mod triangle;
+pub mod vec3;
+
+use camera::Camera;
+use checkpoint::Checkpoint;
+use film::Film;
+use framebuffer::Framebuffer;
+use integrator::PathIntegrator;
+use renderer::{ColorMode, Renderer};
+use scene::Scene;
+use settings::{ImageSettings, SamplingSettings};
+
+/// Error type returned throughout the crate
+pub type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+/// Renders `scene` as seen by `camera` with the path tracer, taking as many samples per pixel and reconstructing the
+/// image with the filter `settings` ask for
+///
+/// # Errors
+///
+/// Returns an error if `settings` ask for no samples per pixel or the render fails
+pub fn render(scene: &Scene, camera: &Camera, settings: &ImageSettings) -> Result<Framebuffer, Error> {
+  let renderer = Renderer::new(
+    ColorMode::Rgb,
+    SamplingSettings::fixed(settings.samples_per_pixel())?,
+    false,
+    settings.seed(),
+  );
+  let film = renderer.render(
+    &PathIntegrator::new(*settings.render_settings()),
+    scene,
+    camera,
+    Checkpoint::new(&[], Film::new(settings.width(), settings.height()).with_filter(*settings.filter())),
+  )?;
+  Ok(Framebuffer::from_film(&film, film.average_sample_count()))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use background::Background;
+  use vec3::Vec3;
+
+  fn sphere_scene(albedo: &Vec3, background: Background) -> Result<(Scene, Camera), Error> {
+    let mut builder = Scene::builder();
+    let material = builder.add_lambertian("sphere", albedo)?;
+    builder.add_sphere(&Vec3::new(0.0, 0.0, 0.0), 1.0, material)?.set_background(background);
+    let camera = Camera::builder()
+      .with_look_from(&Vec3::new(0.0, 0.0, 4.0))
+      .with_look_at(&Vec3::new(0.0, 0.0, 0.0))
+      .with_vertical_fov(40.0)
+      .build()?;
+    Ok((builder.build(), camera))
+  }
+
+  #[test]
+  fn render_returns_every_pixel_of_a_white_sphere_in_a_furnace_as_the_background() -> Result<(), Error> {
+    let grey = Vec3::new(0.5, 0.5, 0.5);
+    let (scene, camera) = sphere_scene(&Vec3::new(1.0, 1.0, 1.0), Background::Constant(grey))?;
+    let framebuffer = render(&scene, &camera, &ImageSettings::new(12, 8, 4))?;
+    assert_eq!((framebuffer.width(), framebuffer.height()), (12, 8));
+    for (x, y) in (0..8).flat_map(|y| (0..12).map(move |x| (x, y))) {
+      assert!(
+        (*framebuffer.pixel(x, y) - grey).length() < 1e-4,
+        "{:?} at ({}, {})",
+        framebuffer.pixel(x, y),
+        x,
+        y
+      );
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn render_repeats_itself_for_the_same_seed() -> Result<(), Error> {
+    let (scene, camera) = sphere_scene(&Vec3::new(0.8, 0.3, 0.3), Background::default())?;
+    let settings = ImageSettings::new(16, 8, 2).with_seed(5);
+    let first = render(&scene, &camera, &settings)?;
+    assert_eq!(render(&scene, &camera, &settings)?, first);
+    assert_ne!(render(&scene, &camera, &settings.with_seed(6))?, first);
+    Ok(())
+  }
+}
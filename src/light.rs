@@ -0,0 +1,554 @@
+use crate::aabb::Aabb;
+use crate::float::consts::PI;
+use crate::float::{random_float, Float};
+use crate::ies::IesProfile;
+use crate::light_bounds::LightBounds;
+use crate::ray::Ray;
+use crate::sampling::{
+  orthonormal_basis, sample_cosine_hemisphere, sample_uniform_cone, sample_uniform_sphere, uniform_cone_pdf, uniform_sphere_pdf,
+};
+use crate::sphere::point_error;
+use crate::vec3::Vec3;
+use crate::Error;
+use std::fmt::Debug;
+use std::path::Path;
+
+/// Incident light arriving at a shading point from a sampled light
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LightSample {
+  direction: Vec3,
+  distance: Float,
+  radiance: Vec3,
+  pdf: Float,
+  is_delta: bool,
+}
+
+impl LightSample {
+  pub fn new(direction: &Vec3, distance: Float, radiance: &Vec3, pdf: Float, is_delta: bool) -> Self {
+    Self {
+      direction: *direction,
+      distance,
+      radiance: *radiance,
+      pdf,
+      is_delta,
+    }
+  }
+  /// Unit vector from the shading point towards the light
+  pub fn direction(&self) -> &Vec3 {
+    &self.direction
+  }
+  /// Distance along `direction` to the light, infinite for lights at infinity
+  pub fn distance(&self) -> Float {
+    self.distance
+  }
+  pub fn radiance(&self) -> &Vec3 {
+    &self.radiance
+  }
+  /// Solid angle density of `direction`, or one for delta lights whose `radiance` is already the incident irradiance
+  pub fn pdf(&self) -> Float {
+    self.pdf
+  }
+  pub fn is_delta(&self) -> bool {
+    self.is_delta
+  }
+  /// Folds the probability of having chosen this light among others into `pdf`
+  pub fn with_selection_probability(mut self, probability: Float) -> Self {
+    self.pdf *= probability;
+    self
+  }
+}
+
+/// Light leaving a sampled point on a light, used to start light subpaths
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EmissionSample {
+  point: Vec3,
+  error: Vec3,
+  normal: Vec3,
+  direction: Vec3,
+  radiance: Vec3,
+  pdf_position: Float,
+  pdf_direction: Float,
+}
+
+impl EmissionSample {
+  pub fn new(point: &Vec3, normal: &Vec3, direction: &Vec3, radiance: &Vec3, pdf_position: Float, pdf_direction: Float) -> Self {
+    Self {
+      point: *point,
+      error: Vec3::new_empty(),
+      normal: *normal,
+      direction: *direction,
+      radiance: *radiance,
+      pdf_position,
+      pdf_direction,
+    }
+  }
+  /// Sets a bound on the rounding error in each coordinate of the sampled point
+  pub fn with_error(mut self, error: &Vec3) -> Self {
+    self.error = *error;
+    self
+  }
+  pub fn point(&self) -> &Vec3 {
+    &self.point
+  }
+  /// Bound on the rounding error in each coordinate of `point`
+  pub fn error(&self) -> &Vec3 {
+    &self.error
+  }
+  /// Ray carrying the light away from `point` that cannot hit the light's own surface there
+  pub fn ray(&self) -> Ray {
+    Ray::spawn(&self.point, &self.error, &self.normal, &self.direction)
+  }
+  /// Surface normal at `point`, zero for lights without area
+  pub fn normal(&self) -> &Vec3 {
+    &self.normal
+  }
+  /// Unit vector along which the light leaves `point`
+  pub fn direction(&self) -> &Vec3 {
+    &self.direction
+  }
+  /// Radiance leaving `point`, or intensity for lights without area
+  pub fn radiance(&self) -> &Vec3 {
+    &self.radiance
+  }
+  /// Area density of `point`, or one for lights without area
+  pub fn pdf_position(&self) -> Float {
+    self.pdf_position
+  }
+  /// Solid angle density of `direction`
+  pub fn pdf_direction(&self) -> Float {
+    self.pdf_direction
+  }
+}
+
+/// A source of direct illumination that shading points can sample
+pub trait Light: Debug + Send + Sync {
+  fn sample(&self, point: &Vec3) -> Option<LightSample>;
+  /// Solid angle density with which `sample` from `point` picks `direction`, zero for delta lights
+  fn pdf(&self, _point: &Vec3, _direction: &Vec3) -> Float {
+    0.0
+  }
+  /// Bounds used by the light BVH, or None for lights at infinity
+  fn bounds(&self) -> Option<LightBounds>;
+  /// Samples light leaving the light, or None for lights at infinity which cannot start light subpaths
+  fn sample_emission(&self) -> Option<EmissionSample> {
+    None
+  }
+  /// Position and direction densities with which `sample_emission` produces light leaving `point` along `direction`
+  fn emission_pdf(&self, _point: &Vec3, _direction: &Vec3) -> (Float, Float) {
+    (0.0, 0.0)
+  }
+  /// Radiance leaving `point` on the light along `direction`, or intensity for lights without area
+  fn emitted(&self, _point: &Vec3, _direction: &Vec3) -> Vec3 {
+    Vec3::new_empty()
+  }
+  /// Surface normal at `point` on the light, zero for lights without area
+  fn normal(&self, _point: &Vec3) -> Vec3 {
+    Vec3::new_empty()
+  }
+  /// Whether the light is a single point or a single direction, which no scattered ray can hit
+  fn is_delta(&self) -> bool {
+    false
+  }
+}
+
+fn max_component(vector: &Vec3) -> Float {
+  vector.x().max(vector.y()).max(vector.z())
+}
+
+/// Angles in degrees of `direction` from the photometric nadir `axis` and around it, for looking up an IES profile
+fn photometric_angles(axis: &Vec3, direction: &Vec3) -> (Float, Float) {
+  let (tangent, bitangent) = orthonormal_basis(axis);
+  let vertical = direction.dot(axis).clamp(-1.0, 1.0).acos().to_degrees();
+  let horizontal = direction.dot(&bitangent).atan2(direction.dot(&tangent)).to_degrees();
+  (vertical, horizontal)
+}
+
+/// An isotropic point emitter with inverse-square falloff, optionally shaped by an IES profile aimed down -y
+#[derive(Clone, Debug, PartialEq)]
+pub struct PointLight {
+  position: Vec3,
+  intensity: Vec3,
+  profile: Option<IesProfile>,
+}
+
+impl PointLight {
+  pub fn new(position: &Vec3, intensity: &Vec3, profile: Option<IesProfile>) -> Self {
+    Self {
+      position: *position,
+      intensity: *intensity,
+      profile,
+    }
+  }
+}
+
+impl Light for PointLight {
+  fn sample(&self, point: &Vec3) -> Option<LightSample> {
+    let to_light = self.position - point;
+    let distance = to_light.length();
+    if distance <= 0.0 {
+      return None;
+    }
+    let direction = to_light / distance;
+    Some(LightSample::new(
+      &direction,
+      distance,
+      &(self.emitted(&self.position, &-direction) / (distance * distance)),
+      1.0,
+      true,
+    ))
+  }
+  fn bounds(&self) -> Option<LightBounds> {
+    Some(LightBounds::new_omnidirectional(
+      &Aabb::new_from_point(&self.position),
+      4.0 * PI * max_component(&self.intensity),
+    ))
+  }
+  fn sample_emission(&self) -> Option<EmissionSample> {
+    let direction = sample_uniform_sphere(random_float(), random_float());
+    Some(EmissionSample::new(
+      &self.position,
+      &Vec3::new_empty(),
+      &direction,
+      &self.emitted(&self.position, &direction),
+      1.0,
+      uniform_sphere_pdf(),
+    ))
+  }
+  fn emission_pdf(&self, _: &Vec3, _: &Vec3) -> (Float, Float) {
+    (1.0, uniform_sphere_pdf())
+  }
+  fn emitted(&self, _: &Vec3, direction: &Vec3) -> Vec3 {
+    let profile_scale = self.profile.as_ref().map_or(1.0, |profile| {
+      let (vertical, horizontal) = photometric_angles(&Vec3::new(0.0, -1.0, 0.0), &direction.unit_vector());
+      profile.intensity(vertical, horizontal)
+    });
+    self.intensity * profile_scale
+  }
+  fn is_delta(&self) -> bool {
+    true
+  }
+}
+
+/// A point emitter restricted to a cone, fading smoothly between the falloff start angle and the cone angle
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpotLight {
+  position: Vec3,
+  direction: Vec3,
+  intensity: Vec3,
+  cos_cone_angle: Float,
+  cos_falloff_start: Float,
+  profile: Option<IesProfile>,
+}
+
+impl SpotLight {
+  /// Creates a spot light whose angles are given in degrees from `direction`
+  pub fn new(position: &Vec3, direction: &Vec3, intensity: &Vec3, cone_angle: Float, falloff_start: Float, profile: Option<IesProfile>) -> Self {
+    Self {
+      position: *position,
+      direction: direction.unit_vector(),
+      intensity: *intensity,
+      cos_cone_angle: cone_angle.to_radians().cos(),
+      cos_falloff_start: falloff_start.min(cone_angle).to_radians().cos(),
+      profile,
+    }
+  }
+  fn falloff(&self, cos_theta: Float) -> Float {
+    if cos_theta >= self.cos_falloff_start {
+      return 1.0;
+    }
+    if cos_theta <= self.cos_cone_angle {
+      return 0.0;
+    }
+    let t = (cos_theta - self.cos_cone_angle) / (self.cos_falloff_start - self.cos_cone_angle);
+    t * t * Float::mul_add(2.0, -t, 3.0)
+  }
+}
+
+impl Light for SpotLight {
+  fn sample(&self, point: &Vec3) -> Option<LightSample> {
+    let to_light = self.position - point;
+    let distance = to_light.length();
+    if distance <= 0.0 {
+      return None;
+    }
+    let direction = to_light / distance;
+    let emitted = self.emitted(&self.position, &-direction);
+    if emitted.squared_length() <= 0.0 {
+      return None;
+    }
+    Some(LightSample::new(&direction, distance, &(emitted / (distance * distance)), 1.0, true))
+  }
+  fn bounds(&self) -> Option<LightBounds> {
+    let cos_theta_e = (self.cos_cone_angle.acos() - self.cos_falloff_start.acos()).cos();
+    Some(LightBounds::new(
+      &Aabb::new_from_point(&self.position),
+      4.0 * PI * max_component(&self.intensity),
+      &self.direction,
+      self.cos_falloff_start,
+      cos_theta_e,
+    ))
+  }
+  fn sample_emission(&self) -> Option<EmissionSample> {
+    let direction = sample_uniform_cone(random_float(), random_float(), &self.direction, self.cos_cone_angle).unit_vector();
+    Some(EmissionSample::new(
+      &self.position,
+      &Vec3::new_empty(),
+      &direction,
+      &self.emitted(&self.position, &direction),
+      1.0,
+      uniform_cone_pdf(self.cos_cone_angle),
+    ))
+  }
+  fn emission_pdf(&self, _: &Vec3, direction: &Vec3) -> (Float, Float) {
+    if direction.unit_vector().dot(&self.direction) >= self.cos_cone_angle {
+      (1.0, uniform_cone_pdf(self.cos_cone_angle))
+    } else {
+      (1.0, 0.0)
+    }
+  }
+  fn emitted(&self, _: &Vec3, direction: &Vec3) -> Vec3 {
+    let direction = direction.unit_vector();
+    let falloff = self.falloff(direction.dot(&self.direction));
+    if falloff <= 0.0 {
+      return Vec3::new_empty();
+    }
+    let profile_scale = self.profile.as_ref().map_or(1.0, |profile| {
+      let (vertical, horizontal) = photometric_angles(&self.direction, &direction);
+      profile.intensity(vertical, horizontal)
+    });
+    self.intensity * (falloff * profile_scale)
+  }
+  fn is_delta(&self) -> bool {
+    true
+  }
+}
+
+/// Parallel light arriving from infinitely far away, such as sunlight without a visible disk
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DirectionalLight {
+  direction: Vec3,
+  irradiance: Vec3,
+}
+
+impl DirectionalLight {
+  /// Creates a light travelling along `direction`
+  pub fn new(direction: &Vec3, irradiance: &Vec3) -> Self {
+    Self {
+      direction: direction.unit_vector(),
+      irradiance: *irradiance,
+    }
+  }
+}
+
+impl Light for DirectionalLight {
+  fn sample(&self, _: &Vec3) -> Option<LightSample> {
+    Some(LightSample::new(&-self.direction, Float::INFINITY, &self.irradiance, 1.0, true))
+  }
+  fn bounds(&self) -> Option<LightBounds> {
+    None
+  }
+  fn is_delta(&self) -> bool {
+    true
+  }
+}
+
+/// The emitting surface of a sphere with a `DiffuseLight` material, sampled by the cone it subtends
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SphereLight {
+  center: Vec3,
+  radius: Float,
+  radiance: Vec3,
+}
+
+impl SphereLight {
+  pub fn new(center: &Vec3, radius: Float, radiance: &Vec3) -> Self {
+    Self {
+      center: *center,
+      radius,
+      radiance: *radiance,
+    }
+  }
+  /// Cosine of the half-angle of the cone the sphere subtends from `point`, or None from inside the sphere
+  fn cos_theta_max(&self, point: &Vec3) -> Option<Float> {
+    let squared_distance = (self.center - point).squared_length();
+    let squared_radius = self.radius * self.radius;
+    if squared_distance <= squared_radius {
+      return None;
+    }
+    Some((1.0 - squared_radius / squared_distance).max(0.0).sqrt())
+  }
+}
+
+impl Light for SphereLight {
+  fn sample(&self, point: &Vec3) -> Option<LightSample> {
+    let cos_theta_max = self.cos_theta_max(point)?;
+    let to_center = self.center - point;
+    let center_distance = to_center.length();
+    let direction = sample_uniform_cone(random_float(), random_float(), &(to_center / center_distance), cos_theta_max).unit_vector();
+    let projected = direction.dot(&to_center);
+    let distance = projected
+      - self
+        .radius
+        .mul_add(self.radius, projected.mul_add(projected, -to_center.squared_length()))
+        .max(0.0)
+        .sqrt();
+    Some(LightSample::new(
+      &direction,
+      distance,
+      &self.radiance,
+      uniform_cone_pdf(cos_theta_max),
+      false,
+    ))
+  }
+  fn pdf(&self, point: &Vec3, direction: &Vec3) -> Float {
+    self.cos_theta_max(point).map_or(0.0, |cos_theta_max| {
+      let to_center = (self.center - point).unit_vector();
+      if direction.unit_vector().dot(&to_center) >= cos_theta_max {
+        uniform_cone_pdf(cos_theta_max)
+      } else {
+        0.0
+      }
+    })
+  }
+  fn bounds(&self) -> Option<LightBounds> {
+    let extent = Vec3::new(self.radius, self.radius, self.radius);
+    Some(LightBounds::new_omnidirectional(
+      &Aabb::new(&(self.center - extent), &(self.center + extent)),
+      PI * 4.0 * PI * self.radius * self.radius * max_component(&self.radiance),
+    ))
+  }
+  fn sample_emission(&self) -> Option<EmissionSample> {
+    let normal = sample_uniform_sphere(random_float(), random_float());
+    let point = self.center + normal * self.radius;
+    let direction = sample_cosine_hemisphere(random_float(), random_float(), &normal).unit_vector();
+    let (pdf_position, pdf_direction) = self.emission_pdf(&point, &direction);
+    Some(EmissionSample::new(&point, &normal, &direction, &self.radiance, pdf_position, pdf_direction).with_error(&point_error(&self.center, &point)))
+  }
+  fn emission_pdf(&self, point: &Vec3, direction: &Vec3) -> (Float, Float) {
+    let cosine = direction.unit_vector().dot(&self.normal(point)).max(0.0);
+    (1.0 / (4.0 * PI * self.radius * self.radius), cosine / PI)
+  }
+  fn emitted(&self, point: &Vec3, direction: &Vec3) -> Vec3 {
+    if direction.dot(&self.normal(point)) > 0.0 {
+      self.radiance
+    } else {
+      Vec3::new_empty()
+    }
+  }
+  fn normal(&self, point: &Vec3) -> Vec3 {
+    (point - self.center).unit_vector()
+  }
+}
+
+fn parse_vec3(field: Option<&str>, name: &str) -> Result<Vec3, Error> {
+  let components: Vec<&str> = field.ok_or(format!("Light is missing its {name}"))?.split(',').collect();
+  match components.as_slice() {
+    [x, y, z] => Vec3::new_from_str_array(&[x, y, z]),
+    _ => Err(format!("Light {name} must be three comma separated numbers").into()),
+  }
+}
+
+fn parse_profile(field: Option<&str>) -> Result<Option<IesProfile>, Error> {
+  field.map(|path| IesProfile::load(Path::new(path))).transpose()
+}
+
+/// Parses a command line light description:
+///
+/// - `point:x,y,z:r,g,b[:profile.ies]`
+/// - `spot:x,y,z:dx,dy,dz:r,g,b:cone_degrees:falloff_start_degrees[:profile.ies]`
+/// - `directional:dx,dy,dz:r,g,b`
+///
+/// # Errors
+///
+/// Returns an error if the description is malformed or its IES profile cannot be loaded
+pub fn parse_light(description: &str) -> Result<Box<dyn Light>, Error> {
+  let mut fields = description.split(':');
+  match fields.next() {
+    Some("point") => {
+      let position = parse_vec3(fields.next(), "position")?;
+      let intensity = parse_vec3(fields.next(), "intensity")?;
+      Ok(Box::new(PointLight::new(&position, &intensity, parse_profile(fields.next())?)))
+    }
+    Some("spot") => {
+      let position = parse_vec3(fields.next(), "position")?;
+      let direction = parse_vec3(fields.next(), "direction")?;
+      let intensity = parse_vec3(fields.next(), "intensity")?;
+      let cone_angle: Float = fields.next().ok_or("Spot light is missing its cone angle")?.parse()?;
+      let falloff_start: Float = fields.next().ok_or("Spot light is missing its falloff start")?.parse()?;
+      Ok(Box::new(SpotLight::new(
+        &position,
+        &direction,
+        &intensity,
+        cone_angle,
+        falloff_start,
+        parse_profile(fields.next())?,
+      )))
+    }
+    Some("directional") => {
+      let direction = parse_vec3(fields.next(), "direction")?;
+      let irradiance = parse_vec3(fields.next(), "irradiance")?;
+      Ok(Box::new(DirectionalLight::new(&direction, &irradiance)))
+    }
+    _ => Err(format!("Unknown light {description}").into()),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// A profile symmetric about its nadir, falling linearly from its peak straight down to nothing at the horizon
+  const DOWNLIGHT: &str = "TILT=NONE\n1 -1 1 3 1 1 2 0 0 0\n1 1 100\n0 45 90\n0\n200 100 0\n";
+
+  fn assert_close(actual: &Vec3, expected: &Vec3) {
+    assert!((actual - expected).length() < 1e-4, "expected {:?} but got {:?}", expected, actual);
+  }
+
+  #[test]
+  fn point_lights_fall_off_with_the_square_of_distance_and_follow_their_profile() {
+    let intensity = Vec3::new(8.0, 4.0, 2.0);
+    let light = PointLight::new(&Vec3::new(0.0, 2.0, 0.0), &intensity, None);
+    let sample = light.sample(&Vec3::new_empty()).unwrap();
+    assert!(sample.is_delta());
+    assert_close(sample.direction(), &Vec3::new(0.0, 1.0, 0.0));
+    assert!((sample.distance() - 2.0).abs() < 1e-6);
+    assert_close(sample.radiance(), &(intensity / 4.0));
+    // The downlight profile halves the intensity 45 degrees off its nadir
+    let profile = IesProfile::parse(DOWNLIGHT).unwrap();
+    let light = PointLight::new(&Vec3::new(0.0, 1.0, 0.0), &intensity, Some(profile));
+    assert_close(light.sample(&Vec3::new_empty()).unwrap().radiance(), &intensity);
+    assert_close(light.sample(&Vec3::new(1.0, 0.0, 0.0)).unwrap().radiance(), &(intensity * 0.25));
+  }
+
+  #[test]
+  fn spot_lights_fade_between_their_falloff_start_and_cone_angle() {
+    let intensity = Vec3::new(1.0, 1.0, 1.0);
+    let light = SpotLight::new(&Vec3::new_empty(), &Vec3::new(0.0, -1.0, 0.0), &intensity, 40.0, 20.0, None);
+    let at_angle = |degrees: Float| {
+      let radians = degrees.to_radians();
+      Vec3::new(radians.sin(), -radians.cos(), 0.0)
+    };
+    assert_close(light.sample(&at_angle(10.0)).unwrap().radiance(), &intensity);
+    let fading = light.sample(&at_angle(30.0)).unwrap().radiance().x();
+    assert!(fading > 0.0 && fading < 1.0);
+    assert!(light.sample(&at_angle(30.0)).unwrap().radiance().x() > light.sample(&at_angle(35.0)).unwrap().radiance().x());
+    assert!(light.sample(&at_angle(45.0)).is_none());
+    let (_, inside_pdf) = light.emission_pdf(&Vec3::new_empty(), &at_angle(30.0));
+    let (_, outside_pdf) = light.emission_pdf(&Vec3::new_empty(), &at_angle(45.0));
+    assert!((inside_pdf - uniform_cone_pdf(Float::to_radians(40.0).cos())).abs() < 1e-3);
+    assert!(outside_pdf == 0.0);
+  }
+
+  #[test]
+  fn parsed_lights_match_their_descriptions() {
+    let light = parse_light("directional:0,-2,0:3,3,3").unwrap();
+    let sample = light.sample(&Vec3::new(5.0, 0.0, 5.0)).unwrap();
+    assert!(light.is_delta() && sample.distance().is_infinite());
+    assert_close(sample.direction(), &Vec3::new(0.0, 1.0, 0.0));
+    assert_close(sample.radiance(), &Vec3::new(3.0, 3.0, 3.0));
+    assert!(parse_light("spot:0,1,0:0,-1,0:1,1,1:30:10").is_ok());
+    for malformed in ["point:0,1,0", "point:0,1:1,1,1", "spot:0,1,0:0,-1,0:1,1,1:30", "area:0,0,0:1,1,1"] {
+      assert!(parse_light(malformed).is_err(), "{} parsed", malformed);
+    }
+  }
+}
@@ -0,0 +1,155 @@
+use crate::aabb::Aabb;
+use crate::float::consts::{FRAC_PI_2, PI};
+use crate::float::Float;
+use crate::vec3::{Vec3, Vec3Index};
+
+fn safe_sqrt(value: Float) -> Float {
+  value.max(0.0).sqrt()
+}
+
+/// Cosine of the difference of two angles, clamped to one when the first is smaller than the second
+fn cos_sub_clamped(sin_theta_a: Float, cos_theta_a: Float, sin_theta_b: Float, cos_theta_b: Float) -> Float {
+  if cos_theta_a > cos_theta_b {
+    1.0
+  } else {
+    cos_theta_a.mul_add(cos_theta_b, sin_theta_a * sin_theta_b)
+  }
+}
+
+/// Sine of the difference of two angles, clamped to zero when the first is smaller than the second
+fn sin_sub_clamped(sin_theta_a: Float, cos_theta_a: Float, sin_theta_b: Float, cos_theta_b: Float) -> Float {
+  if cos_theta_a > cos_theta_b {
+    0.0
+  } else {
+    sin_theta_a.mul_add(cos_theta_b, -(cos_theta_a * sin_theta_b))
+  }
+}
+
+/// Rotates `vector` by `angle` radians about the unit `axis` using Rodrigues' formula
+fn rotate(vector: &Vec3, axis: &Vec3, angle: Float) -> Vec3 {
+  let (sin_angle, cos_angle) = angle.sin_cos();
+  vector * cos_angle + axis.cross(vector) * sin_angle + axis * (axis.dot(vector) * (1.0 - cos_angle))
+}
+
+/// Spatial, power and orientation bounds of one or more emitters, used to estimate their contribution at a point
+///
+/// Emission leaves in directions within `acos(cos_theta_o)` of `direction`, spreading a further `acos(cos_theta_e)`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LightBounds {
+  bounds: Aabb,
+  phi: Float,
+  direction: Vec3,
+  cos_theta_o: Float,
+  cos_theta_e: Float,
+}
+
+impl LightBounds {
+  pub fn new(bounds: &Aabb, phi: Float, direction: &Vec3, cos_theta_o: Float, cos_theta_e: Float) -> Self {
+    Self {
+      bounds: *bounds,
+      phi,
+      direction: direction.unit_vector(),
+      cos_theta_o,
+      cos_theta_e,
+    }
+  }
+  /// Bounds of a light emitting in every direction from every point of `bounds`
+  pub fn new_omnidirectional(bounds: &Aabb, phi: Float) -> Self {
+    Self::new(bounds, phi, &Vec3::new(0.0, 0.0, 1.0), -1.0, 0.0)
+  }
+  pub fn bounds(&self) -> &Aabb {
+    &self.bounds
+  }
+  pub fn phi(&self) -> Float {
+    self.phi
+  }
+  pub fn centroid(&self) -> Vec3 {
+    self.bounds.centroid()
+  }
+  pub fn union(&self, other: &Self) -> Self {
+    if self.phi <= 0.0 {
+      return *other;
+    }
+    if other.phi <= 0.0 {
+      return *self;
+    }
+    let (direction, cos_theta_o) = self.union_cone(other);
+    Self {
+      bounds: self.bounds.union(&other.bounds),
+      phi: self.phi + other.phi,
+      direction,
+      cos_theta_o,
+      cos_theta_e: self.cos_theta_e.min(other.cos_theta_e),
+    }
+  }
+  fn union_cone(&self, other: &Self) -> (Vec3, Float) {
+    let theta_a = self.cos_theta_o.clamp(-1.0, 1.0).acos();
+    let theta_b = other.cos_theta_o.clamp(-1.0, 1.0).acos();
+    let theta_d = self.direction.dot(&other.direction).clamp(-1.0, 1.0).acos();
+    if (theta_d + theta_b).min(PI) <= theta_a {
+      return (self.direction, self.cos_theta_o);
+    }
+    if (theta_d + theta_a).min(PI) <= theta_b {
+      return (other.direction, other.cos_theta_o);
+    }
+    let theta_o = (theta_a + theta_d + theta_b) / 2.0;
+    if theta_o >= PI {
+      return (self.direction, -1.0);
+    }
+    let axis = self.direction.cross(&other.direction);
+    if axis.squared_length() <= 0.0 {
+      return (self.direction, -1.0);
+    }
+    (rotate(&self.direction, &axis.unit_vector(), theta_o - theta_a), theta_o.cos())
+  }
+  /// Conservative estimate of the light arriving at `point`, also accounting for the cosine at a surface with
+  /// `normal` unless it is zero
+  pub fn importance(&self, point: &Vec3, normal: &Vec3) -> Float {
+    let centroid = self.centroid();
+    let (_, bounding_radius) = self.bounds.bounding_sphere();
+    let squared_distance = (point - centroid).squared_length().max(bounding_radius);
+    let to_point = point - centroid;
+    if to_point.squared_length() <= 0.0 {
+      return self.phi / squared_distance;
+    }
+    let to_point = to_point.unit_vector();
+    let cos_theta_w = self.direction.dot(&to_point);
+    let sin_theta_w = safe_sqrt(cos_theta_w.mul_add(-cos_theta_w, 1.0));
+    let squared_sin_theta_b = bounding_radius * bounding_radius / (point - centroid).squared_length();
+    let cos_theta_b = if squared_sin_theta_b >= 1.0 {
+      -1.0
+    } else {
+      safe_sqrt(1.0 - squared_sin_theta_b)
+    };
+    let sin_theta_b = safe_sqrt(cos_theta_b.mul_add(-cos_theta_b, 1.0));
+    let sin_theta_o = safe_sqrt(self.cos_theta_o.mul_add(-self.cos_theta_o, 1.0));
+    let cos_theta_x = cos_sub_clamped(sin_theta_w, cos_theta_w, sin_theta_o, self.cos_theta_o);
+    let sin_theta_x = sin_sub_clamped(sin_theta_w, cos_theta_w, sin_theta_o, self.cos_theta_o);
+    let cos_theta_p = cos_sub_clamped(sin_theta_x, cos_theta_x, sin_theta_b, cos_theta_b);
+    if cos_theta_p <= self.cos_theta_e {
+      return 0.0;
+    }
+    let mut importance = self.phi * cos_theta_p / squared_distance;
+    if normal.squared_length() > 0.0 {
+      let cos_theta_i = to_point.dot(&normal.unit_vector()).abs();
+      let sin_theta_i = safe_sqrt(cos_theta_i.mul_add(-cos_theta_i, 1.0));
+      importance *= cos_sub_clamped(sin_theta_i, cos_theta_i, sin_theta_b, cos_theta_b);
+    }
+    importance.max(0.0)
+  }
+  /// Surface area orientation heuristic cost of these bounds when splitting a node with `parent` bounds along `axis`
+  pub fn cost(&self, parent: &Aabb, axis: Vec3Index) -> Float {
+    let theta_o = self.cos_theta_o.clamp(-1.0, 1.0).acos();
+    let theta_e = self.cos_theta_e.clamp(-1.0, 1.0).acos();
+    let theta_w = (theta_o + theta_e).min(PI);
+    let sin_theta_o = safe_sqrt(self.cos_theta_o.mul_add(-self.cos_theta_o, 1.0));
+    let orientation_measure = (2.0 * PI).mul_add(
+      1.0 - self.cos_theta_o,
+      FRAC_PI_2 * (2.0 * (theta_w - theta_o)).mul_add(sin_theta_o, self.cos_theta_o - Float::mul_add(2.0, -theta_w, theta_o).cos()),
+    );
+    let diagonal = parent.diagonal();
+    let longest = diagonal.x().max(diagonal.y()).max(diagonal.z());
+    let regularization = if diagonal[axis] > 0.0 { longest / diagonal[axis] } else { 1.0 };
+    self.phi * orientation_measure * regularization * self.bounds.surface_area()
+  }
+}
@@ -0,0 +1,382 @@
+use crate::aabb::Aabb;
+use crate::float::Float;
+use crate::light::Light;
+use crate::light_bounds::LightBounds;
+use crate::vec3::{Vec3, Vec3Index};
+use std::fmt::Debug;
+
+const ONE_MINUS_EPSILON: Float = 1.0 - Float::EPSILON;
+const BUCKET_COUNT: usize = 12;
+
+/// Chooses which scene light a shading point samples for direct lighting
+pub trait LightSampler: Debug + Send + Sync {
+  /// Picks a light for the shading point at `point` with surface `normal`, returning its index and probability
+  fn sample(&self, point: &Vec3, normal: &Vec3, u: Float) -> Option<(usize, Float)>;
+  /// Probability that `sample` picks `light` from `point`
+  fn pmf(&self, point: &Vec3, normal: &Vec3, light: usize) -> Float;
+}
+
+/// Picks every light with the same probability
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct UniformLightSampler {
+  light_count: usize,
+}
+
+impl UniformLightSampler {
+  pub fn new(light_count: usize) -> Self {
+    Self { light_count }
+  }
+}
+
+impl LightSampler for UniformLightSampler {
+  #[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss, clippy::cast_sign_loss)]
+  fn sample(&self, _: &Vec3, _: &Vec3, u: Float) -> Option<(usize, Float)> {
+    if self.light_count == 0 {
+      return None;
+    }
+    let light = ((u * self.light_count as Float) as usize).min(self.light_count - 1);
+    Some((light, 1.0 / self.light_count as Float))
+  }
+  #[allow(clippy::cast_precision_loss)]
+  fn pmf(&self, _: &Vec3, _: &Vec3, _: usize) -> Float {
+    if self.light_count == 0 {
+      0.0
+    } else {
+      1.0 / self.light_count as Float
+    }
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum LightBvhNode {
+  Interior { bounds: LightBounds, second_child: usize },
+  Leaf { bounds: LightBounds, light: usize },
+}
+
+impl LightBvhNode {
+  fn bounds(&self) -> &LightBounds {
+    match self {
+      Self::Interior { bounds, .. } | Self::Leaf { bounds, .. } => bounds,
+    }
+  }
+}
+
+/// A bounding volume hierarchy over the bounded lights, traversed stochastically so that each light is picked
+/// roughly in proportion to its estimated contribution, after Conty Estevez and Kulla (2018)
+///
+/// Lights at infinity have no bounds and are picked uniformly alongside the hierarchy as a whole
+#[derive(Clone, Debug, PartialEq)]
+pub struct BvhLightSampler {
+  nodes: Vec<LightBvhNode>,
+  infinite_lights: Vec<usize>,
+  bit_trails: Vec<Option<u64>>,
+}
+
+impl BvhLightSampler {
+  pub fn new(lights: &[Box<dyn Light>]) -> Self {
+    let mut bounded_lights = Vec::new();
+    let mut infinite_lights = Vec::new();
+    lights.iter().enumerate().for_each(|(index, light)| match light.bounds() {
+      Some(bounds) if bounds.phi() > 0.0 => bounded_lights.push((index, bounds)),
+      Some(_) => {}
+      None => infinite_lights.push(index),
+    });
+    let mut sampler = Self {
+      nodes: Vec::new(),
+      infinite_lights,
+      bit_trails: vec![None; lights.len()],
+    };
+    if !bounded_lights.is_empty() {
+      sampler.build(&mut bounded_lights, 0, 0);
+    }
+    sampler
+  }
+  #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss, clippy::cast_precision_loss)]
+  fn bucket(centroid_bounds: &Aabb, centroid: &Vec3, axis: Vec3Index) -> usize {
+    ((BUCKET_COUNT as Float * centroid_bounds.offset(centroid)[axis]) as usize).min(BUCKET_COUNT - 1)
+  }
+  fn split(lights: &mut [(usize, LightBounds)], bounds: &Aabb) -> usize {
+    let centroid_bounds = lights[1..]
+      .iter()
+      .fold(Aabb::new_from_point(&lights[0].1.centroid()), |centroid_bounds, (_, light_bounds)| {
+        centroid_bounds.union_point(&light_bounds.centroid())
+      });
+    let best = Vec3Index::ALL
+      .iter()
+      .filter(|axis| centroid_bounds.maximum()[**axis] > centroid_bounds.minimum()[**axis])
+      .fold(None::<(Float, Vec3Index, usize)>, |best, axis| {
+        let mut buckets = [None::<LightBounds>; BUCKET_COUNT];
+        for (_, light_bounds) in lights.iter() {
+          let bucket = &mut buckets[Self::bucket(&centroid_bounds, &light_bounds.centroid(), *axis)];
+          *bucket = Some(bucket.map_or(*light_bounds, |existing| existing.union(light_bounds)));
+        }
+        let union = |buckets: &[Option<LightBounds>]| {
+          buckets.iter().flatten().fold(None::<LightBounds>, |total, bucket| {
+            Some(total.map_or(*bucket, |total| total.union(bucket)))
+          })
+        };
+        (0..BUCKET_COUNT - 1).fold(best, |best, last_below| {
+          let cost = [union(&buckets[..=last_below]), union(&buckets[last_below + 1..])]
+            .iter()
+            .flatten()
+            .map(|side| side.cost(bounds, *axis))
+            .sum::<Float>();
+          match best {
+            Some((best_cost, _, _)) if best_cost <= cost => best,
+            _ if cost > 0.0 => Some((cost, *axis, last_below)),
+            _ => best,
+          }
+        })
+      });
+    let middle = best.map_or(0, |(_, axis, last_below)| {
+      let mut below = 0;
+      (0..lights.len()).for_each(|index| {
+        if Self::bucket(&centroid_bounds, &lights[index].1.centroid(), axis) <= last_below {
+          lights.swap(index, below);
+          below += 1;
+        }
+      });
+      below
+    });
+    if middle == 0 || middle == lights.len() {
+      lights.len() / 2
+    } else {
+      middle
+    }
+  }
+  fn build(&mut self, lights: &mut [(usize, LightBounds)], bit_trail: u64, depth: u32) -> LightBounds {
+    if lights.len() == 1 {
+      let (light, bounds) = lights[0];
+      self.nodes.push(LightBvhNode::Leaf { bounds, light });
+      self.bit_trails[light] = Some(bit_trail);
+      return bounds;
+    }
+    let bounds = lights[1..]
+      .iter()
+      .fold(*lights[0].1.bounds(), |bounds, (_, light_bounds)| bounds.union(light_bounds.bounds()));
+    let middle = if depth >= 48 { lights.len() / 2 } else { Self::split(lights, &bounds) };
+    let node = self.nodes.len();
+    self.nodes.push(LightBvhNode::Leaf {
+      bounds: lights[0].1,
+      light: lights[0].0,
+    });
+    let (first, second) = lights.split_at_mut(middle);
+    let first_bounds = self.build(first, bit_trail, depth + 1);
+    let second_child = self.nodes.len();
+    let second_bounds = self.build(second, bit_trail | (1 << depth), depth + 1);
+    let bounds = first_bounds.union(&second_bounds);
+    self.nodes[node] = LightBvhNode::Interior { bounds, second_child };
+    bounds
+  }
+  #[allow(clippy::cast_precision_loss)]
+  fn infinite_probability(&self) -> Float {
+    let infinite_count = self.infinite_lights.len() as Float;
+    let tree_count = if self.nodes.is_empty() { 0.0 } else { 1.0 };
+    if infinite_count + tree_count > 0.0 {
+      infinite_count / (infinite_count + tree_count)
+    } else {
+      0.0
+    }
+  }
+}
+
+impl LightSampler for BvhLightSampler {
+  #[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss, clippy::cast_sign_loss)]
+  fn sample(&self, point: &Vec3, normal: &Vec3, u: Float) -> Option<(usize, Float)> {
+    let infinite_probability = self.infinite_probability();
+    if u < infinite_probability {
+      let count = self.infinite_lights.len();
+      let index = ((u / infinite_probability * count as Float) as usize).min(count - 1);
+      return Some((self.infinite_lights[index], infinite_probability / count as Float));
+    }
+    if self.nodes.is_empty() {
+      return None;
+    }
+    let mut u = ((u - infinite_probability) / (1.0 - infinite_probability)).min(ONE_MINUS_EPSILON);
+    let mut node = 0;
+    let mut pmf = 1.0 - infinite_probability;
+    loop {
+      match self.nodes[node] {
+        LightBvhNode::Interior { second_child, .. } => {
+          let first_importance = self.nodes[node + 1].bounds().importance(point, normal);
+          let second_importance = self.nodes[second_child].bounds().importance(point, normal);
+          if first_importance <= 0.0 && second_importance <= 0.0 {
+            return None;
+          }
+          let first_probability = first_importance / (first_importance + second_importance);
+          if u < first_probability {
+            node += 1;
+            u = (u / first_probability).min(ONE_MINUS_EPSILON);
+            pmf *= first_probability;
+          } else {
+            node = second_child;
+            u = ((u - first_probability) / (1.0 - first_probability)).min(ONE_MINUS_EPSILON);
+            pmf *= 1.0 - first_probability;
+          }
+        }
+        LightBvhNode::Leaf { bounds, light } => {
+          return if node > 0 || bounds.importance(point, normal) > 0.0 {
+            Some((light, pmf))
+          } else {
+            None
+          };
+        }
+      }
+    }
+  }
+  #[allow(clippy::cast_precision_loss)]
+  fn pmf(&self, point: &Vec3, normal: &Vec3, light: usize) -> Float {
+    let infinite_probability = self.infinite_probability();
+    let Some(mut bit_trail) = self.bit_trails.get(light).copied().flatten() else {
+      return if self.infinite_lights.contains(&light) {
+        infinite_probability / self.infinite_lights.len() as Float
+      } else {
+        0.0
+      };
+    };
+    let mut node = 0;
+    let mut pmf = 1.0 - infinite_probability;
+    while let LightBvhNode::Interior { second_child, .. } = self.nodes[node] {
+      let first_importance = self.nodes[node + 1].bounds().importance(point, normal);
+      let second_importance = self.nodes[second_child].bounds().importance(point, normal);
+      if first_importance + second_importance <= 0.0 {
+        return 0.0;
+      }
+      if bit_trail & 1 == 0 {
+        pmf *= first_importance / (first_importance + second_importance);
+        node += 1;
+      } else {
+        pmf *= second_importance / (first_importance + second_importance);
+        node = second_child;
+      }
+      bit_trail >>= 1;
+    }
+    pmf
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::float::random_float;
+  use crate::light::SphereLight;
+
+  /// Estimates the irradiance at `point` on an upward facing surface with one light sample per estimate
+  fn irradiance_estimates(sampler: &dyn LightSampler, lights: &[Box<dyn Light>], point: &Vec3, count: usize) -> Vec<Float> {
+    let normal = Vec3::new(0.0, 1.0, 0.0);
+    (0..count)
+      .map(|_| {
+        sampler
+          .sample(point, &normal, random_float())
+          .and_then(|(light, pmf)| lights[light].sample(point).map(|sample| (sample, pmf)))
+          .map_or(0.0, |(sample, pmf)| {
+            let cosine = sample.direction().dot(&normal).max(0.0);
+            sample.radiance().g() * cosine / (sample.pdf() * pmf)
+          })
+      })
+      .collect()
+  }
+
+  fn mean_and_variance(values: &[Float]) -> (Float, Float) {
+    #[allow(clippy::cast_precision_loss)]
+    let count = values.len() as Float;
+    let mean = values.iter().sum::<Float>() / count;
+    let variance = values.iter().map(|value| (value - mean) * (value - mean)).sum::<Float>() / (count - 1.0);
+    (mean, variance)
+  }
+
+  const LIGHT_RADIUS: Float = 0.1;
+  const LIGHT_RADIANCE: Float = 4.0;
+
+  /// Centers of a field of small emissive spheres like the many-lights scene, most of them far from any one shading point
+  #[allow(clippy::cast_precision_loss)]
+  fn light_centers() -> Vec<Vec3> {
+    fastrand::seed(7);
+    (-25..25)
+      .flat_map(|a| (-25..25).map(move |b| (a, b)))
+      .map(|(a, b)| {
+        Vec3::new(
+          Float::mul_add(0.9, random_float(), a as Float),
+          0.3,
+          Float::mul_add(0.9, random_float(), b as Float),
+        )
+      })
+      .collect()
+  }
+
+  fn many_lights(centers: &[Vec3]) -> Vec<Box<dyn Light>> {
+    centers
+      .iter()
+      .map(|center| {
+        let radiance = Vec3::new(LIGHT_RADIANCE, LIGHT_RADIANCE, LIGHT_RADIANCE);
+        Box::new(SphereLight::new(center, LIGHT_RADIUS, &radiance)) as Box<dyn Light>
+      })
+      .collect()
+  }
+
+  /// Exact irradiance at `point` on an upward facing surface, every sphere being wholly above its horizon
+  fn irradiance(centers: &[Vec3], point: &Vec3) -> Float {
+    centers
+      .iter()
+      .map(|center| {
+        let to_center = center - point;
+        let squared_sin_theta_max = LIGHT_RADIUS * LIGHT_RADIUS / to_center.squared_length();
+        LIGHT_RADIANCE * crate::float::consts::PI * squared_sin_theta_max * to_center.unit_vector().y()
+      })
+      .sum()
+  }
+
+  #[test]
+  fn light_bvh_reduces_variance_against_uniform_picking() {
+    let centers = light_centers();
+    let lights = many_lights(&centers);
+    let uniform = UniformLightSampler::new(lights.len());
+    let bvh = BvhLightSampler::new(&lights);
+    for point in &[Vec3::new(0.25, 0.0, 0.25), Vec3::new(-10.6, 0.0, 7.3), Vec3::new(20.1, 0.0, -18.4)] {
+      let expected = irradiance(&centers, point);
+      let (uniform_mean, uniform_variance) = mean_and_variance(&irradiance_estimates(&uniform, &lights, point, 20_000));
+      let (bvh_mean, bvh_variance) = mean_and_variance(&irradiance_estimates(&bvh, &lights, point, 20_000));
+      assert!(
+        (bvh_mean - expected).abs() < 0.05 * expected,
+        "bvh mean at {:?}: expected {}, estimated {}",
+        point,
+        expected,
+        bvh_mean
+      );
+      assert!(
+        (uniform_mean - expected).abs() < 0.25 * expected,
+        "uniform mean at {:?}: expected {}, estimated {}",
+        point,
+        expected,
+        uniform_mean
+      );
+      assert!(
+        bvh_variance * 4.0 < uniform_variance,
+        "variance at {:?}: uniform {}, bvh {}",
+        point,
+        uniform_variance,
+        bvh_variance
+      );
+    }
+  }
+
+  #[test]
+  fn light_bvh_pmf_matches_sampling() {
+    let lights = many_lights(&light_centers());
+    let bvh = BvhLightSampler::new(&lights);
+    let point = Vec3::new(1.5, 0.0, -2.5);
+    let normal = Vec3::new(0.0, 1.0, 0.0);
+    (0..1000).for_each(|_| {
+      if let Some((light, pmf)) = bvh.sample(&point, &normal, random_float()) {
+        let expected = bvh.pmf(&point, &normal, light);
+        assert!(
+          (pmf - expected).abs() <= 1e-4 * expected,
+          "light {}: sampled {}, pmf {}",
+          light,
+          pmf,
+          expected
+        );
+      }
+    });
+  }
+}
@@ -3,7 +3,7 @@
   clippy::dbg_macro,
   clippy::debug_assert_with_mut_call,
   clippy::doc_markdown,
-  clippy::empty_enum,
+  clippy::empty_enums,
   clippy::enum_glob_use,
   clippy::exit,
   clippy::explicit_into_iter_loop,
@@ -20,20 +20,16 @@
   clippy::map_err_ignore,
   clippy::map_flatten,
   clippy::map_unwrap_or,
-  clippy::match_on_vec_items,
   clippy::match_same_arms,
   clippy::match_wildcard_for_single_variants,
   clippy::mem_forget,
-  clippy::mismatched_target_os,
   clippy::needless_borrow,
   clippy::needless_continue,
   clippy::option_option,
-  clippy::pub_enum_variant_names,
   clippy::ref_option_ref,
   clippy::rest_pat_in_fully_bound_structs,
   clippy::string_add_assign,
   clippy::string_add,
-  clippy::string_to_string,
   clippy::suboptimal_flops,
   clippy::todo,
   clippy::unimplemented,
@@ -52,116 +48,295 @@
 )]
 #![warn(clippy::pedantic)]
 
-mod camera;
-mod hit;
-mod material;
-mod ray;
-mod sphere;
-mod vec3;
-
-use camera::Camera;
-use hit::{Hittable, HittableList};
-use material::{Dielectric, Lambertian, Metal};
-use ray::Ray;
-use sphere::Sphere;
+use rust_ray_tracer_in_one_weekend::background::Background;
+use rust_ray_tracer_in_one_weekend::bdpt::BdptIntegrator;
+use rust_ray_tracer_in_one_weekend::camera::Camera;
+use rust_ray_tracer_in_one_weekend::checkpoint::Checkpoint;
+use rust_ray_tracer_in_one_weekend::debug::{parse_debug_mode, sample_count_heatmap, DebugIntegrator, DebugMode};
+use rust_ray_tracer_in_one_weekend::denoise::Denoiser;
+use rust_ray_tracer_in_one_weekend::environment::EnvironmentMap;
+use rust_ray_tracer_in_one_weekend::film::Film;
+use rust_ray_tracer_in_one_weekend::filter::parse_filter;
+use rust_ray_tracer_in_one_weekend::float::Float;
+use rust_ray_tracer_in_one_weekend::framebuffer::Framebuffer;
+use rust_ray_tracer_in_one_weekend::integrator::{PathIntegrator, SimpleIntegrator};
+use rust_ray_tracer_in_one_weekend::light::parse_light;
+use rust_ray_tracer_in_one_weekend::material::{Isotropic, ScatterKind};
+use rust_ray_tracer_in_one_weekend::medium::ConstantMedium;
+use rust_ray_tracer_in_one_weekend::output::{write_exr, write_exr_sidecars};
+use rust_ray_tracer_in_one_weekend::progress::Progress;
+use rust_ray_tracer_in_one_weekend::renderer::{ColorMode, Renderer};
+use rust_ray_tracer_in_one_weekend::scene::Scene;
+use rust_ray_tracer_in_one_weekend::scenes::{caustics_scene, many_lights_scene, random_scene};
+use rust_ray_tracer_in_one_weekend::settings::{RenderSettings, SamplingSettings, Termination};
+use rust_ray_tracer_in_one_weekend::sky::{
+  PhysicalSky, DEFAULT_SKY_INTENSITY, DEFAULT_SUN_ANGULAR_RADIUS, DEFAULT_SUN_AZIMUTH, DEFAULT_SUN_ELEVATION, DEFAULT_TURBIDITY,
+};
+use rust_ray_tracer_in_one_weekend::sphere::Sphere;
+use rust_ray_tracer_in_one_weekend::sppm::SppmIntegrator;
+use rust_ray_tracer_in_one_weekend::stats::{take_ray_counts, Phase, Report};
+use rust_ray_tracer_in_one_weekend::vec3::Vec3;
+use rust_ray_tracer_in_one_weekend::Error;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
 use std::sync::Arc;
-use std::usize;
-use vec3::Vec3;
+use std::time::{Duration, Instant};
 
-fn color(ray: &Ray, world: &HittableList, depth: u8) -> Vec3 {
-  world.is_hit(ray, 0.001, f32::MAX).map_or_else(
-    || {
-      let unit_direction = ray.direction().unit_vector();
-      let lerp_factor = 0.5 * (unit_direction.y() + 1.0);
-      (1.0 - lerp_factor) as f32 * Vec3::new(1.0, 1.0, 1.0) + lerp_factor as f32 * Vec3::new(0.5, 0.7, 1.0)
-    },
-    |hit_record| {
-      if depth < 50 {
-        if let Some(scatter_result) = hit_record.material().scatter(ray, &hit_record) {
-          return scatter_result.attenuation() * color(scatter_result.scattered(), world, depth + 1);
-        }
-      }
-      Vec3::new(0.0, 0.0, 0.0)
-    },
+fn argument_value<'a>(arguments: &'a [String], flag: &str) -> Option<&'a str> {
+  arguments
+    .iter()
+    .position(|argument| argument == flag)
+    .and_then(|index| arguments.get(index + 1))
+    .map(String::as_str)
+}
+
+/// `arguments` with a random `--seed` added if they have none, so the render can be repeated or resumed
+fn with_seed(mut arguments: Vec<String>) -> Vec<String> {
+  if argument_value(&arguments, "--seed").is_none() {
+    arguments.push("--seed".to_string());
+    arguments.push(fastrand::u64(..).to_string());
+  }
+  arguments
+}
+
+/// The background requested on the command line, if any replaces the scene's own
+fn background(arguments: &[String]) -> Result<Option<Background>, Error> {
+  if let Some(path) = argument_value(arguments, "--environment") {
+    let rotation = argument_value(arguments, "--environment-rotation").map_or(Ok(0.0), str::parse)?;
+    let intensity = argument_value(arguments, "--environment-intensity").map_or(Ok(1.0), str::parse)?;
+    return Ok(Some(Background::Environment(EnvironmentMap::load(Path::new(path), rotation, intensity)?)));
+  }
+  let sky_flags = [
+    "--sky",
+    "--sun-elevation",
+    "--sun-azimuth",
+    "--sun-angular-radius",
+    "--turbidity",
+    "--sky-intensity",
+  ];
+  if arguments.iter().any(|argument| sky_flags.contains(&argument.as_str())) {
+    let sun_elevation = argument_value(arguments, "--sun-elevation").map_or(Ok(DEFAULT_SUN_ELEVATION), str::parse)?;
+    let sun_azimuth = argument_value(arguments, "--sun-azimuth").map_or(Ok(DEFAULT_SUN_AZIMUTH), str::parse)?;
+    let sun_angular_radius = argument_value(arguments, "--sun-angular-radius").map_or(Ok(DEFAULT_SUN_ANGULAR_RADIUS), str::parse)?;
+    let turbidity = argument_value(arguments, "--turbidity").map_or(Ok(DEFAULT_TURBIDITY), str::parse)?;
+    let intensity = argument_value(arguments, "--sky-intensity").map_or(Ok(DEFAULT_SKY_INTENSITY), str::parse)?;
+    return Ok(Some(Background::Sky(PhysicalSky::new(
+      sun_elevation,
+      sun_azimuth,
+      sun_angular_radius,
+      turbidity,
+      intensity,
+    ))));
+  }
+  Ok(None)
+}
+
+fn render_settings(arguments: &[String]) -> Result<RenderSettings, Error> {
+  let defaults = RenderSettings::default();
+  Ok(RenderSettings::new(
+    argument_value(arguments, "--roulette-depth").map_or(Ok(defaults.roulette_depth()), str::parse)?,
+    argument_value(arguments, "--max-diffuse-depth").map_or(Ok(defaults.max_depth(ScatterKind::Diffuse)), str::parse)?,
+    argument_value(arguments, "--max-glossy-depth").map_or(Ok(defaults.max_depth(ScatterKind::Glossy)), str::parse)?,
+    argument_value(arguments, "--max-transmission-depth").map_or(Ok(defaults.max_depth(ScatterKind::Transmission)), str::parse)?,
+    argument_value(arguments, "--max-volume-depth").map_or(Ok(defaults.max_depth(ScatterKind::Volume)), str::parse)?,
+  ))
+}
+
+/// The time, sample or noise limit given on the command line, if any, or else stopping once every pixel is done
+fn termination(arguments: &[String]) -> Result<Termination, Error> {
+  if let Some(seconds) = argument_value(arguments, "--time-limit") {
+    return Ok(Termination::TimeLimit(Duration::from_secs_f32(seconds.parse()?)));
+  }
+  if let Some(samples) = argument_value(arguments, "--sample-limit") {
+    return Ok(Termination::SampleLimit(samples.parse()?));
+  }
+  if let Some(target) = argument_value(arguments, "--noise-target") {
+    return Ok(Termination::NoiseTarget(target.parse()?));
+  }
+  Ok(Termination::Converged)
+}
+
+/// Fixed sampling, or adaptive sampling to the relative error given with `--adaptive`, capped at `--max-samples`. The
+/// cap defaults to `NUMBER_OF_SAMPLES_PER_PIXEL` unless a termination policy is given to stop the render instead
+fn sampling_settings(arguments: &[String], termination: Termination) -> Result<SamplingSettings, Error> {
+  let default_max_samples = if termination == Termination::Converged {
+    NUMBER_OF_SAMPLES_PER_PIXEL
+  } else {
+    usize::MAX
+  };
+  let max_samples = argument_value(arguments, "--max-samples").map_or(Ok(default_max_samples), str::parse)?;
+  match argument_value(arguments, "--adaptive") {
+    Some(max_relative_error) => SamplingSettings::adaptive(
+      argument_value(arguments, "--min-samples").map_or(Ok(16), str::parse)?,
+      max_samples,
+      max_relative_error.parse()?,
+    ),
+    None => SamplingSettings::fixed(max_samples),
+  }
+}
+
+const NUMBER_OF_X_PIXELS: usize = 200;
+const NUMBER_OF_Y_PIXELS: usize = 100;
+const NUMBER_OF_SAMPLES_PER_PIXEL: usize = 100;
+
+/// Renders a debug view of the scene with a fixed number of samples per pixel
+fn render_debug(mode: DebugMode, scene: &Scene, camera: &Camera) -> Result<Film, Error> {
+  Renderer::new(ColorMode::Rgb, SamplingSettings::fixed(NUMBER_OF_SAMPLES_PER_PIXEL)?, false, 0).render(
+    &DebugIntegrator::new(mode),
+    scene,
+    camera,
+    Checkpoint::new(&[], Film::new(NUMBER_OF_X_PIXELS, NUMBER_OF_Y_PIXELS)),
   )
 }
 
-fn create_random_scene() -> HittableList {
-  let mut random_array = HittableList::new();
-  random_array.list_mut().push(Box::new(Sphere::new(
-    &Vec3::new(0.0, -1000.0, 0.0),
-    1000.0,
-    Arc::new(Lambertian::new(&Vec3::new(0.5, 0.5, 0.5))),
-  )));
-  (-11..11).for_each(|a| {
-    (-11..11).for_each(|b| {
-      let choose_mat = fastrand::f32();
-      #[allow(clippy::cast_precision_loss)]
-      let center = Vec3::new(
-        0.9_f32.mul_add(fastrand::f32(), a as f32),
-        0.2,
-        0.9_f32.mul_add(fastrand::f32(), b as f32),
-      );
-      if (center - Vec3::new(4.0, 0.2, 2.0)).length() > 0.9 {
-        if choose_mat < 0.8 {
-          random_array.list_mut().push(Box::new(Sphere::new(
-            &center,
-            0.2,
-            Arc::new(Lambertian::new(&Vec3::new(
-              fastrand::f32() * fastrand::f32(),
-              fastrand::f32() * fastrand::f32(),
-              fastrand::f32() * fastrand::f32(),
-            ))),
-          )));
-        } else if choose_mat < 0.95 {
-          random_array.list_mut().push(Box::new(Sphere::new(
-            &center,
-            0.2,
-            Arc::new(Metal::new(
-              &Vec3::new(
-                0.5 * (1.0 + fastrand::f32()),
-                0.5 * (1.0 + fastrand::f32()),
-                0.5 * (1.0 + fastrand::f32()),
-              ),
-              0.5 * fastrand::f32(),
-            )),
-          )))
-        } else {
-          random_array
-            .list_mut()
-            .push(Box::new(Sphere::new(&center, 0.2, Arc::new(Dielectric::new(1.5)))))
+/// Renders the lit image with the integrator given on the command line, carrying on from `resumed` if it is given
+fn render_beauty(
+  arguments: &[String],
+  scene: &Scene,
+  camera: &Camera,
+  color_mode: ColorMode,
+  record_aovs: bool,
+  resumed: Option<Checkpoint>,
+) -> Result<Film, Error> {
+  let settings = render_settings(arguments)?;
+  let seed = argument_value(arguments, "--seed").map_or(Ok(0), str::parse)?;
+  let termination = termination(arguments)?;
+  let mut renderer = Renderer::new(color_mode, sampling_settings(arguments, termination)?, record_aovs, seed).with_termination(termination);
+  if let Some(threads) = argument_value(arguments, "--threads") {
+    renderer = renderer.with_threads(threads.parse()?);
+  }
+  if arguments.iter().any(|argument| argument == "--packets") {
+    renderer = renderer.with_packets(true);
+  }
+  let show_progress = !arguments.iter().any(|argument| argument == "--quiet");
+  renderer = renderer.with_progress(show_progress);
+  if let Some(path) = argument_value(arguments, "--checkpoint") {
+    let interval = argument_value(arguments, "--checkpoint-interval").map_or(Ok(60), str::parse)?;
+    renderer = renderer.with_checkpoints(Path::new(path), Duration::from_secs(interval));
+  }
+  let checkpoint = if let Some(checkpoint) = resumed {
+    checkpoint
+  } else {
+    let filter_radius = argument_value(arguments, "--filter-radius").map(str::parse).transpose()?;
+    let filter = parse_filter(argument_value(arguments, "--filter").unwrap_or("box"), filter_radius)?;
+    Checkpoint::new(arguments, Film::new(NUMBER_OF_X_PIXELS, NUMBER_OF_Y_PIXELS).with_filter(filter))
+  };
+  let film = match argument_value(arguments, "--integrator") {
+    None | Some("path") => renderer.render(&PathIntegrator::new(settings), scene, camera, checkpoint)?,
+    Some("simple") => renderer.render(&SimpleIntegrator::new(settings), scene, camera, checkpoint)?,
+    Some("bdpt") => {
+      let max_depth = argument_value(arguments, "--max-depth").map_or(Ok(8), str::parse)?;
+      renderer.render(&BdptIntegrator::new(scene, camera, max_depth), scene, camera, checkpoint)?
+    }
+    Some("sppm") => {
+      if color_mode == ColorMode::Spectral {
+        return Err("The sppm integrator renders in RGB only".into());
+      }
+      if checkpoint.passes() > 0 || argument_value(arguments, "--checkpoint").is_some() {
+        return Err("The sppm integrator cannot checkpoint or resume".into());
+      }
+      if termination != Termination::Converged {
+        return Err("The sppm integrator always renders a fixed number of passes".into());
+      }
+      let max_depth = argument_value(arguments, "--max-depth").map_or(Ok(8), str::parse)?;
+      let photons_per_pass = argument_value(arguments, "--photons").map_or(Ok(50_000), str::parse)?;
+      let initial_radius = argument_value(arguments, "--photon-radius").map_or(Ok(0.05), str::parse)?;
+      let mut film = Film::new(NUMBER_OF_X_PIXELS, NUMBER_OF_Y_PIXELS);
+      // Each pass traces one camera ray through every pixel
+      let pixels = NUMBER_OF_X_PIXELS * NUMBER_OF_Y_PIXELS;
+      let progress = show_progress.then(|| Progress::new(Some(pixels * NUMBER_OF_SAMPLES_PER_PIXEL), None));
+      SppmIntegrator::new(photons_per_pass, initial_radius, max_depth).render(scene, camera, &mut film, NUMBER_OF_SAMPLES_PER_PIXEL, |_, _| {
+        if let Some(progress) = &progress {
+          progress.add_samples(pixels);
         }
+      });
+      if let Some(progress) = &progress {
+        progress.finish();
       }
-    })
-  });
-  random_array
-    .list_mut()
-    .push(Box::new(Sphere::new(&Vec3::new(0.0, 1.0, 0.0), 1.0, Arc::new(Dielectric::new(1.5)))));
-  random_array.list_mut().push(Box::new(Sphere::new(
-    &Vec3::new(-4.0, 1.0, 0.0),
-    1.0,
-    Arc::new(Lambertian::new(&Vec3::new(0.4, 0.2, 0.1))),
-  )));
-  random_array.list_mut().push(Box::new(Sphere::new(
-    &Vec3::new(4.0, 1.0, 0.0),
-    1.0,
-    Arc::new(Metal::new(&Vec3::new(0.7, 0.6, 0.5), 0.0)),
-  )));
-  random_array
+      film
+    }
+    Some(name) => return Err(format!("Unknown integrator {name}").into()),
+  };
+  Ok(film)
 }
 
-#[allow(
-  clippy::cast_possible_truncation,
-  clippy::cast_precision_loss,
-  clippy::cast_sign_loss,
-  clippy::similar_names
-)]
-fn main() {
-  let number_of_x_pixels = 200;
-  let number_of_y_pixels = 100;
-  let number_of_samples_per_pixel = 100;
-  println!("P3\n{} {}\n255", number_of_x_pixels, number_of_y_pixels);
-  let world = create_random_scene();
+/// Writes each `--aov <mode>=<path>` given, rendering debug modes afresh and the sample count heatmap from `film`
+fn write_aovs(arguments: &[String], film: &Film, scene: &Scene, camera: &Camera, depth_range: Float) -> Result<(), Error> {
+  for (_, aov) in arguments.iter().zip(arguments.iter().skip(1)).filter(|(flag, _)| *flag == "--aov") {
+    let (mode, path) = aov
+      .split_once('=')
+      .ok_or_else(|| format!("Expected <mode>=<path> after --aov, found {aov}"))?;
+    if mode == "sample-count" {
+      let heatmap = sample_count_heatmap(film);
+      Framebuffer::from_film(&heatmap, 1).write_ppm(false, &mut BufWriter::new(File::create(path)?))?;
+      continue;
+    }
+    let aov_film = render_debug(parse_debug_mode(mode, depth_range)?, scene, camera)?;
+    Framebuffer::from_film(&aov_film, NUMBER_OF_SAMPLES_PER_PIXEL).write_ppm(false, &mut BufWriter::new(File::create(path)?))?;
+  }
+  Ok(())
+}
+
+/// Builds the scene the command line asks for, adding the time spent building its BVHs to `report`
+fn build_scene(arguments: &[String], seed: u64, report: &mut Report) -> Result<Scene, Error> {
+  let mut scene = match argument_value(arguments, "--scene") {
+    None | Some("random") => random_scene(seed),
+    Some("many-lights") => many_lights_scene(seed),
+    Some("caustics") => caustics_scene(),
+    Some(name) => return Err(format!("Unknown scene {name}").into()),
+  };
+  if let Some(density) = argument_value(arguments, "--fog") {
+    let phase_function = Arc::new(Isotropic::new(&Vec3::new(0.9, 0.9, 0.9)));
+    let boundary = Sphere::new(&Vec3::new(0.0, 0.0, 0.0), 100.0, phase_function.clone());
+    scene
+      .world_mut()
+      .list_mut()
+      .push(Box::new(ConstantMedium::new(Box::new(boundary), density.parse()?, phase_function)));
+  }
+  report.time(Phase::BvhBuild, || scene.world_mut().build_bvh());
+  if let Some(background) = background(arguments)? {
+    scene.set_background(background);
+  }
+  arguments
+    .iter()
+    .zip(arguments.iter().skip(1))
+    .filter(|(flag, _)| *flag == "--light")
+    .try_for_each(|(_, description)| {
+      scene.add_light(parse_light(description)?);
+      Ok::<(), Error>(())
+    })?;
+  match argument_value(arguments, "--light-sampler") {
+    None | Some("bvh") => report.time(Phase::BvhBuild, || scene.build_light_bvh()),
+    Some("uniform") => {}
+    Some(name) => return Err(format!("Unknown light sampler {name}").into()),
+  }
+  Ok(scene)
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn main() -> Result<(), Error> {
+  let (arguments, resumed) = match argument_value(&std::env::args().collect::<Vec<_>>(), "--resume") {
+    Some(path) => {
+      let checkpoint = Checkpoint::load(Path::new(path))?;
+      (checkpoint.arguments().to_vec(), Some(checkpoint))
+    }
+    None => (with_seed(std::env::args().skip(1).collect()), None),
+  };
+  let seed = argument_value(&arguments, "--seed").map_or(Ok(0), str::parse)?;
+  let color_mode = if arguments.iter().any(|argument| argument == "--spectral") {
+    ColorMode::Spectral
+  } else {
+    ColorMode::Rgb
+  };
+  let stats_format = argument_value(&arguments, "--stats");
+  if let Some(format) = stats_format.filter(|format| !["text", "json"].contains(format)) {
+    return Err(format!("Unknown stats format {format}, expected text or json").into());
+  }
+  let mut report = Report::new();
+  let scene_start = Instant::now();
+  let scene = build_scene(&arguments, seed, &mut report)?;
+  report.add(Phase::SceneBuild, scene_start.elapsed().saturating_sub(report.duration(Phase::BvhBuild)));
   let look_from = Vec3::new(13.0, 2.0, 3.0);
   let look_at = Vec3::new(0.0, 0.0, 0.0);
   let distance_to_focus = 10.0;
@@ -171,25 +346,51 @@ fn main() {
     &look_at,
     &Vec3::new(0.0, 1.0, 0.0),
     20.0,
-    number_of_x_pixels as f32 / number_of_y_pixels as f32,
+    NUMBER_OF_X_PIXELS as Float / NUMBER_OF_Y_PIXELS as Float,
     aperature,
     distance_to_focus,
   );
-  (0..number_of_y_pixels).rev().for_each(|current_y_pixel| {
-    (0..number_of_x_pixels).for_each(|current_x_pixel| {
-      let mut pixel_color = Vec3::new(0.0, 0.0, 0.0);
-      (0..number_of_samples_per_pixel).for_each(|_| {
-        let u = (current_x_pixel as f32 + fastrand::f32()) / number_of_x_pixels as f32;
-        let v = (current_y_pixel as f32 + fastrand::f32()) / number_of_y_pixels as f32;
-        let ray = camera.get_ray(u, v);
-        pixel_color += color(&ray, &world, 0);
-      });
-      pixel_color /= number_of_samples_per_pixel as f32;
-      pixel_color = Vec3::new(pixel_color.x().sqrt(), pixel_color.y().sqrt(), pixel_color.z().sqrt());
-      let red_value = (255.99 * pixel_color.r()) as usize;
-      let green_value = (255.99 * pixel_color.g()) as usize;
-      let blue_value = (255.99 * pixel_color.b()) as usize;
-      println!("{} {} {}", red_value, green_value, blue_value);
-    })
-  })
+  let depth_range = argument_value(&arguments, "--depth-range").map_or(Ok(20.0), str::parse)?;
+  let exr_path = argument_value(&arguments, "--exr").map(Path::new);
+  let denoise_iterations = argument_value(&arguments, "--denoise").map(str::parse).transpose()?;
+  let render_start = Instant::now();
+  let mut film = match argument_value(&arguments, "--debug") {
+    Some(mode) => render_debug(parse_debug_mode(mode, depth_range)?, &scene, &camera)?,
+    None => render_beauty(
+      &arguments,
+      &scene,
+      &camera,
+      color_mode,
+      exr_path.is_some() || denoise_iterations.is_some(),
+      resumed,
+    )?,
+  };
+  let samples_per_pixel = film.average_sample_count();
+  report.add(Phase::Render, render_start.elapsed());
+  if let Some(iterations) = denoise_iterations {
+    let color_sigma = argument_value(&arguments, "--denoise-sigma").map_or(Ok(4.0), str::parse)?;
+    film = report.time(Phase::Denoise, || {
+      Denoiser::new(iterations, color_sigma).denoise(&film, samples_per_pixel)
+    })?;
+  }
+  let output_start = Instant::now();
+  let is_debug = argument_value(&arguments, "--debug").is_some();
+  let output_path = argument_value(&arguments, "--output").unwrap_or("image.ppm");
+  Framebuffer::from_film(&film, samples_per_pixel).write_ppm(!is_debug, &mut BufWriter::new(File::create(output_path)?))?;
+  match exr_path {
+    Some(path) if arguments.iter().any(|argument| argument == "--exr-sidecars") => {
+      write_exr_sidecars(&film, samples_per_pixel, path)?;
+    }
+    Some(path) => write_exr(&film, samples_per_pixel, path)?,
+    None => {}
+  }
+  report.add(Phase::Output, output_start.elapsed());
+  report.time(Phase::Render, || write_aovs(&arguments, &film, &scene, &camera, depth_range))?;
+  report = report.with_counts(take_ray_counts());
+  match stats_format {
+    Some("json") => eprintln!("{}", report.to_json()),
+    Some(_) => eprintln!("{report}"),
+    None => {}
+  }
+  Ok(())
 }
@@ -1,13 +1,21 @@
+use crate::float::consts::PI;
+use crate::float::{random_float, Float};
 use crate::hit::HitRecord;
 use crate::ray::Ray;
 use crate::vec3::Vec3;
 use dyn_clone::{clone_trait_object, DynClone};
+use std::collections::hash_map::DefaultHasher;
 use std::fmt::Debug;
+use std::hash::{Hash, Hasher};
+
+fn random_unit_vector() -> Vec3 {
+  random_in_unit_sphere().unit_vector()
+}
 
 fn random_in_unit_sphere() -> Vec3 {
   let point_in_unit_sphere;
   loop {
-    let potential_point_in_unit_sphere = 2.0 * Vec3::new(fastrand::f32(), fastrand::f32(), fastrand::f32()) - Vec3::new(1.0, 1.0, 1.0);
+    let potential_point_in_unit_sphere = 2.0 * Vec3::new(random_float(), random_float(), random_float()) - Vec3::new(1.0, 1.0, 1.0);
     if potential_point_in_unit_sphere.squared_length() < 1.0 {
       point_in_unit_sphere = potential_point_in_unit_sphere;
       break;
@@ -16,15 +24,43 @@ fn random_in_unit_sphere() -> Vec3 {
   point_in_unit_sphere
 }
 
+/// The kind of bounce a scattered ray makes, each limited by its own depth budget
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScatterKind {
+  Diffuse,
+  Glossy,
+  Transmission,
+  Volume,
+}
+
+impl ScatterKind {
+  pub const ALL: [Self; 4] = [Self::Diffuse, Self::Glossy, Self::Transmission, Self::Volume];
+}
+
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct ScatterResult {
   attenuation: Vec3,
   scattered: Ray,
+  pdf: Option<Float>,
+  kind: ScatterKind,
 }
 
 impl ScatterResult {
-  pub fn new(attenuation: Vec3, scattered: Ray) -> Self {
-    Self { attenuation, scattered }
+  pub fn new(attenuation: Vec3, scattered: Ray, kind: ScatterKind) -> Self {
+    Self {
+      attenuation,
+      scattered,
+      pdf: None,
+      kind,
+    }
+  }
+  pub fn new_with_pdf(attenuation: Vec3, scattered: Ray, pdf: Float, kind: ScatterKind) -> Self {
+    Self {
+      attenuation,
+      scattered,
+      pdf: Some(pdf),
+      kind,
+    }
   }
 }
 
@@ -35,14 +71,41 @@ impl ScatterResult {
   pub fn scattered(&self) -> &Ray {
     &self.scattered
   }
+  /// The solid angle density `scattered` was drawn with, or None if it was a discrete (specular) choice
+  pub fn pdf(&self) -> Option<Float> {
+    self.pdf
+  }
+  pub fn kind(&self) -> ScatterKind {
+    self.kind
+  }
 }
 
-pub trait Material: Debug + DynClone {
+pub trait Material: Debug + DynClone + Send + Sync {
   fn scatter(&self, ray_in: &Ray, hit_record: &HitRecord) -> Option<ScatterResult>;
+  /// Returns the BSDF times the cosine term towards `direction` and the density `scatter` would pick it with, or
+  /// None for materials that only scatter into discrete directions and so cannot be reached by light sampling
+  fn evaluate(&self, _ray_in: &Ray, _hit_record: &HitRecord, _direction: &Vec3) -> Option<(Vec3, Float)> {
+    None
+  }
+  fn emitted(&self, _ray_in: &Ray, _hit_record: &HitRecord) -> Vec3 {
+    Vec3::new_empty()
+  }
+  /// Fraction of light the material reflects or transmits overall, for debug output
+  fn albedo(&self) -> Vec3 {
+    Vec3::new(1.0, 1.0, 1.0)
+  }
 }
 
 clone_trait_object!(Material);
 
+/// A stable ID for materials with the same type and parameters, hashed from their debug representation
+#[allow(clippy::cast_possible_truncation)]
+pub fn material_id(material: &dyn Material) -> u32 {
+  let mut hasher = DefaultHasher::new();
+  format!("{material:?}").hash(&mut hasher);
+  hasher.finish() as u32
+}
+
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Lambertian {
   albedo: Vec3,
@@ -52,16 +115,24 @@ impl Lambertian {
   pub fn new(albedo: &Vec3) -> Self {
     Self { albedo: *albedo }
   }
-  pub fn albedo(&self) -> &Vec3 {
-    &self.albedo
-  }
 }
 
 impl Material for Lambertian {
   fn scatter(&self, _: &Ray, hit_record: &HitRecord) -> Option<ScatterResult> {
-    let target = hit_record.point() + hit_record.normal() + random_in_unit_sphere();
-    let scattered = Ray::new(hit_record.point(), &(target - hit_record.point()));
-    Some(ScatterResult::new(self.albedo, scattered))
+    let mut direction = hit_record.normal() + random_unit_vector();
+    if direction.squared_length() < 1e-12 {
+      direction = *hit_record.normal();
+    }
+    let cosine = direction.unit_vector().dot(hit_record.normal()).max(0.0);
+    let scattered = hit_record.spawn_ray(&direction);
+    Some(ScatterResult::new_with_pdf(self.albedo, scattered, cosine / PI, ScatterKind::Diffuse))
+  }
+  fn evaluate(&self, _: &Ray, hit_record: &HitRecord, direction: &Vec3) -> Option<(Vec3, Float)> {
+    let cosine = direction.unit_vector().dot(hit_record.normal()).max(0.0);
+    Some((self.albedo * (cosine / PI), cosine / PI))
+  }
+  fn albedo(&self) -> Vec3 {
+    self.albedo
   }
 }
 
@@ -72,18 +143,15 @@ fn reflect(v: &Vec3, n: &Vec3) -> Vec3 {
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Metal {
   albedo: Vec3,
-  fuzz: f32,
+  fuzz: Float,
 }
 
 impl Metal {
-  pub fn new(albedo: &Vec3, fuzz: f32) -> Self {
+  pub fn new(albedo: &Vec3, fuzz: Float) -> Self {
     let fuzz = if fuzz < 0.0 { 0.0 } else { fuzz };
     Self { albedo: *albedo, fuzz }
   }
-  pub fn albedo(&self) -> &Vec3 {
-    &self.albedo
-  }
-  pub fn fuzz(&self) -> f32 {
+  pub fn fuzz(&self) -> Float {
     self.fuzz
   }
 }
@@ -91,19 +159,22 @@ impl Metal {
 impl Material for Metal {
   fn scatter(&self, ray_in: &Ray, hit_record: &HitRecord) -> Option<ScatterResult> {
     let reflected = reflect(&ray_in.direction().unit_vector(), hit_record.normal());
-    let scattered = Ray::new(hit_record.point(), &(reflected + self.fuzz() * random_in_unit_sphere()));
+    let scattered = hit_record.spawn_ray(&(reflected + self.fuzz() * random_in_unit_sphere()));
     if scattered.direction().dot(hit_record.normal()) > 0.0 {
-      Some(ScatterResult::new(self.albedo, scattered))
+      Some(ScatterResult::new(self.albedo, scattered, ScatterKind::Glossy))
     } else {
       None
     }
   }
+  fn albedo(&self) -> Vec3 {
+    self.albedo
+  }
 }
 
-fn refract(v: &Vec3, n: &Vec3, ni_over_nt: f32) -> Option<Vec3> {
+fn refract(v: &Vec3, n: &Vec3, ni_over_nt: Float) -> Option<Vec3> {
   let unit_vector = v.unit_vector();
   let dt = unit_vector.dot(n);
-  let discriminant = 1.0 - ni_over_nt * ni_over_nt * (1.0 - dt * dt);
+  let discriminant = (ni_over_nt * ni_over_nt).mul_add(-dt.mul_add(-dt, 1.0), 1.0);
   if discriminant > 0.0 {
     Some(ni_over_nt * (unit_vector - n * dt) - n * discriminant.sqrt())
   } else {
@@ -111,7 +182,7 @@ fn refract(v: &Vec3, n: &Vec3, ni_over_nt: f32) -> Option<Vec3> {
   }
 }
 
-fn schlick(cosine: f32, ref_idx: f32) -> f32 {
+fn schlick(cosine: Float, ref_idx: Float) -> Float {
   let r0 = (1.0 - ref_idx) / (1.0 + ref_idx);
   let r0 = r0 * r0;
   (1.0 - r0).mul_add((1.0 - cosine).powi(5), r0)
@@ -119,11 +190,11 @@ fn schlick(cosine: f32, ref_idx: f32) -> f32 {
 
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Dielectric {
-  ref_idx: f32,
+  ref_idx: Float,
 }
 
 impl Dielectric {
-  pub fn new(ref_idx: f32) -> Self {
+  pub fn new(ref_idx: Float) -> Self {
     Self { ref_idx }
   }
 }
@@ -133,8 +204,15 @@ impl Material for Dielectric {
     let reflected = reflect(ray_in.direction(), hit_record.normal());
     let attenuation = Vec3::new(1.0, 1.0, 1.0);
     let (outward_normal, ni_over_nt, cosine) = if ray_in.direction().dot(hit_record.normal()) > 0.0 {
-      let cosine = self.ref_idx * ray_in.direction().dot(hit_record.normal()) / ray_in.direction().length();
-      (-*hit_record.normal(), self.ref_idx, cosine)
+      // Reflectance is taken at the angle on the outside, where light passing either way makes the same angle, so
+      // glass reflects as much light leaving it as entering it along the same path. Past the critical angle the
+      // cosine is zero, but then all light is reflected anyway
+      let inside_cosine = ray_in.direction().dot(hit_record.normal()) / ray_in.direction().length();
+      let outside_cosine = (self.ref_idx * self.ref_idx)
+        .mul_add(inside_cosine.mul_add(inside_cosine, -1.0), 1.0)
+        .max(0.0)
+        .sqrt();
+      (-*hit_record.normal(), self.ref_idx, outside_cosine)
     } else {
       let cosine = -ray_in.direction().dot(hit_record.normal()) / ray_in.direction().length();
       (*hit_record.normal(), 1.0 / self.ref_idx, cosine)
@@ -145,14 +223,415 @@ impl Material for Dielectric {
     } else {
       1.0
     };
-    let scattered = if fastrand::f32() < reflect_probability {
-      Ray::new(hit_record.point(), &reflected)
+    let (scattered, kind) = if random_float() < reflect_probability {
+      (hit_record.spawn_ray(&reflected), ScatterKind::Glossy)
     } else {
       refraction_result.map_or_else(
         || unreachable!("Refraction not possible"),
-        |refracted| Ray::new(hit_record.point(), &refracted),
+        |refracted| (hit_record.spawn_ray(&refracted), ScatterKind::Transmission),
       )
     };
-    Some(ScatterResult::new(attenuation, scattered))
+    Some(ScatterResult::new(attenuation, scattered, kind))
+  }
+}
+
+/// Emits constant radiance from the front of a surface and scatters nothing
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DiffuseLight {
+  radiance: Vec3,
+}
+
+impl DiffuseLight {
+  pub fn new(radiance: &Vec3) -> Self {
+    Self { radiance: *radiance }
+  }
+}
+
+impl Material for DiffuseLight {
+  fn scatter(&self, _: &Ray, _: &HitRecord) -> Option<ScatterResult> {
+    None
+  }
+  fn emitted(&self, ray_in: &Ray, hit_record: &HitRecord) -> Vec3 {
+    if ray_in.direction().dot(hit_record.normal()) < 0.0 {
+      self.radiance
+    } else {
+      Vec3::new_empty()
+    }
+  }
+  fn albedo(&self) -> Vec3 {
+    Vec3::new_empty()
+  }
+}
+
+/// Scatters light equally in every direction, the phase function of a participating medium
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Isotropic {
+  albedo: Vec3,
+}
+
+impl Isotropic {
+  pub fn new(albedo: &Vec3) -> Self {
+    Self { albedo: *albedo }
+  }
+}
+
+impl Material for Isotropic {
+  fn scatter(&self, _: &Ray, hit_record: &HitRecord) -> Option<ScatterResult> {
+    let scattered = hit_record.spawn_ray(&random_unit_vector());
+    Some(ScatterResult::new_with_pdf(self.albedo, scattered, 1.0 / (4.0 * PI), ScatterKind::Volume))
+  }
+  fn evaluate(&self, _: &Ray, _: &HitRecord, _: &Vec3) -> Option<(Vec3, Float)> {
+    Some((self.albedo / (4.0 * PI), 1.0 / (4.0 * PI)))
+  }
+  fn albedo(&self) -> Vec3 {
+    self.albedo
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::sync::Arc;
+
+  /// Bins of the cosine of the polar angle about +z, an even number so none straddles the horizon
+  const COSINE_BINS: usize = 10;
+  /// Bins of the azimuth about +z
+  const AZIMUTH_BINS: usize = 20;
+  /// Samples taken per chi-square test
+  const CHI_SQUARE_SAMPLES: usize = 200_000;
+
+  /// A hit at the origin on a surface facing +z
+  fn hit_record(material: Arc<dyn Material>) -> HitRecord {
+    HitRecord::new(1.0, &Vec3::new_empty(), &Vec3::new(0.0, 0.0, 1.0), material)
+  }
+
+  /// Ray arriving at the origin from `direction`, a unit vector pointing away from the surface
+  fn arriving_from(direction: &Vec3) -> Ray {
+    Ray::new(&(*direction * 2.0), &-*direction)
+  }
+
+  /// Unit vector at `cosine` to +z and `azimuth` radians around it
+  fn direction(cosine: f64, azimuth: f64) -> Vec3 {
+    let sine = cosine.mul_add(-cosine, 1.0).max(0.0).sqrt();
+    #[allow(clippy::cast_possible_truncation)]
+    Vec3::new((sine * azimuth.cos()) as Float, (sine * azimuth.sin()) as Float, cosine as Float)
+  }
+
+  /// Bin of `direction` in a grid of `COSINE_BINS` by `AZIMUTH_BINS` equal areas of the sphere
+  #[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss, clippy::cast_sign_loss)]
+  fn bin(direction: &Vec3) -> usize {
+    let direction = direction.unit_vector();
+    let cosine = f64::from(direction.z());
+    let azimuth = f64::from(direction.y()).atan2(f64::from(direction.x())).rem_euclid(std::f64::consts::TAU);
+    let cosine_bin = ((f64::midpoint(cosine, 1.0) * COSINE_BINS as f64) as usize).min(COSINE_BINS - 1);
+    let azimuth_bin = ((azimuth / std::f64::consts::TAU * AZIMUTH_BINS as f64) as usize).min(AZIMUTH_BINS - 1);
+    cosine_bin * AZIMUTH_BINS + azimuth_bin
+  }
+
+  /// Middle of the `step`th of `steps` equal parts of the unit interval
+  #[allow(clippy::cast_precision_loss)]
+  fn step_center(step: usize, steps: usize) -> f64 {
+    (step as f64 + 0.5) / steps as f64
+  }
+
+  /// Probability of each bin under the solid angle density `pdf`, integrated over a grid of points inside each bin
+  #[allow(clippy::cast_precision_loss)]
+  fn bin_probabilities(pdf: impl Fn(&Vec3) -> f64) -> Vec<f64> {
+    const STEPS: usize = 32;
+    let cosine_width = 2.0 / COSINE_BINS as f64;
+    let azimuth_width = std::f64::consts::TAU / AZIMUTH_BINS as f64;
+    let step_area = cosine_width * azimuth_width / (STEPS * STEPS) as f64;
+    (0..COSINE_BINS * AZIMUTH_BINS)
+      .map(|index| {
+        let (cosine_start, azimuth_start) = (
+          ((index / AZIMUTH_BINS) as f64).mul_add(cosine_width, -1.0),
+          (index % AZIMUTH_BINS) as f64 * azimuth_width,
+        );
+        let mut probability = 0.0;
+        for cosine_step in 0..STEPS {
+          for azimuth_step in 0..STEPS {
+            let cosine = step_center(cosine_step, STEPS).mul_add(cosine_width, cosine_start);
+            let azimuth = step_center(azimuth_step, STEPS).mul_add(azimuth_width, azimuth_start);
+            probability += pdf(&direction(cosine, azimuth)) * step_area;
+          }
+        }
+        probability
+      })
+      .collect()
+  }
+
+  /// Pearson's chi-square test of `observed` counts against the counts `expected` by the density, merging bins expected
+  /// to see fewer than five samples into one. Fails if the counts are further off than chance would put them more than
+  /// once in ten thousand runs, judged by the Wilson–Hilferty approximation of the chi-square distribution
+  #[allow(clippy::cast_precision_loss)]
+  fn assert_chi_square_fits(name: &str, observed: &[f64], expected: &[f64]) {
+    let (mut statistic, mut bins) = (0.0, 0);
+    let (mut pooled_observed, mut pooled_expected) = (0.0, 0.0);
+    for (observed, expected) in observed.iter().zip(expected) {
+      if *expected < 5.0 {
+        pooled_observed += observed;
+        pooled_expected += expected;
+      } else {
+        statistic += (observed - expected).powi(2) / expected;
+        bins += 1;
+      }
+    }
+    if pooled_expected > 0.0 {
+      statistic += (pooled_observed - pooled_expected).powi(2) / pooled_expected;
+      bins += 1;
+    } else {
+      assert!(
+        pooled_observed == 0.0,
+        "{} sampled {} directions the density never gives",
+        name,
+        pooled_observed
+      );
+    }
+    let degrees_of_freedom = f64::from(bins - 1);
+    let spread = 2.0 / (9.0 * degrees_of_freedom);
+    let z = ((statistic / degrees_of_freedom).cbrt() - (1.0 - spread)) / spread.sqrt();
+    assert!(
+      z < 3.72,
+      "{} fails the chi-square test: {:.1} over {} bins, z = {:.2}",
+      name,
+      statistic,
+      bins,
+      z
+    );
+  }
+
+  /// Counts in each bin of the directions `material` scatters light arriving from `incoming` into, with those it
+  /// absorbs counted in the last, extra bin
+  #[allow(clippy::cast_precision_loss)]
+  fn scattered_counts(material: &Arc<dyn Material>, incoming: &Vec3) -> Vec<f64> {
+    let hit_record = hit_record(material.clone());
+    let mut counts = vec![0.0; COSINE_BINS * AZIMUTH_BINS + 1];
+    for _ in 0..CHI_SQUARE_SAMPLES {
+      match material.scatter(&arriving_from(incoming), &hit_record) {
+        Some(result) => counts[bin(result.scattered().direction())] += 1.0,
+        None => counts[COSINE_BINS * AZIMUTH_BINS] += 1.0,
+      }
+    }
+    counts
+  }
+
+  /// Sum of `value` over a grid of directions covering the sphere, times the solid angle each stands for
+  #[allow(clippy::cast_precision_loss)]
+  fn integrate_over_sphere(value: impl Fn(&Vec3) -> f64) -> f64 {
+    const STEPS: usize = 400;
+    let step_area = 2.0 * std::f64::consts::TAU / (STEPS * STEPS) as f64;
+    let mut integral = 0.0;
+    for cosine_step in 0..STEPS {
+      for azimuth_step in 0..STEPS {
+        let cosine = step_center(cosine_step, STEPS).mul_add(2.0, -1.0);
+        let azimuth = step_center(azimuth_step, STEPS) * std::f64::consts::TAU;
+        integral += value(&direction(cosine, azimuth)) * step_area;
+      }
+    }
+    integral
+  }
+
+  /// Fraction of `SAMPLES` the dielectric reflects rather than refracts, when light arrives from `incoming` on the
+  /// side of a surface whose normal is `normal`
+  #[allow(clippy::cast_precision_loss)]
+  fn reflected_fraction(dielectric: Dielectric, incoming: &Vec3, normal: &Vec3) -> f64 {
+    const SAMPLES: usize = 100_000;
+    let hit_record = HitRecord::new(1.0, &Vec3::new_empty(), normal, Arc::new(dielectric));
+    let reflections = (0..SAMPLES)
+      .filter_map(|_| dielectric.scatter(&arriving_from(incoming), &hit_record))
+      .filter(|result| result.kind() == ScatterKind::Glossy)
+      .count();
+    reflections as f64 / SAMPLES as f64
+  }
+
+  /// Monte Carlo estimate of the light `material` sends on when light arrives from `incoming` on a surface whose
+  /// normal is `normal`, averaging the weight of each sampled direction: the BSDF times the cosine over the density
+  /// of the direction where `scatter` reports one, otherwise the attenuation, and zero where it absorbs the light
+  #[allow(clippy::cast_precision_loss)]
+  fn mean_scattered_weight(material: &Arc<dyn Material>, incoming: &Vec3, normal: &Vec3) -> f64 {
+    const SAMPLES: usize = 100_000;
+    let hit_record = HitRecord::new(1.0, &Vec3::new_empty(), normal, material.clone());
+    let ray_in = arriving_from(incoming);
+    let total: f64 = (0..SAMPLES)
+      .filter_map(|_| material.scatter(&ray_in, &hit_record))
+      .map(|result| match result.pdf() {
+        Some(pdf) => material
+          .evaluate(&ray_in, &hit_record, result.scattered().direction())
+          .map_or(0.0, |(value, _)| f64::from(value.x() / pdf)),
+        None => f64::from(result.attenuation().x()),
+      })
+      .sum();
+    total / SAMPLES as f64
+  }
+
+  #[test]
+  fn white_furnace_returns_all_light_from_non_absorbing_materials() {
+    let white = Vec3::new(1.0, 1.0, 1.0);
+    // Under light of one from every direction, light leaving a surface is the BSDF times the cosine integrated over
+    // the sphere
+    let lambertian = Lambertian::new(&white);
+    let hit_record = hit_record(Arc::new(lambertian));
+    let incoming = direction(0.6, 1.0);
+    let reflected = integrate_over_sphere(|outgoing| {
+      lambertian
+        .evaluate(&arriving_from(&incoming), &hit_record, outgoing)
+        .map_or(0.0, |(value, _)| f64::from(value.x()))
+    });
+    assert!((reflected - 1.0).abs() < 1e-3, "a white Lambertian surface reflects {}", reflected);
+    let isotropic = Isotropic::new(&white);
+    let scattered = integrate_over_sphere(|outgoing| {
+      isotropic
+        .evaluate(&arriving_from(&incoming), &hit_record, outgoing)
+        .map_or(0.0, |(value, _)| f64::from(value.x()))
+    });
+    assert!((scattered - 1.0).abs() < 1e-3, "a white isotropic medium scatters {}", scattered);
+    // The directions the materials sample, weighted as the integrators weight them, carry the same light on
+    let normal = Vec3::new(0.0, 0.0, 1.0);
+    let cases: [(&str, Arc<dyn Material>, Vec3); 5] = [
+      ("white Lambertian surface", Arc::new(lambertian), incoming),
+      ("white isotropic medium", Arc::new(isotropic), incoming),
+      ("perfect mirror", Arc::new(Metal::new(&white, 0.0)), incoming),
+      ("glass entered", Arc::new(Dielectric::new(1.5)), incoming),
+      ("glass left", Arc::new(Dielectric::new(1.5)), direction(-0.6, 1.0)),
+    ];
+    for (name, material, incoming) in &cases {
+      let weight = mean_scattered_weight(material, incoming, &normal);
+      assert!((weight - 1.0).abs() < 1e-2, "sampling a {} returns {} of the light", name, weight);
+    }
+  }
+
+  #[test]
+  fn bsdfs_are_reciprocal() {
+    fastrand::seed(50);
+    let lambertian = Lambertian::new(&Vec3::new(0.8, 0.4, 0.2));
+    let hit_record = hit_record(Arc::new(lambertian));
+    let bsdf = |incoming: &Vec3, outgoing: &Vec3| {
+      let (value, _) = lambertian.evaluate(&arriving_from(incoming), &hit_record, outgoing).unwrap();
+      value / outgoing.z()
+    };
+    for _ in 0..100 {
+      let (first, second) = (
+        direction(f64::from(random_float()), 6.0 * f64::from(random_float())),
+        direction(f64::from(random_float()), 6.0 * f64::from(random_float())),
+      );
+      assert!((bsdf(&first, &second) - bsdf(&second, &first)).length() < 1e-5);
+    }
+    // Glass reflects as much light passing through at a pair of angles one way as the other, and light refracted one
+    // way retraces its path refracted back
+    let glass = Dielectric::new(1.5);
+    let up = Vec3::new(0.0, 0.0, 1.0);
+    let glass_hit = HitRecord::new(1.0, &Vec3::new_empty(), &up, Arc::new(glass));
+    for cosine in [0.2, 0.5, 0.9] {
+      let outside = direction(cosine, 0.3);
+      let inside = loop {
+        let result = glass.scatter(&arriving_from(&outside), &glass_hit).unwrap();
+        if result.kind() == ScatterKind::Transmission {
+          break result.scattered().direction().unit_vector();
+        }
+      };
+      let back = loop {
+        let result = glass.scatter(&arriving_from(&inside), &glass_hit).unwrap();
+        if result.kind() == ScatterKind::Transmission {
+          break result.scattered().direction().unit_vector();
+        }
+      };
+      assert!(
+        (back - outside).length() < 1e-5,
+        "light refracted back into {:?} rather than {:?}",
+        back,
+        outside
+      );
+      let (into, out_of) = (reflected_fraction(glass, &outside, &up), reflected_fraction(glass, &inside, &up));
+      // Four standard errors of the difference between the fractions
+      let tolerance = 4.0 * (2.0 * into * (1.0 - into) / 100_000.0).sqrt();
+      assert!(
+        (into - out_of).abs() < tolerance,
+        "glass reflects {} of light arriving at a cosine of {} from outside but {} from inside",
+        into,
+        cosine,
+        out_of
+      );
+    }
+  }
+
+  #[test]
+  #[allow(clippy::cast_precision_loss)]
+  fn lambertian_samples_follow_its_pdf() {
+    fastrand::seed(50);
+    let lambertian = Lambertian::new(&Vec3::new(0.5, 0.5, 0.5));
+    let material: Arc<dyn Material> = Arc::new(lambertian);
+    let incoming = direction(0.7, 2.0);
+    let mut expected = bin_probabilities(|outgoing| f64::from(outgoing.z()).max(0.0) / std::f64::consts::PI);
+    expected.push(0.0);
+    let expected: Vec<f64> = expected.iter().map(|probability| probability * CHI_SQUARE_SAMPLES as f64).collect();
+    assert_chi_square_fits("Lambertian", &scattered_counts(&material, &incoming), &expected);
+    // The density reported with each sample is the one evaluating that direction gives
+    let hit_record = hit_record(material);
+    for _ in 0..100 {
+      let result = lambertian.scatter(&arriving_from(&incoming), &hit_record).unwrap();
+      let (_, pdf) = lambertian
+        .evaluate(&arriving_from(&incoming), &hit_record, result.scattered().direction())
+        .unwrap();
+      assert!((result.pdf().unwrap() - pdf).abs() < 1e-4);
+    }
+  }
+
+  #[test]
+  #[allow(clippy::cast_precision_loss)]
+  fn fuzzy_metal_samples_follow_its_pdf() {
+    fastrand::seed(50);
+    for (fuzz, cosine) in [(0.3, 0.8), (0.6, 0.4)] {
+      let material: Arc<dyn Material> = Arc::new(Metal::new(&Vec3::new(0.9, 0.9, 0.9), fuzz));
+      let incoming = direction(cosine, 1.0);
+      let reflected = Vec3::new(-incoming.x(), -incoming.y(), incoming.z());
+      // The mirror direction plus a point drawn uniformly from a ball of radius `fuzz`: the density of a direction is
+      // the volume of the ball along it, the integral of the squared distance between the points entering and leaving
+      let fuzz = f64::from(fuzz);
+      let mut expected = bin_probabilities(|outgoing| {
+        let cosine = f64::from(reflected.dot(outgoing));
+        let half_chord_squared = fuzz.mul_add(fuzz, cosine.mul_add(cosine, -1.0));
+        if cosine <= 0.0 || half_chord_squared <= 0.0 {
+          return 0.0;
+        }
+        let half_chord = half_chord_squared.sqrt();
+        ((cosine + half_chord).powi(3) - (cosine - half_chord).powi(3)) / (4.0 * std::f64::consts::PI * fuzz.powi(3))
+      });
+      // Directions below the surface are absorbed
+      let absorbed: f64 = expected.drain(..expected.len() / 2).sum();
+      let mut expected: Vec<f64> = vec![0.0; COSINE_BINS * AZIMUTH_BINS / 2].into_iter().chain(expected).collect();
+      expected.push(absorbed);
+      let expected: Vec<f64> = expected.iter().map(|probability| probability * CHI_SQUARE_SAMPLES as f64).collect();
+      assert_chi_square_fits("fuzzy metal", &scattered_counts(&material, &incoming), &expected);
+    }
+  }
+
+  #[test]
+  #[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+  fn dielectric_chooses_reflection_with_the_fresnel_probability() {
+    fastrand::seed(50);
+    let glass = Dielectric::new(1.5);
+    let up = Vec3::new(0.0, 0.0, 1.0);
+    let glass_hit = HitRecord::new(1.0, &Vec3::new_empty(), &up, Arc::new(glass));
+    for outside_cosine in [0.1, 0.4, 0.8] {
+      let outside = direction(outside_cosine, 0.7);
+      let inside_cosine = (1.0 - outside_cosine.mul_add(-outside_cosine, 1.0) / 2.25).sqrt();
+      let reflectance = f64::from(schlick(outside_cosine as Float, 1.5));
+      // Light reaching the surface from outside, and light leaving through it towards the same direction
+      for incoming in [outside, -direction(inside_cosine, 0.7)] {
+        let mut observed = [0.0; 2];
+        for _ in 0..CHI_SQUARE_SAMPLES {
+          let result = glass.scatter(&arriving_from(&incoming), &glass_hit).unwrap();
+          let reflected = Vec3::new(-incoming.x(), -incoming.y(), incoming.z());
+          if result.kind() == ScatterKind::Glossy {
+            assert!((result.scattered().direction().unit_vector() - reflected).length() < 1e-5);
+            observed[0] += 1.0;
+          } else {
+            observed[1] += 1.0;
+          }
+        }
+        let samples = CHI_SQUARE_SAMPLES as f64;
+        assert_chi_square_fits("glass", &observed, &[reflectance * samples, (1.0 - reflectance) * samples]);
+      }
+    }
   }
 }
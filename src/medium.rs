@@ -0,0 +1,55 @@
+use crate::aabb::Aabb;
+use crate::float::{next_float_up, random_float, Float};
+use crate::hit::{HitRecord, Hittable};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::vec3::Vec3;
+use std::sync::Arc;
+
+/// A homogeneous participating medium filling a closed boundary, scattering rays after exponentially distributed distances
+#[derive(Debug)]
+pub struct ConstantMedium {
+  boundary: Box<dyn Hittable>,
+  density: Float,
+  phase_function: Arc<dyn Material>,
+}
+
+impl ConstantMedium {
+  pub fn new(boundary: Box<dyn Hittable>, density: Float, phase_function: Arc<dyn Material>) -> Self {
+    Self {
+      boundary,
+      density,
+      phase_function,
+    }
+  }
+}
+
+impl Hittable for ConstantMedium {
+  fn is_hit(&self, ray: &Ray, scalar_from_ray_origin_min: Float, scalar_from_ray_origin_max: Float) -> Option<HitRecord> {
+    let entry = self.boundary.is_hit(ray, Float::NEG_INFINITY, Float::INFINITY)?;
+    let exit = self
+      .boundary
+      .is_hit(ray, next_float_up(entry.scalar_from_ray_origin()), Float::INFINITY)?;
+    let entry_scalar = entry.scalar_from_ray_origin().max(scalar_from_ray_origin_min).max(0.0);
+    let exit_scalar = exit.scalar_from_ray_origin().min(scalar_from_ray_origin_max);
+    if entry_scalar >= exit_scalar {
+      return None;
+    }
+    let ray_length = ray.direction().length();
+    let distance_inside = (exit_scalar - entry_scalar) * ray_length;
+    let hit_distance = -(1.0 - random_float()).ln() / self.density;
+    if hit_distance > distance_inside {
+      return None;
+    }
+    let scalar_from_ray_origin = entry_scalar + hit_distance / ray_length;
+    Some(HitRecord::new(
+      scalar_from_ray_origin,
+      &ray.point_at_parameter(scalar_from_ray_origin),
+      &Vec3::new_empty(),
+      self.phase_function.clone(),
+    ))
+  }
+  fn bounding_box(&self) -> Option<Aabb> {
+    self.boundary.bounding_box()
+  }
+}
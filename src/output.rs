@@ -0,0 +1,235 @@
+use crate::aov::id_hash_to_float;
+use crate::film::Film;
+use crate::float::{from_f32, to_f32};
+use crate::vec3::Vec3;
+use crate::Error;
+use exr::prelude::{AnyChannel, AnyChannels, Encoding, FlatSamples, Image, Layer, LayerAttributes, SmallVec, WritableImage};
+use std::path::{Path, PathBuf};
+
+/// Number of most-covered IDs written per pixel for each ID channel, two to each RGBA rank layer
+const ID_RANKS: usize = 6;
+
+/// Samples of one EXR channel, top row first
+struct Channel {
+  name: String,
+  samples: Vec<f32>,
+}
+
+/// Component names an averaged film channel is written with, or None for alpha, which goes with the beauty pass
+fn components(name: &str) -> Option<&'static [&'static str]> {
+  match name {
+    "alpha" => None,
+    "depth" => Some(&["Z"]),
+    "normal" | "position" => Some(&["X", "Y", "Z"]),
+    _ => Some(&["R", "G", "B"]),
+  }
+}
+
+/// Layer name prefix of the Cryptomatte-style rank layers an ID channel is written as
+fn id_layer_name(name: &str) -> String {
+  match name {
+    "object_id" => "CryptoObject".to_string(),
+    "material_id" => "CryptoMaterial".to_string(),
+    _ => name.to_string(),
+  }
+}
+
+fn collect(film: &Film, prefix: &str, suffixes: &[&str], mut value: impl FnMut(usize, usize) -> Vec3) -> Vec<Channel> {
+  let mut channels: Vec<Channel> = suffixes
+    .iter()
+    .map(|suffix| Channel {
+      name: if prefix.is_empty() {
+        (*suffix).to_string()
+      } else {
+        format!("{prefix}.{suffix}")
+      },
+      samples: Vec::with_capacity(film.width() * film.height()),
+    })
+    .collect();
+  for y in (0..film.height()).rev() {
+    for x in 0..film.width() {
+      let value = value(x, y);
+      for (index, channel) in channels.iter_mut().enumerate() {
+        channel.samples.push(to_f32(match index {
+          0 => value.x(),
+          1 => value.y(),
+          _ => value.z(),
+        }));
+      }
+    }
+  }
+  channels
+}
+
+/// The beauty pass with alpha as the unprefixed RGBA channels
+fn beauty_channels(film: &Film, samples_per_pixel: usize) -> Vec<Channel> {
+  let mut channels = collect(film, "", &["R", "G", "B"], |x, y| film.pixel(x, y, samples_per_pixel));
+  if film.channel_names().any(|name| name == "alpha") {
+    channels.extend(collect(film, "", &["A"], |x, y| film.channel_pixel("alpha", x, y, samples_per_pixel)));
+  }
+  channels
+}
+
+/// Every named channel of the film, each as its own group of channels
+fn aov_channels(film: &Film, samples_per_pixel: usize) -> Vec<(String, Vec<Channel>)> {
+  let mut groups: Vec<(String, Vec<Channel>)> = film
+    .channel_names()
+    .filter_map(|name| {
+      components(name).map(|suffixes| {
+        let channels = collect(film, name, suffixes, |x, y| film.channel_pixel(name, x, y, samples_per_pixel));
+        (name.to_string(), channels)
+      })
+    })
+    .collect();
+  for name in film.id_channel_names() {
+    let layer_name = id_layer_name(name);
+    let channels = (0..ID_RANKS / 2)
+      .flat_map(|rank_pair| {
+        let mut rank_channels = Vec::new();
+        let rank_name = format!("{layer_name}{rank_pair:02}");
+        let coverage = |x: usize, y: usize, rank: usize| {
+          film
+            .id_coverage(name, x, y, samples_per_pixel)
+            .get(rank)
+            .map_or((0.0, 0.0), |(id, coverage)| (from_f32(id_hash_to_float(*id)), *coverage))
+        };
+        rank_channels.extend(collect(film, &rank_name, &["R", "G"], |x, y| {
+          let (id, coverage) = coverage(x, y, 2 * rank_pair);
+          Vec3::new(id, coverage, 0.0)
+        }));
+        rank_channels.extend(collect(film, &rank_name, &["B", "A"], |x, y| {
+          let (id, coverage) = coverage(x, y, 2 * rank_pair + 1);
+          Vec3::new(id, coverage, 0.0)
+        }));
+        rank_channels
+      })
+      .collect();
+    groups.push((layer_name, channels));
+  }
+  groups
+}
+
+fn write_channels(path: &Path, width: usize, height: usize, channels: Vec<Channel>) -> Result<(), Error> {
+  let channels: SmallVec<[AnyChannel<FlatSamples>; 4]> = channels
+    .into_iter()
+    .map(|channel| AnyChannel::new(channel.name.as_str(), FlatSamples::F32(channel.samples)))
+    .collect();
+  let layer = Layer::new(
+    (width, height),
+    LayerAttributes::default(),
+    Encoding::FAST_LOSSLESS,
+    AnyChannels::sort(channels),
+  );
+  Image::from_layer(layer).write().to_file(path)?;
+  Ok(())
+}
+
+/// Writes the beauty pass and every channel of the film to one multi-channel EXR, naming the channels of each pass
+/// `<pass>.R` and so on so compositors group them into layers
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be written
+pub fn write_exr(film: &Film, samples_per_pixel: usize, path: &Path) -> Result<(), Error> {
+  let mut channels = beauty_channels(film, samples_per_pixel);
+  for (_, group) in aov_channels(film, samples_per_pixel) {
+    channels.extend(group);
+  }
+  write_channels(path, film.width(), film.height(), channels)
+}
+
+/// Writes the beauty pass to `path` and each channel of the film to a sidecar EXR next to it, so `beauty.exr` gets
+/// `beauty.diffuse_direct.exr` and so on
+///
+/// # Errors
+///
+/// Returns an error if any of the files cannot be written
+pub fn write_exr_sidecars(film: &Film, samples_per_pixel: usize, path: &Path) -> Result<(), Error> {
+  write_channels(path, film.width(), film.height(), beauty_channels(film, samples_per_pixel))?;
+  let stem = path.file_stem().map_or_else(Default::default, |stem| stem.to_string_lossy().into_owned());
+  for (name, mut group) in aov_channels(film, samples_per_pixel) {
+    let prefix = format!("{name}.");
+    for channel in &mut group {
+      if let Some(component) = channel.name.strip_prefix(&prefix) {
+        channel.name = component.to_string();
+      }
+    }
+    let sidecar: PathBuf = path.with_file_name(format!("{stem}.{name}.exr"));
+    write_channels(&sidecar, film.width(), film.height(), group)?;
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::float::Float;
+  use exr::prelude::read_first_flat_layer_from_file;
+
+  /// A 2x2 film whose pixel `x` from the left and `y` from the bottom saw radiance (x, y, 1), half of it direct diffuse
+  #[allow(clippy::cast_precision_loss)]
+  fn film() -> Film {
+    let mut film = Film::new(2, 2);
+    for (x, y) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+      let position = (x as Float + 0.5, y as Float + 0.5);
+      let radiance = Vec3::new(x as Float, y as Float, 1.0);
+      film.add_sample(position, &radiance);
+      film.add_channel_sample("diffuse_direct", position, &(0.5 * radiance));
+      film.add_data_sample("alpha", x, y, &Vec3::new(1.0, 1.0, 1.0));
+      film.add_data_sample("depth", x, y, &Vec3::new(2.0, 2.0, 2.0));
+      film.add_data_sample("normal", x, y, &Vec3::new(0.0, 1.0, 0.0));
+      film.add_id_sample("object_id", x, y, 7);
+    }
+    film
+  }
+
+  /// Names and samples of the channels in the EXR at `path`, top row first
+  fn read_channels(path: &Path) -> Vec<(String, Vec<f32>)> {
+    let image = read_first_flat_layer_from_file(path).unwrap();
+    image
+      .layer_data
+      .channel_data
+      .list
+      .into_iter()
+      .map(|channel| match channel.sample_data {
+        FlatSamples::F32(samples) => (channel.name.to_string(), samples),
+        _ => panic!("{} is not stored as 32-bit floats", channel.name),
+      })
+      .collect()
+  }
+
+  #[test]
+  fn passes_are_written_as_layers_of_one_exr_or_as_sidecars() {
+    let directory = std::env::temp_dir().join(format!("exr-layers-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    let path = directory.join("beauty.exr");
+    write_exr(&film(), 1, &path).unwrap();
+    let channels = read_channels(&path);
+    let samples = |name: &str| {
+      channels
+        .iter()
+        .find(|(channel, _)| channel == name)
+        .unwrap_or_else(|| panic!("no channel {}", name))
+        .1
+        .clone()
+    };
+    assert_eq!(samples("R"), [0.0, 1.0, 0.0, 1.0]);
+    assert_eq!(samples("G"), [1.0, 1.0, 0.0, 0.0]);
+    assert_eq!(samples("A"), [1.0; 4]);
+    assert_eq!(samples("diffuse_direct.G"), [0.5, 0.5, 0.0, 0.0]);
+    assert_eq!(samples("depth.Z"), [2.0; 4]);
+    assert_eq!(samples("normal.Y"), [1.0; 4]);
+    // The only ID covers every pixel fully, leaving the lower ranks empty
+    assert_eq!(samples("CryptoObject00.R"), [id_hash_to_float(7); 4]);
+    assert_eq!(samples("CryptoObject00.G"), [1.0; 4]);
+    assert_eq!(samples("CryptoObject00.A"), [0.0; 4]);
+    assert!(channels.iter().all(|(name, _)| !name.starts_with("alpha")));
+
+    write_exr_sidecars(&film(), 1, &path).unwrap();
+    let names = |path: &Path| read_channels(path).into_iter().map(|(name, _)| name).collect::<Vec<String>>();
+    assert_eq!(names(&path), ["A", "B", "G", "R"]);
+    assert_eq!(names(&directory.join("beauty.diffuse_direct.exr")), ["B", "G", "R"]);
+    assert_eq!(names(&directory.join("beauty.depth.exr")), ["Z"]);
+    std::fs::remove_dir_all(&directory).unwrap();
+  }
+}
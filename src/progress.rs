@@ -0,0 +1,164 @@
+use std::fmt::Write as _;
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Shortest time between updates of the line rewritten in place on a terminal
+const TERMINAL_INTERVAL: Duration = Duration::from_millis(250);
+/// Shortest time between the lines printed when stderr is a file or a pipe, which keeps logs short
+const LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Reports how far a render has got on stderr: the share of its samples or time budget done, elapsed time, time left
+/// and samples per second since the last update. On a terminal one line is rewritten in place as tiles finish,
+/// otherwise a plain line is printed every ten seconds
+#[derive(Debug)]
+pub struct Progress {
+  start: Instant,
+  sample_budget: Option<usize>,
+  time_limit: Option<Duration>,
+  interactive: bool,
+  initial_samples: usize,
+  initial_elapsed: Duration,
+  samples: AtomicUsize,
+  last_update: Mutex<(Instant, usize)>,
+}
+
+impl Progress {
+  /// Progress towards taking `sample_budget` samples across the image or rendering for `time_limit`, whichever comes
+  /// first, or neither if both are None, in which case only elapsed time and throughput are reported
+  pub fn new(sample_budget: Option<usize>, time_limit: Option<Duration>) -> Self {
+    let start = Instant::now();
+    Self {
+      start,
+      sample_budget,
+      time_limit,
+      interactive: std::io::stderr().is_terminal(),
+      initial_samples: 0,
+      initial_elapsed: Duration::ZERO,
+      samples: AtomicUsize::new(0),
+      last_update: Mutex::new((start, 0)),
+    }
+  }
+  /// Carries on from a resumed render that had taken `samples` samples over `elapsed`
+  pub fn with_completed(mut self, samples: usize, elapsed: Duration) -> Self {
+    self.initial_samples = samples;
+    self.initial_elapsed = elapsed;
+    self.samples = AtomicUsize::new(samples);
+    self.last_update = Mutex::new((self.start, samples));
+    self
+  }
+  /// Counts `samples` more samples taken, updating the report if it is due. Safe to call from every render thread
+  pub fn add_samples(&self, samples: usize) {
+    let total = self.samples.fetch_add(samples, Ordering::Relaxed) + samples;
+    let interval = if self.interactive { TERMINAL_INTERVAL } else { LOG_INTERVAL };
+    // A thread that finds another one updating skips its turn rather than waiting
+    let Ok(mut last_update) = self.last_update.try_lock() else {
+      return;
+    };
+    let now = Instant::now();
+    if now.duration_since(last_update.0) >= interval {
+      self.print(&self.status(
+        total,
+        now.duration_since(self.start),
+        total - last_update.1,
+        now.duration_since(last_update.0),
+        false,
+      ));
+      *last_update = (now, total);
+    }
+  }
+  /// Reports the final state of the render as all of its budget done, since pixels that converged or a termination
+  /// policy may end it before the budget is spent, and ends the line rewritten on a terminal
+  pub fn finish(&self) {
+    let total = self.samples.load(Ordering::Relaxed);
+    let elapsed = self.start.elapsed();
+    self.print(&self.status(total, elapsed, total - self.initial_samples, elapsed, true));
+    if self.interactive {
+      eprintln!();
+    }
+  }
+  fn print(&self, status: &str) {
+    if self.interactive {
+      eprint!("\r{status}\x1b[K");
+    } else {
+      eprintln!("{status}");
+    }
+  }
+  /// Share of the budget done with `samples` taken after running for `run_elapsed`, if there is a budget
+  #[allow(clippy::cast_precision_loss)]
+  fn fraction(&self, samples: usize, run_elapsed: Duration) -> Option<f64> {
+    let by_samples = self.sample_budget.map(|budget| samples as f64 / budget.max(1) as f64);
+    let by_time = self
+      .time_limit
+      .map(|limit| (self.initial_elapsed + run_elapsed).as_secs_f64() / limit.as_secs_f64().max(f64::MIN_POSITIVE));
+    by_samples.into_iter().chain(by_time).reduce(f64::max).map(|fraction| fraction.min(1.0))
+  }
+  /// One line describing the render with `samples` taken after running for `run_elapsed`, `recent_samples` of them in
+  /// the last `recent_elapsed`, and whether it `is_finished`
+  #[allow(clippy::cast_precision_loss)]
+  fn status(&self, samples: usize, run_elapsed: Duration, recent_samples: usize, recent_elapsed: Duration, is_finished: bool) -> String {
+    let mut status = String::new();
+    let fraction = self
+      .fraction(samples, run_elapsed)
+      .map(|fraction| if is_finished { 1.0 } else { fraction });
+    if let Some(fraction) = fraction {
+      let _ = write!(status, "{:5.1}% ", 100.0 * fraction);
+    }
+    let _ = write!(
+      status,
+      "{samples} samples, {} elapsed",
+      format_duration(self.initial_elapsed + run_elapsed)
+    );
+    // Time left assumes the rest of the budget goes as fast as this run has so far
+    let start_fraction = self.fraction(self.initial_samples, Duration::ZERO).unwrap_or(0.0);
+    if let Some(fraction) = fraction.filter(|fraction| !is_finished && *fraction > start_fraction) {
+      let remaining = run_elapsed.as_secs_f64() * (1.0 - fraction) / (fraction - start_fraction);
+      let _ = write!(status, ", ETA {}", format_duration(Duration::from_secs_f64(remaining)));
+    }
+    let seconds = recent_elapsed.as_secs_f64();
+    if seconds > 0.0 {
+      let _ = write!(status, ", {:.0} samples/s", recent_samples as f64 / seconds);
+    }
+    status
+  }
+}
+
+/// `duration` as hours, minutes and seconds, leaving out the hours if there are none
+fn format_duration(duration: Duration) -> String {
+  let seconds = duration.as_secs();
+  let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+  if hours > 0 {
+    format!("{hours}:{minutes:02}:{seconds:02}")
+  } else {
+    format!("{minutes}:{seconds:02}")
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn status_gives_share_done_time_left_and_throughput() {
+    let progress = Progress::new(Some(1000), None);
+    assert_eq!(
+      progress.status(250, Duration::from_secs(30), 50, Duration::from_secs(2), false),
+      " 25.0% 250 samples, 0:30 elapsed, ETA 1:30, 25 samples/s"
+    );
+    let resumed = Progress::new(Some(1000), Some(Duration::from_hours(2))).with_completed(500, Duration::from_secs(3590));
+    assert_eq!(
+      resumed.status(750, Duration::from_secs(20), 0, Duration::ZERO, false),
+      " 75.0% 750 samples, 1:00:10 elapsed, ETA 0:20"
+    );
+    assert_eq!(
+      Progress::new(None, None).status(10, Duration::from_secs(5), 10, Duration::from_secs(5), false),
+      "10 samples, 0:05 elapsed, 2 samples/s"
+    );
+    // A render whose pixels converged after taking 830 of the 1000 samples they might have ends at the full share
+    assert_eq!(
+      progress.status(830, Duration::from_secs(10), 830, Duration::from_secs(10), true),
+      "100.0% 830 samples, 0:10 elapsed, 83 samples/s"
+    );
+  }
+}
@@ -1,5 +1,10 @@
+use crate::float::{next_float_down, next_float_up, Float};
 use crate::vec3::Vec3;
 
+/// Fraction of the distance to a point that rays testing whether it can be seen stop short of, so the surface the
+/// point lies on does not count as being in the way
+pub const SHADOW_EPSILON: Float = 0.0001;
+
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub struct Ray {
   origin: Vec3,
@@ -13,13 +18,47 @@ impl Ray {
       direction: *direction,
     }
   }
+  /// Ray leaving the surface at `point` along `direction`, started just past the rounding error `error` of the point
+  /// on the side `direction` points to, so it cannot hit the surface it left again however close to it it runs
+  /// (Pharr et al., Physically Based Rendering, 3rd edition, section 3.9.5)
+  pub fn spawn(point: &Vec3, error: &Vec3, normal: &Vec3, direction: &Vec3) -> Self {
+    let distance = normal.abs().dot(error);
+    let offset = if direction.dot(normal) < 0.0 {
+      -distance * normal
+    } else {
+      distance * normal
+    };
+    let nudge = |coordinate: Float, offset: Float| {
+      if offset > 0.0 {
+        next_float_up(coordinate)
+      } else if offset < 0.0 {
+        next_float_down(coordinate)
+      } else {
+        coordinate
+      }
+    };
+    let origin = point + offset;
+    Self::new(
+      &Vec3::new(
+        nudge(origin.x(), offset.x()),
+        nudge(origin.y(), offset.y()),
+        nudge(origin.z(), offset.z()),
+      ),
+      direction,
+    )
+  }
+  /// Like `spawn`, but aimed at `target` and scaled to reach it at a parameter of one
+  pub fn spawn_to(point: &Vec3, error: &Vec3, normal: &Vec3, target: &Vec3) -> Self {
+    let origin = *Self::spawn(point, error, normal, &(target - point)).origin();
+    Self::new(&origin, &(target - origin))
+  }
   pub fn origin(&self) -> &Vec3 {
     &self.origin
   }
   pub fn direction(&self) -> &Vec3 {
     &self.direction
   }
-  pub fn point_at_parameter(&self, scalar_length: f32) -> Vec3 {
+  pub fn point_at_parameter(&self, scalar_length: Float) -> Vec3 {
     self.origin + scalar_length * self.direction
   }
 }
@@ -0,0 +1,451 @@
+use crate::aov::{record_surface, LightPass, LightPasses};
+use crate::camera::Camera;
+use crate::checkpoint::Checkpoint;
+use crate::film::Film;
+use crate::float::{next_float_down, random_float, Float};
+use crate::hit::{HitRecord, Hittable};
+use crate::integrator::Integrator;
+use crate::progress::Progress;
+use crate::ray::Ray;
+use crate::scene::Scene;
+use crate::settings::{SamplingSettings, Termination};
+use crate::spectrum::{Radiance, SampledSpectrum, SampledWavelengths};
+use crate::stats;
+use crate::vec3::Vec3;
+use crate::Error;
+use std::num::NonZeroUsize;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// Width and height in pixels of the tiles a pass is split into for the threads to share out
+const TILE_SIZE: usize = 16;
+/// Width and height in pixels of the blocks whose camera rays are traced together as packets
+const PACKET_WIDTH: usize = 8;
+/// Mixed into the render seed for the random numbers of light paths, so they are drawn apart from the camera samples
+const PATH_STREAM: u64 = 0x5851_f42d_4c95_7f2d;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColorMode {
+  Rgb,
+  Spectral,
+}
+
+/// Mixes the render seed, pass and pixel into a seed for that pixel's samples in that pass with `SplitMix64`
+fn pixel_seed(seed: u64, pass: usize, pixel: usize) -> u64 {
+  let mut state = seed ^ ((pass as u64) << 32) ^ pixel as u64;
+  state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+  state = (state ^ (state >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+  state = (state ^ (state >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+  state ^ (state >> 31)
+}
+
+/// Position a fraction `offset` of the way across the pixel starting at `pixel`, kept below the next pixel where
+/// rounding would otherwise carry it there and count the sample towards the wrong pixel
+#[allow(clippy::cast_precision_loss)]
+fn pixel_position(pixel: usize, offset: Float) -> Float {
+  (pixel as Float + offset).min(next_float_down(pixel as Float + 1.0))
+}
+
+/// Renders images progressively, in passes that each give every unconverged pixel another batch of samples
+#[derive(Clone, Debug, PartialEq)]
+pub struct Renderer {
+  color_mode: ColorMode,
+  sampling: SamplingSettings,
+  record_aovs: bool,
+  seed: u64,
+  termination: Termination,
+  checkpoints: Option<(PathBuf, Duration)>,
+  threads: usize,
+  packets: bool,
+  progress: bool,
+}
+
+impl Renderer {
+  /// A renderer taking as many samples per pixel as `sampling` asks for, also filling the film's light pass and
+  /// surface channels if `record_aovs`, with random numbers that depend only on `seed`
+  pub fn new(color_mode: ColorMode, sampling: SamplingSettings, record_aovs: bool, seed: u64) -> Self {
+    Self {
+      color_mode,
+      sampling,
+      record_aovs,
+      seed,
+      termination: Termination::Converged,
+      checkpoints: None,
+      threads: std::thread::available_parallelism().map_or(1, NonZeroUsize::get),
+      packets: false,
+      progress: false,
+    }
+  }
+  /// Stops rendering after the first pass that meets `termination`, even if pixels could take more samples
+  pub fn with_termination(mut self, termination: Termination) -> Self {
+    self.termination = termination;
+    self
+  }
+  /// Renders on `threads` threads instead of one per core
+  pub fn with_threads(mut self, threads: usize) -> Self {
+    self.threads = threads.max(1);
+    self
+  }
+  /// Finds where camera rays first hit by tracing each 8x8 block of them as a packet, which visits the BVH once for
+  /// the whole block, leaving the bounces after to be traced one ray at a time. The image is the same either way
+  pub fn with_packets(mut self, packets: bool) -> Self {
+    self.packets = packets;
+    self
+  }
+  /// Reports on stderr how far each render has got as its tiles finish, see [`Progress`]
+  pub fn with_progress(mut self, progress: bool) -> Self {
+    self.progress = progress;
+    self
+  }
+  /// Saves a checkpoint to `path` after the first pass that ends at least `interval` after the last save
+  pub fn with_checkpoints(mut self, path: &Path, interval: Duration) -> Self {
+    self.checkpoints = Some((path.to_path_buf(), interval));
+    self
+  }
+  /// Renders passes into the film of `checkpoint`, starting after the passes it has already made, until every pixel
+  /// has all the samples it needs or the termination policy is met. Only whole passes are rendered, so every pixel has
+  /// been sampled as often as its neighbours allow when this returns
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if a checkpoint cannot be saved
+  pub fn render<I: Integrator>(&self, integrator: &I, scene: &Scene, camera: &Camera, mut checkpoint: Checkpoint) -> Result<Film, Error> {
+    let mut last_save = Instant::now();
+    let progress = self.progress.then(|| self.progress_for(&checkpoint));
+    loop {
+      let (pass, pass_start) = (checkpoint.passes(), Instant::now());
+      if !self.render_pass_reporting(integrator, scene, camera, checkpoint.film_mut(), pass, progress.as_ref()) {
+        break;
+      }
+      checkpoint.complete_pass(pass_start.elapsed());
+      if let Some((path, interval)) = &self.checkpoints {
+        if last_save.elapsed() >= *interval {
+          checkpoint.save(path)?;
+          last_save = Instant::now();
+        }
+      }
+      if self.termination.is_met(checkpoint.film(), checkpoint.elapsed()) {
+        break;
+      }
+    }
+    if let Some(progress) = &progress {
+      progress.finish();
+    }
+    Ok(checkpoint.into_film())
+  }
+  /// Progress towards the most samples the render of `checkpoint` may take, or towards its time limit
+  fn progress_for(&self, checkpoint: &Checkpoint) -> Progress {
+    let film = checkpoint.film();
+    let mut sample_budget = (film.width() * film.height()).checked_mul(self.sampling.max_samples());
+    let mut time_limit = None;
+    match self.termination {
+      Termination::SampleLimit(limit) => sample_budget = Some(sample_budget.map_or(limit, |budget| budget.min(limit))),
+      Termination::TimeLimit(limit) => time_limit = Some(limit),
+      Termination::Converged | Termination::NoiseTarget(_) => {}
+    }
+    Progress::new(sample_budget, time_limit).with_completed(film.total_sample_count(), checkpoint.elapsed())
+  }
+  /// Gives every pixel of `film` that needs more samples its next batch, reseeding the random numbers for each pixel
+  /// from the seed and `pass` so a pass comes out the same however the render got to it. The image is rendered in
+  /// tiles shared out between the threads, which are merged back in order so the result does not depend on how many
+  /// threads there are. Returns whether any pixel took samples
+  ///
+  /// # Panics
+  ///
+  /// Panics if a render thread panics
+  pub fn render_pass<I: Integrator>(&self, integrator: &I, scene: &Scene, camera: &Camera, film: &mut Film, pass: usize) -> bool {
+    self.render_pass_reporting(integrator, scene, camera, film, pass, None)
+  }
+  /// Renders a pass as [`Renderer::render_pass`] does, counting the samples of each tile towards `progress` as it
+  /// finishes
+  fn render_pass_reporting<I: Integrator>(
+    &self,
+    integrator: &I,
+    scene: &Scene,
+    camera: &Camera,
+    film: &mut Film,
+    pass: usize,
+    progress: Option<&Progress>,
+  ) -> bool {
+    let (width, height) = (film.width(), film.height());
+    let tiles: Vec<(Range<usize>, Range<usize>)> = (0..height)
+      .step_by(TILE_SIZE)
+      .flat_map(|y| {
+        (0..width)
+          .step_by(TILE_SIZE)
+          .map(move |x| (x..(x + TILE_SIZE).min(width), y..(y + TILE_SIZE).min(height)))
+      })
+      .collect();
+    let next_tile = AtomicUsize::new(0);
+    let shared_film: &Film = film;
+    let mut rendered: Vec<(usize, Film)> = std::thread::scope(|scope| {
+      let workers: Vec<_> = (0..self.threads.min(tiles.len()))
+        .map(|_| {
+          scope.spawn(|| {
+            let mut rendered = Vec::new();
+            loop {
+              let index = next_tile.fetch_add(1, Ordering::Relaxed);
+              let Some(tile) = tiles.get(index) else {
+                break;
+              };
+              if let Some(tile_film) = self.render_tile(integrator, scene, camera, shared_film, tile, pass) {
+                if let Some(progress) = progress {
+                  progress.add_samples(tile_film.total_sample_count());
+                }
+                rendered.push((index, tile_film));
+              }
+            }
+            stats::flush_thread_counts();
+            rendered
+          })
+        })
+        .collect();
+      workers
+        .into_iter()
+        .flat_map(|worker| worker.join().expect("a render thread panicked"))
+        .collect()
+    });
+    rendered.sort_by_key(|(index, _)| *index);
+    for (_, tile_film) in &rendered {
+      film.merge(tile_film);
+    }
+    !rendered.is_empty()
+  }
+  /// Renders the pixels of `tile` that need more samples into a tile of `film`, splatting into `film` itself, or
+  /// returns None if none of them do. Pixels are taken in blocks whose camera rays are all made before any is traced,
+  /// from their own random numbers, so tracing them as packets or one at a time renders the same image
+  fn render_tile<I: Integrator>(
+    &self,
+    integrator: &I,
+    scene: &Scene,
+    camera: &Camera,
+    film: &Film,
+    (x_range, y_range): &(Range<usize>, Range<usize>),
+    pass: usize,
+  ) -> Option<Film> {
+    let mut tile_film = film.tile(x_range.clone(), y_range.clone());
+    let mut is_sampled = false;
+    for block_y in y_range.clone().rev().step_by(PACKET_WIDTH) {
+      for block_x in x_range.clone().step_by(PACKET_WIDTH) {
+        let block = (
+          block_x..(block_x + PACKET_WIDTH).min(x_range.end),
+          (block_y + 1).saturating_sub(PACKET_WIDTH).max(y_range.start)..block_y + 1,
+        );
+        is_sampled |= self.render_block(integrator, scene, camera, film, &mut tile_film, &block, pass);
+      }
+    }
+    is_sampled.then_some(tile_film)
+  }
+  /// Renders the pixels of `block` that need more samples into `tile_film`, returning whether any did
+  #[allow(clippy::cast_precision_loss, clippy::too_many_arguments)]
+  fn render_block<I: Integrator>(
+    &self,
+    integrator: &I,
+    scene: &Scene,
+    camera: &Camera,
+    film: &Film,
+    tile_film: &mut Film,
+    (x_range, y_range): &(Range<usize>, Range<usize>),
+    pass: usize,
+  ) -> bool {
+    let (width, height) = (film.width(), film.height());
+    let mut camera_samples = Vec::new();
+    for current_y_pixel in y_range.clone().rev() {
+      for current_x_pixel in x_range.clone() {
+        let batch = self.sampling.next_batch(film, current_x_pixel, current_y_pixel);
+        if batch == 0 {
+          continue;
+        }
+        fastrand::seed(pixel_seed(self.seed, pass, current_y_pixel * width + current_x_pixel));
+        for _ in 0..batch {
+          let position = (
+            pixel_position(current_x_pixel, random_float()),
+            pixel_position(current_y_pixel, random_float()),
+          );
+          let ray = camera.get_ray(position.0 / width as Float, position.1 / height as Float);
+          camera_samples.push(((current_x_pixel, current_y_pixel), position, ray));
+        }
+      }
+    }
+    let mut packet_hits: Option<Vec<Option<HitRecord>>> = self.packets.then(|| {
+      let rays: Vec<Ray> = camera_samples.iter().map(|(_, _, ray)| *ray).collect();
+      rays
+        .chunks(PACKET_WIDTH * PACKET_WIDTH)
+        .flat_map(|packet| scene.world().is_hit_packet(packet, 0.0, Float::MAX))
+        .collect()
+    });
+    let mut seeded_pixel = None;
+    for (index, ((x, y), position, ray)) in camera_samples.iter().enumerate() {
+      if seeded_pixel != Some((*x, *y)) {
+        fastrand::seed(pixel_seed(self.seed ^ PATH_STREAM, pass, y * width + x));
+        seeded_pixel = Some((*x, *y));
+      }
+      let packet_hit = packet_hits.as_mut().map(|hits| &mut hits[index]);
+      let (sample, passes) = match self.color_mode {
+        ColorMode::Rgb => {
+          let (radiance, passes) = radiance::<I, Vec3>(integrator, ray, packet_hit, scene, &(), film);
+          (radiance, passes.map(|passes| LightPass::ALL.map(|pass| passes.pass(pass))))
+        }
+        ColorMode::Spectral => {
+          let wavelengths = SampledWavelengths::sample_uniform(random_float());
+          let (radiance, passes) = radiance::<I, SampledSpectrum>(integrator, ray, packet_hit, scene, &wavelengths, film);
+          (
+            radiance.into_rgb(&wavelengths),
+            passes.map(|passes| LightPass::ALL.map(|pass| passes.pass(pass).into_rgb(&wavelengths))),
+          )
+        }
+      };
+      tile_film.add_sample(*position, &sample);
+      if self.record_aovs {
+        for (pass, radiance) in LightPass::ALL.iter().zip(passes.iter().flatten()) {
+          tile_film.add_channel_sample(pass.name(), *position, radiance);
+        }
+        record_surface(tile_film, scene, ray, *x, *y);
+      }
+    }
+    !camera_samples.is_empty()
+  }
+}
+
+/// Estimate along the camera `ray`, starting from its closest hit if a packet has already found it
+fn radiance<I: Integrator, R: Radiance>(
+  integrator: &I,
+  ray: &Ray,
+  packet_hit: Option<&mut Option<HitRecord>>,
+  scene: &Scene,
+  context: &R::Context,
+  film: &Film,
+) -> (R, Option<LightPasses<R>>) {
+  match packet_hit {
+    Some(hit) => integrator.radiance_and_passes_from_hit(ray, hit.take(), scene, context, film),
+    None => integrator.radiance_and_passes(ray, scene, context, film),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::background::Background;
+  use crate::hit::HittableList;
+  use crate::integrator::PathIntegrator;
+  use crate::material::{Dielectric, Lambertian};
+  use crate::settings::RenderSettings;
+  use crate::sphere::Sphere;
+  use crate::stats::{self, Counter};
+  use std::sync::Arc;
+
+  fn test_scene() -> (Scene, Camera) {
+    let mut world = HittableList::new();
+    world.list_mut().push(Box::new(Sphere::new(
+      &Vec3::new(0.0, -1000.0, 0.0),
+      1000.0,
+      Arc::new(Lambertian::new(&Vec3::new(0.5, 0.5, 0.5))),
+    )));
+    world
+      .list_mut()
+      .push(Box::new(Sphere::new(&Vec3::new(0.0, 0.5, 0.0), 0.5, Arc::new(Dielectric::new(1.5)))));
+    world.build_bvh();
+    let mut scene = Scene::new(world);
+    scene.set_background(Background::Constant(Vec3::new(0.2, 0.3, 0.5)));
+    scene.add_sphere_light(&Vec3::new(0.5, 3.0, 0.5), 0.5, &Vec3::new(10.0, 10.0, 10.0));
+    let camera = Camera::new_from_fov_and_aspect(
+      &Vec3::new(0.0, 1.5, 6.0),
+      &Vec3::new(0.0, 0.5, 0.0),
+      &Vec3::new(0.0, 1.0, 0.0),
+      40.0,
+      2.0,
+      0.0,
+      6.0,
+    );
+    (scene, camera)
+  }
+
+  #[test]
+  fn resumed_render_matches_uninterrupted_render() {
+    let (scene, camera) = test_scene();
+    let path = PathIntegrator::new(RenderSettings::default());
+    let renderer = Renderer::new(ColorMode::Rgb, SamplingSettings::fixed(16).unwrap(), true, 7);
+    let arguments = vec!["--seed".to_string(), "7".to_string()];
+    let uninterrupted = renderer
+      .render(&path, &scene, &camera, Checkpoint::new(&arguments, Film::new(16, 8)))
+      .unwrap();
+    let mut checkpoint = Checkpoint::new(&arguments, Film::new(16, 8));
+    for _ in 0..2 {
+      let pass = checkpoint.passes();
+      assert!(renderer.render_pass(&path, &scene, &camera, checkpoint.film_mut(), pass));
+      checkpoint.complete_pass(Duration::ZERO);
+    }
+    let checkpoint_path = std::env::temp_dir().join(format!("resume-test-{}.checkpoint", std::process::id()));
+    checkpoint.save(&checkpoint_path).unwrap();
+    let resumed_checkpoint = Checkpoint::load(&checkpoint_path).unwrap();
+    std::fs::remove_file(&checkpoint_path).unwrap();
+    assert_eq!(resumed_checkpoint.arguments(), &arguments[..]);
+    assert_eq!(resumed_checkpoint.passes(), 2);
+    let resumed = renderer.render(&path, &scene, &camera, resumed_checkpoint).unwrap();
+    assert!(resumed == uninterrupted, "resumed render differs from uninterrupted render");
+  }
+
+  #[test]
+  fn packet_render_matches_single_ray_render() {
+    let (scene, camera) = test_scene();
+    let path = PathIntegrator::new(RenderSettings::default());
+    let renderer = Renderer::new(ColorMode::Rgb, SamplingSettings::fixed(4).unwrap(), true, 3);
+    let render = |renderer: &Renderer| renderer.render(&path, &scene, &camera, Checkpoint::new(&[], Film::new(20, 10))).unwrap();
+    let single = render(&renderer);
+    let packets = render(&renderer.clone().with_packets(true));
+    assert!(packets == single, "packet render differs from single ray render");
+  }
+
+  #[test]
+  #[allow(clippy::cast_precision_loss)]
+  fn jittered_positions_stay_in_their_pixel() {
+    for pixel in [0, 101, 4095] {
+      for offset in [0.0, 0.5, next_float_down(1.0)] {
+        let position = pixel_position(pixel, offset);
+        assert!(
+          (pixel as Float..pixel as Float + 1.0).contains(&position),
+          "{} in pixel {}",
+          position,
+          pixel
+        );
+      }
+    }
+  }
+
+  #[test]
+  fn every_pixel_takes_one_camera_ray_per_sample() {
+    // Near the right edge of so wide a film a few of this seed's samples land so close to the next pixel that adding
+    // their offset rounds up to it, and the pixels they left made up for each with an extra sample
+    let mut scene = Scene::new(HittableList::new());
+    scene.set_background(Background::Constant(Vec3::new(0.5, 0.5, 0.5)));
+    let camera = Camera::new_from_fov_and_aspect(
+      &Vec3::new(0.0, 0.0, 1.0),
+      &Vec3::new(0.0, 0.0, 0.0),
+      &Vec3::new(0.0, 1.0, 0.0),
+      40.0,
+      2048.0,
+      0.0,
+      1.0,
+    );
+    let path = PathIntegrator::new(RenderSettings::default());
+    let renderer = Renderer::new(ColorMode::Rgb, SamplingSettings::fixed(16).unwrap(), false, 240);
+    let (width, height, samples) = (4096, 2, 16);
+    let mut film = Film::new(width, height);
+    let camera_rays = || stats::thread_counts().get(Counter::CameraRays);
+    let before = camera_rays();
+    for pass in 0..samples {
+      for x in (0..width).step_by(TILE_SIZE) {
+        let tile = (x..x + TILE_SIZE, 0..height);
+        if let Some(tile_film) = renderer.render_tile(&path, &scene, &camera, &film, &tile, pass) {
+          film.merge(&tile_film);
+        }
+      }
+    }
+    assert_eq!(camera_rays() - before, (width * height * samples) as u64);
+    for (x, y) in (0..height).flat_map(|y| (0..width).map(move |x| (x, y))) {
+      assert_eq!(film.sample_count(x, y), samples, "samples of pixel ({x}, {y})");
+    }
+  }
+}
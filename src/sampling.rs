@@ -0,0 +1,219 @@
+use crate::float::consts::PI;
+use crate::float::Float;
+use crate::vec3::Vec3;
+
+/// Piecewise-constant distribution over [0, 1) built from non-negative function values
+#[derive(Clone, Debug, PartialEq)]
+pub struct Distribution1D {
+  function: Vec<Float>,
+  cdf: Vec<Float>,
+  integral: Float,
+}
+
+impl Distribution1D {
+  /// Builds the distribution of `function`, sampling uniformly where it is zero everywhere or empty
+  #[allow(clippy::cast_precision_loss)]
+  pub fn new(function: &[Float]) -> Self {
+    if function.is_empty() {
+      return Self::new(&[0.0]);
+    }
+    let count = function.len() as Float;
+    let mut cdf = Vec::with_capacity(function.len() + 1);
+    cdf.push(0.0);
+    function.iter().fold(0.0, |running_total, value| {
+      let running_total = running_total + value.abs() / count;
+      cdf.push(running_total);
+      running_total
+    });
+    let integral = cdf[function.len()];
+    if integral > 0.0 {
+      for value in &mut cdf {
+        *value /= integral;
+      }
+    } else {
+      cdf.iter_mut().enumerate().for_each(|(index, value)| *value = index as Float / count);
+    }
+    Self {
+      function: function.iter().map(|value| value.abs()).collect(),
+      cdf,
+      integral,
+    }
+  }
+  pub fn count(&self) -> usize {
+    self.function.len()
+  }
+  pub fn integral(&self) -> Float {
+    self.integral
+  }
+  /// Returns the sampled position in [0, 1), its density and the index of the segment it fell in
+  #[allow(clippy::cast_precision_loss)]
+  pub fn sample_continuous(&self, u: Float) -> (Float, Float, usize) {
+    let offset = self.cdf[1..self.count()].partition_point(|value| *value <= u);
+    let segment = self.cdf[offset + 1] - self.cdf[offset];
+    let fraction = if segment > 0.0 { (u - self.cdf[offset]) / segment } else { 0.0 };
+    let pdf = if self.integral > 0.0 {
+      self.function[offset] / self.integral
+    } else {
+      1.0
+    };
+    (
+      ((offset as Float + fraction) / self.count() as Float).min(1.0 - Float::EPSILON),
+      pdf,
+      offset,
+    )
+  }
+  /// Returns the index of a segment picked in proportion to its value, and the probability of picking it
+  pub fn sample_discrete(&self, u: Float) -> (usize, Float) {
+    let (_, _, offset) = self.sample_continuous(u);
+    (offset, self.discrete_pmf(offset))
+  }
+  #[allow(clippy::cast_precision_loss)]
+  pub fn discrete_pmf(&self, index: usize) -> Float {
+    if self.integral > 0.0 {
+      self.function[index] / (self.integral * self.count() as Float)
+    } else {
+      1.0 / self.count() as Float
+    }
+  }
+  #[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss, clippy::cast_sign_loss)]
+  pub fn pdf(&self, x: Float) -> Float {
+    let offset = ((x * self.count() as Float) as usize).min(self.count() - 1);
+    if self.integral > 0.0 {
+      self.function[offset] / self.integral
+    } else {
+      1.0
+    }
+  }
+}
+
+/// Piecewise-constant distribution over [0, 1)^2, sampled by picking a row from the marginal and then a column
+/// from that row's conditional distribution
+#[derive(Clone, Debug, PartialEq)]
+pub struct Distribution2D {
+  conditionals: Vec<Distribution1D>,
+  marginal: Distribution1D,
+}
+
+impl Distribution2D {
+  pub fn new(function: &[Float], width: usize, height: usize) -> Self {
+    let conditionals: Vec<Distribution1D> = function.chunks(width).take(height).map(Distribution1D::new).collect();
+    let marginal = Distribution1D::new(&conditionals.iter().map(Distribution1D::integral).collect::<Vec<Float>>());
+    Self { conditionals, marginal }
+  }
+  pub fn sample_continuous(&self, u: Float, v: Float) -> ((Float, Float), Float) {
+    let (y, marginal_pdf, row) = self.marginal.sample_continuous(v);
+    let (x, conditional_pdf, _) = self.conditionals[row].sample_continuous(u);
+    ((x, y), marginal_pdf * conditional_pdf)
+  }
+  #[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss, clippy::cast_sign_loss)]
+  pub fn pdf(&self, x: Float, y: Float) -> Float {
+    let row = ((y * self.marginal.count() as Float) as usize).min(self.marginal.count() - 1);
+    if self.marginal.integral() > 0.0 {
+      self.conditionals[row].pdf(x) * self.conditionals[row].integral() / self.marginal.integral()
+    } else {
+      1.0
+    }
+  }
+}
+
+/// Veach's power heuristic with an exponent of two
+pub fn power_heuristic(pdf: Float, other_pdf: Float) -> Float {
+  let pdf_squared = pdf * pdf;
+  let denominator = other_pdf.mul_add(other_pdf, pdf_squared);
+  if denominator > 0.0 {
+    pdf_squared / denominator
+  } else {
+    0.0
+  }
+}
+
+/// Builds two unit vectors perpendicular to the unit vector `w` and to each other
+pub fn orthonormal_basis(w: &Vec3) -> (Vec3, Vec3) {
+  let helper = if w.x().abs() > 0.9 {
+    Vec3::new(0.0, 1.0, 0.0)
+  } else {
+    Vec3::new(1.0, 0.0, 0.0)
+  };
+  let v = w.cross(&helper).unit_vector();
+  (w.cross(&v), v)
+}
+
+/// Samples a direction uniformly within the cone of half-angle `acos(cos_theta_max)` around the unit vector `axis`
+pub fn sample_uniform_cone(u1: Float, u2: Float, axis: &Vec3, cos_theta_max: Float) -> Vec3 {
+  let cos_theta = u1.mul_add(cos_theta_max - 1.0, 1.0);
+  let sin_theta = cos_theta.mul_add(-cos_theta, 1.0).max(0.0).sqrt();
+  let phi = 2.0 * PI * u2;
+  let (u, v) = orthonormal_basis(axis);
+  sin_theta * phi.cos() * u + sin_theta * phi.sin() * v + cos_theta * axis
+}
+
+pub fn uniform_cone_pdf(cos_theta_max: Float) -> Float {
+  1.0 / (2.0 * PI * (1.0 - cos_theta_max))
+}
+
+/// Samples a direction uniformly over the unit sphere
+pub fn sample_uniform_sphere(u1: Float, u2: Float) -> Vec3 {
+  let z = Float::mul_add(2.0, -u1, 1.0);
+  let radius = z.mul_add(-z, 1.0).max(0.0).sqrt();
+  let phi = 2.0 * PI * u2;
+  Vec3::new(radius * phi.cos(), radius * phi.sin(), z)
+}
+
+pub fn uniform_sphere_pdf() -> Float {
+  1.0 / (4.0 * PI)
+}
+
+/// Samples a direction in the hemisphere around the unit vector `normal` with density proportional to its cosine
+pub fn sample_cosine_hemisphere(u1: Float, u2: Float, normal: &Vec3) -> Vec3 {
+  let radius = u1.sqrt();
+  let phi = 2.0 * PI * u2;
+  let (u, v) = orthonormal_basis(normal);
+  radius * phi.cos() * u + radius * phi.sin() * v + (1.0 - u1).max(0.0).sqrt() * normal
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Points spread across the unit interval, away from the segment boundaries of the distributions tested
+  #[allow(clippy::cast_precision_loss)]
+  fn spread(count: usize) -> impl Iterator<Item = Float> {
+    (0..count).map(move |index| (index as Float + 0.37) / count as Float)
+  }
+
+  #[test]
+  #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+  fn sample_continuous_gives_the_density_of_the_point_it_picks() {
+    let function = [1.0, 0.0, 3.0, 4.0];
+    let distribution = Distribution1D::new(&function);
+    assert!((distribution.integral() - 2.0).abs() < 1e-6);
+    for u in spread(100) {
+      let (x, pdf, segment) = distribution.sample_continuous(u);
+      assert_eq!(segment, (x * 4.0) as usize);
+      assert!((pdf - function[segment] / 2.0).abs() < 1e-6);
+      assert!((pdf - distribution.pdf(x)).abs() < 1e-6);
+    }
+    let function = [1.0, 2.0, 0.0, 4.0, 8.0, 5.0];
+    let distribution = Distribution2D::new(&function, 3, 2);
+    for (u, v) in spread(20).flat_map(|u| spread(20).map(move |v| (u, v))) {
+      let ((x, y), pdf) = distribution.sample_continuous(u, v);
+      let value = function[(y * 2.0) as usize * 3 + (x * 3.0) as usize];
+      assert!(value > 0.0, "sampled ({}, {}), where the function is zero", x, y);
+      assert!((pdf - value / (20.0 / 6.0)).abs() < 1e-5);
+      assert!((pdf - distribution.pdf(x, y)).abs() < 1e-5);
+    }
+  }
+
+  #[test]
+  fn empty_and_zero_functions_sample_uniformly() {
+    for function in [&[][..], &[0.0, 0.0]] {
+      let distribution = Distribution1D::new(function);
+      for u in spread(10) {
+        let (x, pdf, _) = distribution.sample_continuous(u);
+        assert!((0.0..1.0).contains(&x));
+        assert!((pdf - 1.0).abs() < Float::EPSILON);
+        assert!((distribution.pdf(x) - 1.0).abs() < Float::EPSILON);
+      }
+    }
+  }
+}
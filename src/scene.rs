@@ -0,0 +1,338 @@
+use crate::background::Background;
+use crate::float::Float;
+use crate::hit::{Hittable, HittableList};
+use crate::light::{Light, SphereLight};
+use crate::light_sampler::{BvhLightSampler, LightSampler, UniformLightSampler};
+use crate::material::{Dielectric, DiffuseLight, Isotropic, Lambertian, Material, Metal};
+use crate::sphere::Sphere;
+use crate::triangle::Triangle;
+use crate::vec3::Vec3;
+use crate::Error;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub struct Scene {
+  world: HittableList,
+  background: Background,
+  lights: Vec<Box<dyn Light>>,
+  light_sampler: Box<dyn LightSampler>,
+}
+
+impl Scene {
+  /// A builder for an empty scene under the daylight sky
+  pub fn builder() -> SceneBuilder {
+    SceneBuilder::default()
+  }
+  pub fn new(world: HittableList) -> Self {
+    Self {
+      world,
+      background: Background::default(),
+      lights: Vec::new(),
+      light_sampler: Box::new(UniformLightSampler::new(0)),
+    }
+  }
+  pub fn world(&self) -> &HittableList {
+    &self.world
+  }
+  pub fn world_mut(&mut self) -> &mut HittableList {
+    &mut self.world
+  }
+  pub fn background(&self) -> &Background {
+    &self.background
+  }
+  pub fn lights(&self) -> &Vec<Box<dyn Light>> {
+    &self.lights
+  }
+  pub fn light_sampler(&self) -> &dyn LightSampler {
+    self.light_sampler.as_ref()
+  }
+  pub fn set_background(&mut self, background: Background) {
+    self.background = background;
+  }
+  /// Adds a light and returns its index, resetting light selection to uniform until `build_light_bvh` is called
+  pub fn add_light(&mut self, light: Box<dyn Light>) -> usize {
+    self.lights.push(light);
+    self.light_sampler = Box::new(UniformLightSampler::new(self.lights.len()));
+    self.lights.len() - 1
+  }
+  /// Adds an emissive sphere to the world together with the light that samples it
+  pub fn add_sphere_light(&mut self, center: &Vec3, radius: Float, radiance: &Vec3) -> usize {
+    let light = self.add_light(Box::new(SphereLight::new(center, radius, radiance)));
+    self.world.list_mut().push(Box::new(
+      Sphere::new(center, radius, Arc::new(DiffuseLight::new(radiance))).with_light(light),
+    ));
+    light
+  }
+  /// Selects lights through a light BVH rather than uniformly
+  pub fn build_light_bvh(&mut self) {
+    self.light_sampler = Box::new(BvhLightSampler::new(&self.lights));
+  }
+}
+
+/// Refers to a material registered with the `SceneBuilder` that returned it
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct MaterialHandle {
+  builder: u64,
+  index: usize,
+}
+
+/// Source of the ids that tie each `MaterialHandle` to the builder that returned it
+static NEXT_BUILDER_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Error unless `value` is finite and at least `min`, or also above it if `exclusive`
+fn check_at_least(what: &str, value: Float, min: Float, exclusive: bool) -> Result<(), Error> {
+  if value.is_finite() && (value > min || (!exclusive && value >= min)) {
+    return Ok(());
+  }
+  let bound = if exclusive { "greater than" } else { "at least" };
+  Err(format!("{what} must be {bound} {min}, found {value}").into())
+}
+
+/// Error unless every coordinate of `point` is finite
+fn check_finite(what: &str, point: &Vec3) -> Result<(), Error> {
+  for coordinate in [point.x(), point.y(), point.z()] {
+    if !coordinate.is_finite() {
+      return Err(format!("{what} must be finite, found {coordinate}").into());
+    }
+  }
+  Ok(())
+}
+
+/// Error unless every component of `color` is finite, not negative and, if `is_reflectance`, at most one
+fn check_color(what: &str, color: &Vec3, is_reflectance: bool) -> Result<(), Error> {
+  for component in [color.x(), color.y(), color.z()] {
+    check_at_least(what, component, 0.0, false)?;
+    if is_reflectance && component > 1.0 {
+      return Err(format!("{what} must be at most 1, found {component}").into());
+    }
+  }
+  Ok(())
+}
+
+/// Builds a `Scene` from materials registered once under a name and shapes that refer to them by handle, checking
+/// every value it is given
+#[derive(Debug)]
+pub struct SceneBuilder {
+  id: u64,
+  materials: Vec<(String, Arc<dyn Material>)>,
+  scene: Scene,
+}
+
+impl Default for SceneBuilder {
+  fn default() -> Self {
+    Self {
+      id: NEXT_BUILDER_ID.fetch_add(1, Ordering::Relaxed),
+      materials: Vec::new(),
+      scene: Scene::new(HittableList::new()),
+    }
+  }
+}
+
+impl SceneBuilder {
+  /// Registers `material` under `name`
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if a material is already registered under `name`
+  pub fn add_material(&mut self, name: &str, material: Arc<dyn Material>) -> Result<MaterialHandle, Error> {
+    if self.material(name).is_some() {
+      return Err(format!("A material named {name} is already registered").into());
+    }
+    self.materials.push((name.to_string(), material));
+    Ok(self.handle(self.materials.len() - 1))
+  }
+  /// Registers a diffuse material under `name`
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if `albedo` is not between 0 and 1 or the name is taken
+  pub fn add_lambertian(&mut self, name: &str, albedo: &Vec3) -> Result<MaterialHandle, Error> {
+    check_color("Albedo", albedo, true)?;
+    self.add_material(name, Arc::new(Lambertian::new(albedo)))
+  }
+  /// Registers a metal under `name`, blurring its reflections more the higher `fuzz` is
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if `albedo` or `fuzz` is not between 0 and 1 or the name is taken
+  pub fn add_metal(&mut self, name: &str, albedo: &Vec3, fuzz: Float) -> Result<MaterialHandle, Error> {
+    check_color("Albedo", albedo, true)?;
+    check_at_least("Fuzz", fuzz, 0.0, false)?;
+    if fuzz > 1.0 {
+      return Err(format!("Fuzz must be at most 1, found {fuzz}").into());
+    }
+    self.add_material(name, Arc::new(Metal::new(albedo, fuzz)))
+  }
+  /// Registers a glass-like material with the index of refraction `refractive_index` under `name`
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if `refractive_index` is not positive or the name is taken
+  pub fn add_dielectric(&mut self, name: &str, refractive_index: Float) -> Result<MaterialHandle, Error> {
+    check_at_least("Index of refraction", refractive_index, 0.0, true)?;
+    self.add_material(name, Arc::new(Dielectric::new(refractive_index)))
+  }
+  /// Registers a phase function scattering equally in every direction under `name`, for media
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if `albedo` is not between 0 and 1 or the name is taken
+  pub fn add_isotropic(&mut self, name: &str, albedo: &Vec3) -> Result<MaterialHandle, Error> {
+    check_color("Albedo", albedo, true)?;
+    self.add_material(name, Arc::new(Isotropic::new(albedo)))
+  }
+  /// Handle of the material registered under `name`, if any
+  pub fn material(&self, name: &str) -> Option<MaterialHandle> {
+    self
+      .materials
+      .iter()
+      .position(|(existing, _)| existing == name)
+      .map(|index| self.handle(index))
+  }
+  fn handle(&self, index: usize) -> MaterialHandle {
+    MaterialHandle { builder: self.id, index }
+  }
+  fn resolve(&self, material: MaterialHandle) -> Result<Arc<dyn Material>, Error> {
+    if material.builder != self.id {
+      return Err("The material handle was returned by another scene builder".into());
+    }
+    self
+      .materials
+      .get(material.index)
+      .map(|(_, material)| material.clone())
+      .ok_or_else(|| "The material handle does not name a registered material".into())
+  }
+  /// Adds a sphere of `material`
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if the center is not finite, the radius is not positive or the material is not registered here
+  pub fn add_sphere(&mut self, center: &Vec3, radius: Float, material: MaterialHandle) -> Result<&mut Self, Error> {
+    check_finite("Center", center)?;
+    check_at_least("Radius", radius, 0.0, true)?;
+    let material = self.resolve(material)?;
+    self.scene.world_mut().list_mut().push(Box::new(Sphere::new(center, radius, material)));
+    Ok(self)
+  }
+  /// Adds an emissive sphere together with the light that samples it
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if the center is not finite, the radius is not positive or the radiance is negative
+  pub fn add_sphere_light(&mut self, center: &Vec3, radius: Float, radiance: &Vec3) -> Result<&mut Self, Error> {
+    check_finite("Center", center)?;
+    check_at_least("Radius", radius, 0.0, true)?;
+    check_color("Radiance", radiance, false)?;
+    self.scene.add_sphere_light(center, radius, radiance);
+    Ok(self)
+  }
+  /// Adds a flat triangle of `material`, facing the side its vertices wind anticlockwise around
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if a vertex is not finite, the vertices lie on one line or the material is not registered here
+  pub fn add_triangle(&mut self, first: &Vec3, second: &Vec3, third: &Vec3, material: MaterialHandle) -> Result<&mut Self, Error> {
+    for vertex in [first, second, third] {
+      check_finite("Vertex", vertex)?;
+    }
+    if (second - first).cross(&(third - first)).squared_length() <= 0.0 {
+      return Err("Triangle vertices must not lie on one line".into());
+    }
+    let material = self.resolve(material)?;
+    self
+      .scene
+      .world_mut()
+      .list_mut()
+      .push(Box::new(Triangle::new(first, second, third, material)));
+    Ok(self)
+  }
+  /// Adds a light that is only reached by sampling it, such as a point or directional light
+  pub fn add_light(&mut self, light: Box<dyn Light>) -> &mut Self {
+    self.scene.add_light(light);
+    self
+  }
+  /// Adds any other hittable, such as a medium, as it is
+  pub fn add_hittable(&mut self, hittable: Box<dyn Hittable>) -> &mut Self {
+    self.scene.world_mut().list_mut().push(hittable);
+    self
+  }
+  pub fn set_background(&mut self, background: Background) -> &mut Self {
+    self.scene.set_background(background);
+    self
+  }
+  /// The scene, with BVHs built over its hittables and lights so it is ready to render
+  pub fn build(self) -> Scene {
+    let mut scene = self.scene;
+    scene.world_mut().build_bvh();
+    scene.build_light_bvh();
+    scene
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::ray::Ray;
+
+  #[test]
+  fn builder_shares_registered_materials_between_shapes() {
+    let mut builder = Scene::builder();
+    let ground = builder.add_lambertian("ground", &Vec3::new(0.5, 0.5, 0.5)).unwrap();
+    let glass = builder.add_dielectric("glass", 1.5).unwrap();
+    assert_eq!(builder.material("glass"), Some(glass));
+    builder
+      .add_sphere(&Vec3::new(0.0, -1000.0, 0.0), 1000.0, ground)
+      .unwrap()
+      .add_sphere(&Vec3::new(0.0, 1.0, 0.0), 1.0, glass)
+      .unwrap()
+      .add_sphere(&Vec3::new(2.5, 1.0, 0.0), 1.0, glass)
+      .unwrap()
+      .add_sphere_light(&Vec3::new(0.0, 5.0, 0.0), 0.5, &Vec3::new(4.0, 4.0, 4.0))
+      .unwrap();
+    let scene = builder.build();
+    assert_eq!(scene.lights().len(), 1);
+    let down = Ray::new(&Vec3::new(2.5, 10.0, 0.0), &Vec3::new(0.0, -1.0, 0.0));
+    let hit_record = scene.world().is_hit(&down, 0.0, Float::MAX).unwrap();
+    assert!((hit_record.point().y() - 2.0).abs() < 1e-4);
+  }
+
+  #[test]
+  fn builder_rejects_invalid_values() {
+    let mut builder = Scene::builder();
+    let ground = builder.add_lambertian("ground", &Vec3::new(0.5, 0.5, 0.5)).unwrap();
+    assert!(builder.add_lambertian("ground", &Vec3::new(0.5, 0.5, 0.5)).is_err());
+    assert!(builder.add_lambertian("bright", &Vec3::new(1.5, 0.5, 0.5)).is_err());
+    assert!(builder.add_metal("rough", &Vec3::new(0.5, 0.5, 0.5), 2.0).is_err());
+    assert!(builder.add_dielectric("vacuum", 0.0).is_err());
+    assert!(builder.add_dielectric("negative", -1.5).is_err());
+    assert!(builder.add_sphere(&Vec3::new_empty(), -1.0, ground).is_err());
+    let error = builder.add_sphere(&Vec3::new(Float::NAN, 0.0, 0.0), 1.0, ground).err().unwrap();
+    assert_eq!(error.to_string(), "Center must be finite, found NaN");
+    assert!(builder
+      .add_sphere_light(&Vec3::new(0.0, Float::NEG_INFINITY, 0.0), 1.0, &Vec3::new(1.0, 1.0, 1.0))
+      .is_err());
+    assert!(builder.add_sphere_light(&Vec3::new_empty(), 1.0, &Vec3::new(-1.0, 1.0, 1.0)).is_err());
+    let (origin, x, y) = (Vec3::new_empty(), Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+    assert!(builder.add_triangle(&origin, &x, &(2.0 * x), ground).is_err());
+    assert!(builder.add_triangle(&origin, &x, &Vec3::new(0.0, Float::INFINITY, 0.0), ground).is_err());
+    assert!(builder.add_triangle(&origin, &x, &y, ground).is_ok());
+    let mut other = Scene::builder();
+    other.add_lambertian("ground", &Vec3::new(0.5, 0.5, 0.5)).unwrap();
+    let glass = other.add_dielectric("glass", 1.5).unwrap();
+    assert!(builder.add_sphere(&Vec3::new_empty(), 1.0, glass).is_err());
+  }
+
+  #[test]
+  fn builder_rejects_handles_from_another_builder_that_are_in_range() {
+    let mut builder = Scene::builder();
+    builder.add_lambertian("ground", &Vec3::new(0.5, 0.5, 0.5)).unwrap();
+    builder.add_dielectric("glass", 1.5).unwrap();
+    let mut other = Scene::builder();
+    let metal = other.add_metal("metal", &Vec3::new(0.7, 0.6, 0.5), 0.0).unwrap();
+    let error = builder.add_sphere(&Vec3::new_empty(), 1.0, metal).err().unwrap();
+    assert!(error.to_string().contains("another scene builder"));
+    assert!(other.add_sphere(&Vec3::new_empty(), 1.0, metal).is_ok());
+    assert_ne!(builder.material("ground"), other.material("metal"));
+  }
+}
@@ -0,0 +1,140 @@
+use crate::background::Background;
+use crate::float::{random_float, Float};
+use crate::hit::HittableList;
+use crate::material::{Dielectric, Lambertian, Metal};
+use crate::scene::Scene;
+use crate::sphere::Sphere;
+use crate::vec3::Vec3;
+use std::sync::Arc;
+
+/// The cover scene of Ray Tracing in One Weekend: three large spheres among hundreds of small ones of random
+/// materials, scattered the same way for the same `seed`
+pub fn random_scene(seed: u64) -> Scene {
+  fastrand::seed(seed);
+  let mut random_array = HittableList::new();
+  random_array.list_mut().push(Box::new(Sphere::new(
+    &Vec3::new(0.0, -1000.0, 0.0),
+    1000.0,
+    Arc::new(Lambertian::new(&Vec3::new(0.5, 0.5, 0.5))),
+  )));
+  (-11..11).for_each(|a| {
+    (-11..11).for_each(|b| {
+      let choose_mat = random_float();
+      #[allow(clippy::cast_precision_loss)]
+      let center = Vec3::new(
+        Float::mul_add(0.9, random_float(), a as Float),
+        0.2,
+        Float::mul_add(0.9, random_float(), b as Float),
+      );
+      if (center - Vec3::new(4.0, 0.2, 2.0)).length() > 0.9 {
+        if choose_mat < 0.8 {
+          random_array.list_mut().push(Box::new(Sphere::new(
+            &center,
+            0.2,
+            Arc::new(Lambertian::new(&Vec3::new(
+              random_float() * random_float(),
+              random_float() * random_float(),
+              random_float() * random_float(),
+            ))),
+          )));
+        } else if choose_mat < 0.95 {
+          random_array.list_mut().push(Box::new(Sphere::new(
+            &center,
+            0.2,
+            Arc::new(Metal::new(
+              &Vec3::new(0.5 * (1.0 + random_float()), 0.5 * (1.0 + random_float()), 0.5 * (1.0 + random_float())),
+              0.5 * random_float(),
+            )),
+          )));
+        } else {
+          random_array
+            .list_mut()
+            .push(Box::new(Sphere::new(&center, 0.2, Arc::new(Dielectric::new(1.5)))));
+        }
+      }
+    });
+  });
+  random_array
+    .list_mut()
+    .push(Box::new(Sphere::new(&Vec3::new(0.0, 1.0, 0.0), 1.0, Arc::new(Dielectric::new(1.5)))));
+  random_array.list_mut().push(Box::new(Sphere::new(
+    &Vec3::new(-4.0, 1.0, 0.0),
+    1.0,
+    Arc::new(Lambertian::new(&Vec3::new(0.4, 0.2, 0.1))),
+  )));
+  random_array.list_mut().push(Box::new(Sphere::new(
+    &Vec3::new(4.0, 1.0, 0.0),
+    1.0,
+    Arc::new(Metal::new(&Vec3::new(0.7, 0.6, 0.5), 0.0)),
+  )));
+  Scene::new(random_array)
+}
+
+/// A glass ball on a table lit by a small light behind it, focusing a caustic towards the camera
+pub fn caustics_scene() -> Scene {
+  let mut world = HittableList::new();
+  world.list_mut().push(Box::new(Sphere::new(
+    &Vec3::new(0.0, -1000.0, 0.0),
+    1000.0,
+    Arc::new(Lambertian::new(&Vec3::new(0.6, 0.6, 0.6))),
+  )));
+  world
+    .list_mut()
+    .push(Box::new(Sphere::new(&Vec3::new(0.0, 1.0, 0.0), 1.0, Arc::new(Dielectric::new(1.5)))));
+  world.list_mut().push(Box::new(Sphere::new(
+    &Vec3::new(-1.5, 0.5, 2.0),
+    0.5,
+    Arc::new(Lambertian::new(&Vec3::new(0.2, 0.3, 0.7))),
+  )));
+  let mut scene = Scene::new(world);
+  scene.set_background(Background::Constant(Vec3::new(0.02, 0.02, 0.025)));
+  scene.add_sphere_light(&Vec3::new(-4.0, 4.0, -1.0), 0.25, &Vec3::new(300.0, 280.0, 250.0));
+  scene
+}
+
+/// A dark version of the random scene where a quarter of the small spheres are lights, scattered the same way for the
+/// same `seed`
+pub fn many_lights_scene(seed: u64) -> Scene {
+  fastrand::seed(seed);
+  let mut world = HittableList::new();
+  world.list_mut().push(Box::new(Sphere::new(
+    &Vec3::new(0.0, -1000.0, 0.0),
+    1000.0,
+    Arc::new(Lambertian::new(&Vec3::new(0.5, 0.5, 0.5))),
+  )));
+  world.list_mut().push(Box::new(Sphere::new(
+    &Vec3::new(-4.0, 1.0, 0.0),
+    1.0,
+    Arc::new(Lambertian::new(&Vec3::new(0.4, 0.2, 0.1))),
+  )));
+  world.list_mut().push(Box::new(Sphere::new(
+    &Vec3::new(4.0, 1.0, 0.0),
+    1.0,
+    Arc::new(Metal::new(&Vec3::new(0.7, 0.6, 0.5), 0.0)),
+  )));
+  let mut scene = Scene::new(world);
+  scene.set_background(Background::Constant(Vec3::new_empty()));
+  (-22..22).for_each(|a| {
+    (-22..22).for_each(|b| {
+      #[allow(clippy::cast_precision_loss)]
+      let center = Vec3::new(
+        Float::mul_add(0.45, random_float(), a as Float * 0.5),
+        0.1,
+        Float::mul_add(0.45, random_float(), b as Float * 0.5),
+      );
+      if (center - Vec3::new(4.0, 0.1, 0.0)).length() > 1.1 && (center - Vec3::new(-4.0, 0.1, 0.0)).length() > 1.1 {
+        if random_float() < 0.25 {
+          let radiance = Vec3::new(random_float(), random_float(), random_float()) * 8.0;
+          scene.add_sphere_light(&center, 0.1, &radiance);
+        } else {
+          let albedo = Vec3::new(random_float(), random_float(), random_float());
+          scene
+            .world_mut()
+            .list_mut()
+            .push(Box::new(Sphere::new(&center, 0.1, Arc::new(Lambertian::new(&albedo)))));
+        }
+      }
+    });
+  });
+  scene
+}
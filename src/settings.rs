@@ -0,0 +1,334 @@
+use crate::film::Film;
+use crate::filter::Filter;
+use crate::float::Float;
+use crate::material::ScatterKind;
+use crate::vec3::Vec3;
+use crate::Error;
+use std::time::Duration;
+
+/// Limits on how far paths are traced
+#[allow(clippy::struct_field_names)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RenderSettings {
+  roulette_depth: u32,
+  max_diffuse_depth: u32,
+  max_glossy_depth: u32,
+  max_transmission_depth: u32,
+  max_volume_depth: u32,
+}
+
+impl RenderSettings {
+  /// Creates settings that start Russian roulette after `roulette_depth` bounces and cap each kind of bounce separately
+  pub fn new(roulette_depth: u32, max_diffuse_depth: u32, max_glossy_depth: u32, max_transmission_depth: u32, max_volume_depth: u32) -> Self {
+    Self {
+      roulette_depth,
+      max_diffuse_depth,
+      max_glossy_depth,
+      max_transmission_depth,
+      max_volume_depth,
+    }
+  }
+  pub fn roulette_depth(&self) -> u32 {
+    self.roulette_depth
+  }
+  pub fn max_depth(&self, kind: ScatterKind) -> u32 {
+    match kind {
+      ScatterKind::Diffuse => self.max_diffuse_depth,
+      ScatterKind::Glossy => self.max_glossy_depth,
+      ScatterKind::Transmission => self.max_transmission_depth,
+      ScatterKind::Volume => self.max_volume_depth,
+    }
+  }
+  /// Whether a path that has made `bounces` is still within every bounce budget
+  pub fn allows(&self, bounces: &BounceCounts) -> bool {
+    ScatterKind::ALL.iter().all(|kind| bounces.count(*kind) <= self.max_depth(*kind))
+  }
+  /// Probability of continuing a path that has made `bounces` and carries `throughput`, one until the roulette depth
+  pub fn survival_probability(&self, bounces: &BounceCounts, throughput: &Vec3) -> Float {
+    if bounces.total() <= self.roulette_depth {
+      1.0
+    } else {
+      throughput.x().max(throughput.y()).max(throughput.z()).clamp(0.0, 1.0)
+    }
+  }
+}
+
+impl Default for RenderSettings {
+  fn default() -> Self {
+    Self::new(5, 50, 50, 50, 50)
+  }
+}
+
+/// What `render` makes of a scene: the size of the image, the samples taken in each pixel, how far paths are traced,
+/// how the samples are reconstructed into pixels and the seed for the random numbers
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ImageSettings {
+  width: usize,
+  height: usize,
+  samples_per_pixel: usize,
+  render_settings: RenderSettings,
+  filter: Filter,
+  seed: u64,
+}
+
+impl ImageSettings {
+  /// Settings for a `width` by `height` image taking `samples_per_pixel` samples in each pixel, with the default
+  /// bounce limits and box filter and a seed of zero
+  pub fn new(width: usize, height: usize, samples_per_pixel: usize) -> Self {
+    Self {
+      width,
+      height,
+      samples_per_pixel,
+      render_settings: RenderSettings::default(),
+      filter: Filter::default(),
+      seed: 0,
+    }
+  }
+  pub fn with_render_settings(mut self, render_settings: RenderSettings) -> Self {
+    self.render_settings = render_settings;
+    self
+  }
+  pub fn with_filter(mut self, filter: Filter) -> Self {
+    self.filter = filter;
+    self
+  }
+  pub fn with_seed(mut self, seed: u64) -> Self {
+    self.seed = seed;
+    self
+  }
+  pub fn width(&self) -> usize {
+    self.width
+  }
+  pub fn height(&self) -> usize {
+    self.height
+  }
+  pub fn samples_per_pixel(&self) -> usize {
+    self.samples_per_pixel
+  }
+  pub fn render_settings(&self) -> &RenderSettings {
+    &self.render_settings
+  }
+  pub fn filter(&self) -> &Filter {
+    &self.filter
+  }
+  pub fn seed(&self) -> u64 {
+    self.seed
+  }
+}
+
+/// Samples each pixel takes per pass when sampling a fixed number, so the image refines progressively
+const FIXED_PASS_SAMPLES: usize = 4;
+
+/// How many samples each pixel takes, either a fixed number or as many as it needs to converge, and how many of them
+/// it takes in each pass over the image
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SamplingSettings {
+  min_samples: usize,
+  max_samples: usize,
+  pass_samples: usize,
+  max_relative_error: Float,
+}
+
+impl SamplingSettings {
+  /// Every pixel takes exactly `samples` samples, a few more in each pass
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if `samples` is zero
+  pub fn fixed(samples: usize) -> Result<Self, Error> {
+    if samples == 0 {
+      return Err("Pixels must take at least one sample".into());
+    }
+    Ok(Self {
+      min_samples: samples,
+      max_samples: samples,
+      pass_samples: FIXED_PASS_SAMPLES,
+      max_relative_error: 0.0,
+    })
+  }
+  /// Every pixel takes `min_samples` samples, at least two so its variance can be estimated, then more in batches of
+  /// as many until its relative error is at most `max_relative_error` or it has taken `max_samples`
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if `max_samples` is zero
+  pub fn adaptive(min_samples: usize, max_samples: usize, max_relative_error: Float) -> Result<Self, Error> {
+    if max_samples == 0 {
+      return Err("Pixels must be allowed at least one sample".into());
+    }
+    let min_samples = min_samples.max(2);
+    Ok(Self {
+      min_samples,
+      max_samples: max_samples.max(min_samples),
+      pass_samples: min_samples,
+      max_relative_error,
+    })
+  }
+  /// Most samples any pixel takes
+  pub fn max_samples(&self) -> usize {
+    self.max_samples
+  }
+  /// Number of samples the pixel `x` from the left and `y` from the bottom of `film` should take in the next pass,
+  /// zero once it has converged or used up its budget
+  pub fn next_batch(&self, film: &Film, x: usize, y: usize) -> usize {
+    let count = film.sample_count(x, y);
+    if count >= self.max_samples || (count >= self.min_samples && film.relative_error(x, y) <= self.max_relative_error) {
+      0
+    } else {
+      self.pass_samples.min(self.max_samples - count)
+    }
+  }
+}
+
+/// When a render stops taking passes, if its pixels have not all taken every sample they may before then
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Termination {
+  /// Only once every pixel is done
+  Converged,
+  /// After the first pass to end once the render has run this long
+  TimeLimit(Duration),
+  /// After the first pass to end with at least this many samples taken across the image
+  SampleLimit(usize),
+  /// After the first pass to end with the mean relative error of the image's pixels at or below this
+  NoiseTarget(Float),
+}
+
+impl Termination {
+  /// Whether a render that has accumulated `film` over `elapsed` should stop
+  pub fn is_met(&self, film: &Film, elapsed: Duration) -> bool {
+    match *self {
+      Self::Converged => false,
+      Self::TimeLimit(limit) => elapsed >= limit,
+      Self::SampleLimit(limit) => film.total_sample_count() >= limit,
+      Self::NoiseTarget(target) => film.mean_relative_error() <= target,
+    }
+  }
+}
+
+/// Number of bounces of each kind a path has made so far
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BounceCounts {
+  diffuse: u32,
+  glossy: u32,
+  transmission: u32,
+  volume: u32,
+}
+
+impl BounceCounts {
+  pub fn new() -> Self {
+    Self::default()
+  }
+  pub fn count(&self, kind: ScatterKind) -> u32 {
+    match kind {
+      ScatterKind::Diffuse => self.diffuse,
+      ScatterKind::Glossy => self.glossy,
+      ScatterKind::Transmission => self.transmission,
+      ScatterKind::Volume => self.volume,
+    }
+  }
+  pub fn total(&self) -> u32 {
+    self.diffuse + self.glossy + self.transmission + self.volume
+  }
+  /// The counts after one more bounce of `kind`
+  pub fn with_bounce(mut self, kind: ScatterKind) -> Self {
+    match kind {
+      ScatterKind::Diffuse => self.diffuse += 1,
+      ScatterKind::Glossy => self.glossy += 1,
+      ScatterKind::Transmission => self.transmission += 1,
+      ScatterKind::Volume => self.volume += 1,
+    }
+    self
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// A 2x1 film whose left pixel has taken `samples` samples of one and whose right one has taken as many alternating
+  /// between zero and two
+  #[allow(clippy::cast_precision_loss)]
+  fn film_with_samples(samples: usize) -> Film {
+    let mut film = Film::new(2, 1);
+    for sample in 0..samples {
+      let noisy = 2.0 * (sample % 2) as Float;
+      film.add_sample((0.5, 0.5), &Vec3::new(1.0, 1.0, 1.0));
+      film.add_sample((1.5, 0.5), &Vec3::new(noisy, noisy, noisy));
+    }
+    film
+  }
+
+  #[test]
+  fn adaptive_sampling_stops_converged_pixels_and_caps_noisy_ones() {
+    let adaptive = SamplingSettings::adaptive(4, 10, 0.05).unwrap();
+    let film = film_with_samples(0);
+    assert_eq!((adaptive.next_batch(&film, 0, 0), adaptive.next_batch(&film, 1, 0)), (4, 4));
+    // After its minimum the smooth pixel is done while the noisy one keeps going, up to its maximum
+    let film = film_with_samples(4);
+    assert_eq!((adaptive.next_batch(&film, 0, 0), adaptive.next_batch(&film, 1, 0)), (0, 4));
+    let film = film_with_samples(8);
+    assert_eq!(adaptive.next_batch(&film, 1, 0), 2);
+    let film = film_with_samples(10);
+    assert_eq!(adaptive.next_batch(&film, 1, 0), 0);
+    // Pixels take at least two samples, in batches of as many, so their variance can be estimated
+    assert_eq!(
+      SamplingSettings::adaptive(1, 10, 0.05).unwrap().next_batch(&film_with_samples(1), 0, 0),
+      2
+    );
+    let fixed = SamplingSettings::fixed(6).unwrap();
+    assert_eq!(fixed.next_batch(&film_with_samples(0), 0, 0), 4);
+    assert_eq!(fixed.next_batch(&film_with_samples(4), 0, 0), 2);
+    assert_eq!(fixed.next_batch(&film_with_samples(6), 1, 0), 0);
+  }
+
+  #[test]
+  fn sampling_needs_at_least_one_sample_per_pixel() {
+    assert!(SamplingSettings::fixed(0).is_err());
+    assert!(SamplingSettings::adaptive(16, 0, 0.05).is_err());
+    assert_eq!(SamplingSettings::adaptive(16, 1, 0.05).unwrap().max_samples(), 16);
+  }
+
+  #[test]
+  fn terminations_stop_at_their_time_sample_or_noise_limit() {
+    let film = film_with_samples(8);
+    let elapsed = Duration::from_secs(30);
+    assert!(!Termination::Converged.is_met(&film, elapsed));
+    assert!(Termination::TimeLimit(Duration::from_secs(30)).is_met(&film, elapsed));
+    assert!(!Termination::TimeLimit(Duration::from_secs(31)).is_met(&film, elapsed));
+    assert!(Termination::SampleLimit(16).is_met(&film, elapsed));
+    assert!(!Termination::SampleLimit(17).is_met(&film, elapsed));
+    // The smooth pixel has no error and the noisy one a standard error of 0.38 against its mean of one
+    let mean_relative_error = film.mean_relative_error();
+    assert!((mean_relative_error - 0.5 * Float::sqrt(1.0 / 7.0) / 1.01).abs() < 1e-4);
+    assert!(Termination::NoiseTarget(0.2).is_met(&film, elapsed));
+    assert!(!Termination::NoiseTarget(0.15).is_met(&film, elapsed));
+  }
+
+  #[test]
+  fn bounce_budgets_cap_each_kind_of_bounce_separately() {
+    let settings = RenderSettings::new(2, 1, 3, 0, 2);
+    let bounces = BounceCounts::new()
+      .with_bounce(ScatterKind::Diffuse)
+      .with_bounce(ScatterKind::Glossy)
+      .with_bounce(ScatterKind::Glossy)
+      .with_bounce(ScatterKind::Glossy);
+    assert_eq!(bounces.total(), 4);
+    assert!(settings.allows(&bounces));
+    assert!(settings.allows(&bounces.with_bounce(ScatterKind::Volume).with_bounce(ScatterKind::Volume)));
+    assert!(!settings.allows(&bounces.with_bounce(ScatterKind::Diffuse)));
+    assert!(!settings.allows(&bounces.with_bounce(ScatterKind::Glossy)));
+    assert!(!settings.allows(&bounces.with_bounce(ScatterKind::Transmission)));
+  }
+
+  #[test]
+  fn roulette_starts_after_its_depth_and_keeps_bright_paths() {
+    let settings = RenderSettings::new(2, 50, 50, 50, 50);
+    let dim = Vec3::new(0.1, 0.3, 0.2);
+    let two_bounces = BounceCounts::new().with_bounce(ScatterKind::Diffuse).with_bounce(ScatterKind::Glossy);
+    assert!((settings.survival_probability(&two_bounces, &dim) - 1.0).abs() < Float::EPSILON);
+    let three_bounces = two_bounces.with_bounce(ScatterKind::Diffuse);
+    assert!((settings.survival_probability(&three_bounces, &dim) - 0.3).abs() < Float::EPSILON);
+    assert!((settings.survival_probability(&three_bounces, &Vec3::new(2.0, 0.5, 0.0)) - 1.0).abs() < Float::EPSILON);
+    assert!(settings.survival_probability(&three_bounces, &Vec3::new_empty()) <= 0.0);
+  }
+}
@@ -0,0 +1,183 @@
+//! Arithmetic behind `Vec3` and the four-wide BVH, done in SSE registers with the `simd` feature on x86-64 single
+//! precision builds and one lane at a time everywhere else
+
+use crate::float::Float;
+
+/// Whether the SSE backend is compiled in
+pub const ENABLED: bool = cfg!(all(feature = "simd", target_arch = "x86_64", not(feature = "f64")));
+
+#[cfg(all(feature = "simd", target_arch = "x86_64", not(feature = "f64")))]
+pub use sse::*;
+
+#[cfg(not(all(feature = "simd", target_arch = "x86_64", not(feature = "f64"))))]
+pub use scalar::*;
+
+/// Corners of four boxes, one lane per box and one row per axis, tested against a ray together
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Boxes4 {
+  pub minimum: [[Float; 4]; 3],
+  pub maximum: [[Float; 4]; 3],
+}
+
+#[cfg(not(all(feature = "simd", target_arch = "x86_64", not(feature = "f64"))))]
+mod scalar {
+  use super::Boxes4;
+  use crate::float::Float;
+
+  /// Components of a vector, x, y then z
+  pub type Lanes = [Float; 3];
+
+  pub fn lanes(x: Float, y: Float, z: Float) -> Lanes {
+    [x, y, z]
+  }
+  pub fn add(first: &Lanes, second: &Lanes) -> Lanes {
+    [first[0] + second[0], first[1] + second[1], first[2] + second[2]]
+  }
+  pub fn sub(first: &Lanes, second: &Lanes) -> Lanes {
+    [first[0] - second[0], first[1] - second[1], first[2] - second[2]]
+  }
+  pub fn mul(first: &Lanes, second: &Lanes) -> Lanes {
+    [first[0] * second[0], first[1] * second[1], first[2] * second[2]]
+  }
+  pub fn div(first: &Lanes, second: &Lanes) -> Lanes {
+    [first[0] / second[0], first[1] / second[1], first[2] / second[2]]
+  }
+  pub fn scale(lanes: &Lanes, factor: Float) -> Lanes {
+    [lanes[0] * factor, lanes[1] * factor, lanes[2] * factor]
+  }
+  pub fn neg(lanes: &Lanes) -> Lanes {
+    [-lanes[0], -lanes[1], -lanes[2]]
+  }
+  pub fn dot(first: &Lanes, second: &Lanes) -> Float {
+    first[2].mul_add(second[2], first[0].mul_add(second[0], first[1] * second[1]))
+  }
+  pub fn cross(first: &Lanes, second: &Lanes) -> Lanes {
+    [
+      first[1].mul_add(second[2], -(first[2] * second[1])),
+      -first[0].mul_add(second[2], -(first[2] * second[0])),
+      first[0].mul_add(second[1], -(first[1] * second[0])),
+    ]
+  }
+
+  /// Distance along the ray at which it enters each box, or None for boxes it misses between the given scalars
+  pub fn hit_boxes(
+    boxes: &Boxes4,
+    origin: &Lanes,
+    inverse_direction: &Lanes,
+    scalar_from_ray_origin_min: Float,
+    scalar_from_ray_origin_max: Float,
+  ) -> [Option<Float>; 4] {
+    let mut hits = [None; 4];
+    for (lane, hit) in hits.iter_mut().enumerate() {
+      let mut near = scalar_from_ray_origin_min;
+      let mut far = scalar_from_ray_origin_max;
+      for axis in 0..3 {
+        let first = (boxes.minimum[axis][lane] - origin[axis]) * inverse_direction[axis];
+        let second = (boxes.maximum[axis][lane] - origin[axis]) * inverse_direction[axis];
+        near = near.max(first.min(second));
+        far = far.min(first.max(second));
+      }
+      if near <= far {
+        *hit = Some(near);
+      }
+    }
+    hits
+  }
+}
+
+#[cfg(all(feature = "simd", target_arch = "x86_64", not(feature = "f64")))]
+mod sse {
+  // SAFETY, for every `unsafe` block below: SSE and SSE2 are part of the x86-64 baseline so the intrinsics are always
+  // available, and loads and stores only go through references to four floats, which need not be aligned
+  use super::Boxes4;
+  use std::arch::x86_64::{
+    __m128, _mm_add_ps, _mm_add_ss, _mm_and_ps, _mm_castsi128_ps, _mm_cmple_ps, _mm_cvtss_f32, _mm_div_ps, _mm_loadu_ps, _mm_max_ps, _mm_min_ps,
+    _mm_movehl_ps, _mm_movemask_ps, _mm_mul_ps, _mm_set1_ps, _mm_set_epi32, _mm_shuffle_ps, _mm_storeu_ps, _mm_sub_ps, _mm_xor_ps,
+  };
+
+  /// Components of a vector, x, y, z and a fourth lane kept at zero so whole registers can be loaded and stored
+  pub type Lanes = [f32; 4];
+
+  fn load(lanes: &[f32; 4]) -> __m128 {
+    unsafe { _mm_loadu_ps(lanes.as_ptr()) }
+  }
+  fn store(register: __m128) -> [f32; 4] {
+    let mut lanes = [0.0; 4];
+    unsafe { _mm_storeu_ps(lanes.as_mut_ptr(), register) };
+    lanes
+  }
+  /// Clears the fourth lane, which dividing zero by zero or scaling zero by infinity would otherwise leave as NaN
+  fn clear_fourth_lane(register: __m128) -> __m128 {
+    unsafe { _mm_and_ps(register, _mm_castsi128_ps(_mm_set_epi32(0, -1, -1, -1))) }
+  }
+  /// The register with its lanes moved from x, y, z to y, z, x
+  fn rotate(register: __m128) -> __m128 {
+    unsafe { _mm_shuffle_ps::<0b11_00_10_01>(register, register) }
+  }
+
+  pub fn lanes(x: f32, y: f32, z: f32) -> Lanes {
+    [x, y, z, 0.0]
+  }
+  pub fn add(first: &Lanes, second: &Lanes) -> Lanes {
+    store(unsafe { _mm_add_ps(load(first), load(second)) })
+  }
+  pub fn sub(first: &Lanes, second: &Lanes) -> Lanes {
+    store(unsafe { _mm_sub_ps(load(first), load(second)) })
+  }
+  pub fn mul(first: &Lanes, second: &Lanes) -> Lanes {
+    store(unsafe { _mm_mul_ps(load(first), load(second)) })
+  }
+  pub fn div(first: &Lanes, second: &Lanes) -> Lanes {
+    store(clear_fourth_lane(unsafe { _mm_div_ps(load(first), load(second)) }))
+  }
+  pub fn scale(lanes: &Lanes, factor: f32) -> Lanes {
+    store(clear_fourth_lane(unsafe { _mm_mul_ps(load(lanes), _mm_set1_ps(factor)) }))
+  }
+  pub fn neg(lanes: &Lanes) -> Lanes {
+    store(unsafe { _mm_xor_ps(load(lanes), _mm_castsi128_ps(_mm_set_epi32(0, i32::MIN, i32::MIN, i32::MIN))) })
+  }
+  pub fn dot(first: &Lanes, second: &Lanes) -> f32 {
+    unsafe {
+      let products = _mm_mul_ps(load(first), load(second));
+      let swapped = _mm_shuffle_ps::<0b10_11_00_01>(products, products);
+      let pair_sums = _mm_add_ps(products, swapped);
+      _mm_cvtss_f32(_mm_add_ss(pair_sums, _mm_movehl_ps(swapped, pair_sums)))
+    }
+  }
+  pub fn cross(first: &Lanes, second: &Lanes) -> Lanes {
+    let (first, second) = (load(first), load(second));
+    store(rotate(unsafe {
+      _mm_sub_ps(_mm_mul_ps(first, rotate(second)), _mm_mul_ps(rotate(first), second))
+    }))
+  }
+
+  /// Distance along the ray at which it enters each box, or None for boxes it misses between the given scalars
+  pub fn hit_boxes(
+    boxes: &Boxes4,
+    origin: &Lanes,
+    inverse_direction: &Lanes,
+    scalar_from_ray_origin_min: f32,
+    scalar_from_ray_origin_max: f32,
+  ) -> [Option<f32>; 4] {
+    let (near, hit_mask) = unsafe {
+      let mut near = _mm_set1_ps(scalar_from_ray_origin_min);
+      let mut far = _mm_set1_ps(scalar_from_ray_origin_max);
+      for axis in 0..3 {
+        let origin = _mm_set1_ps(origin[axis]);
+        let inverse_direction = _mm_set1_ps(inverse_direction[axis]);
+        let first = _mm_mul_ps(_mm_sub_ps(load(&boxes.minimum[axis]), origin), inverse_direction);
+        let second = _mm_mul_ps(_mm_sub_ps(load(&boxes.maximum[axis]), origin), inverse_direction);
+        near = _mm_max_ps(near, _mm_min_ps(first, second));
+        far = _mm_min_ps(far, _mm_max_ps(first, second));
+      }
+      (store(near), _mm_movemask_ps(_mm_cmple_ps(near, far)))
+    };
+    let mut hits = [None; 4];
+    for (lane, hit) in hits.iter_mut().enumerate() {
+      if hit_mask & (1 << lane) != 0 {
+        *hit = Some(near[lane]);
+      }
+    }
+    hits
+  }
+}
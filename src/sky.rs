@@ -0,0 +1,230 @@
+use crate::float::consts::{FRAC_PI_2, PI};
+use crate::float::Float;
+use crate::sampling::{sample_uniform_cone, uniform_cone_pdf};
+use crate::vec3::Vec3;
+
+/// Extraterrestrial solar illuminance in klx, matching the kcd/m² the Preetham sky luminance is expressed in
+const SOLAR_ILLUMINANCE: Float = 128.0;
+/// Wavelengths in micrometers at which the sun's atmospheric transmittance is evaluated for the red, green and blue
+const SUN_WAVELENGTHS: [Float; 3] = [0.68, 0.55, 0.44];
+/// Degrees above the horizon of the default mid-afternoon sun
+pub const DEFAULT_SUN_ELEVATION: Float = 35.0;
+pub const DEFAULT_SUN_AZIMUTH: Float = 30.0;
+/// The sun's apparent angular radius as seen from the earth, in degrees
+pub const DEFAULT_SUN_ANGULAR_RADIUS: Float = 0.2665;
+/// A clear sky
+pub const DEFAULT_TURBIDITY: Float = 3.0;
+/// Brings the sky's kcd/m² down to roughly the unit radiance the rest of the scene is lit with
+pub const DEFAULT_SKY_INTENSITY: Float = 0.05;
+
+#[allow(clippy::many_single_char_names)]
+fn perez(coefficients: &[Float; 5], cos_theta: Float, gamma: Float) -> Float {
+  let [a, b, c, d, e] = *coefficients;
+  let cos_gamma = gamma.cos();
+  a.mul_add((b / cos_theta).exp(), 1.0) * e.mul_add(cos_gamma * cos_gamma, c.mul_add((d * gamma).exp(), 1.0))
+}
+
+fn zenith_chromaticity(matrix: &[[Float; 4]; 3], turbidity: Float, sun_theta: Float) -> Float {
+  let turbidity_terms = [turbidity * turbidity, turbidity, 1.0];
+  let theta_terms = [sun_theta.powi(3), sun_theta * sun_theta, sun_theta, 1.0];
+  matrix.iter().zip(turbidity_terms.iter()).fold(0.0, |sum, (row, turbidity_term)| {
+    let row_sum = row
+      .iter()
+      .zip(theta_terms.iter())
+      .fold(0.0, |row_sum, (coefficient, theta_term)| coefficient.mul_add(*theta_term, row_sum));
+    turbidity_term.mul_add(row_sum, sum)
+  })
+}
+
+#[allow(clippy::many_single_char_names)]
+fn xyy_to_linear_srgb(luminance: Float, x: Float, y: Float) -> Vec3 {
+  if y <= 0.0 {
+    return Vec3::new_empty();
+  }
+  let big_x = x * luminance / y;
+  let big_z = (1.0 - x - y) * luminance / y;
+  Vec3::new(
+    Float::mul_add(-0.498_531_4, big_z, Float::mul_add(3.240_454_2, big_x, -1.537_138_5 * luminance)),
+    Float::mul_add(0.041_556, big_z, Float::mul_add(-0.969_266, big_x, 1.876_010_8 * luminance)),
+    Float::mul_add(1.057_225_2, big_z, Float::mul_add(0.055_643_4, big_x, -0.204_025_9 * luminance)),
+  )
+}
+
+/// Preetham, Shirley and Smits' analytic daylight model together with a sun disk of finite angular radius
+///
+/// Both the sky and the sun are computed in kcd/m² and brought into scene radiance by the same `intensity`
+#[derive(Clone, Debug, PartialEq)]
+pub struct PhysicalSky {
+  sun_direction: Vec3,
+  sun_cos_angular_radius: Float,
+  sun_theta: Float,
+  zenith: [Float; 3],
+  perez: [[Float; 5]; 3],
+  sun_radiance: Vec3,
+  intensity: Float,
+}
+
+impl Default for PhysicalSky {
+  fn default() -> Self {
+    Self::new(
+      DEFAULT_SUN_ELEVATION,
+      DEFAULT_SUN_AZIMUTH,
+      DEFAULT_SUN_ANGULAR_RADIUS,
+      DEFAULT_TURBIDITY,
+      DEFAULT_SKY_INTENSITY,
+    )
+  }
+}
+
+impl PhysicalSky {
+  /// Creates a sky for a sun at `sun_elevation` degrees above the horizon and `sun_azimuth` degrees from +x towards +z
+  #[allow(clippy::unreadable_literal)]
+  pub fn new(sun_elevation: Float, sun_azimuth: Float, sun_angular_radius: Float, turbidity: Float, intensity: Float) -> Self {
+    let elevation = sun_elevation.clamp(0.0, 90.0).to_radians();
+    let azimuth = sun_azimuth.to_radians();
+    let sun_direction = Vec3::new(elevation.cos() * azimuth.cos(), elevation.sin(), elevation.cos() * azimuth.sin());
+    let sun_theta = FRAC_PI_2 - elevation;
+    let chi = (4.0 / 9.0 - turbidity / 120.0) * Float::mul_add(2.0, -sun_theta, PI);
+    let zenith_luminance = Float::mul_add(4.0453, turbidity, -4.9710).mul_add(chi.tan(), Float::mul_add(-0.2155, turbidity, 2.4192));
+    let zenith_x = zenith_chromaticity(
+      &[
+        [0.00166, -0.00375, 0.00209, 0.0],
+        [-0.02903, 0.06377, -0.03202, 0.00394],
+        [0.11693, -0.21196, 0.06052, 0.25886],
+      ],
+      turbidity,
+      sun_theta,
+    );
+    let zenith_y = zenith_chromaticity(
+      &[
+        [0.00275, -0.00610, 0.00317, 0.0],
+        [-0.04214, 0.08970, -0.04153, 0.00516],
+        [0.15346, -0.26756, 0.06670, 0.26688],
+      ],
+      turbidity,
+      sun_theta,
+    );
+    let linear = |slope: Float, offset: Float| slope.mul_add(turbidity, offset);
+    let perez = [
+      [
+        linear(0.1787, -1.4630),
+        linear(-0.3554, 0.4275),
+        linear(-0.0227, 5.3251),
+        linear(0.1206, -2.5771),
+        linear(-0.0670, 0.3703),
+      ],
+      [
+        linear(-0.0193, -0.2592),
+        linear(-0.0665, 0.0008),
+        linear(-0.0004, 0.2125),
+        linear(-0.0641, -0.8989),
+        linear(-0.0033, 0.0452),
+      ],
+      [
+        linear(-0.0167, -0.2608),
+        linear(-0.0950, 0.0092),
+        linear(-0.0079, 0.2102),
+        linear(-0.0441, -1.6537),
+        linear(-0.0109, 0.0529),
+      ],
+    ];
+    let sun_cos_angular_radius = sun_angular_radius.to_radians().cos();
+    let sun_cos_theta = sun_theta.cos();
+    let relative_optical_mass = 1.0 / Float::mul_add(0.15, (93.885 - sun_theta.to_degrees()).powf(-1.253), sun_cos_theta);
+    let aerosol_beta = Float::mul_add(0.04608, turbidity, -0.04586);
+    let transmittance = SUN_WAVELENGTHS.map(|wavelength| {
+      let rayleigh = 0.008735 * wavelength.powf(-4.08);
+      let aerosol = aerosol_beta * wavelength.powf(-1.3);
+      (-relative_optical_mass * (rayleigh + aerosol)).exp()
+    });
+    let sun_luminance = if elevation > 0.0 {
+      SOLAR_ILLUMINANCE / (2.0 * PI * (1.0 - sun_cos_angular_radius))
+    } else {
+      0.0
+    };
+    Self {
+      sun_direction,
+      sun_cos_angular_radius,
+      sun_theta,
+      zenith: [zenith_luminance, zenith_x, zenith_y],
+      perez,
+      sun_radiance: sun_luminance * Vec3::new_from_array(transmittance),
+      intensity,
+    }
+  }
+  fn sky_radiance(&self, unit_direction: &Vec3) -> Vec3 {
+    let cos_theta = unit_direction.y().max(0.001);
+    let gamma = unit_direction.dot(&self.sun_direction).clamp(-1.0, 1.0).acos();
+    let [luminance, x, y] =
+      [0, 1, 2].map(|index| self.zenith[index] * perez(&self.perez[index], cos_theta, gamma) / perez(&self.perez[index], 1.0, self.sun_theta));
+    xyy_to_linear_srgb(luminance, x, y)
+  }
+  pub fn radiance(&self, direction: &Vec3) -> Vec3 {
+    let unit_direction = direction.unit_vector();
+    let sky = self.sky_radiance(&unit_direction);
+    let sun = if unit_direction.dot(&self.sun_direction) >= self.sun_cos_angular_radius {
+      self.sun_radiance
+    } else {
+      Vec3::new_empty()
+    };
+    self.intensity * (sky + sun)
+  }
+  /// Samples a direction within the sun disk, returning it with its radiance and solid angle pdf
+  pub fn sample_sun(&self, u1: Float, u2: Float) -> Option<(Vec3, Vec3, Float)> {
+    if self.sun_direction.y() <= 0.0 {
+      return None;
+    }
+    let direction = sample_uniform_cone(u1, u2, &self.sun_direction, self.sun_cos_angular_radius);
+    Some((direction, self.radiance(&direction), uniform_cone_pdf(self.sun_cos_angular_radius)))
+  }
+  pub fn sun_pdf(&self, direction: &Vec3) -> Float {
+    if self.sun_direction.y() > 0.0 && direction.unit_vector().dot(&self.sun_direction) >= self.sun_cos_angular_radius {
+      uniform_cone_pdf(self.sun_cos_angular_radius)
+    } else {
+      0.0
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::environment::luminance;
+
+  #[test]
+  fn sky_matches_the_zenith_luminance_and_brightens_towards_the_sun() {
+    let sky = PhysicalSky::new(30.0, 0.0, 0.5, 3.0, 0.5);
+    let zenith = sky.radiance(&Vec3::new(0.0, 1.0, 0.0));
+    assert!(
+      (luminance(&zenith) / (0.5 * sky.zenith[0]) - 1.0).abs() < 1e-3,
+      "zenith luminance {} rather than {}",
+      luminance(&zenith),
+      0.5 * sky.zenith[0]
+    );
+    let towards_sun = sky.radiance(&Vec3::new(0.8, 0.7, 0.3));
+    let away_from_sun = sky.radiance(&Vec3::new(-0.8, 0.7, 0.3));
+    assert!(luminance(&towards_sun) > luminance(&away_from_sun));
+    // Light from a low sun crosses more air, which scatters blue away more than red
+    let low_sun = PhysicalSky::new(5.0, 0.0, 0.5, 3.0, 1.0).sun_radiance;
+    let high_sun = PhysicalSky::new(60.0, 0.0, 0.5, 3.0, 1.0).sun_radiance;
+    assert!(low_sun.r() / low_sun.b() > high_sun.r() / high_sun.b());
+    assert!(luminance(&low_sun) < luminance(&high_sun));
+  }
+
+  #[test]
+  #[allow(clippy::cast_precision_loss)]
+  fn sun_samples_fall_in_its_disk_with_the_pdf_it_reports() {
+    let sky = PhysicalSky::new(40.0, 120.0, 2.0, 3.0, 1.0);
+    for step in 0..100 {
+      let (u1, u2) = ((step % 10) as Float / 10.0 + 0.05, (step / 10) as Float / 10.0 + 0.05);
+      let (direction, radiance, pdf) = sky.sample_sun(u1, u2).unwrap();
+      assert!(direction.dot(&sky.sun_direction) >= sky.sun_cos_angular_radius - 1e-6);
+      assert!((sky.sun_pdf(&direction) - pdf).abs() < 1e-3 * pdf);
+      assert!(luminance(&radiance) > luminance(&sky.sun_radiance));
+    }
+    assert!(sky.sun_pdf(&-sky.sun_direction) == 0.0);
+    let set_sun = PhysicalSky::new(0.0, 0.0, 0.5, 3.0, 1.0);
+    assert!(set_sun.sample_sun(0.5, 0.5).is_none());
+    assert!(set_sun.sun_pdf(&Vec3::new(1.0, 0.0, 0.0)) == 0.0);
+  }
+}
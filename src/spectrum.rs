@@ -0,0 +1,557 @@
+use crate::float::Float;
+use crate::vec3::Vec3;
+use std::ops::{Add, AddAssign, Div, Mul, MulAssign};
+use std::sync::OnceLock;
+
+pub const WAVELENGTH_MIN: Float = 360.0;
+pub const WAVELENGTH_MAX: Float = 830.0;
+/// The hero wavelength plus its four evenly rotated companions
+pub const WAVELENGTH_SAMPLES: usize = 5;
+
+const INTEGRATION_STEP: f64 = 5.0;
+const INTEGRATION_SAMPLES: usize = 95;
+const TABLE_RESOLUTION: usize = 16;
+const ILLUMINANT_TEMPERATURE: f64 = 6504.0;
+
+#[allow(clippy::unreadable_literal)]
+const XYZ_TO_LINEAR_SRGB: [[f64; 3]; 3] = [
+  [3.2404542, -1.5371385, -0.4985314],
+  [-0.9692660, 1.8760108, 0.0415560],
+  [0.0556434, -0.2040259, 1.0572252],
+];
+
+#[allow(clippy::unreadable_literal)]
+const LINEAR_SRGB_TO_XYZ: [[f64; 3]; 3] = [
+  [0.4124564, 0.3575761, 0.1804375],
+  [0.2126729, 0.7151522, 0.0721750],
+  [0.0193339, 0.1191920, 0.9503041],
+];
+
+fn multiply_matrix(matrix: &[[f64; 3]; 3], vector: [f64; 3]) -> [f64; 3] {
+  let row = |index: usize| matrix[index][2].mul_add(vector[2], matrix[index][0].mul_add(vector[0], matrix[index][1] * vector[1]));
+  [row(0), row(1), row(2)]
+}
+
+fn piecewise_gaussian(lambda: f64, mean: f64, sigma_below: f64, sigma_above: f64) -> f64 {
+  let sigma = if lambda < mean { sigma_below } else { sigma_above };
+  let t = (lambda - mean) / sigma;
+  (-0.5 * t * t).exp()
+}
+
+/// Multi-lobe fit of the CIE 1931 2° colour matching functions from Wyman, Sloan and Shirley (2013)
+fn cie_xyz(lambda: f64) -> [f64; 3] {
+  let x = 0.065f64.mul_add(
+    -piecewise_gaussian(lambda, 501.1, 20.4, 26.2),
+    1.056f64.mul_add(
+      piecewise_gaussian(lambda, 599.8, 37.9, 31.0),
+      0.362 * piecewise_gaussian(lambda, 442.0, 16.0, 26.7),
+    ),
+  );
+  let y = 0.821f64.mul_add(
+    piecewise_gaussian(lambda, 568.8, 46.9, 40.5),
+    0.286 * piecewise_gaussian(lambda, 530.9, 16.3, 31.1),
+  );
+  let z = 1.217f64.mul_add(
+    piecewise_gaussian(lambda, 437.0, 11.8, 36.0),
+    0.681 * piecewise_gaussian(lambda, 459.0, 26.0, 13.8),
+  );
+  [x, y, z]
+}
+
+/// Relative spectral power of a blackbody at the D65 correlated colour temperature, normalized at 560nm
+fn illuminant(lambda: f64) -> f64 {
+  #[allow(clippy::unreadable_literal)]
+  let planck = |lambda: f64| {
+    let speed_of_light = 299792458.0;
+    let planck_constant = 6.62606957e-34;
+    let boltzmann_constant = 1.3806488e-23;
+    let meters = lambda * 1e-9;
+    2.0 * planck_constant * speed_of_light * speed_of_light
+      / (meters.powi(5) * ((planck_constant * speed_of_light / (meters * boltzmann_constant * ILLUMINANT_TEMPERATURE)).exp_m1()))
+  };
+  planck(lambda) / planck(560.0)
+}
+
+fn sigmoid(x: f64) -> f64 {
+  if x.is_infinite() {
+    return if x > 0.0 { 1.0 } else { 0.0 };
+  }
+  0.5 + x / (2.0 * x.mul_add(x, 1.0).sqrt())
+}
+
+fn normalized_wavelength(lambda: f64) -> f64 {
+  (lambda - f64::from(WAVELENGTH_MIN)) / f64::from(WAVELENGTH_MAX - WAVELENGTH_MIN)
+}
+
+fn evaluate_polynomial(coefficients: &[f64; 3], lambda: f64) -> f64 {
+  let t = normalized_wavelength(lambda);
+  sigmoid(t.mul_add(t.mul_add(coefficients[0], coefficients[1]), coefficients[2]))
+}
+
+/// Tabulated observer and illuminant used both when fitting spectra and when converting samples back to RGB
+#[derive(Debug)]
+struct ColorSystem {
+  wavelengths: [f64; INTEGRATION_SAMPLES],
+  weights: [[f64; 3]; INTEGRATION_SAMPLES],
+  white: [f64; 3],
+}
+
+impl ColorSystem {
+  #[allow(clippy::cast_precision_loss)]
+  fn new() -> Self {
+    let mut wavelengths = [0.0; INTEGRATION_SAMPLES];
+    let mut weights = [[0.0; 3]; INTEGRATION_SAMPLES];
+    let mut xyz_white = [0.0; 3];
+    (0..INTEGRATION_SAMPLES).for_each(|index| {
+      let lambda = (index as f64).mul_add(INTEGRATION_STEP, f64::from(WAVELENGTH_MIN));
+      let xyz = cie_xyz(lambda);
+      let power = illuminant(lambda) * INTEGRATION_STEP;
+      wavelengths[index] = lambda;
+      (0..3).for_each(|channel| {
+        weights[index][channel] = xyz[channel] * power;
+        xyz_white[channel] += xyz[channel] * power;
+      });
+    });
+    Self {
+      wavelengths,
+      weights,
+      white: multiply_matrix(&XYZ_TO_LINEAR_SRGB, xyz_white),
+    }
+  }
+  fn get() -> &'static Self {
+    static COLOR_SYSTEM: OnceLock<ColorSystem> = OnceLock::new();
+    COLOR_SYSTEM.get_or_init(Self::new)
+  }
+  fn white_balance(&self, xyz: [f64; 3]) -> [f64; 3] {
+    let rgb = multiply_matrix(&XYZ_TO_LINEAR_SRGB, xyz);
+    [rgb[0] / self.white[0], rgb[1] / self.white[1], rgb[2] / self.white[2]]
+  }
+  fn reflectance_to_rgb(&self, coefficients: &[f64; 3]) -> [f64; 3] {
+    let xyz = self
+      .wavelengths
+      .iter()
+      .zip(self.weights.iter())
+      .fold([0.0; 3], |mut xyz, (lambda, weight)| {
+        let reflectance = evaluate_polynomial(coefficients, *lambda);
+        (0..3).for_each(|channel| xyz[channel] += reflectance * weight[channel]);
+        xyz
+      });
+    self.white_balance(xyz)
+  }
+}
+
+fn cie_lab(rgb: [f64; 3]) -> [f64; 3] {
+  let xyz = multiply_matrix(&LINEAR_SRGB_TO_XYZ, rgb);
+  let white = multiply_matrix(&LINEAR_SRGB_TO_XYZ, [1.0, 1.0, 1.0]);
+  let delta: f64 = 6.0 / 29.0;
+  let f = |t: f64| {
+    if t > delta.powi(3) {
+      t.cbrt()
+    } else {
+      t / (3.0 * delta * delta) + 4.0 / 29.0
+    }
+  };
+  let (fx, fy, fz) = (f(xyz[0] / white[0]), f(xyz[1] / white[1]), f(xyz[2] / white[2]));
+  [116.0f64.mul_add(fy, -16.0), 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+fn fit_residual(color_system: &ColorSystem, coefficients: &[f64; 3], target_lab: &[f64; 3]) -> [f64; 3] {
+  let lab = cie_lab(color_system.reflectance_to_rgb(coefficients));
+  [target_lab[0] - lab[0], target_lab[1] - lab[1], target_lab[2] - lab[2]]
+}
+
+fn solve_3x3(matrix: &[[f64; 3]; 3], right_hand_side: &[f64; 3]) -> Option<[f64; 3]> {
+  let determinant = |m: &[[f64; 3]; 3]| {
+    m[0][0].mul_add(
+      m[1][1].mul_add(m[2][2], -(m[1][2] * m[2][1])),
+      m[0][1].mul_add(
+        m[1][2].mul_add(m[2][0], -(m[1][0] * m[2][2])),
+        m[0][2] * m[1][0].mul_add(m[2][1], -(m[1][1] * m[2][0])),
+      ),
+    )
+  };
+  let denominator = determinant(matrix);
+  if denominator.abs() < 1e-15 {
+    return None;
+  }
+  let mut solution = [0.0; 3];
+  (0..3).for_each(|column| {
+    let mut replaced = *matrix;
+    (0..3).for_each(|row| replaced[row][column] = right_hand_side[row]);
+    solution[column] = determinant(&replaced) / denominator;
+  });
+  Some(solution)
+}
+
+/// Gauss-Newton fit of sigmoid polynomial coefficients to an RGB reflectance, minimizing the error in CIELAB
+fn fit_coefficients(color_system: &ColorSystem, rgb: [f64; 3], mut coefficients: [f64; 3]) -> [f64; 3] {
+  let target_lab = cie_lab(rgb);
+  for _ in 0..15 {
+    let residual = fit_residual(color_system, &coefficients, &target_lab);
+    let mut jacobian = [[0.0; 3]; 3];
+    (0..3).for_each(|parameter| {
+      let epsilon = 1e-5;
+      let mut forward = coefficients;
+      let mut backward = coefficients;
+      forward[parameter] += epsilon;
+      backward[parameter] -= epsilon;
+      let residual_forward = fit_residual(color_system, &forward, &target_lab);
+      let residual_backward = fit_residual(color_system, &backward, &target_lab);
+      (0..3).for_each(|row| jacobian[row][parameter] = (residual_forward[row] - residual_backward[row]) / (2.0 * epsilon));
+    });
+    let Some(step) = solve_3x3(&jacobian, &residual) else {
+      break;
+    };
+    (0..3).for_each(|parameter| coefficients[parameter] -= step[parameter]);
+    let largest = coefficients.iter().fold(0.0_f64, |largest, coefficient| largest.max(coefficient.abs()));
+    if largest > 200.0 {
+      for coefficient in &mut coefficients {
+        *coefficient *= 200.0 / largest;
+      }
+    }
+    if residual.iter().map(|value| value * value).sum::<f64>() < 1e-6 {
+      break;
+    }
+  }
+  coefficients
+}
+
+fn smoothstep(x: f64) -> f64 {
+  x * x * 2.0f64.mul_add(-x, 3.0)
+}
+
+/// RGB to sigmoid polynomial lookup table in the style of Jakob and Hanika (2019), indexed by the largest RGB
+/// component, its value and the two remaining components relative to it
+#[derive(Debug)]
+struct CoefficientTable {
+  scale: [f64; TABLE_RESOLUTION],
+  coefficients: Vec<[f64; 3]>,
+}
+
+impl CoefficientTable {
+  #[allow(clippy::cast_precision_loss)]
+  fn new() -> Self {
+    let color_system = ColorSystem::get();
+    let last = (TABLE_RESOLUTION - 1) as f64;
+    let mut scale = [0.0; TABLE_RESOLUTION];
+    scale
+      .iter_mut()
+      .enumerate()
+      .for_each(|(index, value)| *value = smoothstep(smoothstep(index as f64 / last)));
+    let mut coefficients = vec![[0.0; 3]; 3 * TABLE_RESOLUTION * TABLE_RESOLUTION * TABLE_RESOLUTION];
+    let start = TABLE_RESOLUTION / 5;
+    (0..3).for_each(|largest_channel| {
+      (0..TABLE_RESOLUTION).for_each(|y_index| {
+        (0..TABLE_RESOLUTION).for_each(|x_index| {
+          let mut fit = |z_index: usize, warm_start: [f64; 3]| {
+            let brightness = scale[z_index];
+            let mut rgb = [0.0; 3];
+            rgb[largest_channel] = brightness;
+            rgb[(largest_channel + 1) % 3] = x_index as f64 / last * brightness;
+            rgb[(largest_channel + 2) % 3] = y_index as f64 / last * brightness;
+            let fitted = fit_coefficients(color_system, rgb, warm_start);
+            coefficients[Self::index(largest_channel, z_index, y_index, x_index)] = fitted;
+            fitted
+          };
+          (start..TABLE_RESOLUTION).fold([0.0; 3], |warm_start, z_index| fit(z_index, warm_start));
+          (0..start).rev().fold([0.0; 3], |warm_start, z_index| fit(z_index, warm_start));
+        });
+      });
+    });
+    Self { scale, coefficients }
+  }
+  fn get() -> &'static Self {
+    static COEFFICIENT_TABLE: OnceLock<CoefficientTable> = OnceLock::new();
+    COEFFICIENT_TABLE.get_or_init(Self::new)
+  }
+  fn index(largest_channel: usize, z_index: usize, y_index: usize, x_index: usize) -> usize {
+    ((largest_channel * TABLE_RESOLUTION + z_index) * TABLE_RESOLUTION + y_index) * TABLE_RESOLUTION + x_index
+  }
+  #[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss, clippy::cast_sign_loss)]
+  fn lookup(&self, rgb: [f64; 3]) -> [f64; 3] {
+    let largest_channel = if rgb[0] >= rgb[1] && rgb[0] >= rgb[2] {
+      0
+    } else if rgb[1] >= rgb[2] {
+      1
+    } else {
+      2
+    };
+    let z = rgb[largest_channel];
+    let last = (TABLE_RESOLUTION - 1) as f64;
+    let x = rgb[(largest_channel + 1) % 3] / z * last;
+    let y = rgb[(largest_channel + 2) % 3] / z * last;
+    let x_index = (x.floor() as usize).min(TABLE_RESOLUTION - 2);
+    let y_index = (y.floor() as usize).min(TABLE_RESOLUTION - 2);
+    let z_index = self.scale[1..TABLE_RESOLUTION - 1].iter().take_while(|scale| **scale <= z).count();
+    let x_fraction = x - x_index as f64;
+    let y_fraction = y - y_index as f64;
+    let z_fraction = (z - self.scale[z_index]) / (self.scale[z_index + 1] - self.scale[z_index]);
+    let mut result = [0.0; 3];
+    (0..8).for_each(|corner| {
+      let (dx, dy, dz) = (corner & 1, (corner >> 1) & 1, (corner >> 2) & 1);
+      let weight = (if dx == 1 { x_fraction } else { 1.0 - x_fraction })
+        * (if dy == 1 { y_fraction } else { 1.0 - y_fraction })
+        * (if dz == 1 { z_fraction } else { 1.0 - z_fraction });
+      let corner_coefficients = self.coefficients[Self::index(largest_channel, z_index + dz, y_index + dy, x_index + dx)];
+      (0..3).for_each(|parameter| result[parameter] += weight * corner_coefficients[parameter]);
+    });
+    result
+  }
+}
+
+/// A smooth reflectance spectrum reproducing an RGB albedo in [0, 1]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RgbSigmoidPolynomial {
+  coefficients: [f64; 3],
+}
+
+impl RgbSigmoidPolynomial {
+  pub fn new(rgb: &Vec3) -> Self {
+    let rgb = [
+      f64::from(rgb.r().clamp(0.0, 1.0)),
+      f64::from(rgb.g().clamp(0.0, 1.0)),
+      f64::from(rgb.b().clamp(0.0, 1.0)),
+    ];
+    #[allow(clippy::float_cmp)]
+    let coefficients = if rgb[0] == rgb[1] && rgb[1] == rgb[2] {
+      let value = rgb[0];
+      [0.0, 0.0, (value - 0.5) / (value * (1.0 - value)).sqrt()]
+    } else {
+      CoefficientTable::get().lookup(rgb)
+    };
+    Self { coefficients }
+  }
+  #[allow(clippy::cast_possible_truncation)]
+  pub fn evaluate(&self, lambda: Float) -> Float {
+    evaluate_polynomial(&self.coefficients, f64::from(lambda)) as Float
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SampledWavelengths {
+  lambda: [Float; WAVELENGTH_SAMPLES],
+  pdf: [Float; WAVELENGTH_SAMPLES],
+}
+
+impl SampledWavelengths {
+  /// Places the hero wavelength at `u` across the visible range and spaces its companions evenly after it
+  #[allow(clippy::cast_precision_loss)]
+  pub fn sample_uniform(u: Float) -> Self {
+    let range = WAVELENGTH_MAX - WAVELENGTH_MIN;
+    let mut lambda = [0.0; WAVELENGTH_SAMPLES];
+    lambda.iter_mut().enumerate().for_each(|(index, wavelength)| {
+      let offset = (u + index as Float / WAVELENGTH_SAMPLES as Float).fract();
+      *wavelength = offset.mul_add(range, WAVELENGTH_MIN);
+    });
+    Self {
+      lambda,
+      pdf: [1.0 / range; WAVELENGTH_SAMPLES],
+    }
+  }
+  pub fn lambda(&self) -> &[Float; WAVELENGTH_SAMPLES] {
+    &self.lambda
+  }
+  pub fn pdf(&self) -> &[Float; WAVELENGTH_SAMPLES] {
+    &self.pdf
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SampledSpectrum([Float; WAVELENGTH_SAMPLES]);
+
+impl SampledSpectrum {
+  pub fn new(value: Float) -> Self {
+    Self([value; WAVELENGTH_SAMPLES])
+  }
+  pub fn from_fn(wavelengths: &SampledWavelengths, function: impl Fn(Float) -> Float) -> Self {
+    let mut values = [0.0; WAVELENGTH_SAMPLES];
+    values
+      .iter_mut()
+      .zip(wavelengths.lambda().iter())
+      .for_each(|(value, lambda)| *value = function(*lambda));
+    Self(values)
+  }
+  pub fn from_rgb_albedo(rgb: &Vec3, wavelengths: &SampledWavelengths) -> Self {
+    let polynomial = RgbSigmoidPolynomial::new(rgb);
+    Self::from_fn(wavelengths, |lambda| polynomial.evaluate(lambda))
+  }
+  /// Upsamples an unbounded RGB emission by scaling a reflectance spectrum with the reference illuminant
+  #[allow(clippy::cast_possible_truncation)]
+  pub fn from_rgb_illuminant(rgb: &Vec3, wavelengths: &SampledWavelengths) -> Self {
+    let largest = rgb.r().max(rgb.g()).max(rgb.b());
+    if largest <= 0.0 {
+      return Self::new(0.0);
+    }
+    let scale = 2.0 * largest;
+    let polynomial = RgbSigmoidPolynomial::new(&(rgb / scale));
+    Self::from_fn(wavelengths, |lambda| {
+      scale * polynomial.evaluate(lambda) * illuminant(f64::from(lambda)) as Float
+    })
+  }
+  /// Monte Carlo estimate of the XYZ response, white balanced and converted to linear sRGB
+  #[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+  pub fn into_rgb(self, wavelengths: &SampledWavelengths) -> Vec3 {
+    let xyz = self
+      .0
+      .iter()
+      .zip(wavelengths.lambda().iter().zip(wavelengths.pdf().iter()))
+      .filter(|(_, (_, pdf))| **pdf > 0.0)
+      .fold([0.0; 3], |mut xyz, (value, (lambda, pdf))| {
+        let matching = cie_xyz(f64::from(*lambda));
+        (0..3).for_each(|channel| {
+          xyz[channel] += matching[channel] * f64::from(*value) / (f64::from(*pdf) * WAVELENGTH_SAMPLES as f64);
+        });
+        xyz
+      });
+    let rgb = ColorSystem::get().white_balance(xyz);
+    Vec3::new(rgb[0] as Float, rgb[1] as Float, rgb[2] as Float)
+  }
+}
+
+impl Add<SampledSpectrum> for SampledSpectrum {
+  type Output = Self;
+
+  fn add(mut self, other: Self) -> Self::Output {
+    self += other;
+    self
+  }
+}
+
+impl AddAssign<SampledSpectrum> for SampledSpectrum {
+  fn add_assign(&mut self, other: Self) {
+    self.0.iter_mut().zip(other.0.iter()).for_each(|(value, other)| *value += other);
+  }
+}
+
+impl Mul<SampledSpectrum> for SampledSpectrum {
+  type Output = Self;
+
+  fn mul(mut self, other: Self) -> Self::Output {
+    self *= other;
+    self
+  }
+}
+
+impl MulAssign<SampledSpectrum> for SampledSpectrum {
+  fn mul_assign(&mut self, other: Self) {
+    self.0.iter_mut().zip(other.0.iter()).for_each(|(value, other)| *value *= other);
+  }
+}
+
+impl Mul<Float> for SampledSpectrum {
+  type Output = Self;
+
+  fn mul(mut self, other: Float) -> Self::Output {
+    self.0.iter_mut().for_each(|value| *value *= other);
+    self
+  }
+}
+
+impl Div<Float> for SampledSpectrum {
+  type Output = Self;
+
+  fn div(self, other: Float) -> Self::Output {
+    self * (1.0 / other)
+  }
+}
+
+/// The quantity carried along a path: plain RGB, or a spectrum sampled at the wavelengths in `Context`
+pub trait Radiance: Copy + Add<Output = Self> + AddAssign + Mul<Output = Self> + Mul<Float, Output = Self> {
+  type Context;
+
+  fn black() -> Self;
+  fn from_albedo(albedo: &Vec3, context: &Self::Context) -> Self;
+  fn from_illuminant(illuminant: &Vec3, context: &Self::Context) -> Self;
+  fn into_rgb(self, context: &Self::Context) -> Vec3;
+}
+
+impl Radiance for Vec3 {
+  type Context = ();
+
+  fn black() -> Self {
+    Self::new_empty()
+  }
+  fn from_albedo(albedo: &Vec3, (): &Self::Context) -> Self {
+    *albedo
+  }
+  fn from_illuminant(illuminant: &Vec3, (): &Self::Context) -> Self {
+    *illuminant
+  }
+  fn into_rgb(self, (): &Self::Context) -> Vec3 {
+    self
+  }
+}
+
+impl Radiance for SampledSpectrum {
+  type Context = SampledWavelengths;
+
+  fn black() -> Self {
+    Self::new(0.0)
+  }
+  fn from_albedo(albedo: &Vec3, context: &Self::Context) -> Self {
+    Self::from_rgb_albedo(albedo, context)
+  }
+  fn from_illuminant(illuminant: &Vec3, context: &Self::Context) -> Self {
+    Self::from_rgb_illuminant(illuminant, context)
+  }
+  fn into_rgb(self, context: &Self::Context) -> Vec3 {
+    SampledSpectrum::into_rgb(self, context)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Hero wavelengths spread evenly across the visible range
+  const STRATA: usize = 1000;
+
+  /// Mean over evenly spread hero wavelengths of the RGB of the spectrum `spectrum` gives for them
+  #[allow(clippy::cast_precision_loss)]
+  fn mean_rgb(spectrum: impl Fn(&SampledWavelengths) -> SampledSpectrum) -> Vec3 {
+    let total = (0..STRATA).fold(Vec3::new_empty(), |total, stratum| {
+      let wavelengths = SampledWavelengths::sample_uniform((stratum as Float + 0.5) / STRATA as Float);
+      total + spectrum(&wavelengths).into_rgb(&wavelengths)
+    });
+    total / STRATA as Float
+  }
+
+  fn assert_close(actual: &Vec3, expected: &Vec3, tolerance: Float) {
+    assert!((actual - expected).length() < tolerance, "expected {:?} but got {:?}", expected, actual);
+  }
+
+  #[test]
+  fn albedo_spectra_lit_by_white_return_their_rgb() {
+    let white = Vec3::new(1.0, 1.0, 1.0);
+    for rgb in [white, Vec3::new(0.5, 0.5, 0.5), Vec3::new(0.2, 0.2, 0.2), Vec3::new(0.8, 0.4, 0.2)] {
+      let rgb_back =
+        mean_rgb(|wavelengths| SampledSpectrum::from_rgb_albedo(&rgb, wavelengths) * SampledSpectrum::from_rgb_illuminant(&white, wavelengths));
+      assert_close(&rgb_back, &rgb, 1e-2);
+    }
+  }
+
+  #[test]
+  fn illuminant_spectra_return_their_rgb() {
+    for rgb in [Vec3::new(1.0, 1.0, 1.0), Vec3::new(4.0, 4.0, 4.0)] {
+      let rgb_back = mean_rgb(|wavelengths| SampledSpectrum::from_rgb_illuminant(&rgb, wavelengths));
+      assert_close(&rgb_back, &rgb, 1e-2 * rgb.x());
+    }
+  }
+
+  #[test]
+  #[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+  fn constant_spectrum_integrates_to_the_rgb_of_its_xyz() {
+    // The XYZ response of a constant spectrum, by the midpoint rule on a fine grid across the visible range
+    const STEPS: usize = 10_000;
+    let step = f64::from(WAVELENGTH_MAX - WAVELENGTH_MIN) / STEPS as f64;
+    let value = 0.7;
+    let xyz = (0..STEPS).fold([0.0; 3], |mut xyz, index| {
+      let matching = cie_xyz((index as f64 + 0.5).mul_add(step, f64::from(WAVELENGTH_MIN)));
+      (0..3).for_each(|channel| xyz[channel] += matching[channel] * value * step);
+      xyz
+    });
+    let expected = ColorSystem::get().white_balance(xyz);
+    let expected = Vec3::new(expected[0] as Float, expected[1] as Float, expected[2] as Float);
+    assert_close(&mean_rgb(|_| SampledSpectrum::new(value as Float)), &expected, 1e-3 * expected.length());
+  }
+}
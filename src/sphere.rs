@@ -1,30 +1,46 @@
+use crate::aabb::Aabb;
+use crate::float::consts::PI;
+use crate::float::{gamma, Float};
 use crate::hit::{HitRecord, Hittable};
 use crate::material::Material;
 use crate::ray::Ray;
 use crate::vec3::Vec3;
-use itertools::FoldWhile::{Continue, Done};
-use itertools::Itertools;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 use std::sync::Arc;
 
 #[derive(Clone, Debug)]
 pub struct Sphere {
   center: Vec3,
   material: Arc<dyn Material>,
-  radius: f32,
+  radius: Float,
+  light: Option<usize>,
+  id: u32,
 }
 
 impl Sphere {
-  pub fn new(center: &Vec3, radius: f32, material: Arc<dyn Material>) -> Self {
+  pub fn new(center: &Vec3, radius: Float, material: Arc<dyn Material>) -> Self {
+    let mut hasher = DefaultHasher::new();
+    [center.x(), center.y(), center.z(), radius].map(Float::to_bits).hash(&mut hasher);
+    #[allow(clippy::cast_possible_truncation)]
+    let id = hasher.finish() as u32;
     Self {
       center: *center,
       radius,
       material,
+      light: None,
+      id,
     }
   }
+  /// Links the sphere to the scene light at `light` that samples its emission
+  pub fn with_light(mut self, light: usize) -> Self {
+    self.light = Some(light);
+    self
+  }
   pub fn center(&self) -> &Vec3 {
     &self.center
   }
-  pub fn radius(&self) -> f32 {
+  pub fn radius(&self) -> Float {
     self.radius
   }
   pub fn material(&self) -> Arc<dyn Material> {
@@ -33,29 +49,84 @@ impl Sphere {
 }
 
 impl Hittable for Sphere {
-  fn is_hit(&self, ray: &Ray, scalar_from_ray_origin_min: f32, scalar_from_ray_origin_max: f32) -> Option<HitRecord> {
+  fn is_hit(&self, ray: &Ray, scalar_from_ray_origin_min: Float, scalar_from_ray_origin_max: Float) -> Option<HitRecord> {
     let center_offset = ray.origin() - self.center();
     let a = ray.direction().dot(ray.direction());
     let b = center_offset.dot(ray.direction());
-    let c = center_offset.dot(&center_offset) - self.radius * self.radius;
-    #[allow(clippy::style)]
-    let discriminant = b * b - a * c;
-    if discriminant > 0.0 {
-      #[allow(clippy::style)]
-      let scalar_lengths = [(-b - (b * b - a * c).sqrt()) / a, (-b + (b * b - a * c).sqrt()) / a];
-      return scalar_lengths
-        .iter()
-        .fold_while(None::<HitRecord>, |_, scalar_length| {
-          if *scalar_length < scalar_from_ray_origin_max && *scalar_length > scalar_from_ray_origin_min {
-            let point_at_parameter = ray.point_at_parameter(*scalar_length);
-            let normal = (point_at_parameter - self.center()) / self.radius;
-            Done(Some(HitRecord::new(*scalar_length, &point_at_parameter, &normal, self.material())))
-          } else {
-            Continue(None)
-          }
-        })
-        .into_inner();
+    let c = self.radius.mul_add(-self.radius, center_offset.dot(&center_offset));
+    // b² - ac cancels catastrophically for rays passing far from a large sphere, so take it from the distance between
+    // the ray and the center instead (Haines et al., Ray Tracing Gems, chapter 7)
+    let closest_offset = center_offset - (b / a) * ray.direction();
+    let discriminant = a * self.radius.mul_add(self.radius, -closest_offset.squared_length());
+    if discriminant <= 0.0 {
+      return None;
+    }
+    let q = -discriminant.sqrt().copysign(b) - b;
+    // Rounding in c dominates the error of the root found from it, which is the one near zero when the ray leaves the
+    // surface, so a root only counts once it is past t-min by more than its error
+    let c_error = gamma(5) * self.radius.mul_add(self.radius, center_offset.squared_length());
+    let near = (c / q, c_error / q.abs() + gamma(7) * (c / q).abs());
+    let far = (q / a, gamma(7) * (q / a).abs());
+    let roots = if near.0 <= far.0 { [near, far] } else { [far, near] };
+    roots
+      .iter()
+      .find(|(scalar_length, error)| *scalar_length < scalar_from_ray_origin_max && scalar_length - error > scalar_from_ray_origin_min)
+      .map(|(scalar_length, _)| {
+        // Move the hit back onto the sphere, which bounds its error far more tightly than the ray parameter does
+        let offset = ray.point_at_parameter(*scalar_length) - self.center();
+        let point_at_parameter = self.center() + offset * (self.radius.abs() / offset.length());
+        let normal = (point_at_parameter - self.center()) / self.radius;
+        let surface_coordinates = uv(&(normal * self.radius.signum()));
+        HitRecord::new(*scalar_length, &point_at_parameter, &normal, self.material())
+          .with_error(&point_error(self.center(), &point_at_parameter))
+          .with_light(self.light)
+          .with_uv(surface_coordinates.0, surface_coordinates.1)
+          .with_object_id(self.id)
+      })
+  }
+  fn bounding_box(&self) -> Option<Aabb> {
+    let extent = Vec3::new(self.radius.abs(), self.radius.abs(), self.radius.abs());
+    Some(Aabb::new(&(self.center - extent), &(self.center + extent)))
+  }
+}
+
+/// Bound on the rounding error in each coordinate of `point`, projected onto the surface of a sphere around `center`
+pub fn point_error(center: &Vec3, point: &Vec3) -> Vec3 {
+  gamma(5) * ((point - center).abs() + center.abs())
+}
+
+/// Longitude and latitude of a point on the unit sphere, each scaled to [0, 1]
+fn uv(point: &Vec3) -> (Float, Float) {
+  let theta = (-point.y()).clamp(-1.0, 1.0).acos();
+  let phi = (-point.z()).atan2(point.x()) + PI;
+  (phi / (2.0 * PI), theta / PI)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::float::random_float;
+  use crate::material::Lambertian;
+
+  #[test]
+  fn rays_leaving_a_large_sphere_do_not_hit_it_again() {
+    let ground = Sphere::new(
+      &Vec3::new(0.0, -1000.0, 0.0),
+      1000.0,
+      Arc::new(Lambertian::new(&Vec3::new(0.5, 0.5, 0.5))),
+    );
+    fastrand::seed(3);
+    for _ in 0..10_000 {
+      let origin = Vec3::new(Float::mul_add(8.0, random_float(), -4.0), 10.0, Float::mul_add(8.0, random_float(), -4.0));
+      let direction = Vec3::new(random_float() - 0.5, -1.0, random_float() - 0.5);
+      let hit_record = ground.is_hit(&Ray::new(&origin, &direction), 0.0, Float::MAX).unwrap();
+      assert!(((hit_record.point() - ground.center()).length() - 1000.0).abs() <= hit_record.error().length());
+      let mut outgoing = Vec3::new(random_float() - 0.5, random_float() - 0.5, random_float() - 0.5);
+      if outgoing.dot(hit_record.normal()) < 0.0 {
+        outgoing = -outgoing;
+      }
+      let spawned = hit_record.spawn_ray(&outgoing);
+      assert!(ground.is_hit(&spawned, 0.0, Float::MAX).is_none(), "{:?} hit the ground again", spawned);
     }
-    None
   }
 }
@@ -0,0 +1,370 @@
+use crate::camera::Camera;
+use crate::film::Film;
+use crate::float::consts::PI;
+use crate::float::{random_float, Float};
+use crate::hit::{HitRecord, Hittable};
+use crate::integrator::{emitted, escaped, sample_lights};
+use crate::ray::Ray;
+use crate::sampling::Distribution1D;
+use crate::scene::Scene;
+use crate::stats::{self, Counter};
+use crate::vec3::Vec3;
+use std::collections::HashMap;
+
+/// Fraction of the photons found at a pixel that are kept when its radius shrinks
+const ALPHA: Float = 2.0 / 3.0;
+
+/// Progressive statistics of one pixel, kept across passes
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct PixelStatistics {
+  radius: Float,
+  photon_count: Float,
+  flux: Vec3,
+  direct: Vec3,
+}
+
+/// The first diffuse surface a camera ray from a pixel reaches in a pass, gathering the photons that land near it
+#[derive(Clone, Debug)]
+struct VisiblePoint {
+  pixel: usize,
+  hit_record: HitRecord,
+  incoming: Ray,
+  throughput: Vec3,
+  flux: Vec3,
+  photon_count: usize,
+}
+
+impl VisiblePoint {
+  /// BSDF for light arriving from `direction`
+  fn evaluate(&self, direction: &Vec3) -> Vec3 {
+    let normal = self.hit_record.normal();
+    let Some((value, _)) = self.hit_record.material().evaluate(&self.incoming, &self.hit_record, direction) else {
+      return Vec3::new_empty();
+    };
+    if normal.squared_length() <= 0.0 {
+      return value;
+    }
+    let cosine = normal.dot(&direction.unit_vector()).abs();
+    if cosine > 0.0 {
+      value / cosine
+    } else {
+      Vec3::new_empty()
+    }
+  }
+}
+
+/// Uniform grid over the visible points of a pass, hashed so only occupied cells take memory
+#[derive(Clone, Debug, PartialEq)]
+struct VisiblePointGrid {
+  cell_size: Float,
+  cells: HashMap<(i32, i32, i32), Vec<usize>>,
+}
+
+impl VisiblePointGrid {
+  fn new(visible_points: &[VisiblePoint], statistics: &[PixelStatistics]) -> Self {
+    let cell_size = 2.0 * statistics.iter().fold(0.0, |largest: Float, pixel| largest.max(pixel.radius));
+    let mut grid = Self {
+      cell_size,
+      cells: HashMap::new(),
+    };
+    for (index, visible_point) in visible_points.iter().enumerate() {
+      let radius = statistics[visible_point.pixel].radius;
+      let extent = Vec3::new(radius, radius, radius);
+      let minimum = grid.cell(&(visible_point.hit_record.point() - extent));
+      let maximum = grid.cell(&(visible_point.hit_record.point() + extent));
+      for x in minimum.0..=maximum.0 {
+        for y in minimum.1..=maximum.1 {
+          for z in minimum.2..=maximum.2 {
+            grid.cells.entry((x, y, z)).or_default().push(index);
+          }
+        }
+      }
+    }
+    grid
+  }
+  #[allow(clippy::cast_possible_truncation)]
+  fn cell(&self, point: &Vec3) -> (i32, i32, i32) {
+    (
+      (point.x() / self.cell_size).floor() as i32,
+      (point.y() / self.cell_size).floor() as i32,
+      (point.z() / self.cell_size).floor() as i32,
+    )
+  }
+  fn visible_points(&self, point: &Vec3) -> &[usize] {
+    self.cells.get(&self.cell(point)).map_or(&[], Vec::as_slice)
+  }
+}
+
+/// Stochastic progressive photon mapping after Hachisuka and Jensen (2009), which handles caustics seen through
+/// specular surfaces that path tracing rarely finds
+///
+/// Each pass traces one camera ray per pixel to its first diffuse surface, traces photons from the lights with
+/// bounds and gathers them there, shrinking each pixel's gather radius as photons accumulate. Light from the
+/// background and directional lights arrives only by light sampling at the visible points, without interreflection
+#[derive(Clone, Debug, PartialEq)]
+pub struct SppmIntegrator {
+  photons_per_pass: usize,
+  initial_radius: Float,
+  max_depth: usize,
+}
+
+impl SppmIntegrator {
+  /// Creates an integrator tracing `photons_per_pass` photons per pass and gathering them within `initial_radius`
+  /// at first, following camera and photon paths for at most `max_depth` bounces
+  pub fn new(photons_per_pass: usize, initial_radius: Float, max_depth: usize) -> Self {
+    Self {
+      photons_per_pass,
+      initial_radius,
+      max_depth,
+    }
+  }
+  /// Renders `passes` passes into `film`, which holds the estimate so far after each pass and is handed to
+  /// `on_pass` along with the number of finished passes
+  pub fn render(&self, scene: &Scene, camera: &Camera, film: &mut Film, passes: usize, mut on_pass: impl FnMut(usize, &Film)) {
+    let mut statistics = vec![
+      PixelStatistics {
+        radius: self.initial_radius,
+        photon_count: 0.0,
+        flux: Vec3::new_empty(),
+        direct: Vec3::new_empty(),
+      };
+      film.width() * film.height()
+    ];
+    let power: Vec<Float> = scene
+      .lights()
+      .iter()
+      .map(|light| light.bounds().map_or(0.0, |bounds| bounds.phi()))
+      .collect();
+    let light_distribution = (power.iter().sum::<Float>() > 0.0).then(|| Distribution1D::new(&power));
+    for pass in 1..=passes {
+      let mut visible_points = self.camera_pass(scene, camera, film, &mut statistics);
+      if let Some(light_distribution) = &light_distribution {
+        let grid = VisiblePointGrid::new(&visible_points, &statistics);
+        for _ in 0..self.photons_per_pass {
+          self.trace_photon(scene, light_distribution, &grid, &mut visible_points, &statistics);
+        }
+      }
+      for visible_point in &visible_points {
+        let pixel = &mut statistics[visible_point.pixel];
+        if visible_point.photon_count == 0 {
+          continue;
+        }
+        #[allow(clippy::cast_precision_loss)]
+        let photon_count = visible_point.photon_count as Float;
+        let new_photon_count = ALPHA.mul_add(photon_count, pixel.photon_count);
+        let new_radius = pixel.radius * (new_photon_count / (pixel.photon_count + photon_count)).sqrt();
+        pixel.flux = (pixel.flux + visible_point.throughput * visible_point.flux) * (new_radius * new_radius / (pixel.radius * pixel.radius));
+        pixel.photon_count = new_photon_count;
+        pixel.radius = new_radius;
+      }
+      self.write(film, &statistics, pass);
+      on_pass(pass, film);
+    }
+  }
+  /// Traces one camera ray per pixel, adding the light it picks up to the pixel's direct light and returning where it
+  /// reaches a diffuse surface
+  #[allow(clippy::cast_precision_loss)]
+  fn camera_pass(&self, scene: &Scene, camera: &Camera, film: &Film, statistics: &mut [PixelStatistics]) -> Vec<VisiblePoint> {
+    let mut visible_points = Vec::new();
+    for y in 0..film.height() {
+      for x in 0..film.width() {
+        let pixel = y * film.width() + x;
+        let mut ray = camera.get_ray(
+          (x as Float + random_float()) / film.width() as Float,
+          (y as Float + random_float()) / film.height() as Float,
+        );
+        let mut throughput = Vec3::new(1.0, 1.0, 1.0);
+        for _ in 0..=self.max_depth {
+          let Some(hit_record) = scene.world().is_hit(&ray, 0.0, Float::MAX) else {
+            statistics[pixel].direct += throughput * escaped::<Vec3>(&ray, scene, &(), None);
+            break;
+          };
+          statistics[pixel].direct += throughput * emitted::<Vec3>(&ray, scene, &hit_record, &(), None);
+          let Some(scatter_result) = hit_record.material().scatter(&ray, &hit_record) else {
+            break;
+          };
+          if let Some(scattering_pdf) = scatter_result.pdf() {
+            statistics[pixel].direct += throughput * sample_lights::<Vec3>(&ray, scene, &hit_record, &());
+            statistics[pixel].direct +=
+              throughput * scatter_result.attenuation() * scattered_light(scene, &hit_record, scattering_pdf, scatter_result.scattered());
+            visible_points.push(VisiblePoint {
+              pixel,
+              hit_record,
+              incoming: ray,
+              throughput,
+              flux: Vec3::new_empty(),
+              photon_count: 0,
+            });
+            break;
+          }
+          throughput = throughput * scatter_result.attenuation();
+          ray = *scatter_result.scattered();
+          stats::count(Counter::SecondaryRays, 1);
+        }
+      }
+    }
+    visible_points
+  }
+  /// Traces one photon from a light, adding it to the visible points near every surface it reaches after its first
+  fn trace_photon(
+    &self,
+    scene: &Scene,
+    light_distribution: &Distribution1D,
+    grid: &VisiblePointGrid,
+    visible_points: &mut [VisiblePoint],
+    statistics: &[PixelStatistics],
+  ) {
+    let (light, choice_probability) = light_distribution.sample_discrete(random_float());
+    let Some(emission) = scene.lights()[light].sample_emission() else {
+      return;
+    };
+    if emission.pdf_position() <= 0.0 || emission.pdf_direction() <= 0.0 {
+      return;
+    }
+    let cosine = if emission.normal().squared_length() > 0.0 {
+      emission.normal().dot(emission.direction()).abs()
+    } else {
+      1.0
+    };
+    let mut throughput = emission.radiance() * (cosine / (choice_probability * emission.pdf_position() * emission.pdf_direction()));
+    let mut ray = emission.ray();
+    for depth in 0..self.max_depth {
+      stats::count(Counter::SecondaryRays, 1);
+      let Some(hit_record) = scene.world().is_hit(&ray, 0.0, Float::MAX) else {
+        return;
+      };
+      if depth > 0 {
+        let direction = -ray.direction().unit_vector();
+        for index in grid.visible_points(hit_record.point()) {
+          let visible_point = &mut visible_points[*index];
+          let radius = statistics[visible_point.pixel].radius;
+          if (visible_point.hit_record.point() - hit_record.point()).squared_length() > radius * radius {
+            continue;
+          }
+          visible_point.flux += throughput * visible_point.evaluate(&direction);
+          visible_point.photon_count += 1;
+        }
+      }
+      let Some(scatter_result) = hit_record.material().scatter(&ray, &hit_record) else {
+        return;
+      };
+      let new_throughput = throughput * scatter_result.attenuation();
+      let survival_probability = (max_component(&new_throughput) / max_component(&throughput)).min(1.0);
+      if survival_probability <= 0.0 || random_float() >= survival_probability {
+        return;
+      }
+      throughput = new_throughput / survival_probability;
+      ray = *scatter_result.scattered();
+    }
+  }
+  /// Writes the estimate after `passes` passes, each of which traced `photons_per_pass` photons, to `film`
+  #[allow(clippy::cast_precision_loss)]
+  fn write(&self, film: &mut Film, statistics: &[PixelStatistics], passes: usize) {
+    let photon_count = (passes * self.photons_per_pass) as Float;
+    for y in 0..film.height() {
+      for x in 0..film.width() {
+        let pixel = &statistics[y * film.width() + x];
+        let indirect = pixel.flux / (photon_count * PI * pixel.radius * pixel.radius);
+        film.set_pixel(x, y, &(pixel.direct / passes as Float + indirect), passes);
+      }
+    }
+  }
+}
+
+fn max_component(vector: &Vec3) -> Float {
+  vector.x().max(vector.y()).max(vector.z())
+}
+
+/// Emitted light found along the ray scattered from a visible point, weighted against light sampling
+fn scattered_light(scene: &Scene, hit_record: &HitRecord, scattering_pdf: Float, scattered: &Ray) -> Vec3 {
+  let previous_bounce = Some((*hit_record.normal(), scattering_pdf));
+  stats::count(Counter::SecondaryRays, 1);
+  scene.world().is_hit(scattered, 0.0, Float::MAX).map_or_else(
+    || escaped::<Vec3>(scattered, scene, &(), previous_bounce),
+    |next| emitted::<Vec3>(scattered, scene, &next, &(), previous_bounce),
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::background::Background;
+  use crate::environment::luminance;
+  use crate::hit::HittableList;
+  use crate::integrator::{Integrator, PathIntegrator};
+  use crate::material::Lambertian;
+  use crate::settings::RenderSettings;
+  use crate::sphere::Sphere;
+  use std::sync::Arc;
+
+  #[test]
+  #[allow(clippy::cast_precision_loss)]
+  fn sppm_converges_to_path_traced_image() {
+    let mut world = HittableList::new();
+    world.list_mut().push(Box::new(Sphere::new(
+      &Vec3::new(0.0, -1000.0, 0.0),
+      1000.0,
+      Arc::new(Lambertian::new(&Vec3::new(0.5, 0.5, 0.5))),
+    )));
+    world.list_mut().push(Box::new(Sphere::new(
+      &Vec3::new(0.0, 0.5, 0.0),
+      0.5,
+      Arc::new(Lambertian::new(&Vec3::new(0.7, 0.3, 0.2))),
+    )));
+    let mut scene = Scene::new(world);
+    scene.set_background(Background::Constant(Vec3::new_empty()));
+    scene.add_sphere_light(&Vec3::new(0.5, 3.0, 0.5), 0.5, &Vec3::new(10.0, 10.0, 10.0));
+    let camera = Camera::new_from_fov_and_aspect(
+      &Vec3::new(0.0, 1.5, 6.0),
+      &Vec3::new(0.0, 0.5, 0.0),
+      &Vec3::new(0.0, 1.0, 0.0),
+      40.0,
+      2.0,
+      0.0,
+      6.0,
+    );
+    let (width, height, passes, path_samples) = (16, 8, 256, 4096);
+    fastrand::seed(11);
+    let mut sppm_film = Film::new(width, height);
+    SppmIntegrator::new(5_000, 0.1, 5).render(&scene, &camera, &mut sppm_film, passes, |_, _| {});
+    let mut sppm = 0.0;
+    for y in 0..height {
+      for x in 0..width {
+        sppm += f64::from(luminance(&sppm_film.pixel(x, y, passes)));
+      }
+    }
+    let sppm = sppm / (width * height) as f64;
+    // The mean luminance of the path traced image, with the variance of a single path's luminance
+    let path = PathIntegrator::new(RenderSettings::new(100, 4, 4, 4, 4));
+    let path_film = Film::new(width, height);
+    let (mut sum, mut sum_of_squares) = (0.0, 0.0);
+    for y in 0..height {
+      for x in 0..width {
+        for _ in 0..path_samples {
+          let ray = camera.get_ray(
+            (x as Float + random_float()) / width as Float,
+            (y as Float + random_float()) / height as Float,
+          );
+          let sample = f64::from(luminance(&path.radiance::<Vec3>(&ray, &scene, &(), &path_film)));
+          sum += sample;
+          sum_of_squares += sample * sample;
+        }
+      }
+    }
+    let count = (width * height * path_samples) as f64;
+    let path = sum / count;
+    let sample_variance = path.mul_add(-path, sum_of_squares / count) * count / (count - 1.0);
+    // SPPM's noise comes mostly from the light its camera rays pick up by light sampling, which varies about as much
+    // per ray as a whole path does, so its mean is taken to vary like that of one path per pixel per pass. Four
+    // standard errors and 1% for the bias of density estimation keep chance failures rarer than one in ten thousand
+    let standard_error = (sample_variance * (1.0 / count + 1.0 / (width * height * passes) as f64)).sqrt();
+    let tolerance = 4.0f64.mul_add(standard_error, 0.01 * path);
+    assert!(
+      (sppm - path).abs() <= tolerance,
+      "sppm {}, path {}, more than {} apart",
+      sppm,
+      path,
+      tolerance
+    );
+  }
+}
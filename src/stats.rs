@@ -0,0 +1,273 @@
+use std::cell::Cell;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+thread_local! {
+  static THREAD_COUNTS: Cell<RayCounts> = Cell::new(RayCounts::default());
+}
+
+/// Counts flushed from every thread since they were last taken
+static TOTALS: [AtomicU64; 5] = [
+  AtomicU64::new(0),
+  AtomicU64::new(0),
+  AtomicU64::new(0),
+  AtomicU64::new(0),
+  AtomicU64::new(0),
+];
+
+/// Rays traced and work done finding what they hit
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Counter {
+  /// Rays leaving the camera
+  CameraRays,
+  /// Rays looking for the closest hit after the first, scattered along paths or emitted by lights
+  SecondaryRays,
+  /// Rays only checking whether anything blocks the way to a light or another path vertex
+  ShadowRays,
+  /// BVH nodes whose boxes a ray or packet was tested against
+  NodeVisits,
+  /// Ray tests against the primitives in BVH leaves
+  PrimitiveTests,
+}
+
+impl Counter {
+  pub const ALL: [Self; 5] = [
+    Self::CameraRays,
+    Self::SecondaryRays,
+    Self::ShadowRays,
+    Self::NodeVisits,
+    Self::PrimitiveTests,
+  ];
+  /// Key of the counter in JSON reports
+  pub fn name(self) -> &'static str {
+    match self {
+      Self::CameraRays => "camera_rays",
+      Self::SecondaryRays => "secondary_rays",
+      Self::ShadowRays => "shadow_rays",
+      Self::NodeVisits => "node_visits",
+      Self::PrimitiveTests => "primitive_tests",
+    }
+  }
+  fn label(self) -> &'static str {
+    match self {
+      Self::CameraRays => "camera rays",
+      Self::SecondaryRays => "secondary rays",
+      Self::ShadowRays => "shadow rays",
+      Self::NodeVisits => "BVH node visits",
+      Self::PrimitiveTests => "primitive tests",
+    }
+  }
+}
+
+/// Value of every [`Counter`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RayCounts([u64; 5]);
+
+impl RayCounts {
+  pub fn get(&self, counter: Counter) -> u64 {
+    self.0[counter as usize]
+  }
+  pub fn add(&mut self, counter: Counter, amount: u64) {
+    self.0[counter as usize] += amount;
+  }
+  /// Camera, secondary and shadow rays together
+  pub fn total_rays(&self) -> u64 {
+    self.get(Counter::CameraRays) + self.get(Counter::SecondaryRays) + self.get(Counter::ShadowRays)
+  }
+}
+
+/// Adds `amount` to `counter` for the current thread, which keeps its counts to itself until it flushes them
+pub fn count(counter: Counter, amount: u64) {
+  THREAD_COUNTS.with(|counts| {
+    let mut updated = counts.get();
+    updated.add(counter, amount);
+    counts.set(updated);
+  });
+}
+
+/// Returns the counts of the current thread that it has not flushed yet, leaving them to be flushed
+pub fn thread_counts() -> RayCounts {
+  THREAD_COUNTS.with(Cell::get)
+}
+
+/// Adds the counts of the current thread to the totals and starts it counting again from zero. Threads that trace
+/// rays call this before they finish, since counts they have not flushed are lost with them
+pub fn flush_thread_counts() {
+  let counts = THREAD_COUNTS.with(Cell::take);
+  for (total, count) in TOTALS.iter().zip(counts.0) {
+    total.fetch_add(count, Ordering::Relaxed);
+  }
+}
+
+/// Returns the counts flushed by every thread, and those of the current one, since the last call and starts counting
+/// again from zero
+pub fn take_ray_counts() -> RayCounts {
+  flush_thread_counts();
+  RayCounts(TOTALS.each_ref().map(|total| total.swap(0, Ordering::Relaxed)))
+}
+
+/// Stage of a run whose wall-clock time is reported
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phase {
+  SceneBuild,
+  BvhBuild,
+  Render,
+  Denoise,
+  Output,
+}
+
+impl Phase {
+  pub const ALL: [Self; 5] = [Self::SceneBuild, Self::BvhBuild, Self::Render, Self::Denoise, Self::Output];
+  /// Key of the phase in JSON reports
+  pub fn name(self) -> &'static str {
+    match self {
+      Self::SceneBuild => "scene_build",
+      Self::BvhBuild => "bvh_build",
+      Self::Render => "render",
+      Self::Denoise => "denoise",
+      Self::Output => "output",
+    }
+  }
+  fn label(self) -> &'static str {
+    match self {
+      Self::SceneBuild => "scene build",
+      Self::BvhBuild => "BVH build",
+      Self::Render => "render",
+      Self::Denoise => "denoise",
+      Self::Output => "output",
+    }
+  }
+}
+
+/// Time spent in each phase of a run and the rays it traced, printed as a table or as JSON
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Report {
+  durations: [Duration; 5],
+  counts: RayCounts,
+}
+
+impl Report {
+  pub fn new() -> Self {
+    Self::default()
+  }
+  /// Adds `duration` to the time spent in `phase`
+  pub fn add(&mut self, phase: Phase, duration: Duration) {
+    self.durations[phase as usize] += duration;
+  }
+  /// Adds the time `run` takes to `phase`
+  pub fn time<T>(&mut self, phase: Phase, run: impl FnOnce() -> T) -> T {
+    let start = Instant::now();
+    let result = run();
+    self.add(phase, start.elapsed());
+    result
+  }
+  pub fn with_counts(mut self, counts: RayCounts) -> Self {
+    self.counts = counts;
+    self
+  }
+  pub fn duration(&self, phase: Phase) -> Duration {
+    self.durations[phase as usize]
+  }
+  pub fn total_duration(&self) -> Duration {
+    self.durations.iter().sum()
+  }
+  pub fn counts(&self) -> &RayCounts {
+    &self.counts
+  }
+  /// Rays of every kind traced per second of the render phase, or zero if it took no time
+  #[allow(clippy::cast_precision_loss)]
+  pub fn rays_per_second(&self) -> f64 {
+    let seconds = self.duration(Phase::Render).as_secs_f64();
+    if seconds > 0.0 {
+      self.counts.total_rays() as f64 / seconds
+    } else {
+      0.0
+    }
+  }
+  /// The report as one JSON object, with durations in seconds
+  pub fn to_json(&self) -> String {
+    let phases: Vec<String> = Phase::ALL
+      .iter()
+      .map(|phase| format!("\"{}\":{}", phase.name(), self.duration(*phase).as_secs_f64()))
+      .collect();
+    let counts: Vec<String> = Counter::ALL
+      .iter()
+      .map(|counter| format!("\"{}\":{}", counter.name(), self.counts.get(*counter)))
+      .collect();
+    format!(
+      "{{\"phases\":{{{},\"total\":{}}},\"counts\":{{{},\"total_rays\":{}}},\"rays_per_second\":{}}}",
+      phases.join(","),
+      self.total_duration().as_secs_f64(),
+      counts.join(","),
+      self.counts.total_rays(),
+      self.rays_per_second()
+    )
+  }
+}
+
+impl Display for Report {
+  fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+    let total = self.total_duration().as_secs_f64();
+    for phase in Phase::ALL {
+      let seconds = self.duration(phase).as_secs_f64();
+      let share = if total > 0.0 { 100.0 * seconds / total } else { 0.0 };
+      writeln!(f, "{:<16} {:>10.3} s {:>6.1}%", phase.label(), seconds, share)?;
+    }
+    writeln!(f, "{:<16} {:>10.3} s", "total", total)?;
+    for counter in Counter::ALL {
+      writeln!(f, "{:<16} {:>12}", counter.label(), self.counts.get(counter))?;
+    }
+    write!(f, "{:<16} {:>12.0}", "rays per second", self.rays_per_second())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn counts_flushed_by_finished_threads_are_taken() {
+    std::thread::scope(|scope| {
+      for _ in 0..3 {
+        scope.spawn(|| {
+          count(Counter::PrimitiveTests, 1_000_000);
+          flush_thread_counts();
+        });
+      }
+    });
+    // Other tests render on threads of their own, which may flush counts in between
+    assert!(take_ray_counts().get(Counter::PrimitiveTests) >= 3_000_000);
+  }
+
+  #[test]
+  fn thread_counts_hold_what_the_current_thread_has_not_flushed() {
+    std::thread::spawn(|| {
+      count(Counter::NodeVisits, 4);
+      count(Counter::NodeVisits, 3);
+      assert_eq!(thread_counts().get(Counter::NodeVisits), 7);
+      flush_thread_counts();
+      assert_eq!(thread_counts(), RayCounts::default());
+    })
+    .join()
+    .unwrap();
+  }
+
+  #[test]
+  fn report_gives_rays_per_second_of_rendering() {
+    let mut counts = RayCounts::default();
+    counts.add(Counter::CameraRays, 6);
+    counts.add(Counter::ShadowRays, 1);
+    counts.add(Counter::NodeVisits, 15);
+    let mut report = Report::new().with_counts(counts);
+    report.add(Phase::Render, Duration::from_secs(2));
+    report.add(Phase::Output, Duration::from_millis(500));
+    assert_eq!(report.counts().total_rays(), 7);
+    assert!((report.rays_per_second() - 3.5).abs() < 1e-9);
+    assert_eq!(
+      report.to_json(),
+      "{\"phases\":{\"scene_build\":0,\"bvh_build\":0,\"render\":2,\"denoise\":0,\"output\":0.5,\"total\":2.5},\"counts\":{\"camera_rays\":6,\
+       \"secondary_rays\":0,\"shadow_rays\":1,\"node_visits\":15,\"primitive_tests\":0,\"total_rays\":7},\"rays_per_second\":3.5}"
+    );
+  }
+}
@@ -0,0 +1,94 @@
+use crate::aabb::Aabb;
+use crate::float::{gamma, Float};
+use crate::hit::{HitRecord, Hittable};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::vec3::Vec3;
+use std::sync::Arc;
+
+/// Flat triangle, whose normal faces the side its vertices wind anticlockwise around
+#[derive(Clone, Debug)]
+pub struct Triangle {
+  vertices: [Vec3; 3],
+  normal: Vec3,
+  material: Arc<dyn Material>,
+}
+
+impl Triangle {
+  pub fn new(first: &Vec3, second: &Vec3, third: &Vec3, material: Arc<dyn Material>) -> Self {
+    Self {
+      vertices: [*first, *second, *third],
+      normal: (second - first).cross(&(third - first)).unit_vector(),
+      material,
+    }
+  }
+  pub fn vertices(&self) -> &[Vec3; 3] {
+    &self.vertices
+  }
+}
+
+impl Hittable for Triangle {
+  fn is_hit(&self, ray: &Ray, scalar_from_ray_origin_min: Float, scalar_from_ray_origin_max: Float) -> Option<HitRecord> {
+    // Möller–Trumbore: solve for the distance and the barycentric coordinates of the second and third vertices at once
+    let [first, second, third] = self.vertices;
+    let (first_edge, second_edge) = (second - first, third - first);
+    let perpendicular = ray.direction().cross(&second_edge);
+    let determinant = first_edge.dot(&perpendicular);
+    if determinant == 0.0 {
+      return None;
+    }
+    let inverse_determinant = 1.0 / determinant;
+    let offset = ray.origin() - first;
+    let u = offset.dot(&perpendicular) * inverse_determinant;
+    if !(0.0..=1.0).contains(&u) {
+      return None;
+    }
+    let offset_cross_edge = offset.cross(&first_edge);
+    let v = ray.direction().dot(&offset_cross_edge) * inverse_determinant;
+    if v < 0.0 || u + v > 1.0 {
+      return None;
+    }
+    let scalar_length = second_edge.dot(&offset_cross_edge) * inverse_determinant;
+    if scalar_length <= scalar_from_ray_origin_min || scalar_length >= scalar_from_ray_origin_max {
+      return None;
+    }
+    // Interpolating the vertices bounds the hit point's error by their size rather than by how far the ray travelled
+    let weights = [1.0 - u - v, u, v];
+    let point = weights[0] * first + weights[1] * second + weights[2] * third;
+    let error = gamma(7) * ((weights[0] * first).abs() + (weights[1] * second).abs() + (weights[2] * third).abs());
+    Some(
+      HitRecord::new(scalar_length, &point, &self.normal, self.material.clone())
+        .with_error(&error)
+        .with_uv(u, v),
+    )
+  }
+  fn bounding_box(&self) -> Option<Aabb> {
+    let [first, second, third] = self.vertices;
+    Some(Aabb::new_from_point(&first).union_point(&second).union_point(&third))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::material::Lambertian;
+
+  #[test]
+  fn rays_hit_inside_the_triangle_only() {
+    let triangle = Triangle::new(
+      &Vec3::new(0.0, 0.0, -1.0),
+      &Vec3::new(1.0, 0.0, -1.0),
+      &Vec3::new(0.0, 1.0, -1.0),
+      Arc::new(Lambertian::new(&Vec3::new(0.5, 0.5, 0.5))),
+    );
+    let origin = Vec3::new(0.25, 0.25, 0.0);
+    let hit_record = triangle.is_hit(&Ray::new(&origin, &Vec3::new(0.0, 0.0, -1.0)), 0.0, Float::MAX).unwrap();
+    assert!((hit_record.scalar_from_ray_origin() - 1.0).abs() < 1e-6);
+    assert!((hit_record.point() - Vec3::new(0.25, 0.25, -1.0)).length() <= hit_record.error().length() + 1e-6);
+    assert!(hit_record.normal().z() > 0.0);
+    assert!(triangle.is_hit(&Ray::new(&origin, &Vec3::new(0.0, 0.0, -1.0)), 0.0, 0.5).is_none());
+    assert!(triangle
+      .is_hit(&Ray::new(&Vec3::new(0.75, 0.75, 0.0), &Vec3::new(0.0, 0.0, -1.0)), 0.0, Float::MAX)
+      .is_none());
+  }
+}
@@ -1,6 +1,9 @@
 #![deny(clippy::perf, clippy::correctness, clippy::complexity, clippy::style, missing_debug_implementations)]
 #![warn(clippy::pedantic)]
+#![allow(clippy::must_use_candidate, clippy::return_self_not_must_use)]
 
+use crate::float::Float;
+use crate::simd::{self, Lanes};
 use std::{
   convert::TryFrom,
   fmt::{Display, Formatter, Result as FmtResult},
@@ -10,18 +13,19 @@ use try_from_integers::TryFromIntegers;
 
 type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
 
+/// Three component vector, whose arithmetic goes through the `simd` backend
 #[derive(Clone, Copy, Debug, PartialEq)]
-pub struct Vec3([f32; 3]);
+pub struct Vec3(Lanes);
 
 impl Vec3 {
   pub fn new_empty() -> Self {
-    Self([0.0, 0.0, 0.0])
+    Self::new(0.0, 0.0, 0.0)
   }
-  pub fn new(x: f32, y: f32, z: f32) -> Self {
-    Self([x, y, z])
+  pub fn new(x: Float, y: Float, z: Float) -> Self {
+    Self(simd::lanes(x, y, z))
   }
-  pub fn new_from_array(array: [f32; 3]) -> Self {
-    Self(array)
+  pub fn new_from_array(array: [Float; 3]) -> Self {
+    Self::new(array[0], array[1], array[2])
   }
   /// Creates a new Vec3 from an array of 3 strings representing x, y and z
   ///
@@ -30,19 +34,19 @@ impl Vec3 {
   /// Returns an error if any of the strings cannot be parsed into floats
   pub fn new_from_str_array<S: AsRef<str>>(input_strings: &[S; 3]) -> Result<Self, Error> {
     let inner = |input_strings: [&str; 3]| {
-      let x: f32 = match input_strings[0].parse() {
+      let x: Float = match input_strings[0].parse() {
         Ok(x) => x,
         Err(err) => {
           return Err(Box::new(err).into());
         }
       };
-      let y: f32 = match input_strings[1].parse() {
+      let y: Float = match input_strings[1].parse() {
         Ok(y) => y,
         Err(err) => {
           return Err(Box::new(err).into());
         }
       };
-      let z: f32 = match input_strings[2].parse() {
+      let z: Float = match input_strings[2].parse() {
         Ok(z) => z,
         Err(err) => {
           return Err(Box::new(err).into());
@@ -52,22 +56,22 @@ impl Vec3 {
     };
     inner([input_strings[0].as_ref(), input_strings[1].as_ref(), input_strings[2].as_ref()])
   }
-  pub fn x(&self) -> f32 {
+  pub fn x(&self) -> Float {
     self.0[0]
   }
-  pub fn y(&self) -> f32 {
+  pub fn y(&self) -> Float {
     self.0[1]
   }
-  pub fn z(&self) -> f32 {
+  pub fn z(&self) -> Float {
     self.0[2]
   }
-  pub fn r(&self) -> f32 {
+  pub fn r(&self) -> Float {
     self.x()
   }
-  pub fn g(&self) -> f32 {
+  pub fn g(&self) -> Float {
     self.y()
   }
-  pub fn b(&self) -> f32 {
+  pub fn b(&self) -> Float {
     self.z()
   }
   pub fn unit_vector(&self) -> Self {
@@ -78,22 +82,22 @@ impl Vec3 {
     *self = self.unit_vector();
     self
   }
-  pub fn dot(&self, other: &Self) -> f32 {
-    self.z().mul_add(other.z(), self.x().mul_add(other.x(), self.y() * other.y()))
+  pub fn dot(&self, other: &Self) -> Float {
+    simd::dot(&self.0, &other.0)
   }
   pub fn cross(&self, other: &Self) -> Self {
-    Self::new(
-      self.y() * other.z() - self.z() * other.y(),
-      -(self.x() * other.z() - self.z() * other.x()),
-      self.x() * other.y() - self.y() * other.x(),
-    )
+    Self(simd::cross(&self.0, &other.0))
   }
-  pub fn squared_length(&self) -> f32 {
-    self.z().mul_add(self.z(), self.x().mul_add(self.x(), self.y() * self.y()))
+  pub fn squared_length(&self) -> Float {
+    self.dot(self)
   }
-  pub fn length(&self) -> f32 {
+  pub fn length(&self) -> Float {
     self.squared_length().sqrt()
   }
+  /// Magnitudes of the components
+  pub fn abs(&self) -> Self {
+    Self::new(self.x().abs(), self.y().abs(), self.z().abs())
+  }
 }
 
 impl Display for Vec3 {
@@ -106,20 +110,24 @@ impl Neg for Vec3 {
   type Output = Self;
 
   fn neg(self) -> Self::Output {
-    Self([-self.x(), -self.y(), -self.z()])
+    Self(simd::neg(&self.0))
   }
 }
 
 #[allow(clippy::module_name_repetitions)]
 #[derive(Clone, Copy, Debug, PartialEq, TryFromIntegers)]
-enum Vec3Index {
+pub enum Vec3Index {
   Zero,
   One,
   Two,
 }
 
+impl Vec3Index {
+  pub const ALL: [Self; 3] = [Self::Zero, Self::One, Self::Two];
+}
+
 impl Index<Vec3Index> for Vec3 {
-  type Output = f32;
+  type Output = Float;
 
   fn index(&self, vec3_index: Vec3Index) -> &Self::Output {
     match vec3_index {
@@ -134,7 +142,7 @@ impl Add<Vec3> for Vec3 {
   type Output = Self;
 
   fn add(self, other: Self) -> Self::Output {
-    Self([self.x() + other.x(), self.y() + other.y(), self.z() + other.z()])
+    Self(simd::add(&self.0, &other.0))
   }
 }
 
@@ -154,7 +162,7 @@ impl Add<&Vec3> for Vec3 {
   }
 }
 
-impl<'first_vec, 'second_vec> Add<&'first_vec Vec3> for &'second_vec Vec3 {
+impl<'first_vec> Add<&'first_vec Vec3> for &Vec3 {
   type Output = Vec3;
 
   fn add(self, other: &'first_vec Vec3) -> Self::Output {
@@ -172,7 +180,7 @@ impl Sub<Vec3> for Vec3 {
   type Output = Self;
 
   fn sub(self, other: Self) -> Self::Output {
-    Self([self.x() - other.x(), self.y() - other.y(), self.z() - other.z()])
+    Self(simd::sub(&self.0, &other.0))
   }
 }
 
@@ -192,7 +200,7 @@ impl Sub<&Vec3> for Vec3 {
   }
 }
 
-impl<'first_vec, 'second_vec> Sub<&'first_vec Vec3> for &'second_vec Vec3 {
+impl<'first_vec> Sub<&'first_vec Vec3> for &Vec3 {
   type Output = Vec3;
 
   fn sub(self, other: &'first_vec Vec3) -> Self::Output {
@@ -206,23 +214,23 @@ impl SubAssign<Vec3> for Vec3 {
   }
 }
 
-impl Mul<f32> for Vec3 {
+impl Mul<Float> for Vec3 {
   type Output = Self;
 
-  fn mul(self, other: f32) -> Self::Output {
-    Self([self.x() * other, self.y() * other, self.z() * other])
+  fn mul(self, other: Float) -> Self::Output {
+    Self(simd::scale(&self.0, other))
   }
 }
 
-impl Mul<f32> for &Vec3 {
+impl Mul<Float> for &Vec3 {
   type Output = Vec3;
 
-  fn mul(self, other: f32) -> Self::Output {
+  fn mul(self, other: Float) -> Self::Output {
     *self * other
   }
 }
 
-impl Mul<&Vec3> for f32 {
+impl Mul<&Vec3> for Float {
   type Output = Vec3;
 
   fn mul(self, other: &Vec3) -> Self::Output {
@@ -230,13 +238,13 @@ impl Mul<&Vec3> for f32 {
   }
 }
 
-impl MulAssign<f32> for Vec3 {
-  fn mul_assign(&mut self, other: f32) {
+impl MulAssign<Float> for Vec3 {
+  fn mul_assign(&mut self, other: Float) {
     *self = *self * other;
   }
 }
 
-impl Mul<Vec3> for f32 {
+impl Mul<Vec3> for Float {
   type Output = Vec3;
 
   fn mul(self, other: Vec3) -> Vec3 {
@@ -248,7 +256,7 @@ impl Mul<Vec3> for Vec3 {
   type Output = Self;
 
   fn mul(self, other: Self) -> Self::Output {
-    Self([self.x() * other.x(), self.y() * other.y(), self.z() * other.z()])
+    Self(simd::mul(&self.0, &other.0))
   }
 }
 
@@ -268,7 +276,7 @@ impl Mul<&Vec3> for Vec3 {
   }
 }
 
-impl<'first_vec, 'second_vec> Mul<&'first_vec Vec3> for &'second_vec Vec3 {
+impl<'first_vec> Mul<&'first_vec Vec3> for &Vec3 {
   type Output = Vec3;
 
   fn mul(self, other: &'first_vec Vec3) -> Self::Output {
@@ -282,23 +290,23 @@ impl MulAssign<Vec3> for Vec3 {
   }
 }
 
-impl Div<f32> for Vec3 {
+impl Div<Float> for Vec3 {
   type Output = Self;
 
-  fn div(self, other: f32) -> Self::Output {
-    Self([self.x() / other, self.y() / other, self.z() / other])
+  fn div(self, other: Float) -> Self::Output {
+    Self(simd::div(&self.0, &simd::lanes(other, other, other)))
   }
 }
 
-impl Div<f32> for &Vec3 {
+impl Div<Float> for &Vec3 {
   type Output = Vec3;
 
-  fn div(self, other: f32) -> Self::Output {
+  fn div(self, other: Float) -> Self::Output {
     *self / other
   }
 }
 
-impl Div<Vec3> for f32 {
+impl Div<Vec3> for Float {
   type Output = Vec3;
 
   fn div(self, other: Vec3) -> Self::Output {
@@ -306,7 +314,7 @@ impl Div<Vec3> for f32 {
   }
 }
 
-impl Div<&Vec3> for f32 {
+impl Div<&Vec3> for Float {
   type Output = Vec3;
 
   fn div(self, other: &Vec3) -> Self::Output {
@@ -314,8 +322,8 @@ impl Div<&Vec3> for f32 {
   }
 }
 
-impl DivAssign<f32> for Vec3 {
-  fn div_assign(&mut self, other: f32) {
+impl DivAssign<Float> for Vec3 {
+  fn div_assign(&mut self, other: Float) {
     *self = *self / other;
   }
 }
@@ -324,7 +332,7 @@ impl Div<Vec3> for Vec3 {
   type Output = Self;
 
   fn div(self, other: Self) -> Self::Output {
-    Self([self.x() / other.x(), self.y() / other.y(), self.z() / other.z()])
+    Self(simd::div(&self.0, &other.0))
   }
 }
 
@@ -344,7 +352,7 @@ impl Div<&Vec3> for Vec3 {
   }
 }
 
-impl<'first_vec, 'second_vec> Div<&'first_vec Vec3> for &'second_vec Vec3 {
+impl<'first_vec> Div<&'first_vec Vec3> for &Vec3 {
   type Output = Vec3;
 
   fn div(self, other: &'first_vec Vec3) -> Self::Output {
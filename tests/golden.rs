@@ -0,0 +1,261 @@
+//! Renders small fixed-seed scenes and compares them against the reference images in `tests/golden`, so changes to
+//! materials, integrators or sampling cannot change the output unnoticed
+//!
+//! Images are compared after blurring away sample noise, by the root mean square of the difference in their 8-bit
+//! gamma-encoded channels. A failing scene writes the new render and an amplified difference image next to each
+//! other under `target/tmp/golden`. Once a change in output is intended, `GOLDEN_UPDATE=1 cargo test --test golden`
+//! writes the new references
+
+use rust_ray_tracer_in_one_weekend::background::Background;
+use rust_ray_tracer_in_one_weekend::camera::Camera;
+use rust_ray_tracer_in_one_weekend::float::Float;
+use rust_ray_tracer_in_one_weekend::scene::{MaterialHandle, Scene, SceneBuilder};
+use rust_ray_tracer_in_one_weekend::settings::ImageSettings;
+use rust_ray_tracer_in_one_weekend::vec3::Vec3;
+use rust_ray_tracer_in_one_weekend::{render, scenes, Error};
+use std::convert::TryFrom;
+use std::path::{Path, PathBuf};
+
+const WIDTH: usize = 64;
+const HEIGHT: usize = 48;
+const SAMPLES_PER_PIXEL: usize = 16;
+const SEED: u64 = 49;
+/// Largest root mean square difference, in 8-bit levels of the blurred images, that still counts as the same image.
+/// The rounding differences of the `f64` and `simd` builds, which send a few paths another way, stay under half a
+/// level, while darkening every diffuse albedo by 5% moves each scene by more than two and a half
+const TOLERANCE: f64 = 1.0;
+
+/// An 8-bit gamma-encoded image as written to a PPM, top row first
+#[derive(Clone, Debug, PartialEq)]
+struct Image {
+  width: usize,
+  height: usize,
+  channels: Vec<u8>,
+}
+
+impl Image {
+  fn from_ppm(ppm: &str) -> Result<Self, Error> {
+    let mut values = ppm.split_whitespace();
+    if values.next() != Some("P3") {
+      return Err("Expected a plain PPM".into());
+    }
+    let mut next_number = || -> Result<usize, Error> { Ok(values.next().ok_or("The PPM ends early")?.parse()?) };
+    let (width, height, _) = (next_number()?, next_number()?, next_number()?);
+    let channels = (0..width * height * 3)
+      .map(|_| Ok(u8::try_from(next_number()?)?))
+      .collect::<Result<_, Error>>()?;
+    Ok(Self { width, height, channels })
+  }
+  fn to_ppm(&self) -> String {
+    let mut ppm = format!("P3\n{} {}\n255\n", self.width, self.height);
+    for pixel in self.channels.chunks(3) {
+      ppm += &format!("{} {} {}\n", pixel[0], pixel[1], pixel[2]);
+    }
+    ppm
+  }
+  /// Each channel averaged with its neighbours in a 3x3 box, as floats
+  fn blurred(&self) -> Vec<f64> {
+    let mut blurred = Vec::with_capacity(self.channels.len());
+    for y in 0..self.height {
+      for x in 0..self.width {
+        for channel in 0..3 {
+          let (mut sum, mut count) = (0.0, 0.0);
+          for neighbour_y in y.saturating_sub(1)..(y + 2).min(self.height) {
+            for neighbour_x in x.saturating_sub(1)..(x + 2).min(self.width) {
+              sum += f64::from(self.channels[(neighbour_y * self.width + neighbour_x) * 3 + channel]);
+              count += 1.0;
+            }
+          }
+          blurred.push(sum / count);
+        }
+      }
+    }
+    blurred
+  }
+  /// Root mean square difference between the blurred images
+  fn blurred_rmse(&self, other: &Self) -> f64 {
+    let squares: f64 = self
+      .blurred()
+      .iter()
+      .zip(other.blurred())
+      .map(|(first, second)| (first - second).powi(2))
+      .sum();
+    (squares / self.channels.len() as f64).sqrt()
+  }
+  /// Absolute difference of every channel, amplified four times so small changes show
+  fn difference(&self, other: &Self) -> Self {
+    Self {
+      width: self.width,
+      height: self.height,
+      channels: self
+        .channels
+        .iter()
+        .zip(&other.channels)
+        .map(|(first, second)| first.abs_diff(*second).saturating_mul(4))
+        .collect(),
+    }
+  }
+}
+
+fn camera(look_from: &Vec3, look_at: &Vec3, vertical_fov: Float) -> Camera {
+  Camera::builder()
+    .with_look_from(look_from)
+    .with_look_at(look_at)
+    .with_vertical_fov(vertical_fov)
+    .with_aspect(WIDTH as Float / HEIGHT as Float)
+    .build()
+    .expect("the test camera is valid")
+}
+
+/// Renders `scene` and compares it against the reference image `name`, or replaces the reference with it when
+/// `GOLDEN_UPDATE` is set
+fn check_golden(name: &str, scene: &Scene, camera: &Camera) {
+  let framebuffer = render(scene, camera, &ImageSettings::new(WIDTH, HEIGHT, SAMPLES_PER_PIXEL).with_seed(SEED)).expect("the render succeeds");
+  let mut ppm = Vec::new();
+  framebuffer.write_ppm(true, &mut ppm).expect("writing to memory succeeds");
+  let actual = Image::from_ppm(&String::from_utf8(ppm).expect("PPMs are text")).expect("the PPM just written parses");
+  let reference_path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden").join(format!("{name}.ppm"));
+  if std::env::var_os("GOLDEN_UPDATE").is_some() {
+    std::fs::write(&reference_path, actual.to_ppm()).expect("the reference can be written");
+    return;
+  }
+  let reference = std::fs::read_to_string(&reference_path)
+    .map_err(Error::from)
+    .and_then(|ppm| Image::from_ppm(&ppm))
+    .unwrap_or_else(|error| {
+      panic!(
+        "cannot read {}: {error}; run GOLDEN_UPDATE=1 cargo test --test golden to create it",
+        reference_path.display()
+      )
+    });
+  assert_eq!(
+    (reference.width, reference.height),
+    (WIDTH, HEIGHT),
+    "{name} has a reference of another size"
+  );
+  let rmse = actual.blurred_rmse(&reference);
+  if rmse > TOLERANCE {
+    let output_directory = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("golden");
+    std::fs::create_dir_all(&output_directory).expect("the output directory can be created");
+    let actual_path = output_directory.join(format!("{name}.actual.ppm"));
+    let difference_path = output_directory.join(format!("{name}.difference.ppm"));
+    std::fs::write(&actual_path, actual.to_ppm()).expect("the render can be written");
+    std::fs::write(&difference_path, actual.difference(&reference).to_ppm()).expect("the difference can be written");
+    panic!(
+      "{name} differs from its reference by {rmse:.2} levels, more than {TOLERANCE}; see {} and {}",
+      actual_path.display(),
+      difference_path.display()
+    );
+  }
+}
+
+/// Adds a square of `material` with corners in order around it, facing the side they wind anticlockwise around
+fn add_quad(builder: &mut SceneBuilder, corners: [Vec3; 4], material: MaterialHandle) -> Result<(), Error> {
+  builder
+    .add_triangle(&corners[0], &corners[1], &corners[2], material)?
+    .add_triangle(&corners[0], &corners[2], &corners[3], material)?;
+  Ok(())
+}
+
+#[test]
+fn random_spheres() {
+  let mut scene = scenes::random_scene(SEED);
+  scene.world_mut().build_bvh();
+  check_golden(
+    "random_spheres",
+    &scene,
+    &camera(&Vec3::new(13.0, 2.0, 3.0), &Vec3::new(0.0, 0.0, 0.0), 20.0),
+  );
+}
+
+#[test]
+fn cornell_box() -> Result<(), Error> {
+  let mut builder = Scene::builder();
+  let red = builder.add_lambertian("red", &Vec3::new(0.65, 0.05, 0.05))?;
+  let green = builder.add_lambertian("green", &Vec3::new(0.12, 0.45, 0.15))?;
+  let white = builder.add_lambertian("white", &Vec3::new(0.73, 0.73, 0.73))?;
+  let mirror = builder.add_metal("mirror", &Vec3::new(0.9, 0.9, 0.9), 0.0)?;
+  let corner = |x: Float, y: Float, z: Float| Vec3::new(x, y, z);
+  // Floor, ceiling and back wall, then the left and right walls
+  add_quad(
+    &mut builder,
+    [
+      corner(-1.0, 0.0, 1.0),
+      corner(1.0, 0.0, 1.0),
+      corner(1.0, 0.0, -1.0),
+      corner(-1.0, 0.0, -1.0),
+    ],
+    white,
+  )?;
+  add_quad(
+    &mut builder,
+    [
+      corner(-1.0, 2.0, 1.0),
+      corner(-1.0, 2.0, -1.0),
+      corner(1.0, 2.0, -1.0),
+      corner(1.0, 2.0, 1.0),
+    ],
+    white,
+  )?;
+  add_quad(
+    &mut builder,
+    [
+      corner(-1.0, 0.0, -1.0),
+      corner(1.0, 0.0, -1.0),
+      corner(1.0, 2.0, -1.0),
+      corner(-1.0, 2.0, -1.0),
+    ],
+    white,
+  )?;
+  add_quad(
+    &mut builder,
+    [
+      corner(-1.0, 0.0, 1.0),
+      corner(-1.0, 0.0, -1.0),
+      corner(-1.0, 2.0, -1.0),
+      corner(-1.0, 2.0, 1.0),
+    ],
+    red,
+  )?;
+  add_quad(
+    &mut builder,
+    [
+      corner(1.0, 0.0, -1.0),
+      corner(1.0, 0.0, 1.0),
+      corner(1.0, 2.0, 1.0),
+      corner(1.0, 2.0, -1.0),
+    ],
+    green,
+  )?;
+  builder
+    .add_sphere(&Vec3::new(-0.4, 0.45, -0.3), 0.45, white)?
+    .add_sphere(&Vec3::new(0.45, 0.35, 0.3), 0.35, mirror)?
+    .add_sphere_light(&Vec3::new(0.0, 1.75, 0.0), 0.3, &Vec3::new(8.0, 8.0, 8.0))?
+    .set_background(Background::Constant(Vec3::new(0.0, 0.0, 0.0)));
+  check_golden(
+    "cornell_box",
+    &builder.build(),
+    &camera(&Vec3::new(0.0, 1.0, 4.2), &Vec3::new(0.0, 1.0, 0.0), 38.0),
+  );
+  Ok(())
+}
+
+#[test]
+fn glass_and_metal_spheres() -> Result<(), Error> {
+  let mut builder = Scene::builder();
+  let ground = builder.add_lambertian("ground", &Vec3::new(0.5, 0.5, 0.5))?;
+  let glass = builder.add_dielectric("glass", 1.5)?;
+  let polished = builder.add_metal("polished", &Vec3::new(0.8, 0.6, 0.2), 0.0)?;
+  let brushed = builder.add_metal("brushed", &Vec3::new(0.7, 0.7, 0.8), 0.4)?;
+  builder
+    .add_sphere(&Vec3::new(0.0, -1000.0, 0.0), 1000.0, ground)?
+    .add_sphere(&Vec3::new(0.0, 1.0, 0.0), 1.0, glass)?
+    .add_sphere(&Vec3::new(-2.1, 1.0, 0.0), 1.0, polished)?
+    .add_sphere(&Vec3::new(2.1, 1.0, 0.0), 1.0, brushed)?;
+  check_golden(
+    "glass_and_metal_spheres",
+    &builder.build(),
+    &camera(&Vec3::new(0.0, 2.0, 8.0), &Vec3::new(0.0, 0.8, 0.0), 35.0),
+  );
+  Ok(())
+}